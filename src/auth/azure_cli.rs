@@ -0,0 +1,117 @@
+//! Azure CLI credential
+//!
+//! For local development the token is taken from the developer's own
+//! `az login` session (`az account get-access-token`), so no app
+//! registration or secret is needed. The CLI refreshes its own tokens.
+
+use crate::auth::AuthError;
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `az` signs in interactively at most; anything longer is stuck
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Lifetime assumed when the CLI is too old to report `expires_on`
+const FALLBACK_LIFETIME_SECS: u64 = 300;
+
+#[cfg(windows)]
+const AZ: &str = "az.cmd";
+#[cfg(not(windows))]
+const AZ: &str = "az";
+
+/// Arguments of `az account get-access-token`
+pub(crate) fn command_args(resource: &str, tenant: Option<&str>) -> Vec<String> {
+    let mut args = vec![
+        "account".to_string(),
+        "get-access-token".to_string(),
+        "--resource".to_string(),
+        resource.to_string(),
+        "--output".to_string(),
+        "json".to_string(),
+    ];
+    if let Some(tenant) = tenant {
+        args.extend(["--tenant".to_string(), tenant.to_string()]);
+    }
+    args
+}
+
+/// Run the CLI and return the access token with its lifetime in seconds
+pub(crate) async fn fetch_token(resource: &str, tenant: Option<&str>) -> Result<(String, u64), AuthError> {
+    let run = tokio::process::Command::new(AZ)
+        .args(command_args(resource, tenant))
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(COMMAND_TIMEOUT, run).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(AuthError::MissingCredentials(
+                "Azure CLI ('az') not found on PATH; install it and run 'az login'".to_string(),
+            ))
+        }
+        Ok(Err(e)) => return Err(AuthError::TokenRequestFailed(format!("cannot run '{}': {}", AZ, e))),
+        Err(_) => {
+            return Err(AuthError::TokenRequestFailed(format!(
+                "'az account get-access-token' did not finish within {} seconds",
+                COMMAND_TIMEOUT.as_secs()
+            )))
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AuthError::TokenRequestFailed(format!(
+            "'az account get-access-token' failed: {}",
+            stderr.trim()
+        )));
+    }
+    parse_token(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// `accessToken` and lifetime from the CLI's JSON output. `expires_on`
+/// (epoch seconds) is only printed by newer CLIs; `expiresOn` is local
+/// time without a zone, so older CLIs get a short assumed lifetime.
+pub(crate) fn parse_token(stdout: &str) -> Result<(String, u64), AuthError> {
+    let json: Value = serde_json::from_str(stdout)
+        .map_err(|e| AuthError::ParseError(format!("Failed to parse Azure CLI output: {}", e)))?;
+    let access_token = json["accessToken"]
+        .as_str()
+        .ok_or_else(|| AuthError::ParseError("Azure CLI output has no accessToken".to_string()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let expires_in = json["expires_on"]
+        .as_u64()
+        .map(|on| on.saturating_sub(now))
+        .unwrap_or(FALLBACK_LIFETIME_SECS);
+    Ok((access_token.to_string(), expires_in))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_args() {
+        assert_eq!(
+            command_args("https://org.crm.dynamics.com", Some("contoso.onmicrosoft.com")).join(" "),
+            "account get-access-token --resource https://org.crm.dynamics.com --output json --tenant contoso.onmicrosoft.com"
+        );
+        assert!(!command_args("https://org.crm.dynamics.com", None).contains(&"--tenant".to_string()));
+    }
+
+    #[test]
+    fn test_parse_token() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let stdout = format!(
+            r#"{{"accessToken": "abc", "expiresOn": "2024-01-31 10:00:00.000000", "expires_on": {}, "tokenType": "Bearer"}}"#,
+            now + 3000
+        );
+        let (token, expires_in) = parse_token(&stdout).unwrap();
+        assert_eq!(token, "abc");
+        assert!((2990..=3000).contains(&expires_in));
+
+        let (_, expires_in) = parse_token(r#"{"accessToken": "abc", "expiresOn": "2024-01-31 10:00:00.000000"}"#).unwrap();
+        assert_eq!(expires_in, FALLBACK_LIFETIME_SECS);
+        assert!(parse_token("ERROR: Please run 'az login'").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! National clouds
+//!
+//! Tokens for US Government and China environments come from their own
+//! Azure AD authority. The authority is taken from `AUTHORITY_HOST` (a cloud
+//! name or a URL) or, when not set, inferred from the environment URL's
+//! domain suffix.
+
+/// Azure AD authority of the public cloud
+pub const PUBLIC_AUTHORITY_HOST: &str = "https://login.microsoftonline.com";
+
+/// Azure cloud an environment lives in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cloud {
+    /// Commercial cloud, including GCC (`crm9.dynamics.com`)
+    Public,
+    /// GCC High (`crm.microsoftdynamics.us`)
+    UsGovernment,
+    /// Department of Defense (`crm.appsplatform.us`)
+    UsDod,
+    /// Operated by 21Vianet (`crm.dynamics.cn`)
+    China,
+}
+
+/// Domain suffixes of Dataverse and F&O environments outside the public cloud
+const CLOUD_SUFFIXES: &[(&str, Cloud)] = &[
+    (".microsoftdynamics.us", Cloud::UsGovernment),
+    (".dynamics.us", Cloud::UsGovernment),
+    (".appsplatform.us", Cloud::UsDod),
+    (".dynamics.cn", Cloud::China),
+];
+
+impl Cloud {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "public" | "azure" | "commercial" | "gcc" => Some(Cloud::Public),
+            "usgov" | "us_government" | "gcchigh" | "gcc_high" | "gcc-high" => Some(Cloud::UsGovernment),
+            "dod" | "usdod" | "us_dod" => Some(Cloud::UsDod),
+            "china" | "cn" | "21vianet" => Some(Cloud::China),
+            _ => None,
+        }
+    }
+
+    /// Cloud of an environment URL; public unless the domain says otherwise
+    pub fn from_endpoint(endpoint: &str) -> Self {
+        let host = reqwest::Url::parse(endpoint)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+        CLOUD_SUFFIXES
+            .iter()
+            .find(|(suffix, _)| host.ends_with(suffix))
+            .map_or(Cloud::Public, |(_, cloud)| *cloud)
+    }
+
+    pub fn authority_host(self) -> &'static str {
+        match self {
+            Cloud::Public => PUBLIC_AUTHORITY_HOST,
+            // GCC High and DoD share the US Government authority
+            Cloud::UsGovernment | Cloud::UsDod => "https://login.microsoftonline.us",
+            Cloud::China => "https://login.chinacloudapi.cn",
+        }
+    }
+}
+
+/// Authority host URL from a setting (cloud name or URL), else from the
+/// environment URL
+pub fn resolve_authority_host(setting: Option<&str>, endpoint: &str) -> Result<String, String> {
+    let Some(setting) = setting.map(str::trim).filter(|s| !s.is_empty()) else {
+        return Ok(Cloud::from_endpoint(endpoint).authority_host().to_string());
+    };
+    if let Some(cloud) = Cloud::parse(setting) {
+        return Ok(cloud.authority_host().to_string());
+    }
+    let with_scheme = if setting.contains("://") {
+        setting.to_string()
+    } else {
+        format!("https://{}", setting)
+    };
+    match reqwest::Url::parse(&with_scheme) {
+        Ok(url) if url.host_str().is_some() => Ok(with_scheme.trim_end_matches('/').to_string()),
+        _ => Err(format!(
+            "Invalid authority host '{}': use public, usgov, dod, china or a URL",
+            setting
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cloud_from_endpoint() {
+        assert_eq!(Cloud::from_endpoint("https://org.crm.dynamics.com/api/data/v9.2/"), Cloud::Public);
+        assert_eq!(Cloud::from_endpoint("https://org.crm9.dynamics.com/api/data/v9.2/"), Cloud::Public);
+        assert_eq!(
+            Cloud::from_endpoint("https://org.crm.microsoftdynamics.us/api/data/v9.2/"),
+            Cloud::UsGovernment
+        );
+        assert_eq!(Cloud::from_endpoint("https://org.crm.appsplatform.us/api/data/v9.2/"), Cloud::UsDod);
+        assert_eq!(Cloud::from_endpoint("https://org.crm.dynamics.cn/api/data/v9.2/"), Cloud::China);
+    }
+
+    #[test]
+    fn test_resolve_authority_host() {
+        let endpoint = "https://org.crm.microsoftdynamics.us/api/data/v9.2/";
+        assert_eq!(resolve_authority_host(None, endpoint).unwrap(), "https://login.microsoftonline.us");
+        assert_eq!(
+            resolve_authority_host(Some("china"), endpoint).unwrap(),
+            "https://login.chinacloudapi.cn"
+        );
+        assert_eq!(
+            resolve_authority_host(Some("login.example.com/"), endpoint).unwrap(),
+            "https://login.example.com"
+        );
+        assert!(resolve_authority_host(Some("not a host"), endpoint).is_err());
+    }
+}
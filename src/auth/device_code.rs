@@ -0,0 +1,300 @@
+//! Device code flow (delegated user tokens)
+//!
+//! App-only tokens cannot act as a user: audit history, record ownership and
+//! security-role checks need a signed-in user. The device code flow prints a
+//! code and URL to stderr, the user signs in from any browser, and the server
+//! polls until the token arrives. The refresh token is kept on disk so later
+//! runs sign in silently until it expires or is revoked.
+
+use crate::auth::AuthError;
+use crate::sync::state::write_atomic;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// `grant_type` of a device code token request
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Poll interval when the service does not send one
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Added to the interval on `slow_down`
+const SLOW_DOWN_STEP: Duration = Duration::from_secs(5);
+
+/// Refresh token saved between runs
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct CachedRefreshToken {
+    tenant_id: String,
+    client_id: String,
+    refresh_token: String,
+}
+
+/// Response of the `devicecode` endpoint
+#[derive(Debug, Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    expires_in: u64,
+    #[serde(default)]
+    interval: Option<u64>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Outcome of one poll of the token endpoint
+#[derive(Debug, PartialEq)]
+enum Poll {
+    Token {
+        access_token: String,
+        expires_in: u64,
+        refresh_token: Option<String>,
+    },
+    Pending,
+    SlowDown,
+    Failed(String),
+}
+
+/// Signed-in user's tokens for one app registration
+#[derive(Debug)]
+pub(crate) struct DeviceCodeCredential {
+    tenant_id: String,
+    client_id: String,
+    cache_file: Option<PathBuf>,
+    /// Latest refresh token; loaded from the cache file on first use
+    refresh_token: Mutex<Option<String>>,
+}
+
+impl DeviceCodeCredential {
+    pub(crate) fn new(tenant_id: &str, client_id: &str, cache_file: Option<PathBuf>) -> Self {
+        let cached = cache_file
+            .as_deref()
+            .and_then(read_cache)
+            .filter(|c| c.tenant_id == tenant_id && c.client_id == client_id)
+            .map(|c| c.refresh_token);
+        Self {
+            tenant_id: tenant_id.to_string(),
+            client_id: client_id.to_string(),
+            cache_file,
+            refresh_token: Mutex::new(cached),
+        }
+    }
+
+    /// Access token for `scope`: refreshed silently when a refresh token is
+    /// known, otherwise through an interactive device code sign-in
+    pub(crate) async fn token(
+        &self,
+        http: &Client,
+        token_endpoint: &str,
+        scope: &str,
+    ) -> Result<(String, u64), AuthError> {
+        // Held across the sign-in so concurrent calls wait for one prompt
+        let mut refresh_token = self.refresh_token.lock().await;
+
+        if let Some(current) = refresh_token.clone() {
+            let params = [
+                ("grant_type", "refresh_token"),
+                ("client_id", self.client_id.as_str()),
+                ("refresh_token", current.as_str()),
+                ("scope", scope),
+            ];
+            match self.request(http, token_endpoint, &params).await? {
+                Poll::Token {
+                    access_token,
+                    expires_in,
+                    refresh_token: rotated,
+                } => {
+                    if let Some(rotated) = rotated.filter(|r| *r != current) {
+                        self.save(&rotated);
+                        *refresh_token = Some(rotated);
+                    }
+                    return Ok((access_token, expires_in));
+                }
+                other => {
+                    tracing::warn!("Refreshing the user token failed, signing in again: {:?}", other);
+                    *refresh_token = None;
+                }
+            }
+        }
+
+        let (access_token, expires_in, new_refresh) = self.sign_in(http, token_endpoint, scope).await?;
+        if let Some(new_refresh) = new_refresh {
+            self.save(&new_refresh);
+            *refresh_token = Some(new_refresh);
+        }
+        Ok((access_token, expires_in))
+    }
+
+    /// Run the device code flow: show the code, then poll until the user
+    /// has signed in or the code expires
+    async fn sign_in(
+        &self,
+        http: &Client,
+        token_endpoint: &str,
+        scope: &str,
+    ) -> Result<(String, u64, Option<String>), AuthError> {
+        let device_endpoint = token_endpoint.replace("/oauth2/v2.0/token", "/oauth2/v2.0/devicecode");
+        let response = http
+            .post(&device_endpoint)
+            .form(&[("client_id", self.client_id.as_str()), ("scope", scope)])
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(AuthError::TokenRequestFailed(format!(
+                "Device code request failed: Status: {}, Body: {}",
+                status, body
+            )));
+        }
+        let code: DeviceCodeResponse = serde_json::from_str(&body)
+            .map_err(|e| AuthError::ParseError(format!("Failed to parse device code response: {}", e)))?;
+
+        let prompt = code.message.clone().unwrap_or_else(|| {
+            format!(
+                "To sign in, open {} and enter the code {}",
+                code.verification_uri, code.user_code
+            )
+        });
+        // stdout carries the MCP protocol; the prompt goes to stderr and the log
+        eprintln!("{}", prompt);
+        tracing::warn!("{}", prompt);
+
+        let mut interval = code.interval.map(Duration::from_secs).unwrap_or(DEFAULT_POLL_INTERVAL);
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(code.expires_in);
+        let params = [
+            ("grant_type", DEVICE_CODE_GRANT),
+            ("client_id", self.client_id.as_str()),
+            ("device_code", code.device_code.as_str()),
+        ];
+        loop {
+            tokio::time::sleep(interval).await;
+            if tokio::time::Instant::now() >= deadline {
+                return Err(AuthError::TokenRequestFailed(
+                    "The device code expired before sign-in completed".to_string(),
+                ));
+            }
+            match self.request(http, token_endpoint, &params).await? {
+                Poll::Token {
+                    access_token,
+                    expires_in,
+                    refresh_token,
+                } => {
+                    tracing::info!("Device code sign-in completed");
+                    return Ok((access_token, expires_in, refresh_token));
+                }
+                Poll::Pending => {}
+                Poll::SlowDown => interval += SLOW_DOWN_STEP,
+                Poll::Failed(e) => return Err(AuthError::TokenRequestFailed(e)),
+            }
+        }
+    }
+
+    async fn request(&self, http: &Client, token_endpoint: &str, params: &[(&str, &str)]) -> Result<Poll, AuthError> {
+        let response = http.post(token_endpoint).form(params).send().await?;
+        let body = response.text().await.unwrap_or_default();
+        Ok(parse_poll(&body))
+    }
+
+    fn save(&self, refresh_token: &str) {
+        let Some(path) = &self.cache_file else {
+            return;
+        };
+        let entry = CachedRefreshToken {
+            tenant_id: self.tenant_id.clone(),
+            client_id: self.client_id.clone(),
+            refresh_token: refresh_token.to_string(),
+        };
+        if let Err(e) = write_cache(path, &entry) {
+            tracing::warn!("Cannot save the refresh token to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Classify a token endpoint response
+fn parse_poll(body: &str) -> Poll {
+    let Ok(json) = serde_json::from_str::<Value>(body) else {
+        return Poll::Failed(format!("Unreadable token response: {}", body));
+    };
+    if let Some(access_token) = json["access_token"].as_str() {
+        return Poll::Token {
+            access_token: access_token.to_string(),
+            expires_in: json["expires_in"].as_u64().unwrap_or(0),
+            refresh_token: json["refresh_token"].as_str().map(String::from),
+        };
+    }
+    match json["error"].as_str() {
+        Some("authorization_pending") => Poll::Pending,
+        Some("slow_down") => Poll::SlowDown,
+        Some(error) => Poll::Failed(format!(
+            "{}: {}",
+            error,
+            json["error_description"].as_str().unwrap_or_default()
+        )),
+        None => Poll::Failed(format!("Unexpected token response: {}", body)),
+    }
+}
+
+fn read_cache(path: &Path) -> Option<CachedRefreshToken> {
+    let text = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Write the cache readable by the current user only
+fn write_cache(path: &Path, entry: &CachedRefreshToken) -> std::io::Result<()> {
+    let json = serde_json::to_vec(entry).map_err(std::io::Error::other)?;
+    write_atomic(path, &json)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_poll() {
+        assert_eq!(
+            parse_poll(r#"{"access_token":"at","expires_in":3599,"refresh_token":"rt"}"#),
+            Poll::Token {
+                access_token: "at".to_string(),
+                expires_in: 3599,
+                refresh_token: Some("rt".to_string())
+            }
+        );
+        assert_eq!(parse_poll(r#"{"error":"authorization_pending"}"#), Poll::Pending);
+        assert_eq!(parse_poll(r#"{"error":"slow_down"}"#), Poll::SlowDown);
+        assert_eq!(
+            parse_poll(r#"{"error":"expired_token","error_description":"AADSTS70020"}"#),
+            Poll::Failed("expired_token: AADSTS70020".to_string())
+        );
+        assert!(matches!(parse_poll("<html>"), Poll::Failed(_)));
+    }
+
+    #[test]
+    fn test_refresh_token_cache() {
+        let dir = std::env::temp_dir().join(format!("d365-device-code-{}", std::process::id()));
+        let path = dir.join("tokens.json");
+        let entry = CachedRefreshToken {
+            tenant_id: "tenant".to_string(),
+            client_id: "client".to_string(),
+            refresh_token: "rt".to_string(),
+        };
+        write_cache(&path, &entry).unwrap();
+        assert_eq!(read_cache(&path), Some(entry));
+
+        let same = DeviceCodeCredential::new("tenant", "client", Some(path.clone()));
+        assert_eq!(same.refresh_token.try_lock().unwrap().as_deref(), Some("rt"));
+        let other = DeviceCodeCredential::new("tenant", "another-app", Some(path));
+        assert!(other.refresh_token.try_lock().unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
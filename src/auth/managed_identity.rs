@@ -0,0 +1,117 @@
+//! Managed identity tokens
+//!
+//! On Azure VMs the token comes from the Instance Metadata Service (IMDS);
+//! App Service, Functions and Container Apps expose their own endpoint via
+//! `IDENTITY_ENDPOINT` and `IDENTITY_HEADER`. Either way no secret is
+//! configured: a client ID only selects a user-assigned identity.
+
+use crate::auth::AuthError;
+use reqwest::Url;
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Azure Instance Metadata Service token endpoint
+const IMDS_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+
+/// IMDS answers within milliseconds on Azure; elsewhere it is unreachable
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// GET request for a token
+#[derive(Debug, PartialEq)]
+pub(crate) struct TokenRequest {
+    pub url: String,
+    pub header: (&'static str, String),
+}
+
+/// `IDENTITY_ENDPOINT` and `IDENTITY_HEADER` of App Service and Container Apps
+pub(crate) fn app_service_endpoint() -> Option<(String, String)> {
+    let endpoint = std::env::var("IDENTITY_ENDPOINT").ok().filter(|e| !e.is_empty())?;
+    let header = std::env::var("IDENTITY_HEADER").ok()?;
+    Some((endpoint, header))
+}
+
+/// Token request for `resource`, on the App Service endpoint when there is one
+pub(crate) fn token_request(
+    resource: &str,
+    client_id: Option<&str>,
+    app_service: Option<(String, String)>,
+) -> Result<TokenRequest, AuthError> {
+    let (base, api_version, header) = match app_service {
+        Some((endpoint, secret)) => (endpoint, "2019-08-01", ("X-IDENTITY-HEADER", secret)),
+        None => (IMDS_ENDPOINT.to_string(), "2018-02-01", ("Metadata", "true".to_string())),
+    };
+    let mut url = Url::parse(&base)
+        .map_err(|e| AuthError::MissingCredentials(format!("invalid IDENTITY_ENDPOINT '{}': {}", base, e)))?;
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("api-version", api_version);
+        query.append_pair("resource", resource);
+        if let Some(client_id) = client_id {
+            query.append_pair("client_id", client_id);
+        }
+    }
+    Ok(TokenRequest {
+        url: url.to_string(),
+        header,
+    })
+}
+
+/// Access token and lifetime in seconds. The endpoints send numbers as
+/// strings, and App Service sends only `expires_on`.
+pub(crate) fn parse_token(body: &str) -> Result<(String, u64), AuthError> {
+    let json: Value = serde_json::from_str(body)
+        .map_err(|e| AuthError::ParseError(format!("Failed to parse managed identity token: {}", e)))?;
+    let number = |key: &str| match &json[key] {
+        Value::String(s) => s.parse::<u64>().ok(),
+        other => other.as_u64(),
+    };
+    let access_token = json["access_token"]
+        .as_str()
+        .ok_or_else(|| AuthError::ParseError("managed identity response has no access_token".to_string()))?;
+    let expires_in = number("expires_in")
+        .or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+            number("expires_on").map(|on| on.saturating_sub(now))
+        })
+        .unwrap_or(0);
+    Ok((access_token.to_string(), expires_in))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_request() {
+        let imds = token_request("https://org.crm.dynamics.com", None, None).unwrap();
+        assert_eq!(
+            imds.url,
+            "http://169.254.169.254/metadata/identity/oauth2/token?api-version=2018-02-01&resource=https%3A%2F%2Forg.crm.dynamics.com"
+        );
+        assert_eq!(imds.header, ("Metadata", "true".to_string()));
+
+        let app_service = token_request(
+            "https://org.crm.dynamics.com",
+            Some("11111111-2222-3333-4444-555555555555"),
+            Some(("http://localhost:42356/msi/token".to_string(), "secret".to_string())),
+        )
+        .unwrap();
+        assert!(app_service.url.starts_with("http://localhost:42356/msi/token?api-version=2019-08-01&"));
+        assert!(app_service.url.ends_with("&client_id=11111111-2222-3333-4444-555555555555"));
+        assert_eq!(app_service.header, ("X-IDENTITY-HEADER", "secret".to_string()));
+        assert!(token_request("r", None, Some(("not a url".to_string(), String::new()))).is_err());
+    }
+
+    #[test]
+    fn test_parse_token() {
+        let (token, expires_in) = parse_token(r#"{"access_token":"abc","expires_in":"3599","token_type":"Bearer"}"#).unwrap();
+        assert_eq!((token.as_str(), expires_in), ("abc", 3599));
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let body = format!(r#"{{"access_token":"abc","expires_on":"{}"}}"#, now + 600);
+        let (_, expires_in) = parse_token(&body).unwrap();
+        assert!((590..=600).contains(&expires_in));
+
+        assert!(parse_token(r#"{"error":"invalid_request"}"#).is_err());
+    }
+}
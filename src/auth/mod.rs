@@ -1,11 +1,26 @@
 //! Authentication module
 //!
 //! Implements OAuth2 Client Credentials flow for:
-//! - Azure AD (Entra ID) - for cloud D365
+//! - Azure AD (Entra ID) - for cloud D365, with a client secret or certificate
 //! - ADFS - for on-premise D365
+//!
+//! and managed identities, whose tokens come from the Azure host instead, the
+//! developer's own Azure CLI login, or a user signed in with the device code
+//! flow for calls that need user context.
+
+mod azure_cli;
+mod certificate;
+pub mod cloud;
+mod device_code;
+mod managed_identity;
+pub mod secrets;
+
+pub use certificate::ClientCertificate;
 
+use futures::future::BoxFuture;
 use reqwest::{Client, Url};
 use serde::Deserialize;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use thiserror::Error;
@@ -25,6 +40,45 @@ pub enum AuthError {
 
     #[error("Missing credentials: {0}")]
     MissingCredentials(String),
+
+    #[error("Certificate error: {0}")]
+    CertificateError(String),
+}
+
+/// Source of bearer tokens for [`crate::odata::ODataClient`]
+///
+/// Implemented by [`OAuth2Auth`] and [`StaticToken`]; custom schemes (e.g.
+/// tokens issued by a proxy) implement it to plug into the client.
+pub trait TokenProvider: Send + Sync + std::fmt::Debug {
+    /// Access token for `resource`, the environment URL
+    fn get_token<'a>(&'a self, resource: &'a str) -> BoxFuture<'a, Result<String, AuthError>>;
+
+    /// Forget cached tokens after the service rejected one
+    fn clear_cache(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// A fixed bearer token, e.g. one issued by a proxy, or for tests
+#[derive(Clone)]
+pub struct StaticToken(String);
+
+impl StaticToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+}
+
+impl std::fmt::Debug for StaticToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StaticToken(..)")
+    }
+}
+
+impl TokenProvider for StaticToken {
+    fn get_token<'a>(&'a self, _resource: &'a str) -> BoxFuture<'a, Result<String, AuthError>> {
+        Box::pin(async move { Ok(self.0.clone()) })
+    }
 }
 
 /// Token response from OAuth2 server
@@ -54,18 +108,21 @@ impl CachedToken {
 }
 
 /// Authentication type
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum AuthType {
     /// Azure AD (Entra ID) - for cloud D365
+    #[default]
     AzureAd,
     /// ADFS - for on-premise D365
     Adfs,
-}
-
-impl Default for AuthType {
-    fn default() -> Self {
-        AuthType::AzureAd
-    }
+    /// Azure AD with a client certificate instead of a secret
+    Certificate,
+    /// System- or user-assigned managed identity of the Azure host
+    ManagedIdentity,
+    /// The signed-in Azure CLI user (local development)
+    AzureCli,
+    /// A user signed in interactively with the device code flow
+    DeviceCode,
 }
 
 impl std::str::FromStr for AuthType {
@@ -75,7 +132,14 @@ impl std::str::FromStr for AuthType {
         match s.to_lowercase().as_str() {
             "azure" | "azuread" | "azure_ad" | "entra" => Ok(AuthType::AzureAd),
             "adfs" | "on-premise" | "onpremise" => Ok(AuthType::Adfs),
-            _ => Err(format!("Unknown auth type: {}. Use 'azure' or 'adfs'", s)),
+            "certificate" | "cert" => Ok(AuthType::Certificate),
+            "managed_identity" | "managed-identity" | "msi" => Ok(AuthType::ManagedIdentity),
+            "azure_cli" | "azure-cli" | "az" | "cli" => Ok(AuthType::AzureCli),
+            "device_code" | "device-code" | "device" => Ok(AuthType::DeviceCode),
+            _ => Err(format!(
+                "Unknown auth type: {}. Use 'azure', 'certificate', 'managed_identity', 'azure_cli', 'device_code' or 'adfs'",
+                s
+            )),
         }
     }
 }
@@ -91,6 +155,9 @@ pub struct AuthConfig {
     pub token_url: Option<String>,
     /// Resource/audience (required for ADFS)
     pub resource: Option<String>,
+    /// Azure AD authority, e.g. `https://login.microsoftonline.us` for
+    /// national clouds (default: public cloud)
+    pub authority_host: Option<String>,
     /// Skip SSL certificate verification (for self-signed certs)
     pub insecure_ssl: bool,
 }
@@ -101,6 +168,10 @@ pub struct OAuth2Auth {
     config: AuthConfig,
     http_client: Client,
     token_cache: Arc<RwLock<Option<CachedToken>>>,
+    /// Signs client assertions for [`AuthType::Certificate`]
+    certificate: Option<ClientCertificate>,
+    /// User sign-in state for [`AuthType::DeviceCode`]
+    device_code: Option<device_code::DeviceCodeCredential>,
 }
 
 impl OAuth2Auth {
@@ -115,11 +186,35 @@ impl OAuth2Auth {
             Client::new()
         };
         
+        let device_code = (config.auth_type == AuthType::DeviceCode)
+            .then(|| device_code::DeviceCodeCredential::new(&config.tenant_id, &config.client_id, None));
+
         Self {
             config,
             http_client,
             token_cache: Arc::new(RwLock::new(None)),
+            certificate: None,
+            device_code,
+        }
+    }
+
+    /// Authenticate with a client certificate instead of the client secret
+    pub fn with_certificate(mut self, certificate: ClientCertificate) -> Self {
+        self.certificate = Some(certificate);
+        self
+    }
+
+    /// Keep the device code flow's refresh token in `path` so later runs
+    /// sign in without prompting
+    pub fn with_token_cache_file(mut self, path: PathBuf) -> Self {
+        if self.device_code.is_some() {
+            self.device_code = Some(device_code::DeviceCodeCredential::new(
+                &self.config.tenant_id,
+                &self.config.client_id,
+                Some(path),
+            ));
         }
+        self
     }
 
     /// Get the token endpoint URL
@@ -131,10 +226,11 @@ impl OAuth2Auth {
                     format!("https://{}/adfs/oauth2/token", self.config.tenant_id)
                 })
             }
-            AuthType::AzureAd => {
-                // Azure AD standard endpoint
+            AuthType::AzureAd | AuthType::Certificate | AuthType::ManagedIdentity | AuthType::AzureCli
+            | AuthType::DeviceCode => {
                 format!(
-                    "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                    "{}/{}/oauth2/v2.0/token",
+                    self.config.authority_host.as_deref().unwrap_or(cloud::PUBLIC_AUTHORITY_HOST),
                     self.config.tenant_id
                 )
             }
@@ -163,6 +259,72 @@ impl OAuth2Auth {
 
     /// Acquire a new token
     async fn acquire_token(&self, resource: &str) -> Result<String, AuthError> {
+        let (access_token, expires_in) = match self.config.auth_type {
+            AuthType::ManagedIdentity => self.managed_identity_token(resource).await?,
+            AuthType::AzureCli => {
+                let tenant = Some(self.config.tenant_id.as_str()).filter(|t| !t.is_empty());
+                azure_cli::fetch_token(resource, tenant).await?
+            }
+            AuthType::DeviceCode => self.device_code_token(resource).await?,
+            _ => self.client_credentials_token(resource).await?,
+        };
+
+        // Cache the token
+        let cached = CachedToken {
+            access_token: access_token.clone(),
+            expires_at: Instant::now() + Duration::from_secs(expires_in),
+        };
+
+        {
+            let mut cache = self.token_cache.write().await;
+            *cache = Some(cached);
+        }
+
+        tracing::info!("Token acquired successfully, expires in {} seconds", expires_in);
+
+        Ok(access_token)
+    }
+
+    /// Token from the host's managed identity endpoint
+    async fn managed_identity_token(&self, resource: &str) -> Result<(String, u64), AuthError> {
+        let client_id = Some(self.config.client_id.as_str()).filter(|c| !c.is_empty());
+        let request = managed_identity::token_request(resource, client_id, managed_identity::app_service_endpoint())?;
+        tracing::debug!("Managed identity endpoint: {}", request.url);
+
+        let response = self
+            .http_client
+            .get(&request.url)
+            .header(request.header.0, request.header.1)
+            .timeout(managed_identity::REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| {
+                AuthError::TokenRequestFailed(format!(
+                    "managed identity endpoint unreachable ({}); is the server running on an Azure host with an identity assigned?",
+                    e
+                ))
+            })?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            tracing::error!("Managed identity token request failed: {} - {}", status, body);
+            return Err(AuthError::TokenRequestFailed(format!("Status: {}, Body: {}", status, body)));
+        }
+        managed_identity::parse_token(&body)
+    }
+
+    /// Delegated user token; prompts for a device code sign-in when no
+    /// refresh token is cached
+    async fn device_code_token(&self, resource: &str) -> Result<(String, u64), AuthError> {
+        let credential = self.device_code.as_ref().ok_or_else(|| {
+            AuthError::MissingCredentials("device code auth was not initialised".to_string())
+        })?;
+        let scope = format!("{}/.default offline_access", resource.trim_end_matches('/'));
+        credential.token(&self.http_client, &self.token_endpoint(), &scope).await
+    }
+
+    /// Token from the OAuth2 client credentials grant
+    async fn client_credentials_token(&self, resource: &str) -> Result<(String, u64), AuthError> {
         let params = match self.config.auth_type {
             AuthType::AzureAd => {
                 // Azure AD uses scope with /.default suffix
@@ -179,10 +341,34 @@ impl OAuth2Auth {
                     ("scope".to_string(), scope),
                 ]
             }
+            AuthType::Certificate => {
+                let certificate = self.certificate.as_ref().ok_or_else(|| {
+                    AuthError::MissingCredentials("certificate auth needs CERT_PATH".to_string())
+                })?;
+                let scope = if resource.ends_with('/') {
+                    format!("{}.default", resource)
+                } else {
+                    format!("{}/.default", resource)
+                };
+                let assertion = certificate.client_assertion(&self.config.client_id, &self.token_endpoint())?;
+
+                vec![
+                    ("grant_type".to_string(), "client_credentials".to_string()),
+                    ("client_id".to_string(), self.config.client_id.clone()),
+                    ("client_assertion_type".to_string(), certificate::CLIENT_ASSERTION_TYPE.to_string()),
+                    ("client_assertion".to_string(), assertion),
+                    ("scope".to_string(), scope),
+                ]
+            }
+            AuthType::ManagedIdentity | AuthType::AzureCli | AuthType::DeviceCode => {
+                return Err(AuthError::MissingCredentials(format!(
+                    "{:?} auth has no client credentials",
+                    self.config.auth_type
+                )))
+            }
             AuthType::Adfs => {
                 // ADFS uses resource parameter instead of scope
-                let resource = self.config.resource.as_ref()
-                    .map(|r| r.clone())
+                let resource = self.config.resource.clone()
                     .unwrap_or_else(|| resource.to_string());
                 
                 vec![
@@ -199,7 +385,7 @@ impl OAuth2Auth {
 
         let response = self
             .http_client
-            .post(&self.token_endpoint())
+            .post(self.token_endpoint())
             .form(&params)
             .send()
             .await?;
@@ -218,23 +404,7 @@ impl OAuth2Auth {
             AuthError::ParseError(format!("Failed to parse token response: {}", e))
         })?;
 
-        // Cache the token
-        let cached = CachedToken {
-            access_token: token_response.access_token.clone(),
-            expires_at: Instant::now() + Duration::from_secs(token_response.expires_in),
-        };
-
-        {
-            let mut cache = self.token_cache.write().await;
-            *cache = Some(cached);
-        }
-
-        tracing::info!(
-            "Token acquired successfully, expires in {} seconds",
-            token_response.expires_in
-        );
-
-        Ok(token_response.access_token)
+        Ok((token_response.access_token, token_response.expires_in))
     }
 
     /// Clear the token cache
@@ -257,6 +427,16 @@ impl OAuth2Auth {
     }
 }
 
+impl TokenProvider for OAuth2Auth {
+    fn get_token<'a>(&'a self, resource: &'a str) -> BoxFuture<'a, Result<String, AuthError>> {
+        Box::pin(OAuth2Auth::get_token(self, resource))
+    }
+
+    fn clear_cache(&self) -> BoxFuture<'_, ()> {
+        Box::pin(OAuth2Auth::clear_cache(self))
+    }
+}
+
 // Keep AzureAdAuth for backward compatibility
 pub type AzureAdAuth = OAuth2Auth;
 
@@ -270,6 +450,7 @@ impl OAuth2Auth {
             client_secret,
             token_url: None,
             resource: None,
+            authority_host: None,
             insecure_ssl: false,
         })
     }
@@ -281,7 +462,7 @@ mod tests {
 
     #[test]
     fn test_create_azure_auth() {
-        let auth = AzureAdAuth::new(
+        let auth = AzureAdAuth::new_azure(
             "tenant-id".to_string(),
             "client-id".to_string(),
             "secret".to_string(),
@@ -300,6 +481,8 @@ mod tests {
             client_secret: "secret".to_string(),
             token_url: Some("https://fs.example.com/adfs/oauth2/token".to_string()),
             resource: Some("https://d365.example.com".to_string()),
+            authority_host: None,
+            insecure_ssl: false,
         });
         assert_eq!(auth.config.auth_type, AuthType::Adfs);
         assert_eq!(auth.token_endpoint(), "https://fs.example.com/adfs/oauth2/token");
@@ -307,7 +490,7 @@ mod tests {
 
     #[test]
     fn test_azure_token_endpoint() {
-        let auth = AzureAdAuth::new(
+        let auth = AzureAdAuth::new_azure(
             "my-tenant".to_string(),
             "client-id".to_string(),
             "secret".to_string(),
@@ -318,11 +501,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_national_cloud_token_endpoint() {
+        let auth = OAuth2Auth::new(AuthConfig {
+            auth_type: AuthType::Certificate,
+            tenant_id: "my-tenant".to_string(),
+            client_id: "client-id".to_string(),
+            client_secret: String::new(),
+            token_url: None,
+            resource: None,
+            authority_host: Some("https://login.microsoftonline.us".to_string()),
+            insecure_ssl: false,
+        });
+        assert_eq!(
+            auth.token_endpoint(),
+            "https://login.microsoftonline.us/my-tenant/oauth2/v2.0/token"
+        );
+    }
+
     #[test]
     fn test_auth_type_from_str() {
         assert_eq!("azure".parse::<AuthType>().unwrap(), AuthType::AzureAd);
         assert_eq!("adfs".parse::<AuthType>().unwrap(), AuthType::Adfs);
         assert_eq!("ADFS".parse::<AuthType>().unwrap(), AuthType::Adfs);
+        assert_eq!("certificate".parse::<AuthType>().unwrap(), AuthType::Certificate);
+        assert_eq!("managed_identity".parse::<AuthType>().unwrap(), AuthType::ManagedIdentity);
+        assert_eq!("azure_cli".parse::<AuthType>().unwrap(), AuthType::AzureCli);
+        assert_eq!("device-code".parse::<AuthType>().unwrap(), AuthType::DeviceCode);
+    }
+
+    #[tokio::test]
+    async fn test_static_token() {
+        let provider: Arc<dyn TokenProvider> = Arc::new(StaticToken::new("abc"));
+        assert_eq!(provider.get_token("https://org.crm.dynamics.com").await.unwrap(), "abc");
+        assert_eq!(format!("{:?}", provider), "StaticToken(..)");
     }
 
     #[test]
@@ -0,0 +1,246 @@
+//! Secret sources
+//!
+//! `CLIENT_SECRET` and `CERT_PASSWORD` are read through a [`SecretProvider`]:
+//! - `env`: the environment variable itself
+//! - `file`: the file named by `<NAME>_FILE`, as Docker and Kubernetes mount
+//!   secrets, so the value never shows up in the process environment, or
+//!   else by the config (e.g. a profile's `client_secret_file`)
+//! - `keyring`: the OS credential store (macOS Keychain via `security`,
+//!   Linux Secret Service via `secret-tool`)
+//!
+//! The default checks `<NAME>_FILE` first and falls back to the variable.
+
+use crate::auth::AuthError;
+use crate::config::vars;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Keyring service name the secrets are stored under by default
+pub const DEFAULT_KEYRING_SERVICE: &str = "d365-odata-mcp";
+
+/// Source of named secrets such as `CLIENT_SECRET`
+pub trait SecretProvider: Send + Sync + std::fmt::Debug {
+    /// Short name used in error messages
+    fn name(&self) -> &'static str;
+
+    /// The secret called `key`; `None` when this source does not have it
+    fn get(&self, key: &str) -> Result<Option<String>, AuthError>;
+}
+
+/// Which provider to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SecretSource {
+    /// `<NAME>_FILE`, then the environment variable
+    #[default]
+    Auto,
+    Env,
+    File,
+    Keyring,
+}
+
+impl std::str::FromStr for SecretSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" | "" => Ok(SecretSource::Auto),
+            "env" | "environment" => Ok(SecretSource::Env),
+            "file" | "files" => Ok(SecretSource::File),
+            "keyring" | "keychain" => Ok(SecretSource::Keyring),
+            _ => Err(format!(
+                "Unknown secret provider: {}. Use 'auto', 'env', 'file' or 'keyring'",
+                s
+            )),
+        }
+    }
+}
+
+/// Provider for `source`; `keyring_service` names the keyring entry's service
+/// and `file_secrets` the secret files the config names
+pub fn secret_provider(
+    source: SecretSource,
+    keyring_service: &str,
+    file_secrets: FileSecrets,
+) -> Box<dyn SecretProvider> {
+    match source {
+        SecretSource::Auto => Box::new(FirstOf(vec![Box::new(file_secrets), Box::new(EnvSecrets)])),
+        SecretSource::Env => Box::new(EnvSecrets),
+        SecretSource::File => Box::new(file_secrets),
+        SecretSource::Keyring => Box::new(KeyringSecrets {
+            service: keyring_service.to_string(),
+        }),
+    }
+}
+
+/// Secrets from environment variables (`D365_<NAME>`, then `<NAME>`), or the `.env` file
+#[derive(Debug)]
+pub struct EnvSecrets;
+
+impl SecretProvider for EnvSecrets {
+    fn name(&self) -> &'static str {
+        "env"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>, AuthError> {
+        Ok(vars::var(key))
+    }
+}
+
+/// Secrets from the files named by `<NAME>_FILE`, else by the config
+#[derive(Debug, Default)]
+pub struct FileSecrets {
+    /// Secret files from the config, by secret name
+    pub configured: Vec<(String, PathBuf)>,
+    /// Read only the configured files, never `<NAME>_FILE`
+    pub configured_only: bool,
+}
+
+impl SecretProvider for FileSecrets {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>, AuthError> {
+        let configured = self.configured.iter().find(|(name, _)| name == key).map(|(_, path)| path.clone());
+        let path = if self.configured_only {
+            configured
+        } else {
+            let variable = format!("{}_FILE", key);
+            vars::var(&variable).filter(|p| !p.is_empty()).map(PathBuf::from).or(configured)
+        };
+        match path {
+            Some(path) => read_secret_file(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// File content without the trailing newline editors and `echo` add
+fn read_secret_file(path: &Path) -> Result<String, AuthError> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        AuthError::MissingCredentials(format!("cannot read secret file {}: {}", path.display(), e))
+    })?;
+    Ok(text.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Secrets from the OS credential store, stored with the secret name as
+/// the account
+#[derive(Debug)]
+pub struct KeyringSecrets {
+    service: String,
+}
+
+impl SecretProvider for KeyringSecrets {
+    fn name(&self) -> &'static str {
+        "keyring"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>, AuthError> {
+        let (program, args) = keyring_command(std::env::consts::OS, &self.service, key).ok_or_else(|| {
+            AuthError::MissingCredentials(format!(
+                "the keyring secret provider is not available on {}; use SECRET_PROVIDER=file",
+                std::env::consts::OS
+            ))
+        })?;
+        let output = match Command::new(program).args(&args).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(AuthError::MissingCredentials(format!("'{}' not found on PATH", program)))
+            }
+            Err(e) => {
+                return Err(AuthError::MissingCredentials(format!("cannot run '{}': {}", program, e)))
+            }
+        };
+        // Both tools exit non-zero when the entry does not exist
+        if !output.status.success() {
+            tracing::debug!(
+                "No keyring entry {}/{}: {}",
+                self.service,
+                key,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Ok(None);
+        }
+        let secret = String::from_utf8_lossy(&output.stdout);
+        Ok(Some(secret.trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+/// Command that prints the keyring entry `service`/`key` on `os`
+fn keyring_command(os: &str, service: &str, key: &str) -> Option<(&'static str, Vec<String>)> {
+    match os {
+        "macos" => Some((
+            "security",
+            ["find-generic-password", "-s", service, "-a", key, "-w"].map(String::from).to_vec(),
+        )),
+        "linux" | "freebsd" | "openbsd" | "netbsd" => Some((
+            "secret-tool",
+            ["lookup", "service", service, "account", key].map(String::from).to_vec(),
+        )),
+        _ => None,
+    }
+}
+
+/// The first provider that has the secret
+#[derive(Debug)]
+struct FirstOf(Vec<Box<dyn SecretProvider>>);
+
+impl SecretProvider for FirstOf {
+    fn name(&self) -> &'static str {
+        "auto"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>, AuthError> {
+        for provider in &self.0 {
+            if let Some(secret) = provider.get(key)? {
+                return Ok(Some(secret));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_source_from_str() {
+        assert_eq!("file".parse::<SecretSource>().unwrap(), SecretSource::File);
+        assert_eq!("Keychain".parse::<SecretSource>().unwrap(), SecretSource::Keyring);
+        assert!("vault".parse::<SecretSource>().is_err());
+    }
+
+    #[test]
+    fn test_read_secret_file() {
+        let path = std::env::temp_dir().join(format!("d365-secret-{}", std::process::id()));
+        std::fs::write(&path, "s3cret\r\n").unwrap();
+        assert_eq!(read_secret_file(&path).unwrap(), "s3cret");
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_secret_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_configured_secret_file() {
+        let path = std::env::temp_dir().join(format!("d365-profile-secret-{}", std::process::id()));
+        std::fs::write(&path, "uat-secret\n").unwrap();
+        let secrets = FileSecrets {
+            configured: vec![("D365_TEST_PROFILE_SECRET".to_string(), path.clone())],
+            configured_only: false,
+        };
+        assert_eq!(secrets.get("D365_TEST_PROFILE_SECRET").unwrap().as_deref(), Some("uat-secret"));
+        assert_eq!(secrets.get("CERT_PASSWORD_OTHER").unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keyring_command() {
+        let (program, args) = keyring_command("macos", "d365-odata-mcp", "CLIENT_SECRET").unwrap();
+        assert_eq!(program, "security");
+        assert_eq!(args, ["find-generic-password", "-s", "d365-odata-mcp", "-a", "CLIENT_SECRET", "-w"]);
+        let (program, args) = keyring_command("linux", "svc", "CERT_PASSWORD").unwrap();
+        assert_eq!(program, "secret-tool");
+        assert_eq!(args, ["lookup", "service", "svc", "account", "CERT_PASSWORD"]);
+        assert!(keyring_command("windows", "svc", "CLIENT_SECRET").is_none());
+    }
+}
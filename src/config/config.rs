@@ -1,33 +1,90 @@
 //! Configuration module for D365 OData MCP
 //!
 //! Loads configuration from TOML file and environment variables.
-//! Environment variables take precedence over file config.
+//! Environment variables (`D365_<NAME>` or `<NAME>`) take precedence over
+//! file config; a `.env` file fills in variables the environment does not set.
 
+use super::{paths, vars};
+use crate::auth::cloud;
+use crate::auth::secrets::{self, SecretProvider, SecretSource};
+use crate::auth::AuthType;
+use crate::odata::{breaker, etag_cache};
+use crate::odata::impersonation::Caller;
+use crate::odata::spill::{self, SpillLimits};
 use serde::Deserialize;
-use std::env;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Product type - Dataverse or Finance & Operations
-#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ProductType {
+    #[default]
     Dataverse,
     #[serde(alias = "fno", alias = "fo")]
     Finops,
 }
 
-impl Default for ProductType {
-    fn default() -> Self {
-        ProductType::Dataverse
+/// How `update_entity` handles a 412 caused by a stale ETag
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// Report the conflict and change nothing
+    #[default]
+    Fail,
+    /// Retry without the ETag (`If-Match: *`), overwriting the other change
+    #[serde(alias = "force")]
+    Overwrite,
+    /// Re-read the record and retry once with the fields that still differ
+    #[serde(alias = "merge")]
+    RefetchMerge,
+}
+
+impl ConflictStrategy {
+    /// Parse a tool argument value
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "fail" => Ok(ConflictStrategy::Fail),
+            "overwrite" | "force" => Ok(ConflictStrategy::Overwrite),
+            "refetch_merge" | "merge" => Ok(ConflictStrategy::RefetchMerge),
+            other => Err(format!(
+                "Invalid on_conflict '{}': expected fail, overwrite or refetch_merge",
+                other
+            )),
+        }
     }
 }
 
+/// Environment variable selecting a `[profiles.<name>]` section; `--profile` sets it
+pub const PROFILE_VAR: &str = "D365_PROFILE";
+
+/// Environment variable naming the config file; `--config` sets it
+pub const CONFIG_FILE_VAR: &str = "CONFIG_FILE";
+
+/// Config file read when `CONFIG_FILE` is not set, relative to the working directory
+pub const DEFAULT_CONFIG_PATH: &str = "config/default.toml";
+
+/// Name of the connection described by `[global]` when no profile is selected
+pub const DEFAULT_CONNECTION: &str = "default";
+
+/// Default of `shutdown_grace_seconds`
+pub const DEFAULT_SHUTDOWN_GRACE_SECONDS: u64 = 30;
+
+/// Default of `[connection] request_timeout_seconds`; large `$metadata` documents need the time
+pub const DEFAULT_REQUEST_TIMEOUT_SECONDS: u64 = 120;
+
+/// Default of `retry_deadline_seconds`; longer throttles are reported to the caller
+pub const DEFAULT_RETRY_DEADLINE_SECONDS: u64 = 60;
+
 /// Global configuration settings
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct GlobalConfig {
     #[serde(default)]
     pub product: ProductType,
+    /// D365 OData endpoint; may come from a profile instead
+    #[serde(default)]
     pub endpoint: String,
     #[serde(default)]
     pub page_size: Option<usize>,
@@ -37,6 +94,41 @@ pub struct GlobalConfig {
     pub max_retries: Option<u32>,
     #[serde(default)]
     pub retry_delay_ms: Option<u64>,
+    /// Longest time in seconds a request spends waiting between retries (0 = no limit)
+    #[serde(default)]
+    pub retry_deadline_seconds: Option<u64>,
+    /// Default deadline for a single tool call in seconds (none = no deadline)
+    #[serde(default)]
+    pub tool_timeout_seconds: Option<u64>,
+    /// How long idempotency keys of write calls are remembered
+    #[serde(default)]
+    pub idempotency_ttl_seconds: Option<u64>,
+    /// Ask for Int64/Decimal values as JSON strings (`IEEE754Compatible=true`)
+    #[serde(default)]
+    pub ieee754_compatible: Option<bool>,
+    /// Default handling of update conflicts (stale ETag)
+    #[serde(default)]
+    pub conflict_strategy: Option<ConflictStrategy>,
+    /// Dataverse user tool calls run as unless they pass `impersonate_user`
+    #[serde(default)]
+    pub impersonate_user: Option<String>,
+    /// Azure AD authority: "public", "usgov", "dod", "china" or a URL
+    /// (default: inferred from the endpoint)
+    #[serde(default)]
+    pub authority_host: Option<String>,
+    /// How long SIGTERM/SIGINT waits for running tool calls and sync runs
+    #[serde(default)]
+    pub shutdown_grace_seconds: Option<u64>,
+    /// Reject malformed JSON-RPC envelopes (wrong `jsonrpc`, null id,
+    /// positional params) instead of accepting them
+    #[serde(default)]
+    pub strict_jsonrpc: Option<bool>,
+    /// Largest message accepted from a client, in bytes (stdio, socket and HTTP)
+    #[serde(default)]
+    pub max_message_bytes: Option<usize>,
+    /// Profiles served as further connections next to the default one
+    #[serde(default)]
+    pub connections: Option<Vec<String>>,
 }
 
 /// Observability configuration
@@ -44,65 +136,623 @@ pub struct GlobalConfig {
 pub struct ObservabilityConfig {
     #[serde(default)]
     pub log_level: Option<String>,
+    /// Level of `notifications/message` sent to MCP clients until they call
+    /// `logging/setLevel` (default: `log_level`; "off" sends none)
+    #[serde(default)]
+    pub client_log_level: Option<String>,
     #[serde(default)]
     pub enable_tracing: Option<bool>,
+    /// Log file path (defaults to the platform log directory)
+    #[serde(default)]
+    pub log_file: Option<String>,
 }
 
 /// Delta sync storage configuration
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct DeltaConfig {
+    /// State file path (defaults to the platform state directory)
     #[serde(default)]
     pub storage_path: Option<String>,
+    /// Seconds re-read before a stored watermark to catch late or clock-skewed rows (default: 300)
+    #[serde(default)]
+    pub overlap_seconds: Option<u64>,
+    /// Pages between checkpoints of a pull (default: 10, 0 = only when interrupted)
+    #[serde(default)]
+    pub checkpoint_pages: Option<usize>,
+}
+
+/// $metadata loading and caching
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MetadataConfig {
+    /// EDMX file to load instead of fetching $metadata (offline/dev use)
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Directory of the parsed-model cache (defaults to the platform state directory)
+    #[serde(default)]
+    pub cache_dir: Option<String>,
+    /// Seconds a fetched model is reused before it is fetched again
+    /// (default: 86400, 0 = no disk cache)
+    #[serde(default)]
+    pub cache_ttl_seconds: Option<u64>,
+}
+
+/// Resolved `[metadata]` settings
+#[derive(Debug, Clone, Default)]
+pub struct MetadataSettings {
+    pub file: Option<PathBuf>,
+    pub cache_dir: PathBuf,
+    pub cache_ttl_seconds: u64,
+}
+
+/// HTTP connections to the D365 endpoint
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ConnectionConfig {
+    /// Seconds a single request may take, including reading the body (default: 120)
+    #[serde(default)]
+    pub request_timeout_seconds: Option<u64>,
+    /// Seconds to establish a connection (default: no separate limit)
+    #[serde(default)]
+    pub connect_timeout_seconds: Option<u64>,
+    /// Seconds an idle pooled connection is kept (default: 90)
+    #[serde(default)]
+    pub pool_idle_timeout_seconds: Option<u64>,
+    /// Idle connections kept per host (default: unlimited)
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// true speaks HTTP/2 without negotiation, false only HTTP/1.1
+    /// (default: negotiated)
+    #[serde(default)]
+    pub http2: Option<bool>,
+    /// Consecutive 5xx/timeout failures that open the circuit breaker (default: 5, 0 = off)
+    #[serde(default)]
+    pub breaker_failures: Option<u32>,
+    /// Seconds requests fail fast once the circuit is open (default: 30)
+    #[serde(default)]
+    pub breaker_cooldown_seconds: Option<u64>,
+    /// Single-record and $metadata responses kept for `If-None-Match` revalidation (default: 256, 0 = off)
+    #[serde(default)]
+    pub etag_cache_entries: Option<usize>,
+}
+
+/// Resolved `[connection]` settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionSettings {
+    pub request_timeout: Duration,
+    pub connect_timeout: Option<Duration>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub http2: Option<bool>,
+    pub breaker_failures: u32,
+    pub breaker_cool_down: Duration,
+    pub etag_cache_entries: usize,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECONDS),
+            connect_timeout: None,
+            pool_idle_timeout: None,
+            pool_max_idle_per_host: None,
+            http2: None,
+            breaker_failures: breaker::DEFAULT_FAILURE_THRESHOLD,
+            breaker_cool_down: breaker::DEFAULT_COOL_DOWN,
+            etag_cache_entries: etag_cache::DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl From<ConnectionConfig> for ConnectionSettings {
+    fn from(config: ConnectionConfig) -> Self {
+        Self {
+            request_timeout: Duration::from_secs(
+                config.request_timeout_seconds.unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECONDS),
+            ),
+            connect_timeout: config.connect_timeout_seconds.map(Duration::from_secs),
+            pool_idle_timeout: config.pool_idle_timeout_seconds.map(Duration::from_secs),
+            pool_max_idle_per_host: config.pool_max_idle_per_host,
+            http2: config.http2,
+            breaker_failures: config.breaker_failures.unwrap_or(breaker::DEFAULT_FAILURE_THRESHOLD),
+            breaker_cool_down: config
+                .breaker_cooldown_seconds
+                .map(Duration::from_secs)
+                .unwrap_or(breaker::DEFAULT_COOL_DOWN),
+            etag_cache_entries: config.etag_cache_entries.unwrap_or(etag_cache::DEFAULT_CAPACITY),
+        }
+    }
+}
+
+/// Memory limits of full entity reads; past them records spill to a file
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SpillConfig {
+    /// Records kept in memory (default: 100000)
+    #[serde(default)]
+    pub max_records: Option<usize>,
+    /// Megabytes of response bodies kept in memory (default: 256)
+    #[serde(default)]
+    pub max_memory_mb: Option<usize>,
+    /// Directory of spill files (default: `d365-odata-mcp/spill` in the temp directory)
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+impl From<SpillConfig> for SpillLimits {
+    fn from(config: SpillConfig) -> Self {
+        Self {
+            max_records: config.max_records.unwrap_or(spill::DEFAULT_MAX_RECORDS),
+            max_bytes: config
+                .max_memory_mb
+                .map(|mb| mb.saturating_mul(1024 * 1024))
+                .unwrap_or(spill::DEFAULT_MAX_BYTES),
+            dir: config.directory.unwrap_or_else(paths::default_spill_dir),
+        }
+    }
+}
+
+/// Cache of `query_entity` results
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct QueryCacheConfig {
+    /// Reuse results of identical queries (default: false)
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Seconds a result is reused (default: 60)
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+    /// Results kept (default: 100)
+    #[serde(default)]
+    pub max_entries: Option<usize>,
+}
+
+/// Resolved `[query_cache]` settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryCacheSettings {
+    pub enabled: bool,
+    pub ttl: Duration,
+    pub max_entries: usize,
+}
+
+impl From<QueryCacheConfig> for QueryCacheSettings {
+    fn from(config: QueryCacheConfig) -> Self {
+        Self {
+            enabled: config.enabled.unwrap_or(false),
+            ttl: Duration::from_secs(config.ttl_seconds.unwrap_or(60)),
+            max_entries: config.max_entries.unwrap_or(100),
+        }
+    }
+}
+
+/// Background sync configuration
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SyncConfig {
+    /// Periodically sync the configured entities in the background (default: false)
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Seconds between runs of an entity without its own interval (default: 900)
+    #[serde(default)]
+    pub interval_seconds: Option<u64>,
+    /// Where synced rows are written (none = only the sync position advances)
+    #[serde(default)]
+    pub sink: Option<SinkConfig>,
+}
+
+/// Output format of background sync
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    /// Newline-delimited JSON files
+    #[serde(alias = "ndjson")]
+    Jsonl,
+}
+
+/// Sink file rotation
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkRotation {
+    #[default]
+    None,
+    Daily,
+}
+
+/// Where background sync writes rows
+#[derive(Debug, Deserialize, Clone)]
+pub struct SinkConfig {
+    #[serde(rename = "type")]
+    pub kind: SinkKind,
+    /// Output directory
+    pub path: String,
+    /// Start a new file at this size in MB
+    #[serde(default)]
+    pub max_file_mb: Option<u64>,
+    #[serde(default)]
+    pub rotate: Option<SinkRotation>,
+    /// Compress files with gzip (not available in this build)
+    #[serde(default)]
+    pub gzip: Option<bool>,
+}
+
+/// Resolved background sync settings
+#[derive(Debug, Clone)]
+pub struct SyncSettings {
+    pub enabled: bool,
+    pub interval_seconds: u64,
+    /// State file of background runs, next to the delta state file
+    pub state_path: String,
+    pub sink: Option<SinkConfig>,
+}
+
+/// Dataverse service-protection budget configuration
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ServiceProtectionConfig {
+    /// Requests allowed per 5-minute window (default: 6000)
+    #[serde(default)]
+    pub max_requests: Option<u32>,
+    /// Combined execution seconds allowed per 5-minute window (default: 1200)
+    #[serde(default)]
+    pub max_execution_seconds: Option<u64>,
+    /// Percentage of a limit at which requests are slowed down (default: 90)
+    #[serde(default)]
+    pub soft_limit_percent: Option<u8>,
+    /// Delay requests at the soft limit; when false only warn (default: true)
+    #[serde(default)]
+    pub enforce: Option<bool>,
+}
+
+/// Where `CLIENT_SECRET` and `CERT_PASSWORD` are read from
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SecretsConfig {
+    /// "auto" (default: `<NAME>_FILE`, then the variable), "env", "file" or "keyring"
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// Keyring service the entries are stored under (default: "d365-odata-mcp")
+    #[serde(default)]
+    pub keyring_service: Option<String>,
+}
+
+/// One D365 environment in `[profiles.<name>]`
+///
+/// Values replace `[global]` and `[secrets]` and stand in for the environment
+/// variables of the same name, which still take precedence. Secrets are
+/// referenced by file or keyring service, never written here.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ProfileConfig {
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub product: Option<ProductType>,
+    #[serde(default)]
+    pub authority_host: Option<String>,
+    #[serde(default)]
+    pub impersonate_user: Option<String>,
+    /// `AUTH_TYPE` of this environment
+    #[serde(default)]
+    pub auth_type: Option<String>,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// File holding the client secret, as `CLIENT_SECRET_FILE`
+    #[serde(default)]
+    pub client_secret_file: Option<String>,
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    /// File holding the certificate password, as `CERT_PASSWORD_FILE`
+    #[serde(default)]
+    pub cert_password_file: Option<String>,
+    /// Secret provider of this environment (default: `[secrets] provider`)
+    #[serde(default)]
+    pub secret_provider: Option<String>,
+    /// Keyring service holding this environment's secrets
+    #[serde(default)]
+    pub keyring_service: Option<String>,
+    #[serde(default)]
+    pub token_cache_file: Option<String>,
+    /// Delta state file, so environments do not share delta links
+    #[serde(default)]
+    pub delta_storage_path: Option<String>,
+    /// ADFS token endpoint, as `TOKEN_URL`
+    #[serde(default)]
+    pub token_url: Option<String>,
+    /// ADFS resource, as `RESOURCE`
+    #[serde(default)]
+    pub resource: Option<String>,
+    /// Skip TLS verification, as `INSECURE_SSL`
+    #[serde(default)]
+    pub insecure_ssl: Option<bool>,
+    /// EDMX file used instead of fetching `$metadata`, as `METADATA_FILE`
+    #[serde(default)]
+    pub metadata_file: Option<String>,
+}
+
+/// HTTP transport settings
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HttpConfig {
+    /// Header carrying the API key (default: "Authorization", as `Bearer <key>`)
+    #[serde(default)]
+    pub auth_header: Option<String>,
+    /// Minutes after which an unused session is dropped (default: 60)
+    #[serde(default)]
+    pub session_idle_minutes: Option<u64>,
+}
+
+/// Resolved HTTP transport settings
+#[derive(Debug, Clone, Default)]
+pub struct HttpSettings {
+    /// Accepted API keys; empty accepts every client
+    pub api_keys: Vec<String>,
+    pub auth_header: Option<String>,
+    pub session_idle: Option<Duration>,
+}
+
+/// Tool exposure configuration
+///
+/// Entries are tool names (e.g. `get_record`) or group names
+/// (`read`, `metadata`, `write`, `admin`, `sync`).
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ToolsConfig {
+    /// If set, only these tools/groups are exposed
+    #[serde(default)]
+    pub enabled: Option<Vec<String>>,
+    /// Tools/groups that are never exposed (takes precedence over `enabled`)
+    #[serde(default)]
+    pub disabled: Option<Vec<String>>,
+    /// Set to false to never expose `delete_entity` (default: true)
+    #[serde(default)]
+    pub allow_delete: Option<bool>,
+    /// Set to false to skip the `query_<entity>` / `get_<entity>` tools
+    /// generated for `[[entities]]` entries (default: true)
+    #[serde(default)]
+    pub entity_tools: Option<bool>,
+    /// Directory `export_result_set` writes files into; without it, exports
+    /// are only returned inline
+    #[serde(default)]
+    pub export_dir: Option<String>,
+}
+
+impl ToolsConfig {
+    /// Whether a tool in the given group is exposed
+    pub fn is_enabled(&self, tool: &str, group: &str) -> bool {
+        let matches = |list: &Option<Vec<String>>| {
+            list.as_ref().is_some_and(|l| {
+                l.iter()
+                    .any(|entry| entry.eq_ignore_ascii_case(tool) || entry.eq_ignore_ascii_case(group))
+            })
+        };
+
+        if matches(&self.disabled) {
+            return false;
+        }
+        if tool == "delete_entity" && self.allow_delete == Some(false) {
+            return false;
+        }
+        match &self.enabled {
+            Some(list) if !list.is_empty() => matches(&self.enabled),
+            _ => true,
+        }
+    }
 }
 
 /// Entity-specific configuration
 #[derive(Debug, Deserialize, Clone)]
 pub struct EntityConfig {
     pub name: String,
+    /// Read every existing row on the first sync (default); false starts tracking from now
     #[serde(default)]
     pub initial_load: Option<bool>,
     #[serde(default)]
     pub delta_enabled: Option<bool>,
     #[serde(default)]
     pub cross_company: Option<bool>,
+    /// Timestamp column for watermark sync; setting it skips change tracking
+    /// (defaults to `modifiedon` / `ModifiedDateTime`)
+    #[serde(default)]
+    pub watermark_field: Option<String>,
+    /// Seconds between background syncs of this entity (default: `[sync] interval_seconds`)
+    #[serde(default)]
+    pub sync_interval_seconds: Option<u64>,
+    /// Split initial watermark loads into ranges read in parallel: `created`
+    /// for creation-time buckets, or the name of a string key field
+    #[serde(default)]
+    pub partition_by: Option<String>,
+    /// Number of ranges (default: `concurrency`)
+    #[serde(default)]
+    pub partitions: Option<usize>,
 }
 
 /// Root configuration structure
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
+    #[serde(default)]
     pub global: GlobalConfig,
     #[serde(default)]
     pub observability: Option<ObservabilityConfig>,
     #[serde(default)]
     pub delta: Option<DeltaConfig>,
     #[serde(default)]
+    pub sync: Option<SyncConfig>,
+    #[serde(default)]
+    pub metadata: Option<MetadataConfig>,
+    #[serde(default)]
     pub entities: Option<Vec<EntityConfig>>,
+    #[serde(default)]
+    pub tools: Option<ToolsConfig>,
+    #[serde(default)]
+    pub service_protection: Option<ServiceProtectionConfig>,
+    #[serde(default)]
+    pub secrets: Option<SecretsConfig>,
+    #[serde(default)]
+    pub http: Option<HttpConfig>,
+    #[serde(default)]
+    pub connection: Option<ConnectionConfig>,
+    #[serde(default)]
+    pub spill: Option<SpillConfig>,
+    #[serde(default)]
+    pub query_cache: Option<QueryCacheConfig>,
+    /// D365 environments selectable with `--profile` or `D365_PROFILE`
+    #[serde(default)]
+    pub profiles: Option<BTreeMap<String, ProfileConfig>>,
+    /// Profile applied by [`Config::select_profile`]
+    #[serde(skip)]
+    pub profile: Option<(String, ProfileConfig)>,
+    /// Profile values win over environment variables; set for the extra
+    /// connections of [`Config::connection`]
+    #[serde(skip)]
+    pub profile_first: bool,
 }
 
 /// Runtime configuration with resolved values from env vars
 #[derive(Debug, Clone)]
 pub struct RuntimeConfig {
+    /// Selected `[profiles.<name>]`, if any
+    pub profile: Option<String>,
     pub product: ProductType,
     pub endpoint: String,
     pub tenant_id: String,
     pub client_id: String,
     pub client_secret: String,
-    /// Authentication type: "azure" or "adfs"
+    /// Authentication type: "azure", "certificate", "managed_identity", "azure_cli",
+    /// "device_code" or "adfs"
     pub auth_type: String,
+    /// PEM or PFX client certificate (certificate auth)
+    pub cert_path: Option<PathBuf>,
+    /// Password of a PFX file or encrypted key
+    pub cert_password: Option<String>,
+    /// Refresh token cache of the device code flow
+    pub token_cache_file: PathBuf,
     /// Custom token URL (for ADFS)
     pub token_url: Option<String>,
     /// Resource/audience (for ADFS)
     pub resource: Option<String>,
+    /// Azure AD authority host URL
+    pub authority_host: String,
     /// Skip SSL certificate verification (for self-signed certs)
     pub insecure_ssl: bool,
     pub page_size: usize,
     pub concurrency: usize,
     pub max_retries: u32,
     pub retry_delay_ms: u64,
+    /// Longest time a request spends waiting between retries (0 = no limit)
+    pub retry_deadline_seconds: u64,
+    /// Default deadline for a single tool call in seconds
+    pub tool_timeout_seconds: Option<u64>,
+    /// How long idempotency keys are remembered in seconds
+    pub idempotency_ttl_seconds: u64,
+    /// Request Int64/Decimal values as strings
+    pub ieee754_compatible: bool,
+    pub conflict_strategy: ConflictStrategy,
+    /// Default impersonated Dataverse user of tool calls
+    pub impersonate_user: Option<Caller>,
     pub log_level: String,
     pub enable_tracing: bool,
+    pub log_file: PathBuf,
     pub delta_storage_path: String,
+    /// Overlap window of watermark sync in seconds
+    pub delta_overlap_seconds: u64,
+    /// Pages between checkpoints of a sync pull
+    pub delta_checkpoint_pages: usize,
+    pub sync: SyncSettings,
+    pub metadata: MetadataSettings,
     pub entities: Vec<EntityConfig>,
+    /// Which tools are exposed
+    pub tools: ToolsConfig,
+    pub service_protection: ServiceProtectionConfig,
+    pub connection: ConnectionSettings,
+    /// Memory limits of full entity reads
+    pub spill: SpillLimits,
+    pub query_cache: QueryCacheSettings,
+}
+
+impl RuntimeConfig {
+    /// The `[[entities]]` entry of an entity set, matched case-insensitively
+    pub fn entity(&self, name: &str) -> Option<&EntityConfig> {
+        self.entities.iter().find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Effective settings as JSON, with secrets replaced by whether they are set
+    pub fn redacted(&self) -> serde_json::Value {
+        let secret = |value: Option<&str>| match value.filter(|v| !v.is_empty()) {
+            Some(_) => "<set>",
+            None => "<not set>",
+        };
+        serde_json::json!({
+            "profile": self.profile,
+            "product": format!("{:?}", self.product).to_lowercase(),
+            "endpoint": self.endpoint,
+            "auth": {
+                "auth_type": self.auth_type,
+                "tenant_id": self.tenant_id,
+                "client_id": self.client_id,
+                "client_secret": secret(Some(&self.client_secret)),
+                "cert_path": self.cert_path,
+                "cert_password": secret(self.cert_password.as_deref()),
+                "token_cache_file": self.token_cache_file,
+                "token_url": self.token_url,
+                "resource": self.resource,
+                "authority_host": self.authority_host,
+                "insecure_ssl": self.insecure_ssl,
+            },
+            "page_size": self.page_size,
+            "concurrency": self.concurrency,
+            "max_retries": self.max_retries,
+            "retry_delay_ms": self.retry_delay_ms,
+            "retry_deadline_seconds": self.retry_deadline_seconds,
+            "tool_timeout_seconds": self.tool_timeout_seconds,
+            "idempotency_ttl_seconds": self.idempotency_ttl_seconds,
+            "ieee754_compatible": self.ieee754_compatible,
+            "conflict_strategy": format!("{:?}", self.conflict_strategy),
+            "impersonate_user": self.impersonate_user.as_ref().map(|c| c.header().1),
+            "log_level": self.log_level,
+            "log_file": self.log_file,
+            "delta": {
+                "storage_path": self.delta_storage_path,
+                "overlap_seconds": self.delta_overlap_seconds,
+                "checkpoint_pages": self.delta_checkpoint_pages,
+            },
+            "sync": {
+                "enabled": self.sync.enabled,
+                "interval_seconds": self.sync.interval_seconds,
+                "state_path": self.sync.state_path,
+                "sink": self.sync.sink.as_ref().map(|s| s.path.clone()),
+            },
+            "metadata": {
+                "file": self.metadata.file,
+                "cache_dir": self.metadata.cache_dir,
+                "cache_ttl_seconds": self.metadata.cache_ttl_seconds,
+            },
+            "entities": self.entities.iter().map(|e| e.name.clone()).collect::<Vec<_>>(),
+            "tools": {
+                "enabled": self.tools.enabled,
+                "disabled": self.tools.disabled,
+                "allow_delete": self.tools.allow_delete,
+                "entity_tools": self.tools.entity_tools,
+            },
+            "service_protection": {
+                "max_requests": self.service_protection.max_requests,
+                "max_execution_seconds": self.service_protection.max_execution_seconds,
+                "soft_limit_percent": self.service_protection.soft_limit_percent,
+                "enforce": self.service_protection.enforce,
+            },
+            "connection": {
+                "request_timeout_seconds": self.connection.request_timeout.as_secs(),
+                "connect_timeout_seconds": self.connection.connect_timeout.map(|t| t.as_secs()),
+                "pool_idle_timeout_seconds": self.connection.pool_idle_timeout.map(|t| t.as_secs()),
+                "pool_max_idle_per_host": self.connection.pool_max_idle_per_host,
+                "http2": self.connection.http2,
+                "breaker_failures": self.connection.breaker_failures,
+                "breaker_cooldown_seconds": self.connection.breaker_cool_down.as_secs(),
+                "etag_cache_entries": self.connection.etag_cache_entries,
+            },
+            "spill": {
+                "max_records": self.spill.max_records,
+                "max_memory_mb": self.spill.max_bytes / (1024 * 1024),
+                "directory": self.spill.dir,
+            },
+            "query_cache": {
+                "enabled": self.query_cache.enabled,
+                "ttl_seconds": self.query_cache.ttl.as_secs(),
+                "max_entries": self.query_cache.max_entries,
+            },
+        })
+    }
 }
 
 impl Config {
@@ -113,11 +763,25 @@ impl Config {
         Ok(config)
     }
 
-    /// Load from default path or create default config
+    /// Load the file named by `CONFIG_FILE` (set by `--config`), else
+    /// `config/default.toml` in the working directory, else a minimal default,
+    /// and apply the profile `D365_PROFILE` (set by `--profile`) selects
     pub fn load_default() -> Result<Self, Box<dyn std::error::Error>> {
-        let default_path = "config/default.toml";
-        if Path::new(default_path).exists() {
-            Self::load_from_path(default_path)
+        let mut config = Self::load_file()?;
+        if let Some(name) = vars::var(PROFILE_VAR).filter(|p| !p.is_empty()) {
+            config.select_profile(&name)?;
+        }
+        Ok(config)
+    }
+
+    fn load_file() -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(path) = vars::var_os(CONFIG_FILE_VAR) {
+            let path = PathBuf::from(path);
+            return Self::load_from_path(&path)
+                .map_err(|e| format!("Config file {}: {}", path.display(), e).into());
+        }
+        if Path::new(DEFAULT_CONFIG_PATH).exists() {
+            Self::load_from_path(DEFAULT_CONFIG_PATH)
         } else {
             // Minimal default config
             Ok(Config {
@@ -128,33 +792,287 @@ impl Config {
                     concurrency: Some(4),
                     max_retries: Some(3),
                     retry_delay_ms: Some(1000),
+                    retry_deadline_seconds: None,
+                    tool_timeout_seconds: None,
+                    idempotency_ttl_seconds: None,
+                    ieee754_compatible: None,
+                    conflict_strategy: None,
+                    impersonate_user: None,
+                    authority_host: None,
+                    shutdown_grace_seconds: None,
+                    strict_jsonrpc: None,
+                    max_message_bytes: None,
+                    connections: None,
                 },
                 observability: Some(ObservabilityConfig::default()),
                 delta: Some(DeltaConfig::default()),
+                sync: None,
+                metadata: None,
                 entities: None,
+                tools: None,
+                service_protection: None,
+                secrets: None,
+                http: None,
+                connection: None,
+                spill: None,
+                query_cache: None,
+                profiles: None,
+                profile: None,
+                profile_first: false,
+            })
+        }
+    }
+
+    /// Apply `[profiles.<name>]` over `[global]` and `[secrets]`
+    pub fn select_profile(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let profiles = self.profiles.clone().unwrap_or_default();
+        let Some(profile) = profiles.get(name).cloned() else {
+            let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            return Err(if known.is_empty() {
+                format!("Profile '{}' not found: the config file has no [profiles] sections", name)
+            } else {
+                format!("Profile '{}' not found; known profiles: {}", name, known.join(", "))
+            }
+            .into());
+        };
+        if let Some(endpoint) = &profile.endpoint {
+            self.global.endpoint = endpoint.clone();
+        }
+        if let Some(product) = &profile.product {
+            self.global.product = product.clone();
+        }
+        if profile.authority_host.is_some() {
+            self.global.authority_host = profile.authority_host.clone();
+        }
+        if profile.impersonate_user.is_some() {
+            self.global.impersonate_user = profile.impersonate_user.clone();
+        }
+        if profile.secret_provider.is_some() || profile.keyring_service.is_some() {
+            let secrets = self.secrets.get_or_insert_with(SecretsConfig::default);
+            if profile.secret_provider.is_some() {
+                secrets.provider = profile.secret_provider.clone();
+            }
+            if profile.keyring_service.is_some() {
+                secrets.keyring_service = profile.keyring_service.clone();
+            }
+        }
+        self.profile = Some((name.to_string(), profile));
+        Ok(())
+    }
+
+    /// Name of the connection this config describes: the selected profile, else "default"
+    pub fn connection_name(&self) -> &str {
+        self.profile.as_ref().map(|(name, _)| name.as_str()).unwrap_or(DEFAULT_CONNECTION)
+    }
+
+    /// Config of the extra connection `name`, from `[profiles.<name>]`.
+    /// The environment variables describe the default connection, so the
+    /// profile's values win over them here.
+    pub fn connection(&self, name: &str) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut config = self.clone();
+        config.select_profile(name)?;
+        if config.profile.as_ref().and_then(|(_, p)| p.endpoint.as_ref()).is_none() {
+            return Err(format!("Connection '{}' needs an endpoint in [profiles.{}]", name, name).into());
+        }
+        config.profile_first = true;
+        Ok(config)
+    }
+
+    /// Extra connections of `[global] connections`, without the default one
+    pub fn extra_connections(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for name in self.global.connections.iter().flatten() {
+            if name != self.connection_name() && !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    /// Environment variable `name`, else the selected profile's value
+    /// (the other way round with `profile_first`)
+    fn env_or_profile(&self, name: &str, field: fn(&ProfileConfig) -> &Option<String>) -> Option<String> {
+        let profile = self.profile.as_ref().and_then(|(_, profile)| field(profile).clone());
+        if self.profile_first {
+            profile.or_else(|| vars::var(name))
+        } else {
+            vars::var(name).or(profile)
+        }
+    }
+
+    /// `path` with the connection name added, for state an extra connection
+    /// must not share with the default one unless its profile sets `field`
+    fn connection_path(&self, path: String, field: fn(&ProfileConfig) -> &Option<String>) -> String {
+        match &self.profile {
+            Some((name, profile)) if self.profile_first && field(profile).is_none() => sibling_path(&path, name),
+            _ => path,
+        }
+    }
+
+    /// Resolve the log file path: `LOG_FILE` env var, then
+    /// `observability.log_file`, then the platform log directory
+    pub fn log_file_path(&self) -> PathBuf {
+        vars::var("LOG_FILE")
+            .filter(|p| !p.is_empty())
+            .or_else(|| {
+                self.observability
+                    .as_ref()
+                    .and_then(|o| o.log_file.clone())
             })
+            .map(PathBuf::from)
+            .unwrap_or_else(paths::default_log_file)
+    }
+
+    /// Secret provider from `SECRET_PROVIDER` or `[secrets]`.
+    ///
+    /// An extra connection only reads the secrets its profile names: its
+    /// secret files, its `keyring_service`, or the environment when the
+    /// profile sets `secret_provider = "env"`. The default connection's
+    /// secrets belong to another environment.
+    pub fn secret_provider(&self) -> Result<Box<dyn SecretProvider>, Box<dyn std::error::Error>> {
+        let secrets_config = self.secrets.clone().unwrap_or_default();
+        let secret_source = vars::var("SECRET_PROVIDER")
+            .filter(|_| !self.profile_first)
+            .or(secrets_config.provider)
+            .map(|s| s.parse::<SecretSource>())
+            .transpose()?
+            .unwrap_or_default();
+        let keyring_service = secrets_config
+            .keyring_service
+            .unwrap_or_else(|| secrets::DEFAULT_KEYRING_SERVICE.to_string());
+        let files = match &self.profile {
+            Some((_, profile)) => [
+                ("CLIENT_SECRET", &profile.client_secret_file),
+                ("CERT_PASSWORD", &profile.cert_password_file),
+            ]
+            .into_iter()
+            .filter_map(|(name, path)| path.as_ref().map(|p| (name.to_string(), PathBuf::from(p))))
+            .collect(),
+            None => Vec::new(),
+        };
+        let file_secrets = secrets::FileSecrets {
+            configured: files,
+            configured_only: self.profile_first,
+        };
+        if let Some((name, profile)) = self.profile.as_ref().filter(|_| self.profile_first) {
+            match secret_source {
+                SecretSource::Keyring if profile.keyring_service.is_none() => {
+                    return Err(format!(
+                        "Connection '{}' reads secrets from the keyring, so [profiles.{}] needs its own keyring_service",
+                        name, name
+                    )
+                    .into())
+                }
+                SecretSource::Env if profile.secret_provider.is_some() => {}
+                SecretSource::Keyring => {}
+                _ => return Ok(Box::new(file_secrets)),
+            }
         }
+        Ok(secrets::secret_provider(secret_source, &keyring_service, file_secrets))
+    }
+
+    /// HTTP transport settings; API keys are the comma-separated
+    /// `MCP_HTTP_TOKEN` secret, the header comes from `MCP_HTTP_AUTH_HEADER`
+    /// or `[http] auth_header`
+    pub fn http_settings(&self) -> Result<HttpSettings, Box<dyn std::error::Error>> {
+        let http = self.http.clone().unwrap_or_default();
+        let api_keys = self
+            .secret_provider()?
+            .get("MCP_HTTP_TOKEN")?
+            .map(|keys| {
+                keys.split(',')
+                    .map(str::trim)
+                    .filter(|k| !k.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let auth_header = vars::var("MCP_HTTP_AUTH_HEADER")
+            .or(http.auth_header)
+            .filter(|h| !h.trim().is_empty());
+        if let Some(header) = &auth_header {
+            if hyper::header::HeaderName::from_bytes(header.trim().as_bytes()).is_err() {
+                return Err(format!("Invalid HTTP auth header name '{}'", header).into());
+            }
+        }
+        Ok(HttpSettings {
+            api_keys,
+            auth_header: auth_header.map(|h| h.trim().to_string()),
+            session_idle: http.session_idle_minutes.map(|m| Duration::from_secs(m * 60)),
+        })
+    }
+
+    /// How long shutdown waits for in-flight work before exiting
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.global.shutdown_grace_seconds.unwrap_or(DEFAULT_SHUTDOWN_GRACE_SECONDS))
     }
 
     /// Resolve configuration with environment variables
     /// Environment variables take precedence over file config
     pub fn to_runtime(&self) -> Result<RuntimeConfig, Box<dyn std::error::Error>> {
-        // Required env vars (no defaults)
-        let tenant_id = env::var("TENANT_ID")
-            .map_err(|_| "TENANT_ID environment variable is required")?;
-        let client_id = env::var("CLIENT_ID")
-            .map_err(|_| "CLIENT_ID environment variable is required")?;
-        let client_secret = env::var("CLIENT_SECRET")
-            .map_err(|_| "CLIENT_SECRET environment variable is required")?;
+        // Auth type (azure, certificate, managed_identity, azure_cli, device_code
+        // or adfs); which credentials are required depends on it
+        let auth_type = self
+            .env_or_profile("AUTH_TYPE", |p| &p.auth_type)
+            .unwrap_or_else(|| "azure".to_string());
+        let kind = auth_type
+            .parse::<AuthType>()
+            .map_err(|e| format!("Invalid AUTH_TYPE: {}", e))?;
+        let required = |name: &str, field: fn(&ProfileConfig) -> &Option<String>, needed: bool| {
+            match self.env_or_profile(name, field) {
+                Some(value) => Ok(value),
+                None if !needed => Ok(String::new()),
+                None => Err(format!("{} (or {}{}) environment variable is required", name, vars::PREFIX, name)),
+            }
+        };
+        // Managed identities and the Azure CLI need no app registration;
+        // CLIENT_ID then selects a user-assigned identity and TENANT_ID the
+        // CLI's tenant
+        let app_registration = !matches!(kind, AuthType::ManagedIdentity | AuthType::AzureCli);
+        let tenant_id = required("TENANT_ID", |p| &p.tenant_id, app_registration)?;
+        let client_id = required("CLIENT_ID", |p| &p.client_id, app_registration)?;
+        let secret_provider = self.secret_provider()?;
+        let client_secret = match secret_provider.get("CLIENT_SECRET")? {
+            Some(secret) => secret,
+            None if matches!(kind, AuthType::AzureAd | AuthType::Adfs) && self.profile_first => {
+                let name = self.connection_name();
+                return Err(format!(
+                    "Connection '{}' needs its own CLIENT_SECRET: set client_secret_file, or secret_provider and keyring_service, in [profiles.{}]",
+                    name, name
+                )
+                .into());
+            }
+            None if matches!(kind, AuthType::AzureAd | AuthType::Adfs) => {
+                return Err(format!(
+                    "CLIENT_SECRET is required (secret provider: {}; set CLIENT_SECRET or CLIENT_SECRET_FILE)",
+                    secret_provider.name()
+                )
+                .into())
+            }
+            None => String::new(),
+        };
+        let cert_path = self.env_or_profile("CERT_PATH", |p| &p.cert_path).filter(|p| !p.is_empty()).map(PathBuf::from);
+        let cert_password = secret_provider.get("CERT_PASSWORD")?;
+        if kind == AuthType::Certificate && cert_path.is_none() {
+            return Err("CERT_PATH environment variable is required for AUTH_TYPE=certificate".into());
+        }
+        let token_cache_file = self
+            .env_or_profile("TOKEN_CACHE_FILE", |p| &p.token_cache_file)
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| paths::default_token_cache_file().to_string_lossy().into_owned());
+        let token_cache_file = PathBuf::from(self.connection_path(token_cache_file, |p| &p.token_cache_file));
 
         // Optional env vars with fallback to config file
-        let endpoint = env::var("ENDPOINT").unwrap_or_else(|_| self.global.endpoint.clone());
+        let endpoint = self
+            .env_or_profile("ENDPOINT", |p| &p.endpoint)
+            .unwrap_or_else(|| self.global.endpoint.clone());
         if endpoint.is_empty() {
             return Err("ENDPOINT environment variable or config endpoint is required".into());
         }
 
-        let product = env::var("PRODUCT")
-            .ok()
+        let profile_product = self.profile.as_ref().and_then(|(_, p)| p.product.clone());
+        let product = vars::var("PRODUCT")
+            .filter(|_| !(self.profile_first && profile_product.is_some()))
             .and_then(|p| match p.to_lowercase().as_str() {
                 "dataverse" => Some(ProductType::Dataverse),
                 "finops" | "fno" | "fo" => Some(ProductType::Finops),
@@ -164,43 +1082,137 @@ impl Config {
 
         let obs = self.observability.clone().unwrap_or_default();
         let delta = self.delta.clone().unwrap_or_default();
+        let sync = self.sync.clone().unwrap_or_default();
+        let metadata = self.metadata.clone().unwrap_or_default();
+        if let Some(sink) = &sync.sink {
+            if sink.gzip == Some(true) {
+                return Err("[sync.sink] gzip = true is not available in this build".into());
+            }
+        }
+
+        let impersonate_user = self
+            .env_or_profile("IMPERSONATE_USER", |p| &p.impersonate_user)
+            .or_else(|| self.global.impersonate_user.clone())
+            .filter(|u| !u.is_empty())
+            .map(|u| Caller::parse(&u))
+            .transpose()?;
+        if impersonate_user.is_some() && product != ProductType::Dataverse {
+            return Err("impersonate_user is only supported for Dataverse".into());
+        }
+
+        let authority_host = cloud::resolve_authority_host(
+            self.env_or_profile("AUTHORITY_HOST", |p| &p.authority_host)
+                .or_else(|| self.global.authority_host.clone())
+                .as_deref(),
+            &endpoint,
+        )?;
 
-        // Auth type (azure or adfs)
-        let auth_type = env::var("AUTH_TYPE").unwrap_or_else(|_| "azure".to_string());
-        
         // Custom token URL (for ADFS)
-        let token_url = env::var("TOKEN_URL").ok();
+        let token_url = self.env_or_profile("TOKEN_URL", |p| &p.token_url);
         
         // Resource/audience (for ADFS) 
-        let resource = env::var("RESOURCE").ok();
+        let resource = self.env_or_profile("RESOURCE", |p| &p.resource);
+
+        // Skip SSL verification (for self-signed certificates). Neither this
+        // nor the metadata file carries over from the default connection to
+        // an extra one, which is a different environment.
+        let profile = self.profile.as_ref().map(|(_, p)| p);
+        let profile_insecure_ssl = profile.and_then(|p| p.insecure_ssl);
+        let profile_metadata_file = profile.and_then(|p| p.metadata_file.clone());
+        let (insecure_ssl, metadata_file) = if self.profile_first {
+            (profile_insecure_ssl.unwrap_or(false), profile_metadata_file)
+        } else {
+            (
+                vars::var("INSECURE_SSL")
+                    .map(|v| v.to_lowercase() == "true" || v == "1")
+                    .or(profile_insecure_ssl)
+                    .unwrap_or(false),
+                vars::var("METADATA_FILE")
+                    .filter(|p| !p.is_empty())
+                    .or(profile_metadata_file)
+                    .or(metadata.file.clone()),
+            )
+        };
 
-        // Skip SSL verification (for self-signed certificates)
-        let insecure_ssl = env::var("INSECURE_SSL")
-            .map(|v| v.to_lowercase() == "true" || v == "1")
-            .unwrap_or(false);
+        let delta_storage_path = self
+            .env_or_profile("DELTA_STORAGE_PATH", |p| &p.delta_storage_path)
+            .or(delta.storage_path)
+            .unwrap_or_else(|| {
+                paths::default_delta_state_file()
+                    .to_string_lossy()
+                    .into_owned()
+            });
+        let delta_storage_path = self.connection_path(delta_storage_path, |p| &p.delta_storage_path);
 
         Ok(RuntimeConfig {
+            profile: self.profile.as_ref().map(|(name, _)| name.clone()),
             product,
             endpoint,
             tenant_id,
             client_id,
             client_secret,
             auth_type,
+            cert_path,
+            cert_password,
+            token_cache_file,
             token_url,
             resource,
+            authority_host,
             insecure_ssl,
             page_size: self.global.page_size.unwrap_or(500),
             concurrency: self.global.concurrency.unwrap_or(4),
             max_retries: self.global.max_retries.unwrap_or(3),
             retry_delay_ms: self.global.retry_delay_ms.unwrap_or(1000),
+            retry_deadline_seconds: self
+                .global
+                .retry_deadline_seconds
+                .unwrap_or(DEFAULT_RETRY_DEADLINE_SECONDS),
+            tool_timeout_seconds: self.global.tool_timeout_seconds,
+            idempotency_ttl_seconds: self.global.idempotency_ttl_seconds.unwrap_or(3600),
+            ieee754_compatible: self.global.ieee754_compatible.unwrap_or(false),
+            conflict_strategy: self.global.conflict_strategy.unwrap_or_default(),
+            impersonate_user,
             log_level: obs.log_level.unwrap_or_else(|| "info".to_string()),
             enable_tracing: obs.enable_tracing.unwrap_or(false),
-            delta_storage_path: delta.storage_path.unwrap_or_else(|| "./delta_state.json".to_string()),
+            log_file: self.log_file_path(),
+            delta_overlap_seconds: delta.overlap_seconds.unwrap_or(300),
+            delta_checkpoint_pages: delta.checkpoint_pages.unwrap_or(10),
+            sync: SyncSettings {
+                enabled: sync.enabled.unwrap_or(false),
+                interval_seconds: sync.interval_seconds.unwrap_or(900),
+                state_path: sibling_path(&delta_storage_path, "scheduled"),
+                sink: sync.sink,
+            },
+            metadata: MetadataSettings {
+                file: metadata_file.filter(|p| !p.is_empty()).map(PathBuf::from),
+                cache_dir: metadata
+                    .cache_dir
+                    .map(PathBuf::from)
+                    .unwrap_or_else(paths::default_metadata_cache_dir),
+                cache_ttl_seconds: metadata.cache_ttl_seconds.unwrap_or(86400),
+            },
+            delta_storage_path,
             entities: self.entities.clone().unwrap_or_default(),
+            tools: self.tools.clone().unwrap_or_default(),
+            service_protection: self.service_protection.clone().unwrap_or_default(),
+            connection: self.connection.clone().unwrap_or_default().into(),
+            spill: self.spill.clone().unwrap_or_default().into(),
+            query_cache: self.query_cache.clone().unwrap_or_default().into(),
         })
     }
 }
 
+/// `dir/name.ext` -> `dir/name.<suffix>.ext`
+fn sibling_path(path: &str, suffix: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, suffix),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,4 +1237,265 @@ mod tests {
         let test: Test = toml::from_str(toml_str).unwrap();
         assert_eq!(test.product, ProductType::Dataverse);
     }
+
+    #[test]
+    fn test_tools_config_enable_disable() {
+        let all = ToolsConfig::default();
+        assert!(all.is_enabled("query_entity", "read"));
+
+        let tools: ToolsConfig = toml::from_str(r#"disabled = ["write", "get_record"]"#).unwrap();
+        assert!(!tools.is_enabled("create_entity", "write"));
+        assert!(!tools.is_enabled("get_record", "read"));
+        assert!(tools.is_enabled("query_entity", "read"));
+
+        let tools: ToolsConfig =
+            toml::from_str(r#"enabled = ["read", "get_metadata"]
+disabled = ["get_record"]"#).unwrap();
+        assert!(tools.is_enabled("query_entity", "read"));
+        assert!(tools.is_enabled("get_metadata", "metadata"));
+        assert!(!tools.is_enabled("list_entities", "metadata"));
+        assert!(!tools.is_enabled("get_record", "read"));
+    }
+
+    #[test]
+    fn test_conflict_strategy() {
+        #[derive(Deserialize)]
+        struct Test {
+            conflict_strategy: ConflictStrategy,
+        }
+
+        let t: Test = toml::from_str(r#"conflict_strategy = "refetch_merge""#).unwrap();
+        assert_eq!(t.conflict_strategy, ConflictStrategy::RefetchMerge);
+        assert_eq!(ConflictStrategy::parse("force").unwrap(), ConflictStrategy::Overwrite);
+        assert!(ConflictStrategy::parse("ignore").is_err());
+    }
+
+    #[test]
+    fn test_load_default_from_config_file_var() {
+        let path = std::env::temp_dir().join(format!("d365-config-{}.toml", std::process::id()));
+        fs::write(&path, "[global]\nendpoint = \"https://org.crm.dynamics.com/api/data/v9.2/\"\nproduct = \"dataverse\"\n").unwrap();
+        let path_var = path.display().to_string();
+        vars::with_vars(&[(CONFIG_FILE_VAR, &path_var)], || {
+            let loaded = Config::load_default();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap().global.endpoint, "https://org.crm.dynamics.com/api/data/v9.2/");
+
+            let error = Config::load_default().unwrap_err().to_string();
+            assert!(error.contains(&path_var), "{}", error);
+        });
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let mut config: Config = toml::from_str(
+            r#"
+[global]
+product = "dataverse"
+endpoint = "https://dev.crm.dynamics.com/api/data/v9.2/"
+
+[profiles.uat]
+endpoint = "https://uat.sandbox.operations.dynamics.com/data/"
+product = "finops"
+auth_type = "certificate"
+cert_path = "/certs/uat.pfx"
+keyring_service = "d365-uat"
+
+[profiles.prod]
+endpoint = "https://prod.operations.dynamics.com/data/"
+"#,
+        )
+        .unwrap();
+        let error = config.clone().select_profile("test").unwrap_err().to_string();
+        assert!(error.contains("known profiles: prod, uat"), "{}", error);
+
+        config.select_profile("uat").unwrap();
+        assert_eq!(config.global.endpoint, "https://uat.sandbox.operations.dynamics.com/data/");
+        assert_eq!(config.global.product, ProductType::Finops);
+        assert_eq!(config.secrets.as_ref().unwrap().keyring_service.as_deref(), Some("d365-uat"));
+        assert_eq!(config.env_or_profile("D365_TEST_UNSET_CERT_PATH", |p| &p.cert_path).as_deref(), Some("/certs/uat.pfx"));
+        assert_eq!(config.env_or_profile("D365_TEST_UNSET_TENANT_ID", |p| &p.tenant_id), None);
+    }
+
+    #[test]
+    fn test_extra_connections() {
+        let config: Config = toml::from_str(
+            r#"
+[global]
+endpoint = "https://org.crm.dynamics.com/api/data/v9.2/"
+connections = ["fno", "default", "fno", "broken"]
+
+[profiles.fno]
+endpoint = "https://org.operations.dynamics.com/data/"
+product = "finops"
+auth_type = "managed_identity"
+token_url = "https://adfs.contoso.com/adfs/oauth2/token"
+
+[profiles.broken]
+product = "finops"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.connection_name(), "default");
+        assert_eq!(config.extra_connections(), ["fno", "broken"]);
+        assert!(config.connection("broken").unwrap_err().to_string().contains("needs an endpoint"));
+
+        let fno = config.connection("fno").unwrap();
+        assert_eq!(fno.connection_name(), "fno");
+        let env = [
+            ("D365_INSECURE_SSL", "true"),
+            ("D365_METADATA_FILE", "default.edmx"),
+            ("TENANT_ID", "t"),
+            ("CLIENT_ID", "c"),
+            ("CLIENT_SECRET", "s"),
+        ];
+        let (runtime, default) = vars::with_vars(&env, || (fno.to_runtime().unwrap(), config.to_runtime().unwrap()));
+        assert_eq!(runtime.endpoint, "https://org.operations.dynamics.com/data/");
+        assert_eq!(runtime.product, ProductType::Finops);
+        assert_eq!(runtime.auth_type, "managed_identity");
+        assert!(runtime.delta_storage_path.ends_with("delta_state.fno.json"), "{}", runtime.delta_storage_path);
+        assert!(!runtime.insecure_ssl);
+        assert_eq!(runtime.metadata.file, None);
+        assert_eq!(runtime.token_url.as_deref(), Some("https://adfs.contoso.com/adfs/oauth2/token"));
+        assert_ne!(runtime.token_cache_file, default.token_cache_file);
+        assert!(default.insecure_ssl);
+        assert_eq!(default.metadata.file, Some(PathBuf::from("default.edmx")));
+        assert_eq!(default.token_url, None);
+    }
+
+    #[test]
+    fn test_connection_secrets_not_inherited() {
+        let config: Config = toml::from_str(
+            r#"
+[global]
+endpoint = "https://org.crm.dynamics.com/api/data/v9.2/"
+connections = ["uat", "prod", "shared"]
+
+[profiles.uat]
+endpoint = "https://org-uat.crm.dynamics.com/api/data/v9.2/"
+tenant_id = "t"
+client_id = "c"
+
+[profiles.prod]
+endpoint = "https://org-prod.crm.dynamics.com/api/data/v9.2/"
+secret_provider = "keyring"
+
+[profiles.shared]
+endpoint = "https://org-shared.crm.dynamics.com/api/data/v9.2/"
+tenant_id = "t"
+client_id = "c"
+secret_provider = "env"
+"#,
+        )
+        .unwrap();
+        vars::with_vars(&[("CLIENT_SECRET", "default-secret")], || {
+            let uat = config.connection("uat").unwrap();
+            assert_eq!(uat.secret_provider().unwrap().get("CLIENT_SECRET").unwrap(), None);
+            let error = uat.to_runtime().unwrap_err().to_string();
+            assert!(error.contains("Connection 'uat' needs its own CLIENT_SECRET"), "{}", error);
+
+            let error = config.connection("prod").unwrap().secret_provider().err().unwrap().to_string();
+            assert!(error.contains("needs its own keyring_service"), "{}", error);
+
+            // Sharing the environment's secrets is an explicit choice
+            let shared = config.connection("shared").unwrap();
+            assert_eq!(
+                shared.secret_provider().unwrap().get("CLIENT_SECRET").unwrap().as_deref(),
+                Some("default-secret")
+            );
+        });
+    }
+
+    #[test]
+    fn test_unknown_auth_type_rejected() {
+        let config: Config = toml::from_str(
+            r#"
+[global]
+endpoint = "https://org.crm.dynamics.com/api/data/v9.2/"
+
+[profiles.typo]
+endpoint = "https://org-uat.crm.dynamics.com/api/data/v9.2/"
+auth_type = "devicecode"
+"#,
+        )
+        .unwrap();
+        let error = config.connection("typo").unwrap().to_runtime().unwrap_err().to_string();
+        assert!(error.contains("Invalid AUTH_TYPE: Unknown auth type: devicecode"), "{}", error);
+        assert!(error.contains("'device_code'"), "{}", error);
+    }
+
+    #[test]
+    fn test_redacted_hides_secrets() {
+        let config: Config = toml::from_str(
+            r#"
+[global]
+endpoint = "https://org.crm.dynamics.com/api/data/v9.2/"
+"#,
+        )
+        .unwrap();
+        let env = [("TENANT_ID", "t"), ("CLIENT_ID", "c"), ("CLIENT_SECRET", "s")];
+        let redacted = vars::with_vars(&env, || config.to_runtime().unwrap()).redacted();
+        assert_eq!(redacted["auth"]["client_secret"], "<set>");
+        assert_eq!(redacted["auth"]["cert_password"], "<not set>");
+        assert_eq!(redacted["endpoint"], "https://org.crm.dynamics.com/api/data/v9.2/");
+    }
+
+    #[test]
+    fn test_connection_settings() {
+        let config: Config = toml::from_str(
+            r#"
+[global]
+endpoint = "https://org.operations.dynamics.com/data/"
+
+[connection]
+request_timeout_seconds = 600
+pool_max_idle_per_host = 8
+http2 = false
+"#,
+        )
+        .unwrap();
+        let settings = ConnectionSettings::from(config.connection.unwrap());
+        assert_eq!(settings.request_timeout, Duration::from_secs(600));
+        assert_eq!(settings.connect_timeout, None);
+        assert_eq!(settings.pool_max_idle_per_host, Some(8));
+        assert_eq!(settings.http2, Some(false));
+        assert_eq!(
+            ConnectionSettings::from(ConnectionConfig::default()),
+            ConnectionSettings::default()
+        );
+    }
+
+    #[test]
+    fn test_spill_limits() {
+        let config: Config = toml::from_str(
+            r#"
+[global]
+endpoint = "https://org.operations.dynamics.com/data/"
+
+[spill]
+max_memory_mb = 64
+directory = "/var/tmp/d365"
+"#,
+        )
+        .unwrap();
+        let limits = SpillLimits::from(config.spill.unwrap());
+        assert_eq!(limits.max_records, spill::DEFAULT_MAX_RECORDS);
+        assert_eq!(limits.max_bytes, 64 * 1024 * 1024);
+        assert_eq!(limits.dir, PathBuf::from("/var/tmp/d365"));
+        assert_eq!(SpillLimits::from(SpillConfig::default()), SpillLimits::default());
+    }
+
+    #[test]
+    fn test_sibling_path() {
+        assert_eq!(sibling_path("/var/d365/delta_state.json", "scheduled"), "/var/d365/delta_state.scheduled.json");
+        assert_eq!(sibling_path("state", "scheduled"), "state.scheduled");
+    }
+
+    #[test]
+    fn test_tools_config_allow_delete() {
+        assert!(ToolsConfig::default().is_enabled("delete_entity", "write"));
+
+        let tools: ToolsConfig = toml::from_str("allow_delete = false").unwrap();
+        assert!(!tools.is_enabled("delete_entity", "write"));
+        assert!(tools.is_enabled("update_entity", "write"));
+    }
 }
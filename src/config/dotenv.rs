@@ -0,0 +1,155 @@
+//! `.env` file support
+//!
+//! Local development keeps the credentials in a `.env` file instead of
+//! exporting them into every shell. The file is read once, from `ENV_FILE`
+//! or next to the config file. Its values only fill in variables the
+//! environment does not set (see [`super::vars`]), so flags and the
+//! environment still win.
+
+use super::config::{CONFIG_FILE_VAR, DEFAULT_CONFIG_PATH};
+use super::vars;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Environment variable naming the `.env` file; `--env-file` sets it
+pub const ENV_FILE_VAR: &str = "ENV_FILE";
+
+/// Name of the `.env` file looked for next to the config file
+pub const ENV_FILE_NAME: &str = ".env";
+
+static LOADED: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Value of `name` in the `.env` file
+pub fn get(name: &str) -> Option<String> {
+    loaded().get(name).cloned()
+}
+
+/// The `.env` file: `ENV_FILE`, else `.env` in the directory of the config
+/// file, else in the working directory
+pub fn env_file_path() -> PathBuf {
+    if let Some(path) = vars::var_os(ENV_FILE_VAR) {
+        return PathBuf::from(path);
+    }
+    let config_file = vars::var_os(CONFIG_FILE_VAR)
+        .map(PathBuf::from)
+        .or_else(|| Path::new(DEFAULT_CONFIG_PATH).exists().then(|| PathBuf::from(DEFAULT_CONFIG_PATH)));
+    env_file_next_to(config_file.as_deref())
+}
+
+fn env_file_next_to(config_file: Option<&Path>) -> PathBuf {
+    config_file
+        .and_then(Path::parent)
+        .map(|dir| dir.join(ENV_FILE_NAME))
+        .unwrap_or_else(|| PathBuf::from(ENV_FILE_NAME))
+}
+
+fn loaded() -> &'static HashMap<String, String> {
+    LOADED.get_or_init(|| {
+        let path = env_file_path();
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let values: HashMap<String, String> = parse(&text).into_iter().collect();
+                tracing::debug!("Read {} variables from {}", values.len(), path.display());
+                values
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && vars::var_os(ENV_FILE_VAR).is_none() => HashMap::new(),
+            Err(e) => {
+                tracing::warn!("Cannot read {}: {}", path.display(), e);
+                HashMap::new()
+            }
+        }
+    })
+}
+
+/// `KEY=value` lines of a `.env` file. Blank lines and `#` comments are
+/// skipped, an `export ` prefix is allowed, and values may be quoted: `"..."`
+/// with `\n`, `\"` and `\\` escapes, or `'...'` taken literally. Unquoted
+/// values end at ` #`.
+pub fn parse(text: &str) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            continue;
+        }
+        vars.push((key.to_string(), parse_value(value.trim())));
+    }
+    vars
+}
+
+fn parse_value(value: &str) -> String {
+    if let Some(rest) = value.strip_prefix('\'') {
+        return rest.split('\'').next().unwrap_or_default().to_string();
+    }
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut parsed = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('n') => parsed.push('\n'),
+                    Some(other) => parsed.push(other),
+                    None => break,
+                },
+                c => parsed.push(c),
+            }
+        }
+        return parsed;
+    }
+    match value.find(" #") {
+        Some(comment) => value[..comment].trim_end().to_string(),
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let vars = parse(
+            "# local credentials\n\
+             TENANT_ID=contoso.onmicrosoft.com\n\
+             export CLIENT_ID = 1234 # app registration\n\
+             CLIENT_SECRET=\"a#b \\\"c\\\"\"\n\
+             CERT_PASSWORD='p@ss \\n'\n\
+             \n\
+             not a variable\n\
+             BAD-KEY=1\n\
+             EMPTY=\n",
+        );
+        assert_eq!(
+            vars,
+            [
+                ("TENANT_ID".to_string(), "contoso.onmicrosoft.com".to_string()),
+                ("CLIENT_ID".to_string(), "1234".to_string()),
+                ("CLIENT_SECRET".to_string(), "a#b \"c\"".to_string()),
+                ("CERT_PASSWORD".to_string(), "p@ss \\n".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_env_file_next_to_config() {
+        assert_eq!(
+            env_file_next_to(Some(Path::new("/etc/d365/prod.toml"))),
+            PathBuf::from("/etc/d365/.env")
+        );
+        assert_eq!(env_file_next_to(Some(Path::new("prod.toml"))), PathBuf::from(".env"));
+        assert_eq!(env_file_next_to(None), PathBuf::from(".env"));
+    }
+}
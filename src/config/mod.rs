@@ -1,5 +1,13 @@
 //! Config module
 
+#[allow(clippy::module_inception)]
 pub mod config;
+pub mod dotenv;
+pub mod paths;
+pub mod vars;
 
-pub use config::{Config, EntityConfig, ProductType, RuntimeConfig};
+pub use config::{
+    ConflictStrategy, Config, ConnectionSettings, EntityConfig, CONFIG_FILE_VAR, DEFAULT_CONFIG_PATH, DEFAULT_CONNECTION, DEFAULT_SHUTDOWN_GRACE_SECONDS, HttpConfig, HttpSettings, MetadataSettings, ProductType, ProfileConfig, PROFILE_VAR, RuntimeConfig, SecretsConfig,
+    ServiceProtectionConfig,
+    SinkConfig, SinkKind, SinkRotation, SyncSettings, ToolsConfig,
+};
@@ -0,0 +1,160 @@
+//! Platform-specific file locations
+//!
+//! Resolves where logs and state files live when the config does not say
+//! otherwise:
+//! - Windows: `%LOCALAPPDATA%\d365-odata-mcp`
+//! - macOS: `~/Library/Application Support/d365-odata-mcp` (logs in `~/Library/Logs`)
+//! - Linux/other: `$XDG_STATE_HOME/d365-odata-mcp` (default `~/.local/state`)
+//!
+//! Falls back to the system temp directory when no home directory is known.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Directory name used under the platform base directories
+pub const APP_DIR_NAME: &str = "d365-odata-mcp";
+
+/// Default log file name
+pub const LOG_FILE_NAME: &str = "d365-mcp.log";
+
+/// Default delta state file name
+pub const DELTA_STATE_FILE_NAME: &str = "delta_state.json";
+
+/// Default $metadata cache directory name
+pub const METADATA_CACHE_DIR_NAME: &str = "metadata";
+
+/// Default file of the device code flow's refresh token
+pub const TOKEN_CACHE_FILE_NAME: &str = "user_token.json";
+
+/// Directory for persistent state (delta links, checkpoints, caches)
+pub fn state_dir() -> PathBuf {
+    resolve_state_dir(std::env::consts::OS, |key| std::env::var_os(key))
+}
+
+/// Default directory of cached $metadata models
+pub fn default_metadata_cache_dir() -> PathBuf {
+    state_dir().join(METADATA_CACHE_DIR_NAME)
+}
+
+/// Default refresh token cache of the device code flow
+pub fn default_token_cache_file() -> PathBuf {
+    state_dir().join(TOKEN_CACHE_FILE_NAME)
+}
+
+/// Default directory of files that fetched records spill to
+pub fn default_spill_dir() -> PathBuf {
+    std::env::temp_dir().join(APP_DIR_NAME).join("spill")
+}
+
+/// Directory for log files
+pub fn log_dir() -> PathBuf {
+    resolve_log_dir(std::env::consts::OS, |key| std::env::var_os(key))
+}
+
+/// Default log file path
+pub fn default_log_file() -> PathBuf {
+    log_dir().join(LOG_FILE_NAME)
+}
+
+/// Default delta state file path
+pub fn default_delta_state_file() -> PathBuf {
+    state_dir().join(DELTA_STATE_FILE_NAME)
+}
+
+fn resolve_state_dir<F>(os: &str, env: F) -> PathBuf
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let base = match os {
+        "windows" => non_empty(env("LOCALAPPDATA")).or_else(|| non_empty(env("APPDATA"))),
+        "macos" => home_dir(&env).map(|h| h.join("Library").join("Application Support")),
+        _ => non_empty(env("XDG_STATE_HOME"))
+            .or_else(|| home_dir(&env).map(|h| h.join(".local").join("state"))),
+    };
+
+    base.unwrap_or_else(std::env::temp_dir).join(APP_DIR_NAME)
+}
+
+fn resolve_log_dir<F>(os: &str, env: F) -> PathBuf
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    match os {
+        "macos" => match home_dir(&env) {
+            Some(home) => home.join("Library").join("Logs").join(APP_DIR_NAME),
+            None => resolve_state_dir(os, env),
+        },
+        "windows" => resolve_state_dir(os, env).join("logs"),
+        // XDG puts logs under the state directory
+        _ => resolve_state_dir(os, env),
+    }
+}
+
+fn home_dir<F>(env: &F) -> Option<PathBuf>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    non_empty(env("HOME")).or_else(|| non_empty(env("USERPROFILE")))
+}
+
+fn non_empty(value: Option<OsString>) -> Option<PathBuf> {
+    value.filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env_from(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<OsString> {
+        let map: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| map.get(key).map(OsString::from)
+    }
+
+    #[test]
+    fn test_linux_state_dir() {
+        let env = env_from(&[("HOME", "/home/me")]);
+        assert_eq!(
+            resolve_state_dir("linux", env),
+            PathBuf::from("/home/me/.local/state/d365-odata-mcp")
+        );
+
+        let env = env_from(&[("HOME", "/home/me"), ("XDG_STATE_HOME", "/xdg")]);
+        assert_eq!(resolve_state_dir("linux", &env), PathBuf::from("/xdg/d365-odata-mcp"));
+        assert_eq!(resolve_log_dir("linux", &env), PathBuf::from("/xdg/d365-odata-mcp"));
+    }
+
+    #[test]
+    fn test_macos_dirs() {
+        let env = env_from(&[("HOME", "/Users/me")]);
+        assert_eq!(
+            resolve_state_dir("macos", &env),
+            PathBuf::from("/Users/me/Library/Application Support/d365-odata-mcp")
+        );
+        assert_eq!(
+            resolve_log_dir("macos", &env),
+            PathBuf::from("/Users/me/Library/Logs/d365-odata-mcp")
+        );
+    }
+
+    #[test]
+    fn test_windows_dirs() {
+        let env = env_from(&[("LOCALAPPDATA", "C:\\Users\\me\\AppData\\Local")]);
+        let state = resolve_state_dir("windows", &env);
+        assert!(state.starts_with("C:\\Users\\me\\AppData\\Local"));
+        assert!(state.ends_with(APP_DIR_NAME));
+        assert!(resolve_log_dir("windows", &env).ends_with("logs"));
+    }
+
+    #[test]
+    fn test_fallback_to_temp_dir() {
+        let env = env_from(&[]);
+        assert_eq!(
+            resolve_state_dir("linux", env),
+            std::env::temp_dir().join(APP_DIR_NAME)
+        );
+    }
+}
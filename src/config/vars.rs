@@ -0,0 +1,157 @@
+//! Environment variable lookup
+//!
+//! Generic names such as `TENANT_ID` or `ENDPOINT` collide with other tools
+//! configured in the same MCP client, so every variable is also read as
+//! `D365_<NAME>`, which wins over the plain name. Each name is looked up in
+//! the environment, then in the `.env` file.
+
+use super::dotenv;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+
+#[cfg(test)]
+thread_local! {
+    /// Environment seen by lookups on this thread, set by [`with_vars`]
+    static TEST_VARS: std::cell::RefCell<Option<Vec<(String, String)>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Run `f` with `vars` as the whole environment of the current thread, with
+/// no `.env` file. Tests use this instead of `env::set_var`, which would leak
+/// into the tests running alongside them
+#[cfg(test)]
+pub(crate) fn with_vars<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+    let vars = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    TEST_VARS.with(|t| *t.borrow_mut() = Some(vars));
+    let result = f();
+    TEST_VARS.with(|t| *t.borrow_mut() = None);
+    result
+}
+
+/// `name` from the environment, or from [`with_vars`] in tests
+fn env_var(name: &str) -> Option<String> {
+    #[cfg(test)]
+    if let Some(value) = TEST_VARS.with(|t| {
+        t.borrow()
+            .as_ref()
+            .map(|vars| vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone()))
+    }) {
+        return value;
+    }
+    env::var(name).ok()
+}
+
+/// Whether [`with_vars`] replaced the environment and `.env` file of this thread
+fn environment_replaced() -> bool {
+    #[cfg(test)]
+    return TEST_VARS.with(|t| t.borrow().is_some());
+    #[cfg(not(test))]
+    false
+}
+
+/// Prefix of the preferred variable names
+pub const PREFIX: &str = "D365_";
+
+/// Where a variable was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Environment,
+    EnvFile,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Environment => write!(f, "environment"),
+            Source::EnvFile => write!(f, ".env file"),
+        }
+    }
+}
+
+/// A variable's value and the name and source it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub value: String,
+    pub name: String,
+    pub source: Source,
+}
+
+/// `D365_<NAME>`, else `<NAME>`
+pub fn var(name: &str) -> Option<String> {
+    lookup(name).map(|found| found.value)
+}
+
+/// `D365_<NAME>`, else `<NAME>`, each from the environment and then the `.env` file
+pub fn lookup(name: &str) -> Option<Found> {
+    names(name).into_iter().find_map(|candidate| {
+        if let Some(value) = env_var(&candidate) {
+            return Some(Found {
+                value,
+                name: candidate,
+                source: Source::Environment,
+            });
+        }
+        if environment_replaced() {
+            return None;
+        }
+        dotenv::get(&candidate).map(|value| Found {
+            value,
+            name: candidate,
+            source: Source::EnvFile,
+        })
+    })
+}
+
+/// Non-empty `D365_<NAME>` or `<NAME>` from the environment only, for the
+/// variables that locate the config and `.env` files
+pub fn var_os(name: &str) -> Option<OsString> {
+    names(name)
+        .into_iter()
+        .find_map(|candidate| {
+            let value = match environment_replaced() {
+                true => env_var(&candidate).map(OsString::from),
+                false => env::var_os(&candidate),
+            };
+            value.filter(|v| !v.is_empty())
+        })
+}
+
+/// Names `name` is read under, preferred first
+pub fn names(name: &str) -> Vec<String> {
+    if name.starts_with(PREFIX) {
+        vec![name.to_string()]
+    } else {
+        vec![format!("{}{}", PREFIX, name), name.to_string()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        assert_eq!(names("TENANT_ID"), ["D365_TENANT_ID", "TENANT_ID"]);
+        assert_eq!(names("D365_PROFILE"), ["D365_PROFILE"]);
+    }
+
+    #[test]
+    fn test_prefixed_name_wins() {
+        std::env::set_var("VARS_TEST_SETTING", "plain");
+        assert_eq!(
+            lookup("VARS_TEST_SETTING"),
+            Some(Found {
+                value: "plain".to_string(),
+                name: "VARS_TEST_SETTING".to_string(),
+                source: Source::Environment,
+            })
+        );
+        std::env::set_var("D365_VARS_TEST_SETTING", "preferred");
+        assert_eq!(var("VARS_TEST_SETTING").as_deref(), Some("preferred"));
+        std::env::set_var("D365_VARS_TEST_SETTING", "");
+        assert_eq!(var_os("VARS_TEST_SETTING"), Some(OsString::from("plain")));
+        std::env::remove_var("D365_VARS_TEST_SETTING");
+        std::env::remove_var("VARS_TEST_SETTING");
+        assert_eq!(var("VARS_TEST_SETTING"), None);
+    }
+}
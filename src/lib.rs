@@ -7,7 +7,8 @@ pub mod auth;
 pub mod config;
 pub mod mcp;
 pub mod odata;
+pub mod sync;
 
-pub use auth::AzureAdAuth;
+pub use auth::{AzureAdAuth, TokenProvider};
 pub use config::{Config, ProductType, RuntimeConfig};
 pub use odata::{ODataClient, ODataError, QueryOptions};
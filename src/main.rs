@@ -1,30 +1,218 @@
 //! D365 OData MCP Server
 //!
 //! Entry point for the MCP server binary.
-//! Implements MCP protocol over stdio using JSON-RPC 2.0.
-
-use d365_odata_mcp::config::Config;
-use d365_odata_mcp::mcp::{
-    CallToolParams, CallToolResult, D365McpServer, InitializeResult, JsonRpcRequest,
-    JsonRpcResponse, ListToolsResult, ServerCapabilities, ServerInfo, ToolsCapability,
-};
+//! Implements MCP protocol over stdio (or a local socket) using JSON-RPC 2.0.
+
+use d365_odata_mcp::auth::{AuthConfig, AuthType, ClientCertificate, OAuth2Auth};
+use d365_odata_mcp::config::{paths, vars, Config, HttpSettings, RuntimeConfig, CONFIG_FILE_VAR, DEFAULT_CONFIG_PATH, PROFILE_VAR};
+use d365_odata_mcp::mcp::framing::DEFAULT_MAX_MESSAGE_BYTES;
+use d365_odata_mcp::mcp::http::{self, ApiKeys, HttpOptions};
+use d365_odata_mcp::mcp::logging::{LogLevel, McpLogLayer};
+use d365_odata_mcp::mcp::transport::{self, ListenAddr};
+use d365_odata_mcp::mcp::{D365McpServer, McpHandler};
+use d365_odata_mcp::odata::budget::BudgetLimits;
 use d365_odata_mcp::odata::ODataClient;
 use std::env;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
-fn log_to_file(msg: &str) {
-    if let Ok(mut file) = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("/tmp/d365-mcp.log")
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// How MCP clients connect
+enum Transport {
+    Stdio,
+    Listen(ListenAddr),
+    Http { host: String, port: u16 },
+}
+
+/// Environment variables that can also be given as flags, e.g. `--auth-type`
+/// for `AUTH_TYPE`; a flag wins over the variable. Secrets are left out, since
+/// command lines show up in process lists and the log
+const FLAG_VARIABLES: [&str; 16] = [
+    "ENDPOINT",
+    "PRODUCT",
+    "AUTH_TYPE",
+    "TENANT_ID",
+    "CLIENT_ID",
+    "CLIENT_SECRET_FILE",
+    "SECRET_PROVIDER",
+    "CERT_PATH",
+    "TOKEN_CACHE_FILE",
+    "AUTHORITY_HOST",
+    "TOKEN_URL",
+    "RESOURCE",
+    "IMPERSONATE_USER",
+    "LOG_FILE",
+    "METADATA_FILE",
+    "ENV_FILE",
+];
+
+/// Environment variable set by `flag`, if it mirrors one
+fn flag_variable(flag: &str) -> Option<&'static str> {
+    let name = flag.strip_prefix("--")?;
+    FLAG_VARIABLES
+        .iter()
+        .copied()
+        .find(|var| var.to_ascii_lowercase().replace('_', "-") == name)
+}
+
+/// Apply `--config`, `--profile` and the flags mirroring environment variables by setting
+/// the preferred `D365_` names of those variables, so flags take precedence over the
+/// environment and both over the config file. Runs before any other thread starts.
+/// Returns the variables set.
+fn apply_flag_overrides(args: &[String]) -> Vec<String> {
+    let mut set = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        let variable = match flag {
+            "--config" => Some(CONFIG_FILE_VAR),
+            "--profile" => Some(PROFILE_VAR),
+            _ => flag_variable(flag),
+        };
+        if let Some(variable) = variable {
+            i += 1;
+            let name = vars::names(variable).swap_remove(0);
+            env::set_var(&name, flag_value(args, i, flag));
+            set.push(name);
+        }
+        i += 1;
+    }
+    set
+}
+
+/// Variables whose source is logged at startup; secrets only by name
+const LOGGED_VARIABLES: [&str; 8] = [
+    CONFIG_FILE_VAR,
+    PROFILE_VAR,
+    "CLIENT_SECRET",
+    "CERT_PASSWORD",
+    "MCP_HTTP_TOKEN",
+    "MCP_HTTP_AUTH_HEADER",
+    "INSECURE_SSL",
+    "DELTA_STORAGE_PATH",
+];
+
+/// Log where each set variable came from, e.g. `ENDPOINT=D365_ENDPOINT (command line)`,
+/// so a value picked up from another tool's `TENANT_ID` is easy to spot
+fn log_variable_sources(from_flags: &[String]) {
+    let sources: Vec<String> = FLAG_VARIABLES
+        .iter()
+        .chain(LOGGED_VARIABLES.iter())
+        .filter_map(|name| {
+            let found = vars::lookup(name)?;
+            let source = if from_flags.contains(&found.name) {
+                "command line".to_string()
+            } else {
+                found.source.to_string()
+            };
+            Some(format!("{}={} ({})", name, found.name, source))
+        })
+        .collect();
+    if sources.is_empty() {
+        log_to_file("Variables: none set; settings come from the config file");
+    } else {
+        log_to_file(&format!("Variables: {}", sources.join(", ")));
+    }
+}
+
+/// Value following a command-line flag
+fn flag_value<'a>(args: &'a [String], i: usize, flag: &str) -> &'a str {
+    match args.get(i) {
+        Some(value) => value,
+        None => {
+            eprintln!("{} requires a value", flag);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Fix the log file before the first log line: LOG_FILE env var, config file,
+/// then platform default. A config that failed to load still honours LOG_FILE,
+/// so the load error itself reaches the intended file
+fn init_log_file(config: Option<&Config>) {
+    let path = match config {
+        Some(config) => config.log_file_path(),
+        None => vars::var("LOG_FILE")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(paths::default_log_file),
+    };
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let _ = LOG_FILE.set(path);
+}
+
+fn log_file_path() -> &'static PathBuf {
+    LOG_FILE.get_or_init(paths::default_log_file)
+}
+
+/// Open the log file for appending; created readable by the owner only,
+/// since it records request details
+fn open_log_file() -> std::io::Result<std::fs::File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
     {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(log_file_path())?;
+        // Files created by older versions were world-readable
+        let _ = file.set_permissions(std::fs::Permissions::from_mode(0o600));
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(log_file_path())
+}
+
+fn log_to_file(msg: &str) {
+    if let Ok(mut file) = open_log_file() {
         let _ = writeln!(file, "[{}] {}", chrono_lite(), msg);
     }
 }
 
+/// Route library `tracing` output into the same log file, and to MCP
+/// clients as `notifications/message`
+fn init_tracing(config: &Config) {
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+    use tracing_subscriber::Layer;
+
+    let level = config
+        .observability
+        .as_ref()
+        .and_then(|o| o.log_level.clone())
+        .unwrap_or_else(|| "info".to_string());
+
+    let file_layer = open_log_file().ok().map(|file| {
+        let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(level));
+        tracing_subscriber::fmt::layer()
+            .with_writer(Mutex::new(file))
+            .with_ansi(false)
+            .with_filter(filter)
+    });
+    let _ = tracing_subscriber::registry()
+        .with(file_layer)
+        .with(McpLogLayer)
+        .try_init();
+}
+
+/// Level of `notifications/message` before a client calls `logging/setLevel`:
+/// `observability.client_log_level`, else `observability.log_level`; "off" sends none
+fn client_log_level(config: &Config) -> Option<LogLevel> {
+    let level = config
+        .observability
+        .as_ref()
+        .and_then(|o| o.client_log_level.clone().or_else(|| o.log_level.clone()))
+        .unwrap_or_else(|| "info".to_string());
+    LogLevel::parse(&level)
+}
+
 fn chrono_lite() -> String {
     use std::time::SystemTime;
     let duration = SystemTime::now()
@@ -34,14 +222,26 @@ fn chrono_lite() -> String {
 }
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    // Before the first log line, which may go to --log-file
+    let from_flags = apply_flag_overrides(&args);
+    // Loaded once; every startup step below reads this copy
+    let config = Config::load_default();
+    init_log_file(config.as_ref().ok());
     log_to_file("=== MCP Server Starting ===");
-    log_to_file(&format!("Args: {:?}", env::args().collect::<Vec<_>>()));
+    log_to_file(&format!("Args: {:?}", args));
+    log_variable_sources(&from_flags);
     
     // Handle --version and --help flags before starting async runtime
-    let args: Vec<String> = env::args().collect();
-    
-    if args.len() > 1 {
-        match args[1].as_str() {
+    let mut listen: Option<ListenAddr> = None;
+    let mut transport_name: Option<String> = None;
+    let mut host = "127.0.0.1".to_string();
+    let mut port = http::DEFAULT_PORT;
+    let mut login = false;
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
             "--version" | "-V" | "-v" => {
                 println!("d365-odata-mcp {}", env!("CARGO_PKG_VERSION"));
                 log_to_file("Exiting: --version flag");
@@ -50,39 +250,159 @@ fn main() {
             "--help" | "-h" => {
                 println!("d365-odata-mcp {}", env!("CARGO_PKG_VERSION"));
                 println!("MCP Server for Microsoft Dynamics 365 OData API\n");
-                println!("Usage: d365-odata-mcp\n");
+                println!("Usage: d365-odata-mcp [--config <path>] [--profile <name>] [--transport stdio|socket|http] [--listen <addr>] [--port <n>] [--login]\n");
+                println!("Options:");
+                println!("  --config <path>  Config file (default: {} in the working directory)", DEFAULT_CONFIG_PATH);
+                println!("  --profile <name> Use the [profiles.<name>] section of the config file");
+                println!("  --transport <t>  'stdio' (default), 'socket' (with --listen) or 'http'");
+                println!("  --listen <addr>  Serve JSON-RPC on unix:<path>, pipe:<name> (Windows) or tcp:<host>:<port>");
+                println!("  --socket <path>  Same as --listen unix:<path> (or a Windows named pipe,");
+                println!("                   e.g. \\\\.\\pipe\\d365-odata-mcp)");
+                println!("  --port <n>       HTTP port (default: {}); the endpoint is http://<host>:<n>/mcp", http::DEFAULT_PORT);
+                println!("  --host <addr>    HTTP bind address (default: 127.0.0.1)");
+                println!("  --login          Sign in (AUTH_TYPE=device_code), cache the refresh token and exit\n");
                 println!("Environment variables:");
-                println!("  TENANT_ID      Azure AD tenant ID (required)");
-                println!("  CLIENT_ID      Azure AD client/app ID (required)");
-                println!("  CLIENT_SECRET  Azure AD client secret (required)");
+                println!("  Except for secrets, each can also be given as a flag, e.g. --endpoint <url>");
+                println!("  for ENDPOINT or --auth-type <t> for AUTH_TYPE. Flags take precedence over");
+                println!("  environment variables, and both over the config file. Each variable may also be");
+                println!("  set as D365_<NAME> (e.g. D365_TENANT_ID), which wins over the plain name.");
+                println!("  CONFIG_FILE    Config file, as --config");
+                println!("  D365_PROFILE   Config profile, as --profile");
+                println!("  ENV_FILE       .env file whose values fill in unset variables (default: .env next to");
+                println!("                 the config file, else in the working directory)");
+                println!("  TENANT_ID      Azure AD tenant ID (not needed for managed_identity or azure_cli)");
+                println!("  CLIENT_ID      Azure AD client/app ID (not needed for managed_identity or azure_cli)");
+                println!("  CLIENT_SECRET  Azure AD client secret (required for AUTH_TYPE=azure or adfs)");
+                println!("  CLIENT_SECRET_FILE  File holding the client secret, e.g. a mounted Docker/Kubernetes secret");
+                println!("  SECRET_PROVIDER  'auto' (default), 'env', 'file' or 'keyring'");
+                println!("  AUTH_TYPE      'azure' (default), 'certificate', 'managed_identity', 'azure_cli',");
+                println!("                 'device_code' or 'adfs'");
+                println!("  CERT_PATH      PEM or PFX client certificate (AUTH_TYPE=certificate)");
+                println!("  CERT_PASSWORD  Password of the PFX file or encrypted key (optional)");
+                println!("  TOKEN_CACHE_FILE  Refresh token cache for device_code (default: {})", paths::default_token_cache_file().display());
                 println!("  ENDPOINT       D365 OData endpoint URL (required)");
                 println!("  PRODUCT        'dataverse' or 'finops' (required)");
+                println!("  AUTHORITY_HOST Azure AD authority: public, usgov, dod, china or a URL (default: from ENDPOINT)");
+                println!("  IMPERSONATE_USER  Dataverse user tool calls run as (Azure AD object ID or systemuser:<id>)");
+                println!("  LOG_FILE       Log file path (default: {})", paths::default_log_file().display());
+                println!("  METADATA_FILE  EDMX file to use instead of fetching $metadata (optional)");
+                println!("  MCP_HTTP_TOKEN API key(s) HTTP clients must send, comma-separated (required with a");
+                println!("                 non-loopback --host)");
+                println!("  MCP_HTTP_AUTH_HEADER  Header carrying the key (default: Authorization, as 'Bearer <key>')");
                 log_to_file("Exiting: --help flag");
                 return;
             }
-            _ => {
-                log_to_file(&format!("Unknown arg: {}", args[1]));
+            "--login" => login = true,
+            flag if matches!(flag, "--config" | "--profile") || flag_variable(flag).is_some() => {
+                // Applied by apply_flag_overrides
+                i += 1;
+            }
+            "--socket" => {
+                i += 1;
+                listen = Some(ListenAddr::Local(flag_value(&args, i, "--socket").to_string()));
+            }
+            "--listen" => {
+                i += 1;
+                listen = match ListenAddr::parse(flag_value(&args, i, "--listen")) {
+                    Ok(addr) => Some(addr),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(2);
+                    }
+                };
+            }
+            "--transport" => {
+                i += 1;
+                transport_name = Some(flag_value(&args, i, "--transport").to_lowercase());
+            }
+            "--host" => {
+                i += 1;
+                host = flag_value(&args, i, "--host").to_string();
+            }
+            "--port" => {
+                i += 1;
+                port = match flag_value(&args, i, "--port").parse() {
+                    Ok(port) => port,
+                    Err(_) => {
+                        eprintln!("--port requires a port number");
+                        std::process::exit(2);
+                    }
+                };
+            }
+            other => {
+                log_to_file(&format!("Unknown arg: {}", other));
             }
         }
+        i += 1;
     }
 
+    let transport = match (transport_name.as_deref(), listen) {
+        (None | Some("stdio"), None) => Transport::Stdio,
+        (None | Some("socket"), Some(addr)) => Transport::Listen(addr),
+        (Some("http"), None) => Transport::Http { host, port },
+        (Some("socket"), None) => {
+            eprintln!("--transport socket requires --listen <addr>");
+            std::process::exit(2);
+        }
+        (Some(other), _) => {
+            eprintln!("Unknown or conflicting --transport '{}': use stdio, socket (with --listen) or http", other);
+            std::process::exit(2);
+        }
+    };
+
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            log_to_file(&format!("Config invalid: {}", e));
+            eprintln!("Config invalid: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    init_tracing(&config);
     log_to_file("Starting tokio runtime...");
     
-    // Run async main
-    tokio::runtime::Builder::new_multi_thread()
+    let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
-        .unwrap()
-        .block_on(async_main());
+        .unwrap();
+
+    if login {
+        if let Err(e) = runtime.block_on(sign_in(&config)) {
+            eprintln!("Sign-in failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Run async main
+    runtime.block_on(async_main(transport, &config));
+    // A pending stdin read would otherwise keep the process alive
+    runtime.shutdown_timeout(Duration::from_millis(100));
 }
 
-async fn async_main() {
+/// `--login`: acquire one token so the device code prompt is answered in a
+/// terminal and the refresh token is cached for the MCP client's launches
+async fn sign_in(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime_config = config.to_runtime()?;
+    let auth = create_auth(&runtime_config)?;
+    auth.get_token(&OAuth2Auth::resource_from_endpoint(&runtime_config.endpoint)).await?;
+    eprintln!("Signed in to {}", runtime_config.endpoint);
+    if runtime_config.auth_type.parse() == Ok(AuthType::DeviceCode) {
+        eprintln!("Refresh token cached in {}", runtime_config.token_cache_file.display());
+    }
+    Ok(())
+}
+
+async fn async_main(transport: Transport, config: &Config) {
     log_to_file("async_main started");
 
-    // Try to load configuration - but don't fail startup if env vars missing
-    let server = match create_server() {
+    // Build the server - but don't fail startup if env vars missing
+    let server = match create_server(config) {
         Ok(s) => {
             log_to_file("Server configured successfully");
+            s.start_background_sync();
+            s.preload_metadata();
             Some(s)
         },
         Err(e) => {
@@ -91,193 +411,192 @@ async fn async_main() {
         }
     };
 
-    log_to_file("Starting stdio loop...");
-
-    // Run async stdio message loop
-    if let Err(e) = run_stdio_loop(server).await {
-        log_to_file(&format!("Server error: {}", e));
+    let strict_jsonrpc = config.global.strict_jsonrpc.unwrap_or(false);
+    let max_message_bytes = config.global.max_message_bytes.unwrap_or(DEFAULT_MAX_MESSAGE_BYTES);
+    let mut handler = McpHandler::new(server)
+        .with_log_level(client_log_level(config))
+        .with_strict_jsonrpc(strict_jsonrpc)
+        .with_max_message_bytes(max_message_bytes);
+    let mut http_settings = HttpSettings::default();
+    if matches!(transport, Transport::Http { .. }) {
+        match config.http_settings() {
+            Ok(settings) => http_settings = settings,
+            Err(e) => {
+                log_to_file(&format!("HTTP settings invalid: {}", e));
+                eprintln!("HTTP settings invalid: {}", e);
+                std::process::exit(2);
+            }
+        }
+        if let Some(idle) = http_settings.session_idle {
+            handler = handler.with_session_idle_timeout(idle);
+        }
     }
-}
-
-fn create_server() -> Result<D365McpServer, Box<dyn std::error::Error>> {
-    use d365_odata_mcp::auth::{AuthConfig, AuthType, OAuth2Auth};
-    
-    let config = Config::load_default()?;
-    let runtime_config = config.to_runtime()?;
-
-    // Parse auth type
-    let auth_type: AuthType = runtime_config.auth_type.parse()
-        .unwrap_or(AuthType::AzureAd);
-
-    log_to_file(&format!("Auth type: {:?}", auth_type));
-
-    let auth_config = AuthConfig {
-        auth_type,
-        tenant_id: runtime_config.tenant_id.clone(),
-        client_id: runtime_config.client_id.clone(),
-        client_secret: runtime_config.client_secret.clone(),
-        token_url: runtime_config.token_url.clone(),
-        resource: runtime_config.resource.clone(),
-        insecure_ssl: runtime_config.insecure_ssl,
+    let handler = Arc::new(handler);
+    let grace = config.shutdown_grace();
+
+    let serve = serve(handler.clone(), transport, http_settings);
+    tokio::pin!(serve);
+    let result = tokio::select! {
+        result = &mut serve => Some(result),
+        signal = shutdown_signal() => {
+            log_to_file(&format!("{} received; finishing {} running tool calls", signal, handler.calls_in_flight()));
+            None
+        }
+    };
+    // Let running tool calls and sync runs finish so no state file is left half written
+    let drain = async {
+        let (_, result) = tokio::join!(handler.shutdown(), async {
+            match result {
+                Some(result) => result,
+                None => (&mut serve).await,
+            }
+        });
+        result
+    };
+    let result = match tokio::time::timeout(grace, drain).await {
+        Ok(result) => result,
+        Err(_) => {
+            log_to_file(&format!(
+                "Shutdown grace period of {}s over; exiting with {} tool calls running",
+                grace.as_secs(),
+                handler.calls_in_flight()
+            ));
+            Ok(())
+        }
     };
 
-    let auth = Arc::new(OAuth2Auth::new(auth_config));
-
-    let client = Arc::new(ODataClient::new(
-        auth,
-        runtime_config.endpoint.clone(),
-        runtime_config.product.clone(),
-        runtime_config.max_retries,
-        runtime_config.retry_delay_ms,
-        runtime_config.insecure_ssl,
-    ));
-
-    Ok(D365McpServer::new(client, Arc::new(runtime_config)))
+    if let Err(e) = result {
+        log_to_file(&format!("Server error: {}", e));
+    }
+    log_to_file("Shut down");
 }
 
-async fn run_stdio_loop(server: Option<D365McpServer>) -> Result<(), std::io::Error> {
-    let stdin = tokio::io::stdin();
-    let mut stdout = tokio::io::stdout();
-    let mut reader = BufReader::new(stdin);
-    let mut line = String::new();
-
-    log_to_file("Waiting for input...");
-
-    loop {
-        line.clear();
-        
-        log_to_file("Reading line...");
-        let bytes_read = reader.read_line(&mut line).await?;
-        
-        log_to_file(&format!("Read {} bytes: {:?}", bytes_read, line.trim()));
-        
-        if bytes_read == 0 {
-            log_to_file("EOF received, shutting down");
-            break;
+/// Serve `transport` until its input ends or shutdown begins
+async fn serve(handler: Arc<McpHandler>, transport: Transport, http_settings: HttpSettings) -> std::io::Result<()> {
+    match transport {
+        Transport::Listen(addr) => {
+            log_to_file(&format!("Starting socket listener on {:?}...", addr));
+            transport::serve_listen(handler, &addr).await
         }
-
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            log_to_file("Empty line, skipping");
-            continue;
+        Transport::Http { host, port } => match format!("{}:{}", host, port).parse() {
+            Ok(addr) => {
+                log_to_file(&format!("Starting HTTP listener on http://{}/mcp...", addr));
+                let auth = ApiKeys {
+                    keys: http_settings.api_keys,
+                    header: http_settings.auth_header,
+                };
+                http::serve_http(handler, HttpOptions { addr, auth }).await
+            }
+            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("--host {}: {}", host, e))),
+        },
+        Transport::Stdio => {
+            log_to_file("Starting stdio loop...");
+            transport::serve_stdio(handler).await
         }
+    }
+}
 
-        let request: JsonRpcRequest = match serde_json::from_str::<JsonRpcRequest>(trimmed) {
-            Ok(req) => {
-                log_to_file(&format!("Parsed request: method={}, has_id={}", req.method, req.id.is_some()));
-                req
+/// Completes on SIGTERM or SIGINT (Ctrl+C), naming the signal
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = terminate.recv() => "SIGTERM",
+                _ = tokio::signal::ctrl_c() => "SIGINT",
             },
             Err(e) => {
-                log_to_file(&format!("Parse error: {}", e));
-                let error_response = JsonRpcResponse::error(None, -32700, &format!("Parse error: {}", e));
-                let _ = send_response(&mut stdout, &error_response).await;
-                continue;
+                log_to_file(&format!("Cannot listen for SIGTERM: {}", e));
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
             }
-        };
-
-        // Notifications don't have an id and should NOT receive a response
-        let is_notification = request.id.is_none();
-        
-        if is_notification {
-            log_to_file(&format!("Notification received: {}, no response needed", request.method));
-            // Still process the notification but don't send response
-            let _ = handle_request(&server, request).await;
-            continue;
         }
-
-        let response = handle_request(&server, request).await;
-        log_to_file("Sending response...");
-        let _ = send_response(&mut stdout, &response).await;
-        log_to_file("Response sent");
     }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
+}
 
-    Ok(())
+fn create_server(config: &Config) -> Result<D365McpServer, Box<dyn std::error::Error>> {
+    let mut others = Vec::new();
+    for name in config.extra_connections() {
+        let server = connection_server(&config.connection(&name)?)
+            .map_err(|e| format!("Connection '{}': {}", name, e))?;
+        log_to_file(&format!("Connection '{}' configured", name));
+        others.push(server);
+    }
+    Ok(connection_server(config)?.with_connections(others))
 }
 
-async fn handle_request(server: &Option<D365McpServer>, request: JsonRpcRequest) -> JsonRpcResponse {
-    let id = request.id.clone();
-
-    match request.method.as_str() {
-        "initialize" => {
-            log_to_file("Handling: initialize");
-            let result = InitializeResult {
-                protocol_version: "2024-11-05".to_string(),
-                capabilities: ServerCapabilities {
-                    tools: Some(ToolsCapability {
-                        list_changed: Some(false),
-                    }),
-                },
-                server_info: ServerInfo {
-                    name: "d365-odata-mcp".to_string(),
-                    version: env!("CARGO_PKG_VERSION").to_string(),
-                },
-            };
-            JsonRpcResponse::success(id, serde_json::to_value(result).unwrap())
-        }
+/// Server of the one D365 environment `config` describes
+fn connection_server(config: &Config) -> Result<D365McpServer, Box<dyn std::error::Error>> {
+    let runtime_config = config.to_runtime()?;
+    let auth = Arc::new(create_auth(&runtime_config)?);
+
+    let sp = &runtime_config.service_protection;
+    let budget_defaults = BudgetLimits::default();
+    let budget_limits = BudgetLimits {
+        max_requests: sp.max_requests.unwrap_or(budget_defaults.max_requests),
+        max_execution: sp
+            .max_execution_seconds
+            .map(Duration::from_secs)
+            .unwrap_or(budget_defaults.max_execution),
+        soft_limit_percent: sp.soft_limit_percent.unwrap_or(budget_defaults.soft_limit_percent),
+        enforce: sp.enforce.unwrap_or(budget_defaults.enforce),
+    };
 
-        "initialized" | "notifications/initialized" => {
-            log_to_file("Handling: initialized");
-            JsonRpcResponse::success(id, serde_json::json!({}))
-        }
+    let client = Arc::new(
+        ODataClient::new(
+            auth,
+            runtime_config.endpoint.clone(),
+            runtime_config.product.clone(),
+            runtime_config.max_retries,
+            runtime_config.retry_delay_ms,
+            runtime_config.insecure_ssl,
+        )
+        .with_budget_limits(budget_limits)
+        .with_max_concurrent_requests(runtime_config.concurrency)
+        .with_retry_deadline(
+            (runtime_config.retry_deadline_seconds > 0)
+                .then(|| Duration::from_secs(runtime_config.retry_deadline_seconds)),
+        )
+        .with_ieee754_compatible(runtime_config.ieee754_compatible)
+        .with_page_size(runtime_config.page_size)
+        .with_connection_settings(&runtime_config.connection)
+        .with_spill_limits(runtime_config.spill.clone()),
+    );
 
-        "tools/list" => {
-            log_to_file("Handling: tools/list");
-            let tools = match server {
-                Some(s) => s.get_tools(),
-                None => D365McpServer::get_tools_static(),
-            };
-            let result = ListToolsResult { tools };
-            JsonRpcResponse::success(id, serde_json::to_value(result).unwrap())
-        }
+    Ok(D365McpServer::new(client, Arc::new(runtime_config)))
+}
 
-        "tools/call" => {
-            log_to_file("Handling: tools/call");
-            let server = match server {
-                Some(s) => s,
-                None => {
-                    let result = CallToolResult::error(
-                        "Server not configured. Missing environment variables: TENANT_ID, CLIENT_ID, CLIENT_SECRET, ENDPOINT".to_string()
-                    );
-                    return JsonRpcResponse::success(id, serde_json::to_value(result).unwrap());
-                }
-            };
+fn create_auth(runtime_config: &RuntimeConfig) -> Result<OAuth2Auth, Box<dyn std::error::Error>> {
+    // Parse auth type; a typo must not fall back to client-secret Azure AD
+    let auth_type: AuthType = runtime_config
+        .auth_type
+        .parse()
+        .map_err(|e: String| format!("Invalid AUTH_TYPE: {}", e))?;
 
-            let params: CallToolParams = match request.params {
-                Some(p) => match serde_json::from_value(p) {
-                    Ok(params) => params,
-                    Err(e) => {
-                        return JsonRpcResponse::error(id, -32602, &format!("Invalid params: {}", e));
-                    }
-                },
-                None => {
-                    return JsonRpcResponse::error(id, -32602, "Missing params");
-                }
-            };
-
-            let args = params.arguments.unwrap_or_default();
-            let result: CallToolResult = server.call_tool(&params.name, &args).await;
-            JsonRpcResponse::success(id, serde_json::to_value(result).unwrap())
-        }
+    log_to_file(&format!("Auth type: {:?}", auth_type));
 
-        "ping" => {
-            log_to_file("Handling: ping");
-            JsonRpcResponse::success(id, serde_json::json!({}))
-        }
+    let auth_config = AuthConfig {
+        auth_type,
+        tenant_id: runtime_config.tenant_id.clone(),
+        client_id: runtime_config.client_id.clone(),
+        client_secret: runtime_config.client_secret.clone(),
+        token_url: runtime_config.token_url.clone(),
+        resource: runtime_config.resource.clone(),
+        authority_host: Some(runtime_config.authority_host.clone()),
+        insecure_ssl: runtime_config.insecure_ssl,
+    };
 
-        method => {
-            log_to_file(&format!("Unknown method: {}", method));
-            JsonRpcResponse::success(id, serde_json::json!({}))
-        }
+    let mut auth = OAuth2Auth::new(auth_config);
+    if let Some(path) = &runtime_config.cert_path {
+        let certificate = ClientCertificate::load(path, runtime_config.cert_password.as_deref())?;
+        log_to_file(&format!("Client certificate: {} (thumbprint {})", path.display(), certificate.thumbprint()));
+        auth = auth.with_certificate(certificate);
     }
-}
-
-async fn send_response(stdout: &mut tokio::io::Stdout, response: &JsonRpcResponse) -> std::io::Result<()> {
-    let json = serde_json::to_string(response).map_err(|e| {
-        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
-    })?;
-    log_to_file(&format!("Response: {}", json));
-    stdout.write_all(json.as_bytes()).await?;
-    stdout.write_all(b"\n").await?;
-    stdout.flush().await?;
-    Ok(())
+    Ok(auth.with_token_cache_file(runtime_config.token_cache_file.clone()))
 }
@@ -0,0 +1,119 @@
+//! MCP argument completion
+//!
+//! Suggests entity set and field names from `$metadata` while a user types a
+//! prompt argument or a resource template variable. Arguments are matched by
+//! name, so every prompt taking `entity` or `select` completes the same way.
+
+use crate::mcp::protocol::Completion;
+use crate::odata::metadata::EdmModel;
+use std::collections::HashMap;
+
+/// Values returned per request, the maximum MCP allows
+pub const MAX_VALUES: usize = 100;
+
+/// Arguments naming an entity set
+const ENTITY_ARGS: &[&str] = &["entity", "entity_set"];
+
+/// Arguments holding a comma-separated list of fields of the `entity` argument
+const FIELD_ARGS: &[&str] = &["select", "fields", "orderby"];
+
+/// Completion of `argument` being typed as `value`; `context` holds the
+/// arguments already filled in
+pub fn complete(model: &EdmModel, argument: &str, value: &str, context: &HashMap<String, String>) -> Completion {
+    if ENTITY_ARGS.contains(&argument) {
+        return matching(model.entity_sets.iter().map(|s| s.name.as_str()), value, "");
+    }
+    if FIELD_ARGS.contains(&argument) {
+        let entity_type = ENTITY_ARGS
+            .iter()
+            .find_map(|a| context.get(*a))
+            .and_then(|entity| model.find_entity_type(entity.trim()));
+        if let Some(entity_type) = entity_type {
+            // Complete the last field of the list, keeping the ones before it
+            let (done, current) = match value.rfind(',') {
+                Some(i) => value.split_at(i + 1),
+                None => ("", value),
+            };
+            let prefix = format!("{}{}", done, &current[..current.len() - current.trim_start().len()]);
+            let chosen: Vec<&str> = done.split(',').map(str::trim).collect();
+            let fields = model
+                .properties_of(entity_type)
+                .into_iter()
+                .map(|p| p.name.as_str())
+                .filter(|name| !chosen.contains(name));
+            return matching(fields, current.trim_start(), &prefix);
+        }
+    }
+    Completion::default()
+}
+
+/// Candidates starting with `typed` (ignoring case), then those containing
+/// it, each prefixed with `prefix`
+fn matching<'a>(candidates: impl Iterator<Item = &'a str>, typed: &str, prefix: &str) -> Completion {
+    let typed = typed.to_lowercase();
+    let mut scored: Vec<(bool, &str)> = candidates
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            if lower.starts_with(&typed) {
+                Some((false, candidate))
+            } else {
+                lower.contains(&typed).then_some((true, candidate))
+            }
+        })
+        .collect();
+    scored.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.len().cmp(&b.1.len())).then_with(|| a.1.cmp(b.1)));
+    let total = scored.len();
+    Completion {
+        values: scored
+            .into_iter()
+            .take(MAX_VALUES)
+            .map(|(_, name)| format!("{}{}", prefix, name))
+            .collect(),
+        total: Some(total),
+        has_more: Some(total > MAX_VALUES),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> EdmModel {
+        EdmModel::parse(
+            r#"<edmx:Edmx><edmx:DataServices><Schema Namespace="NS">
+                <EntityType Name="Customer"><Key><PropertyRef Name="CustomerAccount"/></Key>
+                    <Property Name="CustomerAccount" Type="Edm.String"/>
+                    <Property Name="CustomerGroupId" Type="Edm.String"/>
+                    <Property Name="Name" Type="Edm.String"/></EntityType>
+                <EntityContainer Name="C">
+                    <EntitySet Name="CustomersV3" EntityType="NS.Customer"/>
+                    <EntitySet Name="CustCustomersV3" EntityType="NS.Customer"/>
+                    <EntitySet Name="VendorsV2" EntityType="NS.Customer"/>
+                </EntityContainer>
+            </Schema></edmx:DataServices></edmx:Edmx>"#,
+        )
+    }
+
+    #[test]
+    fn test_complete_entity() {
+        let completion = complete(&model(), "entity", "cust", &HashMap::new());
+        assert_eq!(completion.values, ["CustomersV3", "CustCustomersV3"]);
+        assert_eq!(completion.has_more, Some(false));
+
+        let completion = complete(&model(), "entity", "omers", &HashMap::new());
+        assert_eq!(completion.values, ["CustomersV3", "CustCustomersV3"]);
+    }
+
+    #[test]
+    fn test_complete_fields() {
+        let context = HashMap::from([("entity".to_string(), "CustomersV3".to_string())]);
+        let completion = complete(&model(), "select", "Name, cust", &context);
+        assert_eq!(completion.values, ["Name, CustomerAccount", "Name, CustomerGroupId"]);
+
+        let completion = complete(&model(), "select", "CustomerAccount,", &context);
+        assert_eq!(completion.values, ["CustomerAccount,Name", "CustomerAccount,CustomerGroupId"]);
+
+        assert!(complete(&model(), "select", "Na", &HashMap::new()).values.is_empty());
+        assert!(complete(&model(), "description", "Na", &context).values.is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+//! Per-call context for tool execution
+//!
+//! Gives tools a way to talk back to the client while they run, e.g.
+//! `notifications/progress` for long-polling or long-paging operations.
+
+use crate::mcp::protocol::JsonRpcNotification;
+use serde_json::Value;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Channel for server-initiated messages on the current connection
+pub type Notifier = UnboundedSender<JsonRpcNotification>;
+
+/// Sends `notifications/progress` for one request, if the client asked for it
+#[derive(Debug, Clone, Default)]
+pub struct ProgressReporter {
+    notifier: Option<Notifier>,
+    token: Option<Value>,
+}
+
+impl ProgressReporter {
+    /// Create a reporter; it is a no-op unless both notifier and token are set
+    pub fn new(notifier: Option<Notifier>, token: Option<Value>) -> Self {
+        Self { notifier, token }
+    }
+
+    /// Whether progress notifications will actually be sent
+    pub fn is_enabled(&self) -> bool {
+        self.notifier.is_some() && self.token.is_some()
+    }
+
+    /// Report progress (monotonically increasing) with an optional total and message
+    pub fn report(&self, progress: f64, total: Option<f64>, message: Option<String>) {
+        let (Some(notifier), Some(token)) = (&self.notifier, &self.token) else {
+            return;
+        };
+
+        let mut params = serde_json::json!({
+            "progressToken": token,
+            "progress": progress,
+        });
+        if let Some(total) = total {
+            params["total"] = serde_json::json!(total);
+        }
+        if let Some(message) = message {
+            params["message"] = Value::String(message);
+        }
+
+        let _ = notifier.send(JsonRpcNotification::new("notifications/progress", params));
+    }
+}
+
+/// Context passed to a tool call
+#[derive(Debug, Clone, Default)]
+pub struct ToolContext {
+    pub progress: ProgressReporter,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_requires_token() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        ProgressReporter::new(Some(tx.clone()), None).report(1.0, None, None);
+        assert!(rx.try_recv().is_err());
+
+        let reporter = ProgressReporter::new(Some(tx), Some(Value::from("tok")));
+        reporter.report(2.0, Some(10.0), Some("working".to_string()));
+        let notification = rx.try_recv().unwrap();
+        assert_eq!(notification.method, "notifications/progress");
+        let params = notification.params.unwrap();
+        assert_eq!(params["progressToken"], "tok");
+        assert_eq!(params["progress"], 2.0);
+        assert_eq!(params["total"], 10.0);
+        assert_eq!(params["message"], "working");
+    }
+}
@@ -0,0 +1,327 @@
+//! Per-entity tools
+//!
+//! Every `[[entities]]` entry gets a `query_<entity>` and a `get_<entity>`
+//! tool (`query_accounts`, `get_account`). Their arguments map onto
+//! `query_entity` and `get_record`, but the input schemas are built from the
+//! $metadata model: `select` lists the entity's fields, key fields become
+//! typed arguments, and enum fields carry their members. Until the model is
+//! loaded the schemas are generic.
+
+use crate::config::{EntityConfig, ProductType};
+use crate::mcp::protocol::{Tool, ToolAnnotations};
+use crate::odata::metadata::{EdmModel, EntityType};
+use crate::odata::validate::property_schema;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// Navigation properties named in the `expand` description
+const MAX_LISTED_NAVIGATIONS: usize = 30;
+
+/// What a generated tool does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityToolKind {
+    /// Maps onto `query_entity`
+    Query,
+    /// Maps onto `get_record`
+    Get,
+}
+
+/// A generated tool and the entity it serves
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityTool {
+    pub name: String,
+    pub entity: String,
+    pub kind: EntityToolKind,
+}
+
+/// Tools for the configured entities. Names taken by `reserved` tools or
+/// an earlier entity are skipped.
+pub fn entity_tools(entities: &[EntityConfig], reserved: &[String]) -> Vec<EntityTool> {
+    let mut tools: Vec<EntityTool> = Vec::new();
+    for entity in entities {
+        let slug = slug(&entity.name);
+        let names = [
+            (format!("query_{}", slug), EntityToolKind::Query),
+            (format!("get_{}", singular(&slug)), EntityToolKind::Get),
+        ];
+        for (name, kind) in names {
+            if reserved.contains(&name) || tools.iter().any(|t| t.name == name) {
+                tracing::warn!("No {} tool for entity {}: the name is taken", name, entity.name);
+                continue;
+            }
+            tools.push(EntityTool {
+                name,
+                entity: entity.name.clone(),
+                kind,
+            });
+        }
+    }
+    tools
+}
+
+/// Lower-case tool name part of an entity set name
+fn slug(entity: &str) -> String {
+    entity
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+/// `accounts` -> `account`, `opportunities` -> `opportunity`; names that do
+/// not end in `s` (e.g. `customersv3`) are kept
+fn singular(slug: &str) -> String {
+    if let Some(stem) = slug.strip_suffix("ies") {
+        format!("{}y", stem)
+    } else if slug.ends_with("ss") {
+        slug.to_string()
+    } else {
+        slug.strip_suffix('s').unwrap_or(slug).to_string()
+    }
+}
+
+/// Tool definition; typed when the model knows the entity
+pub fn definition(tool: &EntityTool, model: Option<&EdmModel>, product: &ProductType) -> Tool {
+    let entity_type = model.and_then(|m| m.find_entity_type(&tool.entity).map(|t| (m, t)));
+    match tool.kind {
+        EntityToolKind::Query => Tool {
+            name: tool.name.clone(),
+            description: format!(
+                "Query {} records. Same as query_entity with entity '{}', with the entity's fields in the argument schema.",
+                tool.entity, tool.entity
+            ),
+            input_schema: query_schema(entity_type, product),
+            annotations: ToolAnnotations::read_only(),
+        },
+        EntityToolKind::Get => Tool {
+            name: tool.name.clone(),
+            description: format!("Get one {} record by its key.", tool.entity),
+            input_schema: get_schema(entity_type),
+            annotations: ToolAnnotations::read_only(),
+        },
+    }
+}
+
+fn query_schema(entity_type: Option<(&EdmModel, &EntityType)>, product: &ProductType) -> Value {
+    let mut props = Map::new();
+    let select = match entity_type {
+        Some((model, t)) => json!({
+            "type": "array",
+            "items": { "type": "string", "enum": model.properties_of(t).iter().map(|p| &p.name).collect::<Vec<_>>() },
+            "description": "Fields to return (default: all)"
+        }),
+        None => json!({ "type": "string", "description": "Comma-separated fields to return (default: all)" }),
+    };
+    props.insert("select".to_string(), select);
+
+    let filter_hint = match entity_type {
+        Some((model, t)) => filter_hint(model, t),
+        None => String::new(),
+    };
+    props.insert(
+        "filter".to_string(),
+        json!({ "type": "string", "description": format!("OData filter expression{}", filter_hint) }),
+    );
+    props.insert(
+        "orderby".to_string(),
+        json!({ "type": "string", "description": "Sort order, e.g. 'name asc' or 'modifiedon desc'" }),
+    );
+    props.insert(
+        "top".to_string(),
+        json!({ "type": "integer", "minimum": 1, "maximum": 1000, "description": "Maximum records to return (default: 50)" }),
+    );
+    props.insert(
+        "skip".to_string(),
+        json!({ "type": "integer", "minimum": 0, "description": "Records to skip" }),
+    );
+    props.insert(
+        "count".to_string(),
+        json!({ "type": "boolean", "description": "Include the total record count" }),
+    );
+    let navigations = match entity_type {
+        Some((_, t)) if !t.navigation_properties.is_empty() => {
+            let names: Vec<&str> = t
+                .navigation_properties
+                .iter()
+                .take(MAX_LISTED_NAVIGATIONS)
+                .map(|n| n.name.as_str())
+                .collect();
+            format!(". Navigation properties: {}", names.join(", "))
+        }
+        _ => String::new(),
+    };
+    props.insert(
+        "expand".to_string(),
+        json!({ "type": "string", "description": format!("Navigation properties to expand, as in query_entity{}", navigations) }),
+    );
+    if *product == ProductType::Finops {
+        props.insert(
+            "cross_company".to_string(),
+            json!({ "type": "boolean", "description": "Query all legal entities (default: the entity's cross_company setting)" }),
+        );
+    }
+    json!({ "type": "object", "properties": props, "required": [] })
+}
+
+/// Example filter on the first key field
+fn filter_hint(model: &EdmModel, entity_type: &EntityType) -> String {
+    let properties = model.properties_of(entity_type);
+    let key = model
+        .keys_of(entity_type)
+        .iter()
+        .find_map(|k| properties.iter().find(|p| &p.name == k));
+    match key {
+        Some(p) if p.edm_type == "Edm.String" => format!(", e.g. \"{} eq 'value'\"", p.name),
+        Some(p) => format!(", e.g. \"{} eq <{}>\"", p.name, p.edm_type.trim_start_matches("Edm.")),
+        None => String::new(),
+    }
+}
+
+fn get_schema(entity_type: Option<(&EdmModel, &EntityType)>) -> Value {
+    let keys = entity_type
+        .map(|(model, t)| {
+            let properties = model.properties_of(t);
+            model
+                .keys_of(t)
+                .iter()
+                .filter_map(|k| properties.iter().find(|p| &p.name == k).copied())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    match entity_type {
+        Some((model, _)) if !keys.is_empty() => {
+            let props: Map<String, Value> = keys
+                .iter()
+                .map(|p| (p.name.clone(), property_schema(model, p)))
+                .collect();
+            let required: Vec<&str> = keys.iter().map(|p| p.name.as_str()).collect();
+            json!({ "type": "object", "properties": props, "required": required })
+        }
+        _ => json!({
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "description": "Record key: a GUID, number or string, or a composite key as JSON {\"dataAreaId\": \"usmf\", \"ItemNumber\": \"A0001\"}" }
+            },
+            "required": ["id"]
+        }),
+    }
+}
+
+/// Arguments of the `query_entity` or `get_record` call a generated tool stands for
+pub fn target_args(
+    tool: &EntityTool,
+    config: Option<&EntityConfig>,
+    model: Option<&EdmModel>,
+    args: &HashMap<String, Value>,
+) -> Result<HashMap<String, Value>, String> {
+    let mut target = args.clone();
+    target.insert("entity".to_string(), Value::String(tool.entity.clone()));
+    match tool.kind {
+        EntityToolKind::Query => {
+            if let Some(Value::Array(fields)) = args.get("select") {
+                let fields: Vec<&str> = fields.iter().filter_map(|f| f.as_str()).collect();
+                target.insert("select".to_string(), Value::String(fields.join(",")));
+            }
+            if !target.contains_key("cross_company") {
+                if let Some(true) = config.and_then(|c| c.cross_company) {
+                    target.insert("cross_company".to_string(), Value::Bool(true));
+                }
+            }
+        }
+        EntityToolKind::Get if !args.contains_key("id") => {
+            let keys = model
+                .and_then(|m| m.find_entity_type(&tool.entity).map(|t| m.keys_of(t)))
+                .unwrap_or_default();
+            if keys.is_empty() {
+                return Err("Missing required parameter: id".to_string());
+            }
+            let mut parts = Map::new();
+            for key in keys {
+                let value = args
+                    .get(key)
+                    .ok_or_else(|| format!("Missing required parameter: {}", key))?;
+                parts.insert(key.clone(), value.clone());
+            }
+            target.insert("id".to_string(), Value::Object(parts));
+        }
+        EntityToolKind::Get => {}
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(name: &str) -> EntityConfig {
+        toml::from_str(&format!("name = \"{}\"", name)).unwrap()
+    }
+
+    const MODEL: &str = r#"<Schema Namespace="Microsoft.Dynamics.DataEntities">
+  <EnumType Name="NoYes"><Member Name="No" Value="0" /><Member Name="Yes" Value="1" /></EnumType>
+  <EntityType Name="CustomerV3">
+    <Key><PropertyRef Name="dataAreaId" /><PropertyRef Name="CustomerAccount" /></Key>
+    <Property Name="dataAreaId" Type="Edm.String" Nullable="false" MaxLength="4" />
+    <Property Name="CustomerAccount" Type="Edm.String" Nullable="false" MaxLength="20" />
+    <Property Name="OnHold" Type="Microsoft.Dynamics.DataEntities.NoYes" />
+  </EntityType>
+  <EntityContainer Name="Resources">
+    <EntitySet Name="CustomersV3" EntityType="Microsoft.Dynamics.DataEntities.CustomerV3" />
+  </EntityContainer>
+</Schema>"#;
+
+    #[test]
+    fn test_tool_names() {
+        let entities = [entity("accounts"), entity("opportunities"), entity("CustomersV3"), entity("account")];
+        let tools = entity_tools(&entities, &["get_record".to_string()]);
+        let names: Vec<&str> = tools.iter().map(|t| t.name.as_str()).collect();
+        // `get_account` is taken by `accounts`, so `account` only gets its query tool
+        assert_eq!(
+            names,
+            vec![
+                "query_accounts",
+                "get_account",
+                "query_opportunities",
+                "get_opportunity",
+                "query_customersv3",
+                "get_customersv3",
+                "query_account"
+            ]
+        );
+        assert!(entity_tools(&[entity("records")], &["get_record".to_string()])
+            .iter()
+            .all(|t| t.kind == EntityToolKind::Query));
+    }
+
+    #[test]
+    fn test_typed_schemas_and_args() {
+        let model = EdmModel::parse(MODEL);
+        let tools = entity_tools(&[entity("CustomersV3")], &[]);
+
+        let query = definition(&tools[0], Some(&model), &ProductType::Finops);
+        assert_eq!(query.input_schema["properties"]["select"]["items"]["enum"][2], "OnHold");
+        assert!(query.input_schema["properties"]["cross_company"].is_object());
+
+        let get = definition(&tools[1], Some(&model), &ProductType::Finops);
+        assert_eq!(get.input_schema["required"], json!(["dataAreaId", "CustomerAccount"]));
+        assert_eq!(get.input_schema["properties"]["CustomerAccount"]["maxLength"], 20);
+        assert_eq!(definition(&tools[1], None, &ProductType::Finops).input_schema["required"], json!(["id"]));
+
+        let args: HashMap<String, Value> = [
+            ("dataAreaId".to_string(), json!("usmf")),
+            ("CustomerAccount".to_string(), json!("US-001")),
+        ]
+        .into_iter()
+        .collect();
+        let target = target_args(&tools[1], None, Some(&model), &args).unwrap();
+        assert_eq!(target["entity"], "CustomersV3");
+        assert_eq!(target["id"], json!({ "dataAreaId": "usmf", "CustomerAccount": "US-001" }));
+        assert!(target_args(&tools[1], None, None, &args).is_err());
+
+        let args: HashMap<String, Value> = [("select".to_string(), json!(["CustomerAccount", "OnHold"]))]
+            .into_iter()
+            .collect();
+        let target = target_args(&tools[0], None, Some(&model), &args).unwrap();
+        assert_eq!(target["select"], "CustomerAccount,OnHold");
+    }
+}
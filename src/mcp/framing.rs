@@ -0,0 +1,354 @@
+//! Message framing for the stdio transport
+//!
+//! MCP over stdio normally uses newline-delimited JSON, but some hosts and
+//! proxies use LSP-style `Content-Length` headers instead. Both framings are
+//! detected per message so replies can be written back the same way.
+//!
+//! Unframed JSON is read value by value rather than line by line, so several
+//! values on one line and values spread over several lines both work.
+//! Messages larger than the size limit are skipped and reported as
+//! [`MessageTooLarge`] instead of being buffered.
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Default limit on the size of one message
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Wire framing of a single message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// One JSON document per line
+    #[default]
+    Newline,
+    /// `Content-Length: N` header block followed by N bytes of JSON
+    ContentLength,
+}
+
+/// A message read from the wire together with the framing it arrived in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramedMessage {
+    pub body: String,
+    pub framing: Framing,
+}
+
+/// A message over the size limit was skipped; the reader is positioned after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageTooLarge {
+    pub limit: usize,
+}
+
+impl std::fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message exceeds the {} byte limit", self.limit)
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+impl MessageTooLarge {
+    /// The limit if `error` reports a skipped message
+    pub fn from_io(error: &std::io::Error) -> Option<Self> {
+        error.get_ref().and_then(|e| e.downcast_ref::<Self>()).copied()
+    }
+
+    fn into_io(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, self)
+    }
+}
+
+/// Read the next message of at most `max_bytes`, auto-detecting its framing.
+///
+/// Returns `Ok(None)` on EOF. Whitespace between messages is skipped. Input
+/// that is neither JSON nor a header is returned up to the end of its line,
+/// so the caller can answer it with a parse error.
+pub async fn read_message<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_bytes: usize,
+) -> std::io::Result<Option<FramedMessage>> {
+    let first = loop {
+        let buf = reader.fill_buf().await?;
+        let Some(&first) = buf.iter().find(|b| !b.is_ascii_whitespace()) else {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            let len = buf.len();
+            reader.consume(len);
+            continue;
+        };
+        let skip = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        reader.consume(skip);
+        break first;
+    };
+
+    if first == b'{' || first == b'[' {
+        let body = read_json_value(reader, max_bytes).await?;
+        return Ok(Some(FramedMessage {
+            body,
+            framing: Framing::Newline,
+        }));
+    }
+
+    let line = read_line(reader, max_bytes).await?;
+    let trimmed = line.trim();
+    let Some(length) = parse_content_length(trimmed) else {
+        return Ok(Some(FramedMessage {
+            body: trimmed.to_string(),
+            framing: Framing::Newline,
+        }));
+    };
+    let length = length?;
+    // Consume remaining headers (e.g. Content-Type) up to the blank separator line
+    loop {
+        let header = read_line(reader, max_bytes).await?;
+        if header.is_empty() {
+            return Err(unexpected_eof());
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+    }
+
+    if length > max_bytes {
+        skip_bytes(reader, length).await?;
+        return Err(MessageTooLarge { limit: max_bytes }.into_io());
+    }
+    let mut body = Vec::with_capacity(length);
+    while body.len() < length {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let take = buf.len().min(length - body.len());
+        body.extend_from_slice(&buf[..take]);
+        reader.consume(take);
+    }
+    let body = String::from_utf8(body).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    Ok(Some(FramedMessage {
+        body,
+        framing: Framing::ContentLength,
+    }))
+}
+
+/// Read one JSON object or array, stopping right after its closing bracket.
+/// At EOF the partial text is returned and fails to parse later.
+async fn read_json_value<R: AsyncBufRead + Unpin>(reader: &mut R, max_bytes: usize) -> std::io::Result<String> {
+    let mut body = Vec::new();
+    let mut size = 0usize;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            break;
+        }
+        let mut end = None;
+        for (i, &byte) in buf.iter().enumerate() {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        end = Some(i + 1);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let take = end.unwrap_or(buf.len());
+        size += take;
+        // Past the limit the rest is read only to find where the message ends
+        if size <= max_bytes {
+            body.extend_from_slice(&buf[..take]);
+        }
+        reader.consume(take);
+        if end.is_some() {
+            break;
+        }
+    }
+    if size > max_bytes {
+        return Err(MessageTooLarge { limit: max_bytes }.into_io());
+    }
+    String::from_utf8(body).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Read up to and including the next newline; empty at EOF
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, max_bytes: usize) -> std::io::Result<String> {
+    let mut line = Vec::new();
+    let mut size = 0usize;
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            break;
+        }
+        let newline = buf.iter().position(|&b| b == b'\n');
+        let take = newline.map_or(buf.len(), |i| i + 1);
+        size += take;
+        if size <= max_bytes {
+            line.extend_from_slice(&buf[..take]);
+        }
+        reader.consume(take);
+        if newline.is_some() {
+            break;
+        }
+    }
+    if size > max_bytes {
+        return Err(MessageTooLarge { limit: max_bytes }.into_io());
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+async fn skip_bytes<R: AsyncBufRead + Unpin>(reader: &mut R, mut remaining: usize) -> std::io::Result<()> {
+    while remaining > 0 {
+        let available = reader.fill_buf().await?.len();
+        if available == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let take = available.min(remaining);
+        reader.consume(take);
+        remaining -= take;
+    }
+    Ok(())
+}
+
+/// Write a message using the given framing and flush
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    body: &str,
+    framing: Framing,
+) -> std::io::Result<()> {
+    match framing {
+        Framing::Newline => {
+            writer.write_all(body.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+        }
+        Framing::ContentLength => {
+            let header = format!("Content-Length: {}\r\n\r\n", body.len());
+            writer.write_all(header.as_bytes()).await?;
+            writer.write_all(body.as_bytes()).await?;
+        }
+    }
+    writer.flush().await
+}
+
+/// Parse a `Content-Length` header line (case-insensitive).
+///
+/// Returns `None` if the line is not a Content-Length header at all.
+fn parse_content_length(line: &str) -> Option<std::io::Result<usize>> {
+    let (name, value) = line.split_once(':')?;
+    if !name.trim().eq_ignore_ascii_case("content-length") {
+        return None;
+    }
+    Some(value.trim().parse::<usize>().map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid Content-Length header: {}", e),
+        )
+    }))
+}
+
+fn unexpected_eof() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "EOF while reading message headers",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    #[tokio::test]
+    async fn test_read_newline_framing() {
+        let input = b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"}\n\n".to_vec();
+        let mut reader = BufReader::new(&input[..]);
+
+        let msg = read_message(&mut reader, DEFAULT_MAX_MESSAGE_BYTES).await.unwrap().unwrap();
+        assert_eq!(msg.framing, Framing::Newline);
+        assert_eq!(msg.body, "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"}");
+        assert!(read_message(&mut reader, DEFAULT_MAX_MESSAGE_BYTES).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_content_length_framing() {
+        let body = "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"}";
+        let input = format!(
+            "Content-Length: {}\r\nContent-Type: application/json\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut reader = BufReader::new(input.as_bytes());
+
+        let msg = read_message(&mut reader, DEFAULT_MAX_MESSAGE_BYTES).await.unwrap().unwrap();
+        assert_eq!(msg.framing, Framing::ContentLength);
+        assert_eq!(msg.body, body);
+    }
+
+    #[tokio::test]
+    async fn test_read_mixed_framing() {
+        let input = "content-length: 2\r\n\r\n{}{\"a\":1}\n";
+        let mut reader = BufReader::new(input.as_bytes());
+
+        let first = read_message(&mut reader, DEFAULT_MAX_MESSAGE_BYTES).await.unwrap().unwrap();
+        assert_eq!(first.framing, Framing::ContentLength);
+        assert_eq!(first.body, "{}");
+
+        let second = read_message(&mut reader, DEFAULT_MAX_MESSAGE_BYTES).await.unwrap().unwrap();
+        assert_eq!(second.framing, Framing::Newline);
+        assert_eq!(second.body, "{\"a\":1}");
+    }
+
+    #[tokio::test]
+    async fn test_read_values_not_lines() {
+        let input = "{\"id\":1,\"s\":\"} \\\" [\"}{\"id\":2}\n[{\"id\":3},\n {\"id\":4}]\n{\n  \"id\": 5\n}\n";
+        let mut reader = BufReader::with_capacity(4, input.as_bytes());
+
+        let mut bodies = Vec::new();
+        while let Some(msg) = read_message(&mut reader, DEFAULT_MAX_MESSAGE_BYTES).await.unwrap() {
+            assert_eq!(msg.framing, Framing::Newline);
+            bodies.push(serde_json::from_str::<serde_json::Value>(&msg.body).unwrap());
+        }
+        assert_eq!(bodies.len(), 4);
+        assert_eq!(bodies[0]["s"], "} \" [");
+        assert_eq!(bodies[1]["id"], 2);
+        assert_eq!(bodies[2][1]["id"], 4);
+        assert_eq!(bodies[3]["id"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_skip_oversized_message() {
+        let input = format!("{{\"data\":\"{}\"}}\nContent-Length: 40\r\n\r\n{}\n{{\"id\":1}}\n", "x".repeat(100), "y".repeat(40));
+        let mut reader = BufReader::new(input.as_bytes());
+
+        let error = read_message(&mut reader, 32).await.unwrap_err();
+        assert_eq!(MessageTooLarge::from_io(&error), Some(MessageTooLarge { limit: 32 }));
+        let error = read_message(&mut reader, 32).await.unwrap_err();
+        assert!(MessageTooLarge::from_io(&error).is_some());
+        let msg = read_message(&mut reader, 32).await.unwrap().unwrap();
+        assert_eq!(msg.body, "{\"id\":1}");
+    }
+
+    #[tokio::test]
+    async fn test_write_content_length_framing() {
+        let mut out = Vec::new();
+        write_message(&mut out, "{}", Framing::ContentLength).await.unwrap();
+        assert_eq!(out, b"Content-Length: 2\r\n\r\n{}");
+
+        let mut out = Vec::new();
+        write_message(&mut out, "{}", Framing::Newline).await.unwrap();
+        assert_eq!(out, b"{}\n");
+    }
+}
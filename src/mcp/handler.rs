@@ -0,0 +1,596 @@
+//! JSON-RPC request dispatch
+//!
+//! Maps MCP methods to server operations. Shared by every transport so a
+//! stdio session and a socket session behave identically.
+
+use crate::mcp::context::{Notifier, ProgressReporter, ToolContext};
+use crate::mcp::framing;
+use crate::mcp::logging::LogLevel;
+use crate::mcp::prompts;
+use crate::mcp::protocol::*;
+use crate::mcp::resources;
+use crate::mcp::server::D365McpServer;
+use crate::mcp::session::{self, Session, Sessions};
+use crate::odata::metadata_cache::ModelWatch;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// Capability a method is served under
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Capability {
+    /// Always available
+    Base,
+    Tools,
+    Logging,
+    Resources,
+    Prompts,
+    Completions,
+}
+
+/// Request methods the handler answers; anything else gets -32601
+const METHODS: &[(&str, Capability)] = &[
+    ("initialize", Capability::Base),
+    ("ping", Capability::Base),
+    ("tools/list", Capability::Tools),
+    ("tools/call", Capability::Tools),
+    ("logging/setLevel", Capability::Logging),
+    ("resources/list", Capability::Resources),
+    ("resources/templates/list", Capability::Resources),
+    ("resources/read", Capability::Resources),
+    ("prompts/list", Capability::Prompts),
+    ("prompts/get", Capability::Prompts),
+    ("completion/complete", Capability::Completions),
+];
+
+/// Dispatches JSON-RPC requests to the MCP server
+pub struct McpHandler {
+    server: Option<D365McpServer>,
+    /// Sessions of HTTP clients
+    sessions: Sessions,
+    /// Level of `notifications/message` until a client sets its own; `None` sends none
+    log_level: Option<LogLevel>,
+    /// Bounds the tool calls running at once; other requests never wait for it
+    call_permits: Option<Arc<Semaphore>>,
+    /// Running tool calls, awaited on shutdown
+    calls: TaskTracker,
+    shutdown: CancellationToken,
+    /// Reject envelopes that bend JSON-RPC 2.0 instead of accepting them
+    strict_jsonrpc: bool,
+    /// Largest message read from a client
+    max_message_bytes: usize,
+}
+
+impl McpHandler {
+    /// Create a handler. `server` is `None` when configuration is incomplete;
+    /// tools can still be listed but calls return a configuration error.
+    pub fn new(server: Option<D365McpServer>) -> Self {
+        let call_permits = server.as_ref().map(|s| Arc::new(Semaphore::new(s.max_concurrent_calls())));
+        Self {
+            server,
+            sessions: Sessions::default(),
+            log_level: None,
+            call_permits,
+            calls: TaskTracker::new(),
+            shutdown: CancellationToken::new(),
+            strict_jsonrpc: false,
+            max_message_bytes: framing::DEFAULT_MAX_MESSAGE_BYTES,
+        }
+    }
+
+    /// Send clients log messages at `level` until they call `logging/setLevel`
+    pub fn with_log_level(mut self, level: Option<LogLevel>) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    /// Level of `notifications/message` for a connection
+    pub fn client_log_level(&self, session: Option<&Session>) -> Option<LogLevel> {
+        session.and_then(|s| s.log_level()).or(self.log_level)
+    }
+
+    /// Validate the JSON-RPC envelope of every message (`strict_jsonrpc`)
+    pub fn with_strict_jsonrpc(mut self, strict: bool) -> Self {
+        self.strict_jsonrpc = strict;
+        self
+    }
+
+    /// Skip client messages larger than `bytes` instead of reading them
+    pub fn with_max_message_bytes(mut self, bytes: usize) -> Self {
+        self.max_message_bytes = bytes;
+        self
+    }
+
+    /// Largest message read from a client
+    pub fn max_message_bytes(&self) -> usize {
+        self.max_message_bytes
+    }
+
+    /// Parse one message into a request, or the error response to send
+    pub fn parse_request(&self, message: Value) -> Result<JsonRpcRequest, Box<JsonRpcResponse>> {
+        if self.strict_jsonrpc {
+            JsonRpcRequest::validate_strict(&message)?;
+        }
+        serde_json::from_value(message)
+            .map_err(|e| Box::new(JsonRpcResponse::error(None, -32600, &format!("Invalid request: {}", e))))
+    }
+
+    /// Forget HTTP sessions after `idle_timeout` without requests
+    pub fn with_session_idle_timeout(mut self, idle_timeout: std::time::Duration) -> Self {
+        self.sessions = Sessions::new(idle_timeout);
+        self
+    }
+
+    pub fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    /// Wakes when the tool list changed and clients should be sent
+    /// `notifications/tools/list_changed`; `None` if it never changes
+    pub fn tool_changes(&self) -> Option<ModelWatch> {
+        self.server.as_ref().and_then(|s| s.tool_changes())
+    }
+
+    /// Stop taking tool calls, then wait for the running ones and for
+    /// background sync runs to finish. Transports stop reading once
+    /// [`Self::shutting_down`] completes.
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        self.calls.close();
+        let sync = async {
+            if let Some(server) = &self.server {
+                server.stop_background_sync().await;
+            }
+        };
+        tokio::join!(self.calls.wait(), sync);
+    }
+
+    /// Completes once shutdown has begun
+    pub async fn shutting_down(&self) {
+        self.shutdown.cancelled().await
+    }
+
+    /// Tool calls still running
+    pub fn calls_in_flight(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Capabilities advertised in `initialize`
+    fn capabilities(&self) -> ServerCapabilities {
+        ServerCapabilities {
+            tools: Some(ToolsCapability {
+                list_changed: Some(self.tool_changes().is_some()),
+            }),
+            logging: Some(serde_json::json!({})),
+            resources: Some(ResourcesCapability {
+                subscribe: Some(false),
+                list_changed: Some(false),
+            }),
+            prompts: Some(PromptsCapability {
+                list_changed: Some(false),
+            }),
+            completions: Some(serde_json::json!({})),
+        }
+    }
+
+    /// Request methods of the advertised capabilities
+    pub fn supported_methods(&self) -> Vec<&'static str> {
+        let capabilities = self.capabilities();
+        METHODS
+            .iter()
+            .filter(|(_, capability)| match capability {
+                Capability::Base => true,
+                Capability::Tools => capabilities.tools.is_some(),
+                Capability::Logging => capabilities.logging.is_some(),
+                Capability::Resources => capabilities.resources.is_some(),
+                Capability::Prompts => capabilities.prompts.is_some(),
+                Capability::Completions => capabilities.completions.is_some(),
+            })
+            .map(|(method, _)| *method)
+            .collect()
+    }
+
+    /// Handle a single request and build its response.
+    ///
+    /// `notifier` carries server-initiated messages (e.g. progress) back to
+    /// the connection the request arrived on.
+    pub async fn handle_request(
+        &self,
+        request: JsonRpcRequest,
+        notifier: Option<Notifier>,
+    ) -> JsonRpcResponse {
+        let id = request.id.clone();
+
+        // Notifications get no response, so unknown ones are simply ignored
+        if id.is_some() && !self.supported_methods().contains(&request.method.as_str()) {
+            tracing::debug!("Unknown method: {}", request.method);
+            return JsonRpcResponse::error(id, -32601, &format!("Method not found: {}", request.method));
+        }
+
+        match request.method.as_str() {
+            "initialize" => {
+                tracing::debug!("Handling: initialize");
+                let requested = request
+                    .params
+                    .as_ref()
+                    .and_then(|p| p.get("protocolVersion"))
+                    .and_then(|v| v.as_str());
+                let version = ProtocolVersion::negotiate(requested);
+                tracing::info!("Protocol version {} (client asked for {:?})", version.as_str(), requested);
+                if let Some(session) = session::current() {
+                    session.set_protocol_version(version);
+                }
+                let result = InitializeResult {
+                    protocol_version: version.as_str().to_string(),
+                    capabilities: self.capabilities(),
+                    server_info: ServerInfo {
+                        name: "d365-odata-mcp".to_string(),
+                        title: (version >= ProtocolVersion::V2025_06_18).then(|| "Dynamics 365 OData".to_string()),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                    },
+                };
+                JsonRpcResponse::success(id, serde_json::to_value(result).unwrap())
+            }
+
+            "initialized" | "notifications/initialized" => {
+                tracing::debug!("Handling: initialized");
+                JsonRpcResponse::success(id, serde_json::json!({}))
+            }
+
+            "tools/list" => {
+                tracing::debug!("Handling: tools/list");
+                let mut tools = match &self.server {
+                    Some(s) => s.get_tools(),
+                    None => D365McpServer::get_tools_static(),
+                };
+                if session::protocol_version() < ProtocolVersion::V2025_03_26 {
+                    tools.iter_mut().for_each(|t| t.annotations = None);
+                }
+                JsonRpcResponse::success(id, serde_json::to_value(ListToolsResult { tools }).unwrap())
+            }
+
+            "tools/call" => {
+                tracing::debug!("Handling: tools/call");
+                if self.shutdown.is_cancelled() {
+                    return JsonRpcResponse::error(id, -32000, "Server is shutting down");
+                }
+                let server = match &self.server {
+                    Some(s) => s,
+                    None => {
+                        let result = CallToolResult::error(
+                            "Server not configured. Missing environment variables: TENANT_ID, CLIENT_ID, CLIENT_SECRET, ENDPOINT".to_string()
+                        );
+                        return JsonRpcResponse::success(id, serde_json::to_value(result).unwrap());
+                    }
+                };
+
+                let params: CallToolParams = match request.params {
+                    Some(p) => match serde_json::from_value(p) {
+                        Ok(params) => params,
+                        Err(e) => {
+                            return JsonRpcResponse::error(id, -32602, &format!("Invalid params: {}", e));
+                        }
+                    },
+                    None => {
+                        return JsonRpcResponse::error(id, -32602, "Missing params");
+                    }
+                };
+
+                let progress_token = params.meta.and_then(|m| m.progress_token);
+                let ctx = ToolContext {
+                    progress: ProgressReporter::new(notifier, progress_token),
+                };
+
+                let args = params.arguments.unwrap_or_default();
+                // Boxed: tool futures are large and would otherwise be copied into every caller
+                let call = self.calls.track_future(Box::pin(async {
+                    // Queued calls can still be cancelled while they wait
+                    let _permit = match &self.call_permits {
+                        Some(permits) => permits.acquire().await.ok(),
+                        None => None,
+                    };
+                    server.call_tool_with_context(&params.name, &args, &ctx).await
+                }));
+                let result: CallToolResult = match (session::current(), &id) {
+                    (Some(session), Some(request_id)) => match session.cancellable(request_id, call).await {
+                        Some(result) => result,
+                        None => {
+                            tracing::info!("Tool call {} ({}) cancelled", request_id, params.name);
+                            return JsonRpcResponse::error(id, REQUEST_CANCELLED, "Request cancelled");
+                        }
+                    },
+                    _ => call.await,
+                };
+                JsonRpcResponse::success(id, serde_json::to_value(result).unwrap())
+            }
+
+            "notifications/cancelled" => {
+                let request_id = request.params.as_ref().and_then(|p| p.get("requestId"));
+                let cancelled = match (session::current(), request_id) {
+                    (Some(session), Some(request_id)) => session.cancel(request_id),
+                    _ => false,
+                };
+                // The request may already have finished
+                tracing::debug!("Handling: notifications/cancelled (found running request: {})", cancelled);
+                JsonRpcResponse::success(id, serde_json::json!({}))
+            }
+
+            "resources/list" => {
+                tracing::debug!("Handling: resources/list");
+                let resources = self.server.as_ref().map(|s| s.list_resources()).unwrap_or_default();
+                JsonRpcResponse::success(id, serde_json::to_value(ListResourcesResult { resources }).unwrap())
+            }
+
+            "resources/templates/list" => {
+                tracing::debug!("Handling: resources/templates/list");
+                let result = ListResourceTemplatesResult {
+                    resource_templates: resources::templates(),
+                };
+                JsonRpcResponse::success(id, serde_json::to_value(result).unwrap())
+            }
+
+            "resources/read" => {
+                let Some(uri) = request.params.as_ref().and_then(|p| p.get("uri")).and_then(|u| u.as_str()) else {
+                    return JsonRpcResponse::error(id, -32602, "Missing params: uri");
+                };
+                tracing::debug!("Handling: resources/read {}", uri);
+                let Some(server) = &self.server else {
+                    return JsonRpcResponse::error(id, -32603, "Server not configured; resources need ENDPOINT and credentials");
+                };
+                match server.read_resource(uri).await {
+                    Ok(contents) => {
+                        let result = ReadResourceResult { contents: vec![contents] };
+                        JsonRpcResponse::success(id, serde_json::to_value(result).unwrap())
+                    }
+                    Err(e) => JsonRpcResponse::error(id, e.code(), e.message()),
+                }
+            }
+
+            "prompts/list" => {
+                tracing::debug!("Handling: prompts/list");
+                let result = ListPromptsResult {
+                    prompts: prompts::prompt_list(),
+                };
+                JsonRpcResponse::success(id, serde_json::to_value(result).unwrap())
+            }
+
+            "prompts/get" => {
+                let Some(name) = request.params.as_ref().and_then(|p| p.get("name")).and_then(|n| n.as_str()) else {
+                    return JsonRpcResponse::error(id, -32602, "Missing params: name");
+                };
+                tracing::debug!("Handling: prompts/get {}", name);
+                let args: std::collections::HashMap<String, String> = request
+                    .params
+                    .as_ref()
+                    .and_then(|p| p.get("arguments"))
+                    .and_then(|a| serde_json::from_value(a.clone()).ok())
+                    .unwrap_or_default();
+                let Some(server) = &self.server else {
+                    return JsonRpcResponse::error(id, -32603, "Server not configured; prompts need ENDPOINT and credentials");
+                };
+                match server.get_prompt(name, &args).await {
+                    Ok(result) => JsonRpcResponse::success(id, serde_json::to_value(result).unwrap()),
+                    Err(e) => JsonRpcResponse::error(id, e.code(), e.message()),
+                }
+            }
+
+            "completion/complete" => {
+                let params = request.params.as_ref();
+                let reference = params.and_then(|p| p.get("ref"));
+                let known = match reference.and_then(|r| r.get("type")).and_then(|t| t.as_str()) {
+                    Some("ref/prompt") => reference
+                        .and_then(|r| r.get("name"))
+                        .and_then(|n| n.as_str())
+                        .is_some_and(|name| prompts::PromptKind::parse(name).is_some()),
+                    Some("ref/resource") => {
+                        let uri = reference.and_then(|r| r.get("uri")).and_then(|u| u.as_str());
+                        resources::templates().iter().any(|t| Some(t.uri_template.as_str()) == uri)
+                    }
+                    _ => false,
+                };
+                if !known {
+                    return JsonRpcResponse::error(id, -32602, "Invalid params: ref must name a prompt or resource template");
+                }
+                let argument = params.and_then(|p| p.get("argument"));
+                let Some(name) = argument.and_then(|a| a.get("name")).and_then(|n| n.as_str()) else {
+                    return JsonRpcResponse::error(id, -32602, "Missing params: argument.name");
+                };
+                let value = argument.and_then(|a| a.get("value")).and_then(|v| v.as_str()).unwrap_or_default();
+                let context: std::collections::HashMap<String, String> = params
+                    .and_then(|p| p.get("context"))
+                    .and_then(|c| c.get("arguments"))
+                    .and_then(|a| serde_json::from_value(a.clone()).ok())
+                    .unwrap_or_default();
+                tracing::debug!("Handling: completion/complete {}={:?}", name, value);
+                let completion = match &self.server {
+                    Some(server) => server.complete(name, value, &context).await,
+                    None => Completion::default(),
+                };
+                JsonRpcResponse::success(id, serde_json::to_value(CompleteResult { completion }).unwrap())
+            }
+
+            "logging/setLevel" => {
+                let level = request.params.as_ref().and_then(|p| p.get("level")).and_then(|l| l.as_str());
+                let Some(level) = level.and_then(LogLevel::parse) else {
+                    return JsonRpcResponse::error(
+                        id,
+                        -32602,
+                        &format!("Invalid params: unknown log level {:?}", level.unwrap_or_default()),
+                    );
+                };
+                tracing::debug!("Handling: logging/setLevel {}", level.as_str());
+                if let Some(session) = session::current() {
+                    session.set_log_level(level);
+                }
+                JsonRpcResponse::success(id, serde_json::json!({}))
+            }
+
+            "ping" => {
+                tracing::debug!("Handling: ping");
+                JsonRpcResponse::success(id, serde_json::json!({}))
+            }
+
+            method => {
+                tracing::debug!("Ignoring notification: {}", method);
+                JsonRpcResponse::success(id, serde_json::json!({}))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: Option<i64>, method: &str) -> JsonRpcRequest {
+        JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: id.map(Into::into),
+            method: method.to_string(),
+            params: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_method_not_found() {
+        let handler = McpHandler::new(None);
+        let response = Box::pin(handler.handle_request(request(Some(4), "resources/subscribe"), None)).await;
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 4,
+                "error": {"code": -32601, "message": "Method not found: resources/subscribe"}
+            })
+        );
+
+        let response = Box::pin(handler.handle_request(request(None, "notifications/cancelled"), None)).await;
+        assert!(response.error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_protocol_version_negotiation() {
+        use crate::mcp::session::{in_session, Session};
+        use std::sync::Arc;
+
+        let handler = McpHandler::new(None);
+        let initialize = |version: &str| JsonRpcRequest {
+            params: Some(serde_json::json!({ "protocolVersion": version })),
+            ..request(Some(1), "initialize")
+        };
+
+        let call = |session: &Arc<Session>, request| {
+            in_session(Some(session.clone()), Box::pin(handler.handle_request(request, None)))
+        };
+
+        let old = Arc::new(Session::connection());
+        let result = call(&old, initialize("2024-11-05")).await.result.unwrap();
+        assert_eq!(result["protocolVersion"], "2024-11-05");
+        assert!(result["serverInfo"].get("title").is_none());
+        let tools = call(&old, request(Some(2), "tools/list")).await.result.unwrap();
+        assert!(tools["tools"][0].get("annotations").is_none());
+
+        let new = Arc::new(Session::connection());
+        let result = call(&new, initialize("2026-01-01")).await.result.unwrap();
+        assert_eq!(result["protocolVersion"], "2025-06-18");
+        assert!(result["serverInfo"]["title"].is_string());
+        let tools = call(&new, request(Some(2), "tools/list")).await.result.unwrap();
+        let get_record = tools["tools"].as_array().unwrap().iter().find(|t| t["name"] == "get_record").unwrap();
+        assert_eq!(get_record["annotations"], serde_json::json!({ "readOnlyHint": true }));
+        let annotations = |name: &str| {
+            tools["tools"].as_array().unwrap().iter().find(|t| t["name"] == name).unwrap()["annotations"].clone()
+        };
+        assert_eq!(annotations("delete_entity")["destructiveHint"], true);
+        assert_eq!(annotations("bulk_update")["destructiveHint"], true);
+        assert_eq!(annotations("create_entity")["destructiveHint"], false);
+        assert_eq!(annotations("upsert_entity")["idempotentHint"], true);
+    }
+
+    #[tokio::test]
+    async fn test_set_log_level() {
+        use crate::mcp::session::in_session;
+        use std::sync::Arc;
+
+        let handler = McpHandler::new(None).with_log_level(Some(LogLevel::Warning));
+        let session = Arc::new(Session::connection());
+        let set_level = |level: &str| JsonRpcRequest {
+            params: Some(serde_json::json!({ "level": level })),
+            ..request(Some(3), "logging/setLevel")
+        };
+
+        let response = in_session(Some(session.clone()), Box::pin(handler.handle_request(set_level("loud"), None))).await;
+        assert_eq!(response.error.unwrap().code, -32602);
+        assert_eq!(handler.client_log_level(Some(&session)), Some(LogLevel::Warning));
+
+        let response = in_session(Some(session.clone()), Box::pin(handler.handle_request(set_level("debug"), None))).await;
+        assert!(response.error.is_none());
+        assert_eq!(handler.client_log_level(Some(&session)), Some(LogLevel::Debug));
+        assert_eq!(handler.client_log_level(None), Some(LogLevel::Warning));
+    }
+
+    #[tokio::test]
+    async fn test_complete_checks_ref() {
+        let handler = McpHandler::new(None);
+        let complete = |reference: serde_json::Value| JsonRpcRequest {
+            params: Some(serde_json::json!({ "ref": reference, "argument": {"name": "entity", "value": "acc"} })),
+            ..request(Some(5), "completion/complete")
+        };
+
+        let call = |reference| Box::pin(handler.handle_request(complete(reference), None));
+
+        let response = call(serde_json::json!({"type": "ref/prompt", "name": "build_filter"})).await;
+        assert_eq!(response.result.unwrap()["completion"]["values"], serde_json::json!([]));
+
+        let response = call(serde_json::json!({"type": "ref/resource", "uri": "d365://entities/{entity}"})).await;
+        assert!(response.error.is_none());
+
+        let response = call(serde_json::json!({"type": "ref/prompt", "name": "nope"})).await;
+        assert_eq!(response.error.unwrap().code, -32602);
+    }
+
+    #[test]
+    fn test_parse_request_strict() {
+        let strict = McpHandler::new(None).with_strict_jsonrpc(true);
+        let lenient = McpHandler::new(None);
+        let code = |handler: &McpHandler, message: serde_json::Value| handler.parse_request(message).err().map(|e| e.error.unwrap().code);
+
+        let null_id = serde_json::json!({"jsonrpc": "2.0", "id": null, "method": "ping"});
+        assert_eq!(code(&strict, null_id.clone()), Some(-32600));
+        assert_eq!(code(&lenient, null_id), None);
+
+        let old_version = serde_json::json!({"jsonrpc": "1.0", "id": 1, "method": "ping"});
+        let error = strict.parse_request(old_version).unwrap_err();
+        assert_eq!(error.id, Some(1.into()));
+        assert_eq!(error.error.unwrap().code, -32600);
+
+        let positional = serde_json::json!({"jsonrpc": "2.0", "id": "a", "method": "tools/call", "params": ["list_entities"]});
+        assert_eq!(code(&strict, positional), Some(-32602));
+        assert_eq!(code(&strict, serde_json::json!({"jsonrpc": "2.0", "id": 1.5, "method": "ping"})), Some(-32600));
+        assert_eq!(code(&strict, serde_json::json!({"jsonrpc": "2.0", "method": "notifications/initialized"})), None);
+    }
+
+    #[tokio::test]
+    async fn test_supported_methods() {
+        let handler = McpHandler::new(None);
+        assert_eq!(
+            handler.supported_methods(),
+            [
+                "initialize",
+                "ping",
+                "tools/list",
+                "tools/call",
+                "logging/setLevel",
+                "resources/list",
+                "resources/templates/list",
+                "resources/read",
+                "prompts/list",
+                "prompts/get",
+                "completion/complete"
+            ]
+        );
+        let response = Box::pin(handler.handle_request(request(Some(1), "tools/list"), None)).await;
+        assert!(response.result.unwrap()["tools"].is_array());
+    }
+}
@@ -0,0 +1,493 @@
+//! Streamable HTTP transport
+//!
+//! Implements the MCP streamable-HTTP transport on a single `/mcp` endpoint,
+//! so one server process can serve several agent clients:
+//! - `POST` carries one JSON-RPC message (or a batch). Tool calls answer with
+//!   an SSE stream carrying progress notifications followed by the response;
+//!   other requests answer with plain JSON, notifications with 202.
+//! - `GET` opens an SSE stream for server-initiated messages
+//!   (`notifications/tools/list_changed`, `notifications/message`).
+//! - `DELETE` ends the session named by `Mcp-Session-Id`.
+//!
+//! `initialize` starts a session whose id the client sends on later
+//! requests, so concurrent agents keep separate state. Browsers are kept out
+//! by rejecting foreign `Origin`s, and API keys guard servers reachable from
+//! other machines.
+
+use crate::mcp::handler::McpHandler;
+use crate::mcp::logging;
+use crate::mcp::protocol::{JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, ProtocolVersion};
+use crate::mcp::session::{self, Session};
+use crate::mcp::transport::tools_changed;
+use bytes::Bytes;
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, ORIGIN};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Path of the MCP endpoint
+pub const MCP_PATH: &str = "/mcp";
+
+/// Default port of `--transport http`
+pub const DEFAULT_PORT: u16 = 8765;
+
+/// Header carrying the session id
+pub const SESSION_HEADER: &str = "Mcp-Session-Id";
+
+/// Header carrying the negotiated protocol version after `initialize`
+pub const PROTOCOL_VERSION_HEADER: &str = "MCP-Protocol-Version";
+
+/// Comment sent on idle `GET` streams so proxies keep them open
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+type Body = UnsyncBoxBody<Bytes, Infallible>;
+
+/// Options of the HTTP listener
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    pub addr: SocketAddr,
+    pub auth: ApiKeys,
+}
+
+/// Accepted API keys and the header they arrive in
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    /// No keys accepts every client
+    pub keys: Vec<String>,
+    /// `Authorization` (as `Bearer <key>`) or a custom header such as `X-API-Key`
+    pub header: Option<String>,
+}
+
+impl ApiKeys {
+    fn header_name(&self) -> &str {
+        self.header.as_deref().unwrap_or("Authorization")
+    }
+
+    /// Whether the request carries one of the keys
+    fn allows<B>(&self, request: &Request<B>) -> bool {
+        if self.keys.is_empty() {
+            return true;
+        }
+        let Some(value) = request.headers().get(self.header_name()).and_then(|v| v.to_str().ok()) else {
+            return false;
+        };
+        let presented = if self.header_name().eq_ignore_ascii_case(AUTHORIZATION.as_str()) {
+            match value.strip_prefix("Bearer ") {
+                Some(key) => key,
+                None => return false,
+            }
+        } else {
+            value
+        };
+        self.keys.iter().any(|key| constant_time_eq(key.as_bytes(), presented.trim().as_bytes()))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Serve MCP over HTTP until the listener fails or shutdown begins
+pub async fn serve_http(handler: Arc<McpHandler>, options: HttpOptions) -> std::io::Result<()> {
+    if options.auth.keys.is_empty() && !options.addr.ip().is_loopback() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "refusing to serve HTTP on non-loopback {} without an API key; set MCP_HTTP_TOKEN",
+                options.addr
+            ),
+        ));
+    }
+    let listener = TcpListener::bind(options.addr).await?;
+    tracing::info!("Listening on http://{}{}", listener.local_addr()?, MCP_PATH);
+    serve_listener(handler, listener, options.auth).await
+}
+
+async fn serve_listener(handler: Arc<McpHandler>, listener: TcpListener, auth: ApiKeys) -> std::io::Result<()> {
+    let auth = Arc::new(auth);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = handler.shutting_down() => return Ok(()),
+        };
+        tracing::debug!("Accepted HTTP connection from {}", peer);
+        let handler = handler.clone();
+        let auth = auth.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let handler = handler.clone();
+                let auth = auth.clone();
+                async move { Ok::<_, Infallible>(route(handler, request, &auth).await) }
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("HTTP connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn route(handler: Arc<McpHandler>, request: Request<Incoming>, auth: &ApiKeys) -> Response<Body> {
+    if request.uri().path() != MCP_PATH {
+        return plain(StatusCode::NOT_FOUND, "Not found; the MCP endpoint is /mcp");
+    }
+    if let Some(origin) = request.headers().get(ORIGIN) {
+        if !is_local_origin(origin) {
+            return plain(StatusCode::FORBIDDEN, "Origin not allowed");
+        }
+    }
+    if !auth.allows(&request) {
+        return plain(
+            StatusCode::UNAUTHORIZED,
+            &format!("Missing or wrong API key in the {} header", auth.header_name()),
+        );
+    }
+
+    if let Some(version) = request.headers().get(PROTOCOL_VERSION_HEADER) {
+        if version.to_str().ok().and_then(ProtocolVersion::parse).is_none() {
+            return plain(StatusCode::BAD_REQUEST, "Unsupported MCP-Protocol-Version");
+        }
+    }
+
+    let session_id = request
+        .headers()
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let session = match &session_id {
+        Some(id) => match handler.sessions().get(id) {
+            Some(session) => Some(session),
+            // Tells the client to initialize again
+            None => return plain(StatusCode::NOT_FOUND, "Unknown or expired session"),
+        },
+        None => None,
+    };
+
+    match *request.method() {
+        Method::POST => post(handler, request, session).await,
+        Method::GET => event_stream(handler, session),
+        Method::DELETE => match session_id {
+            Some(id) if handler.sessions().close(&id) => empty(StatusCode::OK),
+            _ => plain(StatusCode::BAD_REQUEST, "DELETE needs an Mcp-Session-Id header"),
+        },
+        _ => plain(StatusCode::METHOD_NOT_ALLOWED, "Use POST, GET or DELETE"),
+    }
+}
+
+async fn post(handler: Arc<McpHandler>, request: Request<Incoming>, session: Option<Arc<Session>>) -> Response<Body> {
+    let accepts_sse = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/event-stream"));
+    let body = match http_body_util::Limited::new(request.into_body(), handler.max_message_bytes()).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return plain(StatusCode::PAYLOAD_TOO_LARGE, &format!("Cannot read body: {}", e)),
+    };
+    let message: Value = match serde_json::from_slice(&body) {
+        Ok(message) => message,
+        Err(e) => {
+            let error = JsonRpcResponse::error(None, -32700, &format!("Parse error: {}", e));
+            return json(StatusCode::BAD_REQUEST, &error);
+        }
+    };
+
+    if let Value::Array(batch) = message {
+        let session = session.or_else(temporary_session);
+        let mut responses = Vec::new();
+        for message in batch {
+            if let Some(response) = session::in_session(session.clone(), Box::pin(handle_message(&handler, message))).await {
+                responses.push(response);
+            }
+        }
+        return match responses.is_empty() {
+            true => empty(StatusCode::ACCEPTED),
+            false => json(StatusCode::OK, &responses),
+        };
+    }
+
+    // Responses to server requests; this server sends none
+    if message.get("method").is_none() && (message.get("result").is_some() || message.get("error").is_some()) {
+        return empty(StatusCode::ACCEPTED);
+    }
+    let request = match handler.parse_request(message) {
+        Ok(request) => request,
+        Err(error) => return json(StatusCode::BAD_REQUEST, &error),
+    };
+    if request.id.is_none() {
+        session::in_session(session.or_else(temporary_session), Box::pin(handler.handle_request(request, None))).await;
+        return empty(StatusCode::ACCEPTED);
+    }
+    if accepts_sse && request.method == "tools/call" {
+        return tool_call_stream(handler, request, session.or_else(temporary_session));
+    }
+
+    // A new session starts with every initialize that does not name one
+    let new_session = (request.method == "initialize" && session.is_none()).then(|| handler.sessions().open());
+    let session = session
+        .or_else(|| new_session.as_deref().and_then(|id| handler.sessions().get(id)))
+        .or_else(temporary_session);
+    // Boxed: the handler future is large and the session scope would copy it
+    let response = session::in_session(session, Box::pin(handler.handle_request(request, None))).await;
+    let mut response = json(StatusCode::OK, &response);
+    if let Some(id) = new_session.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        response.headers_mut().insert(SESSION_HEADER, id);
+    }
+    response
+}
+
+/// Session for a request without `Mcp-Session-Id`: a throwaway one, so
+/// clients that skip sessions never share stored result sets
+fn temporary_session() -> Option<Arc<Session>> {
+    Some(Arc::new(Session::temporary()))
+}
+
+/// Response of one message of a batch; none for notifications
+async fn handle_message(handler: &McpHandler, message: Value) -> Option<JsonRpcResponse> {
+    match handler.parse_request(message) {
+        Ok(request) if request.id.is_some() => Some(handler.handle_request(request, None).await),
+        Ok(request) => {
+            handler.handle_request(request, None).await;
+            None
+        }
+        Err(error) => Some(*error),
+    }
+}
+
+/// Run a tool call, streaming its notifications and then its response
+fn tool_call_stream(handler: Arc<McpHandler>, request: JsonRpcRequest, session: Option<Arc<Session>>) -> Response<Body> {
+    let (events, body) = sse_body();
+    tokio::spawn(session::in_session(session, Box::pin(async move {
+        let (notify_tx, mut notify_rx) = mpsc::unbounded_channel::<JsonRpcNotification>();
+        let handle = handler.handle_request(request, Some(notify_tx));
+        tokio::pin!(handle);
+        let response = loop {
+            tokio::select! {
+                response = &mut handle => break response,
+                Some(notification) = notify_rx.recv() => {
+                    let _ = events.send(sse_event(&notification));
+                }
+            }
+        };
+        while let Ok(notification) = notify_rx.try_recv() {
+            let _ = events.send(sse_event(&notification));
+        }
+        // Dropping the sender ends the stream after the response; cancelled
+        // calls end it without one
+        if !response.is_cancelled() {
+            let _ = events.send(sse_event(&response));
+        }
+    })));
+    sse_response(body)
+}
+
+/// Stream of server-initiated messages for `GET`
+fn event_stream(handler: Arc<McpHandler>, session: Option<Arc<Session>>) -> Response<Body> {
+    let (events, body) = sse_body();
+    tokio::spawn(async move {
+        let mut changes = handler.tool_changes();
+        let mut logs = logging::subscribe();
+        let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+        loop {
+            let event = tokio::select! {
+                log = logging::next_at(&mut logs, || handler.client_log_level(session.as_deref())) => {
+                    sse_event(&log.notification())
+                }
+                changed = tools_changed(&mut changes) => {
+                    if !changed {
+                        changes = None;
+                        continue;
+                    }
+                    let notification = JsonRpcNotification::new("notifications/tools/list_changed", serde_json::json!({}));
+                    sse_event(&notification)
+                }
+                _ = keepalive.tick() => Bytes::from_static(b": keepalive\n\n"),
+            };
+            // The client went away
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    sse_response(body)
+}
+
+fn sse_body() -> (mpsc::UnboundedSender<Bytes>, Body) {
+    let (tx, rx) = mpsc::unbounded_channel::<Bytes>();
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok::<_, Infallible>(Frame::data(event)), rx))
+    });
+    (tx, StreamBody::new(stream).boxed_unsync())
+}
+
+fn sse_event<T: serde::Serialize>(message: &T) -> Bytes {
+    let json = serde_json::to_string(message).unwrap_or_default();
+    Bytes::from(format!("event: message\ndata: {}\n\n", json))
+}
+
+fn sse_response(body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+    response
+}
+
+fn json<T: serde::Serialize>(status: StatusCode, message: &T) -> Response<Body> {
+    let json = serde_json::to_vec(message).unwrap_or_default();
+    let mut response = Response::new(Full::new(Bytes::from(json)).boxed_unsync());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn plain(status: StatusCode, text: &str) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::from(text.to_string())).boxed_unsync());
+    *response.status_mut() = status;
+    response
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::new()).boxed_unsync());
+    *response.status_mut() = status;
+    response
+}
+
+/// Whether a browser `Origin` is this machine (guards against DNS rebinding)
+fn is_local_origin(origin: &HeaderValue) -> bool {
+    let Some(host) = origin
+        .to_str()
+        .ok()
+        .and_then(|o| reqwest::Url::parse(o).ok())
+        .and_then(|u| u.host_str().map(str::to_string))
+    else {
+        return false;
+    };
+    matches!(host.as_str(), "localhost" | "127.0.0.1" | "[::1]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start(auth: ApiKeys) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler = Arc::new(McpHandler::new(None));
+        tokio::spawn(async move { serve_listener(handler, listener, auth).await });
+        format!("http://{}{}", addr, MCP_PATH)
+    }
+
+    fn keys(keys: &[&str], header: Option<&str>) -> ApiKeys {
+        ApiKeys {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            header: header.map(String::from),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_json_and_notifications() {
+        let url = start(ApiKeys::default()).await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(&url)
+            .body(r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), r#"{"jsonrpc":"2.0","id":1,"result":{}}"#);
+
+        let response = client
+            .post(&url)
+            .body(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 202);
+
+        let response = client
+            .post(&url)
+            .header("Origin", "https://evil.example")
+            .body(r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_streams_response() {
+        let url = start(keys(&["secret"], None)).await;
+        let client = reqwest::Client::new();
+        let body = r#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"list_entities","arguments":{}}}"#;
+
+        let response = client.post(&url).body(body).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = client
+            .post(&url)
+            .bearer_auth("secret")
+            .header("Accept", "application/json, text/event-stream")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let text = response.text().await.unwrap();
+        assert!(text.starts_with("event: message\ndata: {\"jsonrpc\":\"2.0\",\"id\":2,"), "{}", text);
+        assert!(text.ends_with("\n\n"));
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let url = start(keys(&["old", "new"], Some("X-API-Key"))).await;
+        let client = reqwest::Client::new();
+        let initialize = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#;
+
+        let response = client.post(&url).bearer_auth("new").body(initialize).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = client
+            .post(&url)
+            .header("X-API-Key", "new")
+            .body(initialize)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let id = response.headers()[SESSION_HEADER].to_str().unwrap().to_string();
+
+        let ping = r#"{"jsonrpc":"2.0","id":2,"method":"ping"}"#;
+        let with_session = |method: Method| {
+            client
+                .request(method, &url)
+                .header("X-API-Key", "old")
+                .header(SESSION_HEADER, &id)
+        };
+        assert_eq!(with_session(Method::POST).body(ping).send().await.unwrap().status(), 200);
+        assert_eq!(with_session(Method::DELETE).send().await.unwrap().status(), 200);
+        assert_eq!(with_session(Method::POST).body(ping).send().await.unwrap().status(), 404);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"key", b"key"));
+        assert!(!constant_time_eq(b"key", b"kez"));
+        assert!(!constant_time_eq(b"key", b"keys"));
+    }
+}
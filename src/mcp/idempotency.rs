@@ -0,0 +1,257 @@
+//! Idempotency key tracking for tool calls
+//!
+//! Remembers the outcome of recent calls that carried an `idempotency_key`
+//! so a client retry after a dropped response replays the original result
+//! instead of performing the write again.
+
+use crate::mcp::protocol::CallToolResult;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Argument name clients use to pass the key
+pub const IDEMPOTENCY_KEY_ARG: &str = "idempotency_key";
+
+/// Maximum number of remembered keys
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone)]
+enum EntryState {
+    InFlight,
+    Completed(CallToolResult),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    fingerprint: String,
+    state: EntryState,
+    recorded_at: Instant,
+}
+
+/// Outcome of reserving a key before running a tool call
+#[derive(Debug)]
+pub enum Reservation {
+    /// First time this key is seen; run the call and then `complete` it
+    Proceed,
+    /// Same key and arguments completed earlier; replay this result
+    Replay(CallToolResult),
+    /// Key is unusable (in progress or reused with different arguments)
+    Rejected(String),
+}
+
+/// In-memory store of recent idempotency keys and their outcomes
+#[derive(Debug)]
+pub struct IdempotencyStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyStore {
+    /// Create a store remembering keys for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Build a stable fingerprint of a call, excluding the key itself
+    pub fn fingerprint(tool: &str, args: &HashMap<String, Value>) -> String {
+        // serde_json::Map is ordered, so this is independent of HashMap order
+        let map: serde_json::Map<String, Value> = args
+            .iter()
+            .filter(|(k, _)| k.as_str() != IDEMPOTENCY_KEY_ARG)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        format!("{}:{}", tool, Value::Object(map))
+    }
+
+    /// Reserve `key` for a call with the given fingerprint
+    pub fn reserve(&self, key: &str, fingerprint: &str) -> Reservation {
+        let mut entries = self.entries.lock().unwrap();
+        self.evict_expired(&mut entries);
+
+        if let Some(entry) = entries.get(key) {
+            if entry.fingerprint != fingerprint {
+                return Reservation::Rejected(format!(
+                    "idempotency_key '{}' was already used with a different tool or arguments",
+                    key
+                ));
+            }
+            return match &entry.state {
+                EntryState::InFlight => Reservation::Rejected(format!(
+                    "A request with idempotency_key '{}' is still in progress",
+                    key
+                )),
+                EntryState::Completed(result) => Reservation::Replay(result.clone()),
+            };
+        }
+
+        if entries.len() >= MAX_ENTRIES {
+            // Drop the oldest entry to stay bounded
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, e)| e.recorded_at)
+                .map(|(k, _)| k.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key.to_string(),
+            Entry {
+                fingerprint: fingerprint.to_string(),
+                state: EntryState::InFlight,
+                recorded_at: Instant::now(),
+            },
+        );
+        Reservation::Proceed
+    }
+
+    /// Record the outcome of a reserved call.
+    ///
+    /// Failed calls release the key so the client can retry them.
+    pub fn complete(&self, key: &str, result: &CallToolResult) {
+        let mut entries = self.entries.lock().unwrap();
+        if result.is_error == Some(true) {
+            entries.remove(key);
+            return;
+        }
+        if let Some(entry) = entries.get_mut(key) {
+            entry.state = EntryState::Completed(result.clone());
+            entry.recorded_at = Instant::now();
+        }
+    }
+
+    /// Forget `key` if its call is still in flight, e.g. when the call was
+    /// cancelled before it could `complete`
+    pub fn release(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if matches!(entries.get(key), Some(Entry { state: EntryState::InFlight, .. })) {
+            entries.remove(key);
+        }
+    }
+
+    fn evict_expired(&self, entries: &mut HashMap<String, Entry>) {
+        // In-flight entries expire too, so an abandoned call cannot pin its key forever
+        let ttl = self.ttl;
+        entries.retain(|_, e| e.recorded_at.elapsed() < ttl);
+    }
+}
+
+/// Holds a key reserved with [`Reservation::Proceed`] while its call runs.
+///
+/// Dropping the guard without completing it (the call's future was dropped,
+/// as on cancellation) releases the key, so a retry runs the call again.
+pub struct ReservationGuard<'a> {
+    store: &'a IdempotencyStore,
+    key: &'a str,
+    completed: bool,
+}
+
+impl<'a> ReservationGuard<'a> {
+    /// Guard `key`, just reserved in `store`
+    pub fn new(store: &'a IdempotencyStore, key: &'a str) -> Self {
+        Self {
+            store,
+            key,
+            completed: false,
+        }
+    }
+
+    /// Record the outcome of the call, see [`IdempotencyStore::complete`]
+    pub fn complete(mut self, result: &CallToolResult) {
+        self.store.complete(self.key, result);
+        self.completed = true;
+    }
+}
+
+impl Drop for ReservationGuard<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.store.release(self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(pairs: &[(&str, &str)]) -> HashMap<String, Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_key_and_order() {
+        let a = args(&[("entity", "accounts"), ("data", "{}"), ("idempotency_key", "k1")]);
+        let b = args(&[("data", "{}"), ("entity", "accounts"), ("idempotency_key", "k2")]);
+        assert_eq!(
+            IdempotencyStore::fingerprint("create_entity", &a),
+            IdempotencyStore::fingerprint("create_entity", &b)
+        );
+    }
+
+    #[test]
+    fn test_replay_after_success() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Proceed));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Rejected(_)));
+
+        store.complete("k1", &CallToolResult::text("created".to_string()));
+        match store.reserve("k1", "fp") {
+            Reservation::Replay(result) => assert_eq!(result.content[0].text, "created"),
+            other => panic!("expected replay, got {:?}", other),
+        }
+        assert!(matches!(store.reserve("k1", "other"), Reservation::Rejected(_)));
+    }
+
+    #[test]
+    fn test_failure_releases_key() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Proceed));
+        store.complete("k1", &CallToolResult::error("boom".to_string()));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Proceed));
+    }
+
+    #[test]
+    fn test_release_keeps_completed_results() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Proceed));
+        store.complete("k1", &CallToolResult::text("created".to_string()));
+        store.release("k1");
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Replay(_)));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_call_releases_key() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Proceed));
+        let call = async {
+            let guard = ReservationGuard::new(&store, "k1");
+            // A write that never returns, cancelled by the timeout below
+            std::future::pending::<()>().await;
+            guard.complete(&CallToolResult::text("created".to_string()));
+        };
+        assert!(tokio::time::timeout(Duration::from_millis(10), call).await.is_err());
+
+        // The retry runs again instead of being rejected as in progress
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Proceed));
+        let guard = ReservationGuard::new(&store, "k1");
+        guard.complete(&CallToolResult::text("created".to_string()));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Replay(_)));
+    }
+
+    #[test]
+    fn test_expired_entries_are_forgotten() {
+        let store = IdempotencyStore::new(Duration::from_millis(0));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Proceed));
+        store.complete("k1", &CallToolResult::text("ok".to_string()));
+        assert!(matches!(store.reserve("k1", "fp"), Reservation::Proceed));
+    }
+}
@@ -0,0 +1,202 @@
+//! MCP logging capability
+//!
+//! A `tracing` layer forwards the server's log events to connected clients as
+//! `notifications/message`. Each connection (or HTTP session) receives events
+//! at or above its level: the configured default until the client sends
+//! `logging/setLevel`.
+
+use crate::mcp::protocol::JsonRpcNotification;
+use std::fmt::Write as _;
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Log events buffered per slow client before the oldest are dropped
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Targets whose events are never forwarded: they log the messages being
+/// sent, so forwarding them would loop
+const SILENT_TARGETS: &[&str] = &["d365_odata_mcp::mcp::transport", "d365_odata_mcp::mcp::http"];
+
+/// Syslog severities used by MCP, least severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl LogLevel {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "debug" | "trace" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "notice" => Some(LogLevel::Notice),
+            "warning" | "warn" => Some(LogLevel::Warning),
+            "error" => Some(LogLevel::Error),
+            "critical" => Some(LogLevel::Critical),
+            "alert" => Some(LogLevel::Alert),
+            "emergency" => Some(LogLevel::Emergency),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+            LogLevel::Error => "error",
+            LogLevel::Critical => "critical",
+            LogLevel::Alert => "alert",
+            LogLevel::Emergency => "emergency",
+        }
+    }
+
+    fn from_tracing(level: &Level) -> Self {
+        match *level {
+            Level::ERROR => LogLevel::Error,
+            Level::WARN => LogLevel::Warning,
+            Level::INFO => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
+}
+
+/// One forwarded log event
+#[derive(Debug, Clone)]
+pub struct LogMessage {
+    pub level: LogLevel,
+    /// Module that logged it
+    pub logger: String,
+    pub message: String,
+}
+
+impl LogMessage {
+    pub fn notification(&self) -> JsonRpcNotification {
+        JsonRpcNotification::new(
+            "notifications/message",
+            serde_json::json!({
+                "level": self.level.as_str(),
+                "logger": self.logger,
+                "data": self.message,
+            }),
+        )
+    }
+}
+
+fn channel() -> &'static broadcast::Sender<LogMessage> {
+    static CHANNEL: OnceLock<broadcast::Sender<LogMessage>> = OnceLock::new();
+    CHANNEL.get_or_init(|| broadcast::channel(CHANNEL_CAPACITY).0)
+}
+
+/// Receive log events from now on
+pub fn subscribe() -> broadcast::Receiver<LogMessage> {
+    channel().subscribe()
+}
+
+/// Next log event at or above `level`; pending forever once the channel is gone
+pub async fn next_at(receiver: &mut broadcast::Receiver<LogMessage>, level: impl Fn() -> Option<LogLevel>) -> LogMessage {
+    loop {
+        match receiver.recv().await {
+            Ok(message) if level().is_some_and(|l| message.level >= l) => return message,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+/// Log events already received at or above `level`, without waiting
+pub fn drain_at(receiver: &mut broadcast::Receiver<LogMessage>, level: Option<LogLevel>) -> Vec<LogMessage> {
+    let mut messages = Vec::new();
+    loop {
+        match receiver.try_recv() {
+            Ok(message) if level.is_some_and(|l| message.level >= l) => messages.push(message),
+            Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+            Err(_) => return messages,
+        }
+    }
+}
+
+/// `tracing` layer publishing events of this crate to MCP clients
+#[derive(Debug, Default)]
+pub struct McpLogLayer;
+
+impl<S: Subscriber> Layer<S> for McpLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let target = event.metadata().target();
+        if !target.starts_with("d365_odata_mcp") || SILENT_TARGETS.iter().any(|t| target.starts_with(t)) {
+            return;
+        }
+        // Skip formatting while no client listens
+        if channel().receiver_count() == 0 {
+            return;
+        }
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let _ = channel().send(LogMessage {
+            level: LogLevel::from_tracing(event.metadata().level()),
+            logger: target.to_string(),
+            message: visitor.text,
+        });
+    }
+}
+
+/// Renders the `message` field followed by the other fields as `key=value`
+#[derive(Default)]
+struct MessageVisitor {
+    text: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.text.is_empty() {
+            self.text.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.text, "{:?}", value);
+        } else {
+            let _ = write!(self.text, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_log_level_order() {
+        assert_eq!(LogLevel::parse("WARN"), Some(LogLevel::Warning));
+        assert!(LogLevel::Error > LogLevel::Warning);
+        assert!(LogLevel::parse("verbose").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_layer_forwards_events() {
+        let mut receiver = subscribe();
+        let subscriber = tracing_subscriber::registry().with(McpLogLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("below the level");
+            tracing::warn!(entity = "accounts", "Slow page");
+            tracing::warn!(target: "hyper::proto", "not ours");
+        });
+
+        let message = next_at(&mut receiver, || Some(LogLevel::Warning)).await;
+        assert_eq!(message.level, LogLevel::Warning);
+        assert_eq!(message.message, "Slow page entity=\"accounts\"");
+        let notification = serde_json::to_value(message.notification()).unwrap();
+        assert_eq!(notification["params"]["logger"], "d365_odata_mcp::mcp::logging::tests");
+        assert!(receiver.try_recv().is_err());
+    }
+}
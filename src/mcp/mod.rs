@@ -2,8 +2,23 @@
 //!
 //! Exposes tools for querying and interacting with Dynamics 365 data
 
+pub mod completion;
+pub mod context;
+pub mod entity_tools;
+pub mod framing;
+mod handler;
+pub mod http;
+pub mod idempotency;
+pub mod logging;
 pub mod protocol;
+pub mod query_cache;
+pub mod prompts;
+pub mod resources;
+pub mod result_sets;
 mod server;
+pub mod session;
+pub mod transport;
 
+pub use handler::McpHandler;
 pub use protocol::*;
 pub use server::D365McpServer;
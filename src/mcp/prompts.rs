@@ -0,0 +1,229 @@
+//! MCP prompts
+//!
+//! Guided query workflows a client UI can offer as templates. Each prompt
+//! embeds the live schema of the chosen entity set from `$metadata`, so the
+//! model writes filters against real field names and types.
+
+use crate::config::ProductType;
+use crate::mcp::protocol::{GetPromptResult, Prompt, PromptArgument, PromptMessage, TextContent};
+use std::collections::HashMap;
+
+/// A prompt template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    /// Turn a plain-language description into `$filter`
+    BuildFilter,
+    /// Explain what an entity set holds and how to query it
+    SummarizeSchema,
+    /// Records changed within a time window
+    RecentChanges,
+}
+
+impl PromptKind {
+    const ALL: [PromptKind; 3] = [PromptKind::BuildFilter, PromptKind::SummarizeSchema, PromptKind::RecentChanges];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PromptKind::BuildFilter => "build_filter",
+            PromptKind::SummarizeSchema => "summarize_schema",
+            PromptKind::RecentChanges => "recent_changes",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            PromptKind::BuildFilter => "Build an OData $filter for an entity from a plain-language description and run it",
+            PromptKind::SummarizeSchema => "Summarize an entity's schema: what it stores, its keys, lookups and useful fields",
+            PromptKind::RecentChanges => "Show records of an entity created or modified recently",
+        }
+    }
+
+    /// Arguments as (name, description, required)
+    fn arguments(self) -> &'static [(&'static str, &'static str, bool)] {
+        const ENTITY: (&str, &str, bool) = ("entity", "Entity set name, e.g. accounts or CustomersV3", true);
+        match self {
+            PromptKind::BuildFilter => &[
+                ENTITY,
+                ("description", "Which records to find, e.g. 'open orders over 10k from last month'", true),
+                ("select", "Comma-separated fields to return (default: chosen from the description)", false),
+            ],
+            PromptKind::SummarizeSchema => &[ENTITY],
+            PromptKind::RecentChanges => &[
+                ENTITY,
+                ("window", "How far back to look, e.g. 24h or 7d (default: 7d)", false),
+            ],
+        }
+    }
+
+    pub fn definition(self) -> Prompt {
+        Prompt {
+            name: self.name().to_string(),
+            description: Some(self.description().to_string()),
+            arguments: self
+                .arguments()
+                .iter()
+                .map(|(name, description, required)| PromptArgument {
+                    name: name.to_string(),
+                    description: Some(description.to_string()),
+                    required: Some(*required),
+                })
+                .collect(),
+        }
+    }
+
+    /// Error for the first missing required argument
+    pub fn check_arguments(self, args: &HashMap<String, String>) -> Result<(), String> {
+        match self
+            .arguments()
+            .iter()
+            .find(|(name, _, required)| *required && args.get(*name).map_or(true, |v| v.trim().is_empty()))
+        {
+            Some((name, _, _)) => Err(format!("Missing required argument '{}' of prompt {}", name, self.name())),
+            None => Ok(()),
+        }
+    }
+
+    /// Prompt text for `entity`, whose schema is `schema` (as from `describe_entity`)
+    pub fn render(self, args: &HashMap<String, String>, schema: &str, product: &ProductType) -> GetPromptResult {
+        let entity = args.get("entity").map(String::as_str).unwrap_or_default();
+        let text = match self {
+            PromptKind::BuildFilter => format!(
+                "Find records in the {product} entity set `{entity}` matching this description:\n\n\
+                 > {description}\n\n\
+                 Write an OData `$filter` using only the fields below, with literals matching each \
+                 field's type (quoted strings, unquoted numbers and GUIDs, {enum_hint}). {lookup_hint}\
+                 Then call `query_entity` with `entity` = `{entity}`, the filter, and {select}. If the \
+                 description cannot be expressed with these fields, say which part is missing instead \
+                 of guessing.\n\n{schema}",
+                product = product_name(product),
+                entity = entity,
+                description = args.get("description").map(String::as_str).unwrap_or_default().trim(),
+                select = match args.get("select").map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                    Some(select) => format!("`select` = `{}`", select),
+                    None => "a `select` of the fields that matter for the description".to_string(),
+                },
+                enum_hint = match product {
+                    ProductType::Finops => "enums as `Namespace.EnumType'Member'`",
+                    ProductType::Dataverse => "option sets as integers",
+                },
+                lookup_hint = match product {
+                    ProductType::Dataverse => "Filter lookups on their `_<name>_value` fields. ",
+                    ProductType::Finops => "Add `cross_company: true` to search every legal entity. ",
+                },
+                schema = schema,
+            ),
+            PromptKind::SummarizeSchema => format!(
+                "Summarize the {product} entity set `{entity}` for someone about to query it: what one \
+                 record represents, its key, the most useful fields for filtering and display, its \
+                 lookups to other entities, and any enum fields with their values. Finish with two \
+                 example `query_entity` calls.\n\n{schema}",
+                product = product_name(product),
+                entity = entity,
+                schema = schema,
+            ),
+            PromptKind::RecentChanges => format!(
+                "List the `{entity}` records created or modified in the last {window}. Call \
+                 `query_entity` with `entity` = `{entity}`, `modified_within` = `{window}`, \
+                 `orderby` on the modified-on field descending and a `select` of identifying fields \
+                 from the schema below. Then summarize what changed, separating new records (also \
+                 created within {window}) from updated ones.\n\n{schema}",
+                entity = entity,
+                window = args.get("window").map(|w| w.trim()).filter(|w| !w.is_empty()).unwrap_or("7d"),
+                schema = schema,
+            ),
+        };
+        GetPromptResult {
+            description: Some(format!("{} ({})", self.description(), entity)),
+            messages: vec![PromptMessage {
+                role: "user".to_string(),
+                content: TextContent {
+                    content_type: "text".to_string(),
+                    text,
+                },
+            }],
+        }
+    }
+}
+
+/// Why a prompt cannot be rendered
+#[derive(Debug, Clone, PartialEq)]
+pub enum PromptError {
+    /// Unknown prompt, missing argument or unknown entity
+    InvalidParams(String),
+    /// `$metadata` failed to load
+    Unavailable(String),
+}
+
+impl PromptError {
+    /// JSON-RPC error code
+    pub fn code(&self) -> i32 {
+        match self {
+            PromptError::InvalidParams(_) => -32602,
+            PromptError::Unavailable(_) => -32603,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            PromptError::InvalidParams(message) | PromptError::Unavailable(message) => message,
+        }
+    }
+}
+
+fn product_name(product: &ProductType) -> &'static str {
+    match product {
+        ProductType::Dataverse => "Dataverse",
+        ProductType::Finops => "Finance & Operations",
+    }
+}
+
+/// Definitions for `prompts/list`
+pub fn prompt_list() -> Vec<Prompt> {
+    PromptKind::ALL.into_iter().map(PromptKind::definition).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_prompt_list() {
+        let prompts = prompt_list();
+        assert_eq!(prompts.len(), 3);
+        assert_eq!(PromptKind::parse("build_filter"), Some(PromptKind::BuildFilter));
+        assert!(PromptKind::parse("nope").is_none());
+        let build_filter = serde_json::to_value(&prompts[0]).unwrap();
+        assert_eq!(build_filter["arguments"][1]["name"], "description");
+        assert_eq!(build_filter["arguments"][1]["required"], true);
+    }
+
+    #[test]
+    fn test_check_arguments() {
+        assert!(PromptKind::BuildFilter.check_arguments(&args(&[("entity", "accounts")])).is_err());
+        assert!(PromptKind::RecentChanges.check_arguments(&args(&[("entity", "accounts")])).is_ok());
+    }
+
+    #[test]
+    fn test_render_embeds_schema() {
+        let result = PromptKind::BuildFilter.render(
+            &args(&[("entity", "accounts"), ("description", "big customers in Oslo")]),
+            "## accounts\n| revenue | Edm.Decimal |",
+            &ProductType::Dataverse,
+        );
+        let text = &result.messages[0].content.text;
+        assert!(text.contains("> big customers in Oslo"));
+        assert!(text.contains("| revenue | Edm.Decimal |"));
+        assert!(text.contains("_<name>_value"));
+
+        let result = PromptKind::RecentChanges.render(&args(&[("entity", "CustomersV3")]), "", &ProductType::Finops);
+        assert!(result.messages[0].content.text.contains("`modified_within` = `7d`"));
+    }
+}
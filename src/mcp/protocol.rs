@@ -16,6 +16,41 @@ pub struct JsonRpcRequest {
     pub params: Option<Value>,
 }
 
+impl JsonRpcRequest {
+    /// Check a message against JSON-RPC 2.0 and MCP before deserializing it:
+    /// `jsonrpc` is "2.0", `method` a string, `id` (if present) a string or
+    /// integer and `params` (if present) an object. The error response keeps
+    /// the id when it is usable.
+    pub fn validate_strict(message: &Value) -> Result<(), Box<JsonRpcResponse>> {
+        let Some(object) = message.as_object() else {
+            return Err(Box::new(JsonRpcResponse::error(None, -32600, "Invalid request: expected a JSON object")));
+        };
+        let id = object.get("id");
+        let valid_id = id.filter(|id| id.is_string() || id.is_i64() || id.is_u64()).cloned();
+        let invalid = |message: &str| {
+            Err(Box::new(JsonRpcResponse::error(valid_id.clone(), -32600, &format!("Invalid request: {}", message))))
+        };
+        if object.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return invalid("jsonrpc must be \"2.0\"");
+        }
+        if id.is_some() && valid_id.is_none() {
+            return invalid("id must be a string or an integer");
+        }
+        if !object.get("method").is_some_and(Value::is_string) {
+            return invalid("method must be a string");
+        }
+        match object.get("params") {
+            None | Some(Value::Object(_)) => Ok(()),
+            Some(Value::Array(_)) => Err(Box::new(JsonRpcResponse::error(
+                valid_id,
+                -32602,
+                "Invalid params: expected an object, not positional params",
+            ))),
+            Some(_) => invalid("params must be an object"),
+        }
+    }
+}
+
 /// JSON-RPC 2.0 Response
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonRpcResponse {
@@ -37,6 +72,10 @@ pub struct JsonRpcError {
     pub data: Option<Value>,
 }
 
+/// Error code of a request aborted by `notifications/cancelled`; such
+/// responses are not sent to the client
+pub const REQUEST_CANCELLED: i32 = -32800;
+
 impl JsonRpcResponse {
     pub fn success(id: Option<Value>, result: Value) -> Self {
         Self {
@@ -59,15 +98,102 @@ impl JsonRpcResponse {
             }),
         }
     }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.error.as_ref().is_some_and(|e| e.code == REQUEST_CANCELLED)
+    }
+}
+
+/// JSON-RPC 2.0 Notification (server to client, no id)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JsonRpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+impl JsonRpcNotification {
+    pub fn new(method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+        }
+    }
 }
 
 // MCP Protocol Types
 
+/// MCP protocol revisions the server speaks, oldest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum ProtocolVersion {
+    /// Assumed until `initialize` negotiates another
+    #[default]
+    V2024_11_05,
+    /// Adds tool annotations
+    V2025_03_26,
+    /// Adds `title` on server info
+    V2025_06_18,
+}
+
+impl ProtocolVersion {
+    pub const SUPPORTED: [ProtocolVersion; 3] = [
+        ProtocolVersion::V2024_11_05,
+        ProtocolVersion::V2025_03_26,
+        ProtocolVersion::V2025_06_18,
+    ];
+
+    pub const LATEST: ProtocolVersion = ProtocolVersion::V2025_06_18;
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProtocolVersion::V2024_11_05 => "2024-11-05",
+            ProtocolVersion::V2025_03_26 => "2025-03-26",
+            ProtocolVersion::V2025_06_18 => "2025-06-18",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        Self::SUPPORTED.into_iter().find(|v| v.as_str() == text)
+    }
+
+    /// The client's version if supported, else the latest one; the client
+    /// disconnects if it cannot speak that
+    pub fn negotiate(requested: Option<&str>) -> Self {
+        requested.and_then(Self::parse).unwrap_or(Self::LATEST)
+    }
+}
+
 /// Server capabilities
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct ServerCapabilities {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<ToolsCapability>,
+    /// Present (as `{}`) when the server sends `notifications/message`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logging: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourcesCapability>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompts: Option<PromptsCapability>,
+    /// Present (as `{}`) when the server answers `completion/complete`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completions: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ResourcesCapability {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscribe: Option<bool>,
+    #[serde(rename = "listChanged", skip_serializing_if = "Option::is_none")]
+    pub list_changed: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct PromptsCapability {
+    #[serde(rename = "listChanged", skip_serializing_if = "Option::is_none")]
+    pub list_changed: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -80,6 +206,9 @@ pub struct ToolsCapability {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerInfo {
     pub name: String,
+    /// Display name (2025-06-18)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
     pub version: String,
 }
 
@@ -100,6 +229,41 @@ pub struct Tool {
     pub description: String,
     #[serde(rename = "inputSchema")]
     pub input_schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<ToolAnnotations>,
+}
+
+/// Hints at a tool's effects on the D365 environment (protocol 2025-03-26),
+/// so clients can ask for confirmation before destructive calls
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ToolAnnotations {
+    #[serde(rename = "readOnlyHint", skip_serializing_if = "Option::is_none")]
+    pub read_only_hint: Option<bool>,
+    /// Overwrites or removes data; only meaningful when not read-only
+    #[serde(rename = "destructiveHint", skip_serializing_if = "Option::is_none")]
+    pub destructive_hint: Option<bool>,
+    /// Repeating a call with the same arguments has no further effect
+    #[serde(rename = "idempotentHint", skip_serializing_if = "Option::is_none")]
+    pub idempotent_hint: Option<bool>,
+}
+
+impl ToolAnnotations {
+    /// Reads data only
+    pub fn read_only() -> Option<Self> {
+        Some(Self {
+            read_only_hint: Some(true),
+            ..Default::default()
+        })
+    }
+
+    /// Changes data
+    pub fn write(destructive: bool, idempotent: bool) -> Option<Self> {
+        Some(Self {
+            read_only_hint: Some(false),
+            destructive_hint: Some(destructive),
+            idempotent_hint: Some(idempotent),
+        })
+    }
 }
 
 /// List tools result
@@ -108,16 +272,135 @@ pub struct ListToolsResult {
     pub tools: Vec<Tool>,
 }
 
+/// Resource listed by `resources/list`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "mimeType", skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// Parameterized resource listed by `resources/templates/list`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResourceTemplate {
+    #[serde(rename = "uriTemplate")]
+    pub uri_template: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "mimeType", skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// List resources result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListResourcesResult {
+    pub resources: Vec<Resource>,
+}
+
+/// List resource templates result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListResourceTemplatesResult {
+    #[serde(rename = "resourceTemplates")]
+    pub resource_templates: Vec<ResourceTemplate>,
+}
+
+/// Text contents of a read resource
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResourceContents {
+    pub uri: String,
+    #[serde(rename = "mimeType", skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    pub text: String,
+}
+
+/// Read resource result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadResourceResult {
+    pub contents: Vec<ResourceContents>,
+}
+
+/// Prompt template listed by `prompts/list`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Prompt {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub arguments: Vec<PromptArgument>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PromptArgument {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required: Option<bool>,
+}
+
+/// List prompts result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListPromptsResult {
+    pub prompts: Vec<Prompt>,
+}
+
+/// Message of a rendered prompt
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PromptMessage {
+    /// `user` or `assistant`
+    pub role: String,
+    pub content: TextContent,
+}
+
+/// Get prompt result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetPromptResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub messages: Vec<PromptMessage>,
+}
+
+/// Suggested values of `completion/complete`
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Completion {
+    pub values: Vec<String>,
+    /// Number of matches, including those beyond `values`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    #[serde(rename = "hasMore", skip_serializing_if = "Option::is_none")]
+    pub has_more: Option<bool>,
+}
+
+/// Complete result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompleteResult {
+    pub completion: Completion,
+}
+
 /// Call tool request params
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CallToolParams {
     pub name: String,
     #[serde(default)]
     pub arguments: Option<HashMap<String, Value>>,
+    #[serde(rename = "_meta", default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<RequestMeta>,
+}
+
+/// Request metadata (`_meta`) sent by the client
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RequestMeta {
+    /// Token to attach to `notifications/progress` for this request
+    #[serde(rename = "progressToken", default, skip_serializing_if = "Option::is_none")]
+    pub progress_token: Option<Value>,
 }
 
 /// Tool result content
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TextContent {
     #[serde(rename = "type")]
     pub content_type: String,
@@ -125,7 +408,7 @@ pub struct TextContent {
 }
 
 /// Call tool result
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CallToolResult {
     pub content: Vec<TextContent>,
     #[serde(rename = "isError", skip_serializing_if = "Option::is_none")]
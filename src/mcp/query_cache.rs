@@ -0,0 +1,154 @@
+//! Short-lived cache of query results
+//!
+//! Agents often send the same query twice within one conversation, e.g.
+//! to look at a result again after a few other calls. When `[query_cache]`
+//! is enabled, complete results of `query_entity` are kept for a few
+//! seconds to minutes by entity and query string. Writes through this
+//! server drop the cached results of the entity they touched; changes made
+//! by others show up once the entry expires.
+
+use crate::odata::PagedFetch;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Argument that bypasses the cache for one call when `false`
+pub const CACHE_ARG: &str = "cache";
+
+#[derive(Debug)]
+struct Entry {
+    fetch: PagedFetch,
+    stored_at: Instant,
+}
+
+/// Query results by (entity, query string)
+#[derive(Debug)]
+pub struct QueryCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<(String, String), Entry>>,
+}
+
+impl QueryCache {
+    /// Keep up to `max_entries` results for `ttl` each
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Result of `query` on `entity` and its age, if stored less than the TTL ago
+    pub fn get(&self, entity: &str, query: &str) -> Option<(PagedFetch, Duration)> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (entity.to_string(), query.to_string());
+        match entries.get(&key) {
+            Some(entry) if entry.stored_at.elapsed() < self.ttl => {
+                Some((entry.fetch.clone(), entry.stored_at.elapsed()))
+            }
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Store a complete result; partial results are never cached
+    pub fn insert(&self, entity: &str, query: &str, fetch: &PagedFetch) {
+        if fetch.partial || self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = self.ttl;
+        entries.retain(|_, entry| entry.stored_at.elapsed() < ttl);
+        let key = (entity.to_string(), query.to_string());
+        if !entries.contains_key(&key) && entries.len() >= self.max_entries {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                fetch: fetch.clone(),
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Drop the results of `entity`, or of every entity when `None`
+    pub fn invalidate(&self, entity: Option<&str>) {
+        let mut entries = self.entries.lock().unwrap();
+        match entity {
+            Some(entity) => entries.retain(|(cached, _), _| !cached.eq_ignore_ascii_case(entity)),
+            None => entries.clear(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fetch(n: i64) -> PagedFetch {
+        PagedFetch {
+            records: vec![json!({ "n": n })],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_get_insert_and_expiry() {
+        let cache = QueryCache::new(Duration::from_secs(60), 10);
+        assert!(cache.get("accounts", "?$top=5").is_none());
+        cache.insert("accounts", "?$top=5", &fetch(1));
+        assert_eq!(cache.get("accounts", "?$top=5").unwrap().0.records, [json!({ "n": 1 })]);
+        assert!(cache.get("accounts", "?$top=6").is_none());
+
+        let expired = QueryCache::new(Duration::ZERO, 10);
+        expired.insert("accounts", "?$top=5", &fetch(1));
+        assert!(expired.get("accounts", "?$top=5").is_none());
+    }
+
+    #[test]
+    fn test_partial_results_not_cached() {
+        let cache = QueryCache::new(Duration::from_secs(60), 10);
+        let partial = PagedFetch {
+            partial: true,
+            ..fetch(1)
+        };
+        cache.insert("accounts", "", &partial);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_evicts_oldest_and_invalidates() {
+        let cache = QueryCache::new(Duration::from_secs(60), 2);
+        cache.insert("accounts", "a", &fetch(1));
+        cache.insert("contacts", "b", &fetch(2));
+        cache.insert("contacts", "c", &fetch(3));
+        assert!(cache.get("accounts", "a").is_none());
+        assert_eq!(cache.len(), 2);
+
+        cache.insert("accounts", "a", &fetch(1));
+        cache.invalidate(Some("Contacts"));
+        assert_eq!(cache.len(), 1);
+        cache.invalidate(None);
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+//! MCP resources
+//!
+//! Read-only context a client can pull without spending a tool call: the
+//! entity catalog, the schema of each entity set, the parsed `$metadata`
+//! model and the effective configuration with secrets redacted.
+
+use crate::mcp::protocol::{Resource, ResourceTemplate};
+use crate::odata::metadata::EdmModel;
+use serde_json::Value;
+
+/// Entity sets with their types and keys
+pub const CATALOG_URI: &str = "d365://entities";
+
+/// Prefix of `d365://entities/{entity}` schema resources
+pub const ENTITY_URI_PREFIX: &str = "d365://entities/";
+
+/// Parsed `$metadata` model
+pub const METADATA_URI: &str = "d365://metadata";
+
+/// Effective configuration
+pub const CONFIG_URI: &str = "d365://config";
+
+/// Resource named by a URI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceUri {
+    Catalog,
+    Entity(String),
+    Metadata,
+    Config,
+}
+
+impl ResourceUri {
+    pub fn parse(uri: &str) -> Option<Self> {
+        match uri {
+            CATALOG_URI => Some(ResourceUri::Catalog),
+            METADATA_URI => Some(ResourceUri::Metadata),
+            CONFIG_URI => Some(ResourceUri::Config),
+            _ => uri
+                .strip_prefix(ENTITY_URI_PREFIX)
+                .filter(|name| !name.is_empty() && !name.contains('/'))
+                .map(|name| ResourceUri::Entity(name.to_string())),
+        }
+    }
+}
+
+pub fn entity_uri(entity: &str) -> String {
+    format!("{}{}", ENTITY_URI_PREFIX, entity)
+}
+
+/// Why a resource cannot be read
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceError {
+    NotFound(String),
+    /// Exists but cannot be produced now, e.g. `$metadata` failed to load
+    Unavailable(String),
+}
+
+impl ResourceError {
+    /// JSON-RPC error code
+    pub fn code(&self) -> i32 {
+        match self {
+            ResourceError::NotFound(_) => -32002,
+            ResourceError::Unavailable(_) => -32603,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ResourceError::NotFound(message) | ResourceError::Unavailable(message) => message,
+        }
+    }
+}
+
+fn resource(uri: String, name: &str, description: &str, mime_type: &str) -> Resource {
+    Resource {
+        uri,
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        mime_type: Some(mime_type.to_string()),
+    }
+}
+
+/// Resources every configured server offers, followed by the schemas of `entities`
+pub fn resource_list<'a>(entities: impl IntoIterator<Item = &'a str>) -> Vec<Resource> {
+    let mut resources = vec![
+        resource(
+            CATALOG_URI.to_string(),
+            "Entity catalog",
+            "Entity sets with their entity types and key fields",
+            "application/json",
+        ),
+        resource(
+            METADATA_URI.to_string(),
+            "$metadata",
+            "Parsed $metadata model: entity sets, entity types with properties and navigation properties, enum types",
+            "application/json",
+        ),
+        resource(
+            CONFIG_URI.to_string(),
+            "Configuration",
+            "Effective server configuration with secrets redacted",
+            "application/json",
+        ),
+    ];
+    resources.extend(entities.into_iter().map(|entity| {
+        resource(
+            entity_uri(entity),
+            &format!("{} schema", entity),
+            &format!("Fields, keys and enum values of {}", entity),
+            "text/markdown",
+        )
+    }));
+    resources
+}
+
+pub fn templates() -> Vec<ResourceTemplate> {
+    vec![ResourceTemplate {
+        uri_template: format!("{}{{entity}}", ENTITY_URI_PREFIX),
+        name: "Entity schema".to_string(),
+        description: Some("Fields, keys and enum values of an entity set from $metadata".to_string()),
+        mime_type: Some("text/markdown".to_string()),
+    }]
+}
+
+/// Catalog of the entity sets in `model`
+pub fn catalog(model: &EdmModel) -> Value {
+    let entities: Vec<Value> = model
+        .entity_sets
+        .iter()
+        .map(|set| {
+            let keys = model
+                .entity_type_for_set(set)
+                .map(|t| model.keys_of(t).to_vec())
+                .unwrap_or_default();
+            serde_json::json!({
+                "name": set.name,
+                "entity_type": set.entity_type,
+                "keys": keys,
+                "uri": entity_uri(&set.name),
+            })
+        })
+        .collect();
+    serde_json::json!({ "entities": entities })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resource_uri() {
+        assert_eq!(ResourceUri::parse("d365://entities"), Some(ResourceUri::Catalog));
+        assert_eq!(
+            ResourceUri::parse("d365://entities/accounts"),
+            Some(ResourceUri::Entity("accounts".to_string()))
+        );
+        assert_eq!(ResourceUri::parse("d365://config"), Some(ResourceUri::Config));
+        assert_eq!(ResourceUri::parse("d365://entities/"), None);
+        assert_eq!(ResourceUri::parse("file:///etc/passwd"), None);
+    }
+
+    #[test]
+    fn test_catalog() {
+        let model = EdmModel::parse(
+            r#"<edmx:Edmx><edmx:DataServices><Schema Namespace="NS">
+                <EntityType Name="account"><Key><PropertyRef Name="accountid"/></Key>
+                    <Property Name="accountid" Type="Edm.Guid" Nullable="false"/></EntityType>
+                <EntityContainer Name="C"><EntitySet Name="accounts" EntityType="NS.account"/></EntityContainer>
+            </Schema></edmx:DataServices></edmx:Edmx>"#,
+        );
+        let catalog = catalog(&model);
+        assert_eq!(catalog["entities"][0]["name"], "accounts");
+        assert_eq!(catalog["entities"][0]["keys"], serde_json::json!(["accountid"]));
+        assert_eq!(catalog["entities"][0]["uri"], "d365://entities/accounts");
+    }
+}
@@ -0,0 +1,551 @@
+//! Named result sets kept in memory for the session
+//!
+//! A query can store its full result under a name; later tool calls filter,
+//! sort, aggregate or export that set locally instead of re-querying D365.
+
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Maximum number of stored sets; the oldest is evicted beyond this
+pub const MAX_RESULT_SETS: usize = 20;
+
+/// Maximum rows a query may fetch into a stored set
+pub const MAX_STORED_ROWS: usize = 50_000;
+
+/// A stored query result
+#[derive(Debug)]
+pub struct ResultSet {
+    pub entity: String,
+    pub records: Vec<Value>,
+    pub stored_at: Instant,
+    /// Insertion order, for eviction
+    seq: u64,
+}
+
+/// Session-wide store of named result sets
+#[derive(Debug, Default)]
+pub struct ResultSetStore {
+    sets: Mutex<HashMap<String, Arc<ResultSet>>>,
+    next_seq: AtomicU64,
+}
+
+impl ResultSetStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store (or replace) a set, evicting the oldest one when full
+    pub fn insert(&self, name: &str, entity: &str, records: Vec<Value>) {
+        let mut sets = self.sets.lock().unwrap();
+        if !sets.contains_key(name) && sets.len() >= MAX_RESULT_SETS {
+            if let Some(oldest) = sets
+                .iter()
+                .min_by_key(|(_, set)| set.seq)
+                .map(|(name, _)| name.clone())
+            {
+                tracing::info!("Evicting result set '{}'", oldest);
+                sets.remove(&oldest);
+            }
+        }
+        sets.insert(
+            name.to_string(),
+            Arc::new(ResultSet {
+                entity: entity.to_string(),
+                records,
+                stored_at: Instant::now(),
+                seq: self.next_seq.fetch_add(1, AtomicOrdering::Relaxed),
+            }),
+        );
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<ResultSet>> {
+        self.sets.lock().unwrap().get(name).cloned()
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        self.sets.lock().unwrap().remove(name).is_some()
+    }
+
+    /// (name, set) pairs, oldest first
+    pub fn list(&self) -> Vec<(String, Arc<ResultSet>)> {
+        let mut sets: Vec<_> = self
+            .sets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, set)| (name.clone(), set.clone()))
+            .collect();
+        sets.sort_by_key(|(_, set)| set.seq);
+        sets
+    }
+}
+
+/// Comparison operator of a filter condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+/// One `field op value` condition
+#[derive(Debug, Clone)]
+pub struct Condition {
+    field: String,
+    op: Op,
+    value: Value,
+}
+
+/// Parse an OData-style filter over stored rows.
+///
+/// Supports `field eq|ne|gt|ge|lt|le <literal>` and
+/// `contains|startswith|endswith(field,'text')`, joined with `and`.
+/// Literals are quoted strings, numbers, `true`, `false` or `null`.
+pub fn parse_filter(text: &str) -> Result<Vec<Condition>, String> {
+    split_and(text)
+        .iter()
+        .map(|clause| parse_condition(clause))
+        .collect()
+}
+
+/// Split on ` and ` outside quoted strings
+fn split_and(text: &str) -> Vec<String> {
+    let mut clauses = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\'' {
+            in_quotes = !in_quotes;
+        }
+        if !in_quotes && rest.get(..5).is_some_and(|s| s.eq_ignore_ascii_case(" and ")) {
+            clauses.push(current.trim().to_string());
+            current.clear();
+            rest = &rest[5..];
+            continue;
+        }
+        current.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if !current.trim().is_empty() {
+        clauses.push(current.trim().to_string());
+    }
+    clauses
+}
+
+fn parse_condition(clause: &str) -> Result<Condition, String> {
+    let invalid = || format!("Invalid filter condition '{}'", clause);
+
+    for (name, op) in [
+        ("contains(", Op::Contains),
+        ("startswith(", Op::StartsWith),
+        ("endswith(", Op::EndsWith),
+    ] {
+        if clause.get(..name.len()).is_some_and(|p| p.eq_ignore_ascii_case(name)) {
+            let args = clause[name.len()..].strip_suffix(')').ok_or_else(invalid)?;
+            let (field, literal) = args.split_once(',').ok_or_else(invalid)?;
+            return Ok(Condition {
+                field: field.trim().to_string(),
+                op,
+                value: parse_literal(literal.trim()).ok_or_else(invalid)?,
+            });
+        }
+    }
+
+    let (field, rest) = clause.split_once(char::is_whitespace).ok_or_else(invalid)?;
+    let (op, literal) = rest.trim_start().split_once(char::is_whitespace).ok_or_else(invalid)?;
+    let op = match op.to_ascii_lowercase().as_str() {
+        "eq" => Op::Eq,
+        "ne" => Op::Ne,
+        "gt" => Op::Gt,
+        "ge" => Op::Ge,
+        "lt" => Op::Lt,
+        "le" => Op::Le,
+        _ => return Err(invalid()),
+    };
+    Ok(Condition {
+        field: field.to_string(),
+        op,
+        value: parse_literal(literal.trim()).ok_or_else(invalid)?,
+    })
+}
+
+fn parse_literal(text: &str) -> Option<Value> {
+    if let Some(inner) = text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+        return Some(Value::String(inner.replace("''", "'")));
+    }
+    match text {
+        "null" => Some(Value::Null),
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => serde_json::from_str::<Value>(text).ok().filter(Value::is_number),
+    }
+}
+
+/// Whether a record satisfies every condition
+pub fn matches(record: &Value, conditions: &[Condition]) -> bool {
+    conditions.iter().all(|c| {
+        let actual = record.get(&c.field).unwrap_or(&Value::Null);
+        match c.op {
+            Op::Eq => compare(actual, &c.value) == Some(Ordering::Equal),
+            Op::Ne => compare(actual, &c.value) != Some(Ordering::Equal),
+            Op::Gt => compare(actual, &c.value) == Some(Ordering::Greater),
+            Op::Ge => matches!(compare(actual, &c.value), Some(Ordering::Greater | Ordering::Equal)),
+            Op::Lt => compare(actual, &c.value) == Some(Ordering::Less),
+            Op::Le => matches!(compare(actual, &c.value), Some(Ordering::Less | Ordering::Equal)),
+            Op::Contains | Op::StartsWith | Op::EndsWith => {
+                let (Some(actual), Some(wanted)) = (actual.as_str(), c.value.as_str()) else {
+                    return false;
+                };
+                let (actual, wanted) = (actual.to_lowercase(), wanted.to_lowercase());
+                match c.op {
+                    Op::Contains => actual.contains(&wanted),
+                    Op::StartsWith => actual.starts_with(&wanted),
+                    _ => actual.ends_with(&wanted),
+                }
+            }
+        }
+    })
+}
+
+/// Numeric value of a number or numeric string (IEEE754Compatible decimals)
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Compare two values: numerically when both are numeric, strings
+/// case-insensitively; `None` when they are not comparable
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        (Value::String(x), Value::String(y)) => match (as_number(a), as_number(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => Some(x.to_lowercase().cmp(&y.to_lowercase())),
+        },
+        _ => as_number(a)?.partial_cmp(&as_number(b)?),
+    }
+}
+
+/// Sort by an OData-style `orderby` list, e.g. `Amount desc, Name`. Nulls sort first.
+pub fn sort_records(records: &mut [Value], orderby: &str) -> Result<(), String> {
+    let mut keys = Vec::new();
+    for part in orderby.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut words = part.split_whitespace();
+        let field = words.next().unwrap_or_default().to_string();
+        let descending = match words.next().map(|w| w.to_ascii_lowercase()) {
+            None => false,
+            Some(w) if w == "asc" => false,
+            Some(w) if w == "desc" => true,
+            Some(_) => return Err(format!("Invalid sort '{}': expected 'field [asc|desc]'", part)),
+        };
+        keys.push((field, descending));
+    }
+
+    records.sort_by(|a, b| {
+        for (field, descending) in &keys {
+            let (x, y) = (a.get(field).unwrap_or(&Value::Null), b.get(field).unwrap_or(&Value::Null));
+            let ordering = match (x.is_null(), y.is_null()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                _ => compare(x, y).unwrap_or(Ordering::Equal),
+            };
+            let ordering = if *descending { ordering.reverse() } else { ordering };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    });
+    Ok(())
+}
+
+/// An aggregate function over a group
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregate {
+    Count,
+    Sum(String),
+    Avg(String),
+    Min(String),
+    Max(String),
+}
+
+impl Aggregate {
+    fn column(&self) -> String {
+        match self {
+            Aggregate::Count => "count".to_string(),
+            Aggregate::Sum(f) => format!("sum_{}", f),
+            Aggregate::Avg(f) => format!("avg_{}", f),
+            Aggregate::Min(f) => format!("min_{}", f),
+            Aggregate::Max(f) => format!("max_{}", f),
+        }
+    }
+}
+
+/// Parse `count, sum(Amount), avg(Qty), min(Date), max(Date)`
+pub fn parse_aggregates(text: &str) -> Result<Vec<Aggregate>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|part| {
+            if part.eq_ignore_ascii_case("count") {
+                return Ok(Aggregate::Count);
+            }
+            let (func, field) = part
+                .strip_suffix(')')
+                .and_then(|p| p.split_once('('))
+                .map(|(func, field)| (func.trim().to_ascii_lowercase(), field.trim().to_string()))
+                .filter(|(_, field)| !field.is_empty())
+                .ok_or_else(|| format!("Invalid aggregate '{}': expected e.g. 'sum(Amount)'", part))?;
+            match func.as_str() {
+                "sum" => Ok(Aggregate::Sum(field)),
+                "avg" => Ok(Aggregate::Avg(field)),
+                "min" => Ok(Aggregate::Min(field)),
+                "max" => Ok(Aggregate::Max(field)),
+                _ => Err(format!("Unknown aggregate function '{}'", func)),
+            }
+        })
+        .collect()
+}
+
+/// Group records and compute aggregates; groups keep first-seen order
+pub fn aggregate(records: &[Value], group_by: &[String], aggregates: &[Aggregate]) -> Vec<Value> {
+    let mut order: Vec<String> = Vec::new();
+    let mut groups: HashMap<String, Vec<&Value>> = HashMap::new();
+    for record in records {
+        let key = group_by
+            .iter()
+            .map(|f| record.get(f).unwrap_or(&Value::Null).to_string())
+            .collect::<Vec<_>>()
+            .join("\u{1f}");
+        groups
+            .entry(key.clone())
+            .or_insert_with(|| {
+                order.push(key);
+                Vec::new()
+            })
+            .push(record);
+    }
+
+    order
+        .iter()
+        .map(|key| {
+            let rows = &groups[key];
+            let mut row = Map::new();
+            for field in group_by {
+                row.insert(field.clone(), rows[0].get(field).cloned().unwrap_or(Value::Null));
+            }
+            for agg in aggregates {
+                row.insert(agg.column(), compute(agg, rows));
+            }
+            Value::Object(row)
+        })
+        .collect()
+}
+
+fn compute(agg: &Aggregate, rows: &[&Value]) -> Value {
+    let field_values = |field: &str| -> Vec<&Value> {
+        rows.iter()
+            .filter_map(|r| r.get(field))
+            .filter(|v| !v.is_null())
+            .collect()
+    };
+    let numbers = |field: &str| -> Vec<f64> { field_values(field).into_iter().filter_map(as_number).collect() };
+
+    match agg {
+        Aggregate::Count => Value::from(rows.len()),
+        Aggregate::Sum(f) => Value::from(numbers(f).iter().sum::<f64>()),
+        Aggregate::Avg(f) => {
+            let n = numbers(f);
+            if n.is_empty() {
+                Value::Null
+            } else {
+                Value::from(n.iter().sum::<f64>() / n.len() as f64)
+            }
+        }
+        Aggregate::Min(f) | Aggregate::Max(f) => {
+            let want = if matches!(agg, Aggregate::Min(_)) { Ordering::Less } else { Ordering::Greater };
+            field_values(f)
+                .into_iter()
+                .reduce(|best, v| if compare(v, best) == Some(want) { v } else { best })
+                .cloned()
+                .unwrap_or(Value::Null)
+        }
+    }
+}
+
+/// Render records as CSV; columns are the union of fields in first-seen order
+pub fn to_csv(records: &[Value]) -> String {
+    let mut columns: Vec<String> = Vec::new();
+    for record in records {
+        if let Value::Object(fields) = record {
+            for name in fields.keys() {
+                if !columns.contains(name) {
+                    columns.push(name.clone());
+                }
+            }
+        }
+    }
+
+    let mut csv = columns.iter().map(|c| csv_field(c)).collect::<Vec<_>>().join(",");
+    csv.push('\n');
+    for record in records {
+        let line = columns
+            .iter()
+            .map(|c| match record.get(c) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => csv_field(s),
+                Some(other) => csv_field(&other.to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        csv.push_str(&line);
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Write an export to `path` inside `dir`. `path` must be relative and stay
+/// within `dir`; existing files are never overwritten.
+pub fn write_export(dir: &Path, path: &str, content: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    if path.trim().is_empty() || relative.has_root() {
+        return Err(format!("Export path '{}' must be relative to the export directory", path));
+    }
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!("Export path '{}' must not leave the export directory", path));
+    }
+    let target = dir.join(relative);
+    let parent = target.parent().unwrap_or(dir);
+    let io_error = |e: io::Error| format!("Failed to write {}: {}", target.display(), e);
+    fs::create_dir_all(parent).map_err(io_error)?;
+    // A symlinked subdirectory could still point elsewhere
+    let root = dir.canonicalize().map_err(io_error)?;
+    if !parent.canonicalize().map_err(io_error)?.starts_with(&root) {
+        return Err(format!("Export path '{}' must not leave the export directory", path));
+    }
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&target) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(format!("{} already exists; choose another path", target.display()))
+        }
+        Err(e) => return Err(io_error(e)),
+    };
+    file.write_all(content.as_bytes()).map_err(io_error)?;
+    Ok(target)
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rows() -> Vec<Value> {
+        vec![
+            json!({ "Name": "Contoso", "Status": "Open", "Amount": 100 }),
+            json!({ "Name": "Fabrikam, Inc", "Status": "open", "Amount": "250.5" }),
+            json!({ "Name": "Litware", "Status": "Closed", "Amount": 40 }),
+            json!({ "Name": "Tailspin", "Status": null, "Amount": null }),
+        ]
+    }
+
+    #[test]
+    fn test_filter() {
+        let conditions = parse_filter("Status eq 'OPEN' and Amount gt 150").unwrap();
+        let hits: Vec<_> = rows().into_iter().filter(|r| matches(r, &conditions)).collect();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0]["Name"], "Fabrikam, Inc");
+
+        let conditions = parse_filter("contains(Name,'ware') and Status ne null").unwrap();
+        assert_eq!(rows().iter().filter(|r| matches(r, &conditions)).count(), 1);
+
+        let conditions = parse_filter("Name eq 'Bob and Alice'").unwrap();
+        assert_eq!(conditions.len(), 1);
+        assert!(parse_filter("Amount between 1").is_err());
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut records = rows();
+        sort_records(&mut records, "Amount desc").unwrap();
+        let names: Vec<_> = records.iter().map(|r| r["Name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["Fabrikam, Inc", "Contoso", "Litware", "Tailspin"]);
+        assert!(sort_records(&mut records, "Amount sideways").is_err());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let aggs = parse_aggregates("count, sum(Amount), max(Name)").unwrap();
+        let result = aggregate(&rows(), &["Status".to_string()], &aggs);
+        assert_eq!(result.len(), 4); // "Open" and "open" are distinct groups
+        assert_eq!(result[0], json!({ "Status": "Open", "count": 1, "sum_Amount": 100.0, "max_Name": "Contoso" }));
+
+        let total = aggregate(&rows(), &[], &parse_aggregates("count,avg(Amount)").unwrap());
+        assert_eq!(total, vec![json!({ "count": 4, "avg_Amount": 130.16666666666666 })]);
+        assert!(parse_aggregates("median(Amount)").is_err());
+    }
+
+    #[test]
+    fn test_csv_escaping() {
+        let csv = to_csv(&rows()[..2]);
+        assert_eq!(csv, "Amount,Name,Status\n100,Contoso,Open\n250.5,\"Fabrikam, Inc\",open\n");
+    }
+
+    #[test]
+    fn test_write_export() {
+        let dir = std::env::temp_dir().join(format!("d365-export-{}", std::process::id()));
+        let written = write_export(&dir, "reports/open.csv", "a,b\n").unwrap();
+        assert_eq!(fs::read_to_string(&written).unwrap(), "a,b\n");
+        let error = write_export(&dir, "reports/open.csv", "c").unwrap_err();
+        assert!(error.contains("already exists"), "{}", error);
+        assert_eq!(fs::read_to_string(&written).unwrap(), "a,b\n");
+
+        assert!(write_export(&dir, "../escape.csv", "x").is_err());
+        assert!(write_export(&dir, "reports/../../escape.csv", "x").is_err());
+        assert!(write_export(&dir, "/tmp/absolute.csv", "x").is_err());
+        assert!(write_export(&dir, "", "x").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_store_evicts_oldest() {
+        let store = ResultSetStore::new();
+        for i in 0..=MAX_RESULT_SETS {
+            store.insert(&format!("set{}", i), "accounts", vec![]);
+        }
+        assert_eq!(store.list().len(), MAX_RESULT_SETS);
+        assert!(store.get("set0").is_none());
+        assert!(store.remove("set1"));
+    }
+}
@@ -2,37 +2,376 @@
 //!
 //! Exposes tools for querying and interacting with Dynamics 365 data
 
-use crate::config::RuntimeConfig;
+use crate::config::{ConflictStrategy, ProductType, RuntimeConfig, DEFAULT_CONNECTION};
+use crate::mcp::completion;
+use crate::mcp::context::ToolContext;
+use crate::mcp::entity_tools::{self, EntityTool};
+use crate::mcp::idempotency::{IdempotencyStore, Reservation, ReservationGuard, IDEMPOTENCY_KEY_ARG};
+use crate::mcp::prompts::{PromptError, PromptKind};
 use crate::mcp::protocol::*;
-use crate::odata::{ODataClient, QueryOptions};
+use crate::mcp::query_cache::{QueryCache, CACHE_ARG};
+use crate::mcp::resources::{self, ResourceError, ResourceUri};
+use crate::mcp::result_sets::{self, ResultSetStore, MAX_STORED_ROWS};
+use crate::mcp::session;
+use crate::odata::batch::{self, BatchOperation, BulkReport};
+use crate::odata::error_hints;
+use crate::odata::impersonation::{self, Caller, IMPERSONATE_USER_ARG};
+use crate::odata::join::{self, JoinType};
+use crate::odata::metadata::{suggest_names, unqualified, RelationshipKind};
+use crate::odata::metadata_cache::{cache_file_name, MetadataCache, ModelWatch};
+use crate::odata::metadata_diff::{self, MetadataDiff};
+use crate::odata::script;
+use crate::odata::time_window::{self, AuditField};
+use crate::odata::transform::{self, CollectionMode};
+use crate::odata::validate::{self, PayloadKind};
+use crate::odata::{
+    alternate_key_segment, is_guid, key_from_entity_id, key_segment, odata_literal, DeepInsert,
+    EdmModel, EntityKey, ExpandOption, ODataClient, ODataError, PageCursor, PagedFetch, QueryOptions,
+    UpdatePayload, UpsertMode, DATAVERSE_COUNT_LIMIT,
+};
+use crate::sync::{
+    self, DeletedRecord, DeltaSync, EntitySyncState, JsonStateStore, PullOptions, Scheduler,
+    StateStore, SyncError, SyncMode,
+};
+use futures::StreamExt;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Interval between status monitor polls for `respond_async` queries
+const ASYNC_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum wait for a `respond_async` query without a tool deadline
+const ASYNC_MAX_WAIT: Duration = Duration::from_secs(600);
+
+/// Rows per progress notification when streaming query results
+const STREAM_CHUNK_ROWS: usize = 50;
+
+/// Default and maximum rows fetched per side of a `join_queries` call
+const JOIN_DEFAULT_ROWS: usize = 5000;
+const JOIN_MAX_ROWS: usize = 20000;
+
+/// Rows previewed when a query result is stored as a named set
+const STORED_PREVIEW_ROWS: usize = 10;
+
+/// Maximum queries in one `batch_query` call
+const BATCH_MAX_QUERIES: usize = 100;
+
+/// Default and maximum records per `$batch` in bulk writes, and records per call
+const BULK_DEFAULT_CHUNK: usize = 100;
+const BULK_MAX_RECORDS: usize = 10_000;
+
+/// Failed records listed individually in a bulk write report
+const BULK_REPORT_MAX_FAILURES: usize = 50;
+
+/// Argument accepted by every tool to append reproducible scripts to the result
+const INCLUDE_SCRIPT_ARG: &str = "include_script";
+
+/// Argument routing a tool call to another configured connection
+const CONNECTION_ARG: &str = "connection";
 
 /// MCP Server for D365 OData
 pub struct D365McpServer {
     client: Arc<ODataClient>,
     config: Arc<RuntimeConfig>,
+    idempotency: IdempotencyStore,
+    /// Result sets of stdio/socket clients; HTTP sessions have their own
+    result_sets: Arc<ResultSetStore>,
+    delta: DeltaSync,
+    /// Background sync, when `[sync] enabled = true`
+    scheduler: Option<Arc<Scheduler>>,
+    /// Parsed $metadata, loaded on first use
+    metadata: Arc<MetadataCache>,
+    /// `query_<entity>` / `get_<entity>` tools for the `[[entities]]` entries
+    entity_tools: Vec<EntityTool>,
+    /// Recent `query_entity` results, when `[query_cache] enabled = true`
+    query_cache: Option<QueryCache>,
+    /// Name of this connection in the `connection` argument
+    connection: String,
+    /// Further connections tool calls can be routed to, by name
+    connections: BTreeMap<String, Arc<D365McpServer>>,
 }
 
 impl D365McpServer {
     /// Create a new MCP server instance
     pub fn new(client: Arc<ODataClient>, config: Arc<RuntimeConfig>) -> Self {
-        Self { client, config }
+        let idempotency =
+            IdempotencyStore::new(Duration::from_secs(config.idempotency_ttl_seconds));
+        let open = |path: &str| -> Box<dyn StateStore> { Box::new(JsonStateStore::new(path)) };
+        let scheduler = config.sync.enabled.then(|| {
+            let sync = DeltaSync::new(Arc::clone(&client), open(&config.sync.state_path));
+            let scheduler = Scheduler::new(sync, Scheduler::schedules_from_config(&config));
+            match &config.sync.sink {
+                Some(sink) => Arc::new(scheduler.with_sink(sync::open_sink(sink))),
+                None => Arc::new(scheduler),
+            }
+        });
+        let delta = DeltaSync::new(Arc::clone(&client), open(&config.delta_storage_path));
+        let entity_tools = if config.tools.entity_tools == Some(false) {
+            Vec::new()
+        } else {
+            let reserved: Vec<String> = Self::tool_definitions().into_iter().map(|t| t.name).collect();
+            entity_tools::entity_tools(&config.entities, &reserved)
+        };
+        let query_cache = config
+            .query_cache
+            .enabled
+            .then(|| QueryCache::new(config.query_cache.ttl, config.query_cache.max_entries));
+        let connection = config.profile.clone().unwrap_or_else(|| DEFAULT_CONNECTION.to_string());
+        Self {
+            metadata: Arc::new(MetadataCache::new(Arc::clone(&client)).with_settings(&config.metadata)),
+            client,
+            config,
+            idempotency,
+            result_sets: Arc::new(ResultSetStore::new()),
+            delta,
+            scheduler,
+            entity_tools,
+            query_cache,
+            connection,
+            connections: BTreeMap::new(),
+        }
+    }
+
+    /// Serve `others` next to this connection, selected by the `connection`
+    /// argument; result sets are shared so results of both can be compared
+    pub fn with_connections(mut self, others: Vec<D365McpServer>) -> Self {
+        for mut other in others {
+            other.result_sets = Arc::clone(&self.result_sets);
+            self.connections.insert(other.connection.clone(), Arc::new(other));
+        }
+        self
+    }
+
+    /// This connection and the others, this one first
+    fn all_connections(&self) -> impl Iterator<Item = &D365McpServer> {
+        std::iter::once(self).chain(self.connections.values().map(|c| &**c))
+    }
+
+    /// Connection named by the `connection` argument of a call; this one when absent
+    fn routed(&self, name: &str, args: &HashMap<String, Value>) -> Result<&D365McpServer, String> {
+        let wanted = match args.get(CONNECTION_ARG).and_then(|v| v.as_str()).map(str::trim) {
+            None | Some("") => return Ok(self),
+            Some(_) if name == "list_connections" => return Ok(self),
+            Some(wanted) => wanted,
+        };
+        if wanted == self.connection {
+            return Ok(self);
+        }
+        match self.connections.get(wanted) {
+            Some(connection) => Ok(connection),
+            None => Err(format!(
+                "Unknown connection '{}'; configured connections: {}",
+                wanted,
+                self.all_connections().map(|c| c.connection.as_str()).collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+
+    /// The parsed $metadata model, fetched once and cached; `refresh` reloads it
+    async fn metadata_model(&self, refresh: bool) -> Result<Arc<EdmModel>, ODataError> {
+        self.metadata.model(refresh).await
+    }
+
+    /// Model used to check a write payload; none when `skip_validation` is
+    /// set or $metadata cannot be loaded, in which case the service decides
+    async fn validation_model(&self, args: &HashMap<String, Value>) -> Option<Arc<EdmModel>> {
+        if parse_bool_arg(args, "skip_validation") {
+            return None;
+        }
+        match self.metadata_model(false).await {
+            Ok(model) => Some(model),
+            Err(e) => {
+                tracing::debug!("Writing without payload validation, $metadata unavailable: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Load $metadata in the background so per-entity tools get typed
+    /// schemas; needs a Tokio runtime
+    pub fn preload_metadata(&self) {
+        if self.entity_tools.is_empty() {
+            return;
+        }
+        for connection in self.all_connections() {
+            let metadata = Arc::clone(&connection.metadata);
+            tokio::spawn(async move {
+                if let Err(e) = metadata.model(false).await {
+                    tracing::warn!("Loading $metadata for entity tools failed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Tool calls that may run at once, from the `concurrency` setting
+    pub fn max_concurrent_calls(&self) -> usize {
+        self.config.concurrency.max(1)
+    }
+
+    /// Wakes when the tool list changes, if it can change at all
+    pub fn tool_changes(&self) -> Option<ModelWatch> {
+        (!self.entity_tools.is_empty()).then(|| self.metadata.subscribe())
+    }
+
+    /// Resources of `resources/list`: the fixed ones and the schemas of the `[[entities]]` entries
+    pub fn list_resources(&self) -> Vec<Resource> {
+        resources::resource_list(self.config.entities.iter().map(|e| e.name.as_str()))
+    }
+
+    /// Contents of a resource
+    pub async fn read_resource(&self, uri: &str) -> Result<ResourceContents, ResourceError> {
+        let resource = ResourceUri::parse(uri).ok_or_else(|| ResourceError::NotFound(format!("Unknown resource {}", uri)))?;
+        let contents = |mime_type: &str, text: String| ResourceContents {
+            uri: uri.to_string(),
+            mime_type: Some(mime_type.to_string()),
+            text,
+        };
+        let model = || async {
+            self.metadata_model(false)
+                .await
+                .map_err(|e| ResourceError::Unavailable(format!("Failed to fetch metadata: {}", e)))
+        };
+        match resource {
+            ResourceUri::Config => Ok(contents(
+                "application/json",
+                serde_json::to_string_pretty(&self.config.redacted()).unwrap_or_default(),
+            )),
+            ResourceUri::Catalog => Ok(contents(
+                "application/json",
+                serde_json::to_string_pretty(&resources::catalog(&*model().await?)).unwrap_or_default(),
+            )),
+            ResourceUri::Metadata => Ok(contents(
+                "application/json",
+                serde_json::to_string(&*model().await?).unwrap_or_default(),
+            )),
+            ResourceUri::Entity(entity) => match format_entity_description(&*model().await?, &entity) {
+                Some(text) => Ok(contents("text/markdown", text)),
+                None => Err(ResourceError::NotFound(format!(
+                    "Entity '{}' not found in metadata; read {} for entity set names",
+                    entity,
+                    resources::CATALOG_URI
+                ))),
+            },
+        }
     }
 
-    /// Get list of available tools
+    /// Render a prompt with the schema of its `entity` argument
+    pub async fn get_prompt(&self, name: &str, args: &HashMap<String, String>) -> Result<GetPromptResult, PromptError> {
+        let prompt = PromptKind::parse(name).ok_or_else(|| PromptError::InvalidParams(format!("Unknown prompt: {}", name)))?;
+        prompt.check_arguments(args).map_err(PromptError::InvalidParams)?;
+        let entity = args.get("entity").map(|e| e.trim()).unwrap_or_default();
+        let model = self
+            .metadata_model(false)
+            .await
+            .map_err(|e| PromptError::Unavailable(format!("Failed to fetch metadata: {}", e)))?;
+        let Some(schema) = format_entity_description(&model, entity) else {
+            let suggestions = suggest_names(entity, model.entity_sets.iter().map(|s| s.name.as_str()), 5);
+            return Err(PromptError::InvalidParams(if suggestions.is_empty() {
+                format!("Entity '{}' not found in metadata; read {} for entity set names", entity, resources::CATALOG_URI)
+            } else {
+                format!("Entity '{}' not found in metadata. Did you mean {}?", entity, suggestions.join(" / "))
+            }));
+        };
+        Ok(prompt.render(args, &schema, self.client.product()))
+    }
+
+    /// Suggestions for an argument being typed; none while `$metadata` cannot be loaded
+    pub async fn complete(&self, argument: &str, value: &str, context: &HashMap<String, String>) -> Completion {
+        match self.metadata_model(false).await {
+            Ok(model) => completion::complete(&model, argument, value, context),
+            Err(e) => {
+                tracing::debug!("No completions, $metadata unavailable: {}", e);
+                Completion::default()
+            }
+        }
+    }
+
+    /// Start background sync tasks if `[sync] enabled = true`; needs a Tokio runtime
+    pub fn start_background_sync(&self) {
+        for connection in self.all_connections() {
+            if let Some(scheduler) = &connection.scheduler {
+                tracing::info!(
+                    "Starting background sync of {} entities on connection {}",
+                    scheduler.status().len(),
+                    connection.connection
+                );
+                scheduler.start();
+            }
+        }
+    }
+
+    /// Stop background sync, letting running pulls finish
+    pub async fn stop_background_sync(&self) {
+        for connection in self.all_connections() {
+            if let Some(scheduler) = &connection.scheduler {
+                scheduler.stop().await;
+            }
+        }
+    }
+
+    /// Get list of available tools, honoring the `[tools]` config
     pub fn get_tools(&self) -> Vec<Tool> {
-        Self::get_tools_static()
+        let model = self.metadata.current();
+        let mut entity_tools: Vec<Tool> = self
+            .entity_tools
+            .iter()
+            .map(|t| entity_tools::definition(t, model.as_deref(), self.client.product()))
+            .collect();
+        add_include_script_arg(&mut entity_tools);
+        let connections_tool = (!self.connections.is_empty()).then(list_connections_tool);
+        let mut tools: Vec<Tool> = Self::get_tools_static()
+            .into_iter()
+            .chain(entity_tools)
+            .chain(connections_tool)
+            .filter(|t| self.is_tool_enabled(&t.name))
+            .collect();
+        if self.all_connections().any(|c| *c.client.product() == ProductType::Dataverse) {
+            add_impersonate_user_arg(&mut tools);
+        }
+        if !self.connections.is_empty() {
+            let names: Vec<&str> = self.all_connections().map(|c| c.connection.as_str()).collect();
+            add_connection_arg(&mut tools, &self.connection, &names);
+        }
+        tools
+    }
+
+    /// Whether a tool is exposed by the `[tools]` config; per-entity tools are in the `read` group
+    fn is_tool_enabled(&self, name: &str) -> bool {
+        let group = match self.entity_tool(name) {
+            Some(_) => "read",
+            None => tool_group(name),
+        };
+        self.config.tools.is_enabled(name, group)
+    }
+
+    /// Result sets of the calling session
+    fn result_sets(&self) -> Arc<ResultSetStore> {
+        session::current()
+            .and_then(|s| s.result_sets.clone())
+            .unwrap_or_else(|| self.result_sets.clone())
+    }
+
+    fn entity_tool(&self, name: &str) -> Option<&EntityTool> {
+        self.entity_tools.iter().find(|t| t.name == name)
     }
 
     /// Get list of available tools (static version for unconfigured server)
     pub fn get_tools_static() -> Vec<Tool> {
+        let mut tools = Self::tool_definitions();
+        add_include_script_arg(&mut tools);
+        tools
+    }
+
+    fn tool_definitions() -> Vec<Tool> {
         vec![
             Tool {
                 name: "list_entities".to_string(),
                 description: "List all available D365 entities/tables that can be queried".to_string(),
                 input_schema: create_tool_schema(vec![]),
+                annotations: ToolAnnotations::read_only(),
             },
             Tool {
                 name: "query_entity".to_string(),
@@ -41,13 +380,105 @@ impl D365McpServer {
                     ("entity", "Entity set name, e.g., 'CustomersV3', 'SalesOrderHeaders'", true),
                     ("select", "Comma-separated fields to select, e.g., 'Name,Id,Status'", false),
                     ("filter", "OData filter expression, e.g., \"dataAreaId eq 'bc' and Status ne 'Closed'\"", false),
+                    ("page_size", "Records per server page (Prefer: odata.maxpagesize); defaults to page_size from the config", false),
+                    ("params", "Parameter aliases as a JSON object, e.g., {\"p1\": \"A & B\"} for filter \"name eq @p1\". Values are quoted and escaped safely", false),
+                    ("search", "Full-text search terms ($search), e.g., 'contoso'. Dataverse only; needs Dataverse search enabled for the entity", false),
                     ("orderby", "Sort order, e.g., 'CreatedDate desc' or 'Name asc'", false),
                     ("top", "Maximum records to return (default: 50, max: 1000)", false),
                     ("skip", "Number of records to skip (for pagination)", false),
-                    ("expand", "Comma-separated navigation properties to expand", false),
+                    ("expand", "Navigation properties to expand: comma-separated text with optional nested options, e.g., \"primarycontactid,contact_customer_accounts($select=fullname;$top=5)\", or JSON [{\"property\", \"select\", \"filter\", \"orderby\", \"top\", \"expand\"}]", false),
                     ("cross_company", "Set to 'true' for cross-company query (F&O only)", false),
                     ("count", "Set to 'true' to include total record count in response", false),
+                    ("modified_within", "Only records modified within this window, e.g., '24h', '7d' (UTC, on modifiedon / ModifiedDateTime)", false),
+                    ("created_within", "Only records created within this window, e.g., '30m', '2w' (UTC, on createdon / CreatedDateTime)", false),
+                    ("timeout_seconds", "Deadline for this call; when exceeded, rows fetched so far are returned with a continuation cursor", false),
+                    ("cursor", "Continuation cursor from a previous partial result; other query arguments are ignored", false),
+                    ("respond_async", "Set to 'true' to send Prefer: respond-async for heavy queries; the server polls the status monitor and reports progress", false),
+                    ("stream", "Set to 'true' to send rows as progress notifications while paging continues (requires a progressToken)", false),
+                    ("flatten", "Set to 'true' to flatten expanded navigation properties into prefixed columns, e.g., 'primarycontactid.fullname'", false),
+                    ("flatten_collections", "How flatten renders expanded collections: 'count' (default) or 'summary' (first text field of each item)", false),
+                    ("slim", "Set to 'true' to drop @odata annotations, null fields and raw lookup GUIDs (keeping formatted values) to shrink the output", false),
+                    ("store_as", "Store the full result server-side under this name (up to 50000 rows; all pages unless 'top' is given) and return a preview", false),
+                    ("cache", "Set to 'false' to bypass the query result cache and read fresh data (only when [query_cache] is enabled)", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "query_page".to_string(),
+                description: "Read a large result page by page. Returns up to 'limit' records and an opaque cursor; call again with only the cursor to get the next page, until no cursor is returned.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'CustomersV3'; required unless 'cursor' is given", false),
+                    ("select", "Comma-separated fields to select, e.g., 'Name,Id,Status'", false),
+                    ("filter", "OData filter expression, e.g., \"dataAreaId eq 'bc'\"", false),
+                    ("params", "Parameter aliases as a JSON object, e.g., {\"p1\": \"A & B\"} for filter \"name eq @p1\"", false),
+                    ("orderby", "Sort order; give a stable one (e.g., the key) so pages do not overlap", false),
+                    ("expand", "Navigation properties to expand, as for query_entity", false),
+                    ("cross_company", "Set to 'true' for cross-company query (F&O only)", false),
+                    ("count", "Set to 'true' to include the total record count with the first page", false),
+                    ("slim", "Set to 'true' to drop @odata annotations, null fields and raw lookup GUIDs", false),
+                    ("limit", "Records per page (default: 50, max: 1000)", false),
+                    ("cursor", "Cursor from the previous query_page result; the query arguments are then ignored", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "entity_profile".to_string(),
+                description: "Quick profile of an entity: total row count, first/last created and modified timestamps, most frequent values of a column, and a 5-row sample".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts', 'CustomersV3'", true),
+                    ("column", "Column to show the most frequent values of (grouped with $apply)", false),
+                    ("top_values", "Number of frequent values to show (default: 10)", false),
+                    ("filter", "OData filter expression applied to every statistic", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "batch_query".to_string(),
+                description: "Run several small queries in one HTTP round trip (OData $batch). Each query reports its own records or error, so one failing lookup does not fail the rest.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("queries", "JSON array of query specs: {\"entity\", \"id\"?, \"select\"?, \"filter\"?, \"orderby\"?, \"top\"?, \"expand\"?, \"params\"?, \"cross_company\"?}. With 'id' a single record is fetched. Max 100 queries", true),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "join_queries".to_string(),
+                description: "Run two queries and join them client-side on key columns (inner or left join). Use when $expand is not possible, e.g. across F&O entities or unrelated tables.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("left_entity", "Entity set for the left side, e.g., 'SalesOrderHeadersV2'", true),
+                    ("right_entity", "Entity set for the right side, e.g., 'CustomersV3'", true),
+                    ("left_key", "Comma-separated key column(s) on the left side, e.g., 'OrderingCustomerAccountNumber'", true),
+                    ("right_key", "Comma-separated key column(s) on the right side, same order as left_key", true),
+                    ("join_type", "'inner' (default) or 'left'", false),
+                    ("left_filter", "OData filter for the left query", false),
+                    ("right_filter", "OData filter for the right query", false),
+                    ("left_select", "Comma-separated fields for the left query (key columns are added)", false),
+                    ("right_select", "Comma-separated fields for the right query (key columns are added)", false),
+                    ("right_prefix", "Prefix for right-side columns in the output (default: '<right_entity>.')", false),
+                    ("max_rows", "Maximum rows fetched per side (default: 5000, max: 20000)", false),
+                    ("top", "Maximum joined rows to return (default: 100, max: 1000)", false),
+                    ("cross_company", "Set to 'true' for cross-company queries on both sides (F&O only)", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "describe_entity".to_string(),
+                description: "Describe an entity from $metadata: every field with its EDM type, required/nullable flag and size facets, the key fields, enum values, and navigation properties with their target entity sets. Use it to build correct $select/$filter expressions and write payloads, or pass payload_schema for the JSON Schema of a create/update payload.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set or type name, e.g., 'accounts' or 'CustomersV3'", true),
+                    ("refresh", "Reload $metadata instead of using the cached model (default: false)", false),
+                    ("payload_schema", "'create' or 'update' to return the JSON Schema that write payloads are validated against instead of the description", false),
                 ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "list_relationships".to_string(),
+                description: "List the relationships of an entity from $metadata: each navigation property with its kind (N:1 lookup, 1:N, N:N), target entity set and lookup field, and how to use it in $expand, @odata.bind or associate_records.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set or type name, e.g., 'accounts' or 'SalesOrderHeadersV2'", true),
+                    ("kind", "Only relationships of this kind: 'N:1', '1:N', 'N:N' or '1:1'", false),
+                    ("refresh", "Reload $metadata instead of using the cached model (default: false)", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
             },
             Tool {
                 name: "get_entity_schema".to_string(),
@@ -55,47 +486,466 @@ impl D365McpServer {
                 input_schema: create_tool_schema(vec![
                     ("entity", "Entity set name, e.g., 'contacts'", true),
                 ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "count_entities".to_string(),
+                description: "Count the records of an entity, optionally filtered, without fetching them (GET entity/$count). Beyond Dataverse's 5000 cap it falls back to an aggregate count or RetrieveTotalRecordCount.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts', 'CustomersV3'", true),
+                    ("filter", "OData filter expression", false),
+                    ("cross_company", "Set to 'true' to count across companies (F&O only)", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "start_change_tracking".to_string(),
+                description: "Start change tracking on an entity (Prefer: odata.track-changes; Dataverse tables with change tracking enabled). Reads the current rows once and returns a delta link; pass it as 'cursor' to query_entity later to get only rows created, updated or deleted since.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts'", true),
+                    ("select", "Comma-separated fields to track (recommended; filter, orderby, top and expand are not supported with change tracking)", false),
+                    ("timeout_seconds", "Deadline for reading the initial rows", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "get_changes".to_string(),
+                description: "Get the records created, updated or deleted in an entity since the last get_changes call (change tracking; the delta link is stored in the [delta] storage_path file). The first call returns every row and starts tracking. Entities with delta_enabled = false in the config are refused.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts'", true),
+                    ("select", "Comma-separated fields to track (first call only)", false),
+                    ("reset", "Set to 'true' to discard the stored delta link and start over with a full read", false),
+                    ("resume", "Set to 'true' to continue an interrupted pull from its checkpoint instead of starting it over", false),
+                    ("store_as", "Store the changed rows as a named result set instead of returning them all", false),
+                    ("timeout_seconds", "Deadline for the pull; on timeout the stored state is left unchanged", false),
+                ]),
+                // Advances the stored delta link
+                annotations: ToolAnnotations::write(false, false),
+            },
+            Tool {
+                name: "sync_status".to_string(),
+                description: "Show background sync status per configured entity (interval, last run, result, failures, next run) and the stored get_changes state (delta link, watermark, checkpoint)".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Only show this entity", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
             },
             Tool {
                 name: "get_record".to_string(),
                 description: "Get a single record by its ID/primary key".to_string(),
                 input_schema: create_tool_schema(vec![
                     ("entity", "Entity set name, e.g., 'contacts'", true),
-                    ("id", "Record ID/GUID", true),
+                    ("id", "Record key: a GUID, number or string, or an F&O composite key as \"dataAreaId='usmf',ItemNumber='A0001'\" or JSON {\"dataAreaId\": \"usmf\", \"ItemNumber\": \"A0001\"}", true),
                 ]),
+                annotations: ToolAnnotations::read_only(),
             },
             Tool {
                 name: "get_environment_info".to_string(),
                 description: "Get information about the connected D365 environment".to_string(),
                 input_schema: create_tool_schema(vec![]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "usage_stats".to_string(),
+                description: "Show request usage against the Dataverse service-protection limits (requests and execution time in the current 5-minute window)".to_string(),
+                input_schema: create_tool_schema(vec![]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "create_entity".to_string(),
+                description: "Create a record in a D365 entity set (POST). Returns the created record and its key. Pass an idempotency_key so a retried call does not create a duplicate.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts', 'CustomersV3'", true),
+                    ("data", "JSON object with the field values, e.g., {\"name\": \"Contoso\"}. Bind lookups with 'nav@odata.bind': '/contacts(<id>)'", true),
+                    ("skip_validation", "Set to 'true' to send the payload without checking it against $metadata (default: false)", false),
+                    (IDEMPOTENCY_KEY_ARG, "Unique key for this write; retries with the same key return the first result instead of creating again", false),
+                ]),
+                annotations: ToolAnnotations::write(false, false),
+            },
+            Tool {
+                name: "create_deep".to_string(),
+                description: "Create a record together with related records in one request (Dataverse deep insert), e.g. an account with its contacts. Nested objects create single-valued related records, arrays of objects create child collections, and 'nav@odata.bind' links existing records.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name of the parent, e.g., 'accounts'", true),
+                    ("data", "Nested JSON document, e.g., {\"name\": \"Contoso\", \"contact_customer_accounts\": [{\"firstname\": \"Ann\"}]}", true),
+                    ("skip_validation", "Set to 'true' to send the payload without checking it against $metadata (default: false)", false),
+                    (IDEMPOTENCY_KEY_ARG, "Unique key for this write; retries with the same key return the first result instead of creating again", false),
+                ]),
+                annotations: ToolAnnotations::write(false, false),
+            },
+            Tool {
+                name: "update_entity".to_string(),
+                description: "Update fields of an existing record (PATCH). Only the fields in 'data' are changed; list fields to blank out in 'clear_fields'. Pass 'etag' (the record's @odata.etag) to fail with a conflict if someone else changed it meanwhile.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts', 'CustomersV3'", true),
+                    ("id", "Record ID/GUID or key segment", true),
+                    ("data", "JSON object with the fields to change, e.g., {\"telephone1\": \"555-0100\"}", false),
+                    ("clear_fields", "Comma-separated fields to set to null. Dataverse lookups given as '_<nav>_value' or '<nav>@odata.bind' are disassociated", false),
+                    ("etag", "ETag from a previous read; the update fails with 412 Conflict if the record has changed", false),
+                    ("on_conflict", "What to do when the ETag is stale: 'fail', 'overwrite' (retry with If-Match: *) or 'refetch_merge' (re-read and reapply changed fields once). Default from server config", false),
+                    ("skip_validation", "Set to 'true' to send the payload without checking it against $metadata (default: false)", false),
+                    (IDEMPOTENCY_KEY_ARG, "Unique key for this write; retries with the same key return the first result", false),
+                ]),
+                annotations: ToolAnnotations::write(true, true),
+            },
+            Tool {
+                name: "upsert_entity".to_string(),
+                description: "Create or update a record addressed by alternate key(s) (PATCH entity(key='value')). Reports whether the record was created or updated.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts'", true),
+                    ("keys", "JSON object of alternate key names and values, e.g., {\"accountnumber\": \"A-1001\"}", true),
+                    ("data", "JSON object with the field values to write", true),
+                    ("mode", "'upsert' (default), 'create_only' (If-None-Match: *, fails if it exists) or 'update_only' (If-Match: *, fails if missing)", false),
+                    ("skip_validation", "Set to 'true' to send the payload without checking it against $metadata (default: false)", false),
+                    (IDEMPOTENCY_KEY_ARG, "Unique key for this write; retries with the same key return the first result", false),
+                ]),
+                annotations: ToolAnnotations::write(true, true),
+            },
+            Tool {
+                name: "bulk_create".to_string(),
+                description: "Create many records in one call. Records are sent in $batch chunks (several chunks in parallel) and a failing record does not stop the rest; the result reports successes and each failure.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts'", true),
+                    ("records", "JSON array of records to create (max 10000)", true),
+                    ("chunk_size", "Records per $batch request (default: 100, max: 1000)", false),
+                    ("skip_validation", "Set to 'true' to send the payload without checking it against $metadata (default: false)", false),
+                    (IDEMPOTENCY_KEY_ARG, "Unique key for this write; retries with the same key return the first result instead of creating again", false),
+                ]),
+                annotations: ToolAnnotations::write(false, false),
+            },
+            Tool {
+                name: "bulk_update".to_string(),
+                description: "Update many records in one call. Each record carries its key in 'key_field'; records are sent in $batch chunks and the result reports successes and each failure. A record's @odata.etag, if present, is sent as If-Match.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts'", true),
+                    ("records", "JSON array of records with the key and the fields to change (max 10000)", true),
+                    ("key_field", "Field of each record holding its key, e.g., 'accountid'", true),
+                    ("chunk_size", "Records per $batch request (default: 100, max: 1000)", false),
+                    ("skip_validation", "Set to 'true' to send the payload without checking it against $metadata (default: false)", false),
+                    (IDEMPOTENCY_KEY_ARG, "Unique key for this write; retries with the same key return the first result", false),
+                ]),
+                annotations: ToolAnnotations::write(true, true),
+            },
+            Tool {
+                name: "associate_records".to_string(),
+                description: "Link records through a collection-valued relationship, e.g. an N:N relationship (POST <entity>(<id>)/<relationship>/$ref).".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set of the record, e.g., 'systemusers'", true),
+                    ("id", "Key of the record", true),
+                    ("relationship", "Collection-valued navigation property, e.g., 'systemuserroles_association'", true),
+                    ("related_entity", "Entity set of the records to link, e.g., 'roles'", true),
+                    ("related_ids", "Comma-separated keys of the records to link", true),
+                ]),
+                annotations: ToolAnnotations::write(false, true),
+            },
+            Tool {
+                name: "disassociate_records".to_string(),
+                description: "Unlink records from a collection-valued relationship, e.g. an N:N relationship (DELETE <entity>(<id>)/<relationship>(<related_id>)/$ref).".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set of the record, e.g., 'systemusers'", true),
+                    ("id", "Key of the record", true),
+                    ("relationship", "Collection-valued navigation property, e.g., 'systemuserroles_association'", true),
+                    ("related_ids", "Comma-separated keys of the records to unlink", true),
+                ]),
+                annotations: ToolAnnotations::write(true, true),
+            },
+            Tool {
+                name: "transaction".to_string(),
+                description: "Apply an ordered list of create/update/delete operations atomically ($batch changeset): either all succeed or none are applied. On rollback the failing operation is reported.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("operations", "JSON array of {\"op\": \"create\"|\"update\"|\"delete\", \"entity\", \"id\" (update/delete), \"data\" (create/update), \"etag\"? (update/delete)}", true),
+                    (IDEMPOTENCY_KEY_ARG, "Unique key for this write; retries with the same key return the first result", false),
+                ]),
+                annotations: ToolAnnotations::write(true, false),
+            },
+            Tool {
+                name: "set_record_state".to_string(),
+                description: "Change the status of a Dataverse record (e.g. deactivate an account) by setting statecode and optionally statuscode.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts'", true),
+                    ("id", "Record GUID", true),
+                    ("statecode", "State value, or 'active' (0) / 'inactive' (1)", true),
+                    ("statuscode", "Status reason value valid for the state; omitted = the state's default status", false),
+                ]),
+                annotations: ToolAnnotations::write(true, true),
+            },
+            Tool {
+                name: "assign_record".to_string(),
+                description: "Assign a Dataverse record to a user or team. The owner may be a GUID, a user's email/domain name/full name, or a team name.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts'", true),
+                    ("id", "Record GUID", true),
+                    ("owner", "Owner GUID, user email, domain name or full name, or team name", true),
+                    ("owner_type", "'systemuser' or 'team' (default: detected)", false),
+                ]),
+                annotations: ToolAnnotations::write(true, true),
+            },
+            Tool {
+                name: "delete_entity".to_string(),
+                description: "Delete a record by key. Irreversible; confirm with the user first. Can be disabled with allow_delete = false in the [tools] config.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("entity", "Entity set name, e.g., 'accounts', 'CustomersV3'", true),
+                    ("id", "Record GUID, key value, or composite key like \"dataAreaId='usmf',CustomerAccount='C1'\"", true),
+                    ("etag", "ETag from a previous read; the delete fails with 412 Conflict if the record has changed (default: If-Match: *)", false),
+                    (IDEMPOTENCY_KEY_ARG, "Unique key for this write; retries with the same key return the first result", false),
+                ]),
+                annotations: ToolAnnotations::write(true, true),
+            },
+            Tool {
+                name: "list_result_sets".to_string(),
+                description: "List result sets stored with query_entity's 'store_as'".to_string(),
+                input_schema: create_tool_schema(vec![]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "query_result_set".to_string(),
+                description: "Filter, sort and page a stored result set without re-querying D365".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("name", "Result set name", true),
+                    ("filter", "Filter like OData: \"Status eq 'Open' and Amount gt 100\", contains(Name,'x'), startswith/endswith", false),
+                    ("orderby", "Sort order, e.g., 'Amount desc, Name'", false),
+                    ("select", "Comma-separated fields to return", false),
+                    ("top", "Maximum rows to return (default: 50, max: 1000)", false),
+                    ("skip", "Rows to skip", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "aggregate_result_set".to_string(),
+                description: "Group and aggregate a stored result set (count, sum, avg, min, max)".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("name", "Result set name", true),
+                    ("aggregates", "Comma-separated aggregates, e.g., 'count,sum(Amount),avg(Qty)' (default: count)", false),
+                    ("group_by", "Comma-separated fields to group by (omit for a single total row)", false),
+                    ("filter", "Filter applied before grouping, same syntax as query_result_set", false),
+                    ("orderby", "Sort order of the groups, e.g., 'sum_Amount desc'", false),
+                    ("top", "Maximum groups to return (default: 100, max: 1000)", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "export_result_set".to_string(),
+                description: "Export a stored result set as CSV, JSON or JSON Lines, returned inline or written to a new file in the server's export directory".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("name", "Result set name", true),
+                    ("format", "'csv' (default), 'json' or 'jsonl'", false),
+                    ("path", "File to create, relative to the export directory ([tools] export_dir); existing files are not overwritten. Omit to return the content", false),
+                    ("filter", "Filter applied before export, same syntax as query_result_set", false),
+                    ("orderby", "Sort order, e.g., 'Amount desc'", false),
+                    ("select", "Comma-separated fields to export", false),
+                ]),
+                annotations: ToolAnnotations::write(false, false),
+            },
+            Tool {
+                name: "drop_result_set".to_string(),
+                description: "Remove a stored result set to free memory".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("name", "Result set name", true),
+                ]),
+                // Removes session state, not D365 data
+                annotations: ToolAnnotations::write(true, true),
+            },
+            Tool {
+                name: "refresh_metadata".to_string(),
+                description: "Reload $metadata from the service (or the configured metadata file), replacing the cached model, e.g. after deploying new fields or entities. Reports where the model came from and its size.".to_string(),
+                input_schema: create_tool_schema(vec![]),
+                annotations: ToolAnnotations::read_only(),
+            },
+            Tool {
+                name: "diff_metadata".to_string(),
+                description: "Detect metadata drift: compare the current $metadata model with a snapshot saved on disk and report entity sets and fields that were added, removed or retyped since. The first call saves the snapshot.".to_string(),
+                input_schema: create_tool_schema(vec![
+                    ("snapshot", "Snapshot name (default: 'baseline')", false),
+                    ("entities", "Comma-separated entity sets to compare (default: all)", false),
+                    ("update_snapshot", "Set to 'true' to replace the snapshot with the current model after comparing (default: false)", false),
+                    ("refresh", "Set to 'false' to compare the cached model instead of reloading $metadata (default: true)", false),
+                ]),
+                annotations: ToolAnnotations::read_only(),
             },
             Tool {
                 name: "get_metadata".to_string(),
-                description: "Get entity metadata from $metadata including properties and navigation properties (expandable fields). Use this to understand entity schema and available joins.".to_string(),
+                description: "Get entity metadata from $metadata. Without 'entity', returns a summary of entity sets with field counts (filterable). With 'entity', returns its properties and navigation properties (expandable fields). Use this to understand entity schema and available joins.".to_string(),
                 input_schema: create_tool_schema(vec![
-                    ("entity", "Entity name to get metadata for, e.g., 'CustomersV3'", true),
+                    ("entity", "Entity name to get metadata for, e.g., 'CustomersV3'. Omit for a summary", false),
+                    ("filter", "Case-insensitive substring to filter entity set names in the summary", false),
+                    ("top", "Maximum entity sets to list in the summary (default: 200)", false),
+                    ("refresh", "Reload $metadata instead of using the cached model (default: false)", false),
                 ]),
+                annotations: ToolAnnotations::read_only(),
             },
         ]
     }
 
     /// Handle a tool call
+    ///
+    /// Calls carrying an `idempotency_key` are deduplicated: a retry with the
+    /// same key and arguments replays the first successful result.
     pub async fn call_tool(&self, name: &str, args: &HashMap<String, Value>) -> CallToolResult {
-        match name {
+        self.call_tool_with_context(name, args, &ToolContext::default()).await
+    }
+
+    /// Handle a tool call with a per-call context (progress reporting)
+    pub async fn call_tool_with_context(
+        &self,
+        name: &str,
+        args: &HashMap<String, Value>,
+        ctx: &ToolContext,
+    ) -> CallToolResult {
+        match self.routed(name, args) {
+            Ok(connection) => connection.call_connection_tool(name, args, ctx).await,
+            Err(e) => CallToolResult::error(e),
+        }
+    }
+
+    /// Handle a tool call on this connection
+    async fn call_connection_tool(
+        &self,
+        name: &str,
+        args: &HashMap<String, Value>,
+        ctx: &ToolContext,
+    ) -> CallToolResult {
+        if !self.is_tool_enabled(name) {
+            return CallToolResult::error(format!("Tool '{}' is disabled by server configuration", name));
+        }
+        let caller = match self.caller(args) {
+            Ok(caller) => caller,
+            Err(e) => return CallToolResult::error(e),
+        };
+
+        let key = match args.get(IDEMPOTENCY_KEY_ARG).and_then(|v| v.as_str()) {
+            Some(k) if !k.is_empty() => k.to_string(),
+            _ => return impersonation::as_caller(caller, self.dispatch_tool(name, args, ctx)).await,
+        };
+
+        let fingerprint = IdempotencyStore::fingerprint(name, args);
+        match self.idempotency.reserve(&key, &fingerprint) {
+            Reservation::Replay(result) => {
+                tracing::info!("Replaying result for idempotency_key {}", key);
+                result
+            }
+            Reservation::Rejected(message) => CallToolResult::error(message),
+            Reservation::Proceed => {
+                // Released if the call is cancelled and this future dropped
+                let reservation = ReservationGuard::new(&self.idempotency, &key);
+                let result = impersonation::as_caller(caller, self.dispatch_tool(name, args, ctx)).await;
+                reservation.complete(&result);
+                result
+            }
+        }
+    }
+
+    /// User the call runs as: the `impersonate_user` argument, else the
+    /// configured default; `none` opts out of the default
+    fn caller(&self, args: &HashMap<String, Value>) -> Result<Option<Caller>, String> {
+        match args.get(IMPERSONATE_USER_ARG).and_then(|v| v.as_str()).map(str::trim) {
+            None | Some("") => Ok(self.config.impersonate_user.clone()),
+            Some(_) if *self.client.product() != ProductType::Dataverse => {
+                Err("impersonate_user is only supported for Dataverse".to_string())
+            }
+            Some(user) if user.eq_ignore_ascii_case("none") => Ok(None),
+            Some(user) => Caller::parse(user).map(Some),
+        }
+    }
+
+    async fn dispatch_tool(
+        &self,
+        name: &str,
+        args: &HashMap<String, Value>,
+        ctx: &ToolContext,
+    ) -> CallToolResult {
+        if !parse_bool_arg(args, INCLUDE_SCRIPT_ARG) {
+            return self.run_tool(name, args, ctx).await;
+        }
+
+        let (mut result, requests) = script::record_requests(self.run_tool(name, args, ctx)).await;
+        result.content.push(TextContent {
+            content_type: "text".to_string(),
+            text: script::render_scripts(&requests, &self.client.resource_url()),
+        });
+        result
+    }
+
+    async fn run_tool(
+        &self,
+        name: &str,
+        args: &HashMap<String, Value>,
+        ctx: &ToolContext,
+    ) -> CallToolResult {
+        let result = match name {
             "list_entities" => self.list_entities().await,
-            "query_entity" => self.query_entity(args).await,
+            "query_entity" => self.query_entity(args, ctx).await,
+            "query_page" => self.query_page(args).await,
+            "describe_entity" => self.describe_entity(args).await,
+            "list_relationships" => self.list_relationships(args).await,
             "get_entity_schema" => self.get_entity_schema(args).await,
+            "entity_profile" => self.entity_profile(args).await,
+            "join_queries" => self.join_queries(args).await,
+            "batch_query" => self.batch_query(args).await,
             "get_record" => self.get_record(args).await,
+            "count_entities" => self.count_entities(args).await,
+            "get_changes" => self.get_changes(args, ctx).await,
+            "sync_status" => self.sync_status(args),
+            "start_change_tracking" => self.start_change_tracking(args, ctx).await,
             "get_environment_info" => self.get_environment_info().await,
             "get_metadata" => self.get_metadata(args).await,
-            _ => CallToolResult::error(format!("Unknown tool: {}", name)),
+            "refresh_metadata" => self.refresh_metadata().await,
+            "diff_metadata" => self.diff_metadata(args).await,
+            "usage_stats" => self.usage_stats(),
+            "create_entity" => self.create_entity(args).await,
+            "update_entity" => self.update_entity(args).await,
+            "delete_entity" => self.delete_entity(args).await,
+            "upsert_entity" => self.upsert_entity(args).await,
+            "create_deep" => self.create_deep(args).await,
+            "transaction" => self.transaction(args).await,
+            "set_record_state" => self.set_record_state(args).await,
+            "assign_record" => self.assign_record(args).await,
+            "bulk_create" => self.bulk_write(args, false, ctx).await,
+            "bulk_update" => self.bulk_write(args, true, ctx).await,
+            "associate_records" => self.relate_records(args, true).await,
+            "disassociate_records" => self.relate_records(args, false).await,
+            "list_result_sets" => self.list_result_sets(),
+            "query_result_set" => self.query_result_set(args),
+            "aggregate_result_set" => self.aggregate_result_set(args),
+            "export_result_set" => self.export_result_set(args),
+            "drop_result_set" => self.drop_result_set(args),
+            "list_connections" if !self.connections.is_empty() => self.list_connections(),
+            _ => match self.entity_tool(name) {
+                Some(tool) => self.run_entity_tool(tool, args, ctx).await,
+                None => CallToolResult::error(format!("Unknown tool: {}", name)),
+            },
+        };
+        if let Some(cache) = &self.query_cache {
+            // Cached queries of the written entity would hide the change
+            // Exports write files, not D365 records
+            if tool_group(name) == "write" && name != "export_result_set" && result.is_error != Some(true) {
+                cache.invalidate(args.get("entity").and_then(|v| v.as_str()));
+            }
+        }
+        append_error_hints(result)
+    }
+
+    /// Run a `query_<entity>` or `get_<entity>` tool as the generic tool it stands for
+    async fn run_entity_tool(
+        &self,
+        tool: &EntityTool,
+        args: &HashMap<String, Value>,
+        ctx: &ToolContext,
+    ) -> CallToolResult {
+        let model = self.metadata.current();
+        let args = match entity_tools::target_args(tool, self.config.entity(&tool.entity), model.as_deref(), args) {
+            Ok(args) => args,
+            Err(e) => return CallToolResult::error(e),
+        };
+        match tool.kind {
+            entity_tools::EntityToolKind::Query => self.query_entity(&args, ctx).await,
+            entity_tools::EntityToolKind::Get => self.get_record(&args).await,
         }
     }
 
     async fn list_entities(&self) -> CallToolResult {
-        match self.client.fetch_metadata().await {
-            Ok(metadata) => {
-                let entities = extract_entity_sets_from_metadata(&metadata);
+        match self.metadata_model(false).await {
+            Ok(model) => {
+                let entities = entity_set_names(&model);
                 let text = format!("Available entities:\n{}", entities.join("\n"));
                 CallToolResult::text(text)
             }
@@ -103,7 +953,7 @@ impl D365McpServer {
         }
     }
 
-    async fn query_entity(&self, args: &HashMap<String, Value>) -> CallToolResult {
+    async fn query_entity(&self, args: &HashMap<String, Value>, ctx: &ToolContext) -> CallToolResult {
         let entity = match args.get("entity").and_then(|v| v.as_str()) {
             Some(e) => e,
             None => return CallToolResult::error("Missing required parameter: entity".to_string()),
@@ -115,128 +965,1969 @@ impl D365McpServer {
             .and_then(|v| v.as_str())
             .map(|s| s.split(',').map(|f| f.trim().to_string()).collect());
 
-        // Parse filter
+        // Parse filter, combined with modified_within / created_within windows
         let filter = args.get("filter").and_then(|v| v.as_str()).map(String::from);
+        let filter = match self.with_time_windows(filter, args) {
+            Ok(f) => f,
+            Err(e) => return CallToolResult::error(e),
+        };
+
+        // Parse search (Dataverse only)
+        let search = args
+            .get("search")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from);
+        if search.is_some() && *self.client.product() == ProductType::Finops {
+            return CallToolResult::error("$search is not supported by F&O OData; use filter with contains()".to_string());
+        }
 
         // Parse orderby
         let orderby = args.get("orderby").and_then(|v| v.as_str()).map(String::from);
 
-        // Parse top (with max limit 1000)
-        let top = parse_number_arg(args, "top").unwrap_or(50).min(1000);
+        // Parse top (with max limit 1000; a stored result set may hold more)
+        let store_as = args.get("store_as").and_then(|v| v.as_str()).filter(|s| !s.is_empty());
+        let top = match store_as {
+            Some(_) => parse_number_arg(args, "top").unwrap_or(MAX_STORED_ROWS).min(MAX_STORED_ROWS),
+            None => parse_number_arg(args, "top").unwrap_or(50).min(1000),
+        };
 
         // Parse skip
         let skip = parse_number_arg(args, "skip");
 
-        // Parse expand
-        let expand = args
-            .get("expand")
-            .and_then(|v| v.as_str())
-            .map(|s| s.split(',').map(|f| f.trim().to_string()).collect());
+        // Parse expand (text or structured JSON)
+        let expand = match parse_expand_arg(args, "expand") {
+            Ok(e) => e,
+            Err(e) => return CallToolResult::error(e),
+        };
 
         // Parse cross_company (boolean)
-        let cross_company = args
-            .get("cross_company")
-            .and_then(|v| v.as_str().map(|s| s == "true").or_else(|| v.as_bool()))
-            .unwrap_or(false);
+        let cross_company = parse_bool_arg(args, "cross_company");
 
         // Parse count (boolean)
-        let count = args
-            .get("count")
-            .and_then(|v| v.as_str().map(|s| s == "true").or_else(|| v.as_bool()))
-            .unwrap_or(false);
+        let count = parse_bool_arg(args, "count");
+
+        let aliases = match parse_alias_arg(args, "params") {
+            Ok(a) => a,
+            Err(e) => return CallToolResult::error(e),
+        };
 
         let options = QueryOptions {
             select,
             filter,
-            top: Some(top),
+            apply: None,
+            search,
+            // Stored sets page through everything instead of sending a large $top
+            top: if store_as.is_some() && !args.contains_key("top") { None } else { Some(top) },
             skip,
             orderby,
             expand,
             cross_company,
             count,
+            aliases,
+            max_page_size: parse_number_arg(args, "page_size"),
+            track_changes: false,
         };
 
-        match self.client.fetch_entity_page(entity, None, &options).await {
-            Ok(response) => {
-                let record_count = response.value.len();
-                let has_more = response.next_link.is_some();
-                let total_count = response.count;
-                let json = serde_json::to_string_pretty(&response.value)
-                    .unwrap_or_else(|_| "[]".to_string());
+        let flatten = match args.get("flatten_collections").and_then(|v| v.as_str()) {
+            Some(mode) => match CollectionMode::parse(mode) {
+                Ok(mode) => parse_bool_arg(args, "flatten").then_some(mode),
+                Err(e) => return CallToolResult::error(e),
+            },
+            None => parse_bool_arg(args, "flatten").then(CollectionMode::default),
+        };
 
-                let mut result = String::new();
-                
-                if let Some(total) = total_count {
-                    result.push_str(&format!("Total records: {}\n", total));
-                }
-                
-                result.push_str(&format!(
-                    "Showing {} records{}:\n\n{}",
-                    record_count,
-                    if has_more { " (more available)" } else { "" },
-                    json
-                ));
-                
-                CallToolResult::text(result)
+        let slim = parse_bool_arg(args, "slim");
+        let shape = |record: &Value| {
+            let record = if slim { transform::slim_record(record) } else { record.clone() };
+            match flatten {
+                Some(mode) => transform::flatten_record(&record, mode),
+                None => record,
             }
-            Err(e) => CallToolResult::error(format!("Error querying {}: {}", entity, e)),
+        };
+
+        let cursor = args.get("cursor").and_then(|v| v.as_str());
+        // The link is fetched with this server's token, so it must stay on its endpoint
+        if cursor.is_some_and(|link| !link.starts_with(self.client.endpoint())) {
+            return CallToolResult::error("Invalid cursor: it points to another service".to_string());
         }
-    }
+        let deadline = self.tool_deadline(args);
+        let respond_async = parse_bool_arg(args, "respond_async");
 
-    async fn get_entity_schema(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let stream = parse_bool_arg(args, "stream") && ctx.progress.is_enabled();
+
+        let fetched = if respond_async && cursor.is_none() {
+            self.query_entity_async(entity, &options, deadline, ctx).await.map(|f| (f, None))
+        } else if stream {
+            let mut chunk_start = 0;
+            self.client
+                .fetch_pages_with(entity, cursor, &options, Some(top), deadline, |page, fetched| {
+                    // The last page may run past `top`; only stream rows that will be returned
+                    let page = &page[..page.len().min(top.saturating_sub(chunk_start))];
+                    for chunk in page.chunks(STREAM_CHUNK_ROWS) {
+                        let rows: Vec<Value> = chunk.iter().map(shape).collect();
+                        let json = serde_json::to_string(&rows).unwrap_or_else(|_| "[]".to_string());
+                        ctx.progress.report(
+                            (chunk_start + chunk.len()) as f64,
+                            Some(top as f64),
+                            Some(format!(
+                                "Rows {}-{} of {}:\n{}",
+                                chunk_start + 1,
+                                chunk_start + chunk.len(),
+                                entity,
+                                json
+                            )),
+                        );
+                        chunk_start += chunk.len();
+                    }
+                    tracing::debug!("Streamed {} of {} fetched records", chunk_start, fetched);
+                })
+                .await
+                .map(|f| (f, None))
+        } else {
+            self.cached_fetch(entity, cursor, &options, top, deadline, args).await
+        };
+
+        match fetched {
+            Ok((mut fetched, cached_age)) => {
+                if slim || flatten.is_some() {
+                    for record in fetched.records.iter_mut() {
+                        *record = shape(record);
+                    }
+                }
+
+                let mut result = String::new();
+                let mut record_count = fetched.records.len();
+                let mut shown = &fetched.records[..];
+
+                if let Some(age) = cached_age {
+                    result.push_str(&format!(
+                        "cached: true (same query {}s ago; pass cache=false for fresh data)\n",
+                        age.as_secs()
+                    ));
+                }
+
+                if let Some(name) = store_as {
+                    fetched.records.truncate(top);
+                    self.result_sets().insert(name, entity, fetched.records.clone());
+                    result.push_str(&format!(
+                        "Stored {} records as result set '{}'. Use query_result_set, aggregate_result_set or export_result_set to work with it.\n",
+                        fetched.records.len(),
+                        name
+                    ));
+                    shown = &fetched.records[..fetched.records.len().min(STORED_PREVIEW_ROWS)];
+                    record_count = shown.len();
+                }
+
+                let json = serde_json::to_string_pretty(shown)
+                    .unwrap_or_else(|_| "[]".to_string());
+                
+                if let Some(total) = fetched.count {
+                    result.push_str(&format!("Total records: {}\n", total));
+                }
+
+                if fetched.partial {
+                    result.push_str("partial: true (deadline reached before all pages were fetched)\n");
+                }
+                if let Some(ref link) = fetched.next_link {
+                    result.push_str(&format!("cursor: {}\n", link));
+                }
+                
+                result.push_str(&format!(
+                    "Showing {} records{}:\n\n{}",
+                    record_count,
+                    if fetched.next_link.is_some() { " (more available)" } else { "" },
+                    json
+                ));
+                
+                CallToolResult::text(result)
+            }
+            Err(e) => CallToolResult::error(format!("Error querying {}: {}", entity, e)),
+        }
+    }
+
+    /// `fetch_pages` through the query result cache, unless it is disabled
+    /// or the call passes `cache: false`; cached results come with their age
+    async fn cached_fetch(
+        &self,
+        entity: &str,
+        cursor: Option<&str>,
+        options: &QueryOptions,
+        top: usize,
+        deadline: Option<Instant>,
+        args: &HashMap<String, Value>,
+    ) -> Result<(PagedFetch, Option<Duration>), ODataError> {
+        let bypass = args.get(CACHE_ARG).is_some_and(|v| *v == false || *v == "false");
+        let cache = match &self.query_cache {
+            Some(cache) if !bypass => cache,
+            _ => {
+                let fetched = self.client.fetch_pages(entity, cursor, options, Some(top), deadline).await?;
+                return Ok((fetched, None));
+            }
+        };
+
+        // Impersonated callers may see different rows
+        let caller = impersonation::current_header().map(|(_, user)| user).unwrap_or_default();
+        let query = format!(
+            "{}|{}|{}",
+            options.to_query_string(self.client.product()),
+            cursor.unwrap_or_default(),
+            caller
+        );
+        if let Some((fetched, age)) = cache.get(entity, &query) {
+            tracing::debug!("Serving {} query from the cache ({}s old)", entity, age.as_secs());
+            return Ok((fetched, Some(age)));
+        }
+        let fetched = self.client.fetch_pages(entity, cursor, options, Some(top), deadline).await?;
+        cache.insert(entity, &query, &fetched);
+        Ok((fetched, None))
+    }
+
+    /// One page of a query, resumable with the returned cursor
+    async fn query_page(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let limit = parse_number_arg(args, "limit").unwrap_or(50).clamp(1, 1000);
+        let cursor = match args.get("cursor").and_then(|v| v.as_str()).filter(|s| !s.is_empty()) {
+            Some(text) => match PageCursor::decode(text) {
+                // The link is fetched with this server's token, so it must stay on its endpoint
+                Ok(cursor) if cursor.link.starts_with(self.client.endpoint()) => Some(cursor),
+                Ok(_) => return CallToolResult::error("Invalid cursor: it points to another service".to_string()),
+                Err(e) => return CallToolResult::error(e),
+            },
+            None => None,
+        };
+
+        let (entity, options) = match &cursor {
+            Some(cursor) => (
+                cursor.entity.as_str(),
+                QueryOptions {
+                    max_page_size: Some(limit),
+                    ..Default::default()
+                },
+            ),
+            None => {
+                let entity = match args.get("entity").and_then(|v| v.as_str()) {
+                    Some(e) => e,
+                    None => return CallToolResult::error("Missing required parameter: entity (or cursor)".to_string()),
+                };
+                let expand = match parse_expand_arg(args, "expand") {
+                    Ok(e) => e,
+                    Err(e) => return CallToolResult::error(e),
+                };
+                let aliases = match parse_alias_arg(args, "params") {
+                    Ok(a) => a,
+                    Err(e) => return CallToolResult::error(e),
+                };
+                let options = QueryOptions {
+                    select: args
+                        .get("select")
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.split(',').map(|f| f.trim().to_string()).collect()),
+                    filter: args.get("filter").and_then(|v| v.as_str()).map(String::from),
+                    orderby: args.get("orderby").and_then(|v| v.as_str()).map(String::from),
+                    expand,
+                    cross_company: parse_bool_arg(args, "cross_company"),
+                    count: parse_bool_arg(args, "count"),
+                    aliases,
+                    // Server pages of the same size keep one page per call
+                    max_page_size: Some(limit),
+                    ..Default::default()
+                };
+                (entity, options)
+            }
+        };
+
+        let mut window = match self.client.fetch_page_window(entity, cursor.as_ref(), &options, limit).await {
+            Ok(window) => window,
+            Err(e) => return CallToolResult::error(format!("Error querying {}: {}", entity, e)),
+        };
+        if parse_bool_arg(args, "slim") {
+            for record in window.records.iter_mut() {
+                *record = transform::slim_record(record);
+            }
+        }
+
+        let mut result = String::new();
+        if let Some(total) = window.count {
+            result.push_str(&format!("Total records: {}\n", total));
+        }
+        match &window.next {
+            Some(next) => result.push_str(&format!("cursor: {}\n", next.encode())),
+            None => result.push_str("Last page; no cursor\n"),
+        }
+        let json = serde_json::to_string_pretty(&window.records).unwrap_or_else(|_| "[]".to_string());
+        result.push_str(&format!("Showing {} records of {}:\n\n{}", window.records.len(), entity, json));
+        CallToolResult::text(result)
+    }
+
+    /// AND the `modified_within` / `created_within` windows onto a filter
+    fn with_time_windows(
+        &self,
+        filter: Option<String>,
+        args: &HashMap<String, Value>,
+    ) -> Result<Option<String>, String> {
+        let now = SystemTime::now();
+        let mut clauses = Vec::new();
+        for (arg, field) in [
+            ("modified_within", AuditField::Modified),
+            ("created_within", AuditField::Created),
+        ] {
+            if let Some(text) = args.get(arg).and_then(|v| v.as_str()) {
+                let window = time_window::parse_window(text)?;
+                clauses.push(time_window::within_filter(field, self.client.product(), window, now));
+            }
+        }
+
+        if clauses.is_empty() {
+            return Ok(filter);
+        }
+        if let Some(f) = filter {
+            clauses.insert(0, format!("({})", f));
+        }
+        Ok(Some(clauses.join(" and ")))
+    }
+
+    /// Run a query with `Prefer: respond-async`, reporting progress while the
+    /// service works on it
+    async fn query_entity_async(
+        &self,
+        entity: &str,
+        options: &QueryOptions,
+        deadline: Option<Instant>,
+        ctx: &ToolContext,
+    ) -> Result<PagedFetch, ODataError> {
+        let max_wait = deadline
+            .map(|d| d.saturating_duration_since(Instant::now()))
+            .unwrap_or(ASYNC_MAX_WAIT);
+
+        let response = self
+            .client
+            .fetch_entity_page_async(entity, options, ASYNC_POLL_INTERVAL, max_wait, |polls, elapsed| {
+                ctx.progress.report(
+                    polls as f64,
+                    None,
+                    Some(format!(
+                        "Waiting for D365 to finish the query on {} ({}s elapsed)",
+                        entity,
+                        elapsed.as_secs()
+                    )),
+                );
+            })
+            .await?;
+
+        Ok(PagedFetch {
+            records: response.value,
+            count: response.count,
+            next_link: response.next_link,
+            partial: false,
+            delta_link: response.delta_link,
+        })
+    }
+
+    fn usage_stats(&self) -> CallToolResult {
+        let b = self.client.budget_snapshot();
+        let pct = |used: f64, max: f64| if max > 0.0 { used * 100.0 / max } else { 0.0 };
+
+        let mut text = format!(
+            "Service Protection Budget (sliding {}s window):\n\
+             - Requests: {} / {} ({:.1}%)\n\
+             - Execution time: {:.1}s / {}s ({:.1}%)\n\
+             - Soft limit: {}% (requests are delayed above it)\n\
+             \n\
+             Totals since start:\n\
+             - Requests: {}\n\
+             - Throttled by service (429): {}\n\
+             - Soft-limit delays: {}",
+            b.window_seconds,
+            b.requests_in_window,
+            b.max_requests,
+            pct(f64::from(b.requests_in_window), f64::from(b.max_requests)),
+            b.execution_seconds_in_window,
+            b.max_execution_seconds,
+            pct(b.execution_seconds_in_window, b.max_execution_seconds as f64),
+            b.soft_limit_percent,
+            b.total_requests,
+            b.total_throttled,
+            b.total_soft_delays,
+        );
+        if b.reported_requests_remaining.is_some() || b.reported_execution_seconds_remaining.is_some() {
+            let or_unknown = |v: Option<String>| v.unwrap_or_else(|| "unknown".to_string());
+            text.push_str(&format!(
+                "\n\nRemaining as reported by Dataverse (all clients of this user):\n\
+                 - Requests: {}\n\
+                 - Execution time: {}",
+                or_unknown(b.reported_requests_remaining.map(|n| n.to_string())),
+                or_unknown(b.reported_execution_seconds_remaining.map(|s| format!("{:.1}s", s))),
+            ));
+        }
+        CallToolResult::text(text)
+    }
+
+    /// Deadline for a tool call from the `timeout_seconds` argument or config
+    fn tool_deadline(&self, args: &HashMap<String, Value>) -> Option<Instant> {
+        parse_number_arg(args, "timeout_seconds")
+            .map(|s| s as u64)
+            .or(self.config.tool_timeout_seconds)
+            .filter(|s| *s > 0)
+            // A timeout too large to represent is no deadline at all
+            .and_then(|s| Instant::now().checked_add(Duration::from_secs(s)))
+    }
+
+    async fn describe_entity(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let model = match self.metadata_model(parse_bool_arg(args, "refresh")).await {
+            Ok(m) => m,
+            Err(e) => return CallToolResult::error(format!("Failed to fetch metadata: {}", e)),
+        };
+        if let Some(kind) = args.get("payload_schema").and_then(|v| v.as_str()) {
+            let kind = match PayloadKind::parse(kind) {
+                Ok(k) => k,
+                Err(e) => return CallToolResult::error(e),
+            };
+            return match validate::entity_type_for_payload(&model, entity) {
+                Some(entity_type) => CallToolResult::text(
+                    serde_json::to_string_pretty(&validate::payload_schema(&model, entity_type, kind))
+                        .unwrap_or_default(),
+                ),
+                None => CallToolResult::error(format!(
+                    "Entity set '{}' not found in metadata. Use list_entities to see entity set names",
+                    entity
+                )),
+            };
+        }
+        match format_entity_description(&model, entity) {
+            Some(text) => CallToolResult::text(text),
+            None => CallToolResult::error(format!(
+                "Entity '{}' not found in metadata. Use list_entities to see entity set names",
+                entity
+            )),
+        }
+    }
+
+    async fn list_relationships(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let kind = args.get("kind").and_then(|v| v.as_str());
+        if let Some(kind) = kind.filter(|k| !["N:1", "1:N", "N:N", "1:1"].contains(k)) {
+            return CallToolResult::error(format!("Invalid kind '{}': expected N:1, 1:N, N:N or 1:1", kind));
+        }
+        let model = match self.metadata_model(parse_bool_arg(args, "refresh")).await {
+            Ok(m) => m,
+            Err(e) => return CallToolResult::error(format!("Failed to fetch metadata: {}", e)),
+        };
+        match format_relationships(&model, entity, kind) {
+            Some(text) => CallToolResult::text(text),
+            None => CallToolResult::error(format!(
+                "Entity '{}' not found in metadata. Use list_entities to see entity set names",
+                entity
+            )),
+        }
+    }
+
+    async fn get_entity_schema(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+
+        let options = QueryOptions {
+            top: Some(1),
+            ..Default::default()
+        };
+
+        match self.client.fetch_entity_page(entity, None, &options).await {
+            Ok(response) => {
+                if let Some(sample) = response.value.into_iter().next() {
+                    if let Value::Object(map) = &sample {
+                        let fields: Vec<String> = map.keys().cloned().collect();
+                        let result = format!(
+                            "Entity: {}\nFields ({}):\n{}\n\nSample record:\n{}",
+                            entity,
+                            fields.len(),
+                            fields.join(", "),
+                            serde_json::to_string_pretty(&sample).unwrap_or_default()
+                        );
+                        CallToolResult::text(result)
+                    } else {
+                        CallToolResult::text(serde_json::to_string_pretty(&sample).unwrap_or_default())
+                    }
+                } else {
+                    CallToolResult::text(format!("No records found in entity '{}'", entity))
+                }
+            }
+            Err(e) => CallToolResult::error(format!("Error fetching schema for {}: {}", entity, e)),
+        }
+    }
+
+    /// Profile an entity: row count, audit-date ranges, frequent values and a sample.
+    ///
+    /// Statistics are fetched concurrently; one that fails (e.g. an F&O entity
+    /// without audit fields) is shown as unavailable instead of failing the call.
+    async fn entity_profile(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let filter = args.get("filter").and_then(|v| v.as_str()).map(String::from);
+        let column = args.get("column").and_then(|v| v.as_str());
+        let top_values = parse_number_arg(args, "top_values").unwrap_or(10).clamp(1, 100);
+        let product = self.client.product();
+
+        let base = QueryOptions {
+            filter: filter.clone(),
+            ..Default::default()
+        };
+        let count_options = QueryOptions {
+            top: Some(1),
+            count: true,
+            ..base.clone()
+        };
+        let sample_options = QueryOptions {
+            top: Some(5),
+            ..base.clone()
+        };
+
+        let edge = |field: AuditField, direction: &str| {
+            let name = field.name(product);
+            let options = QueryOptions {
+                select: Some(vec![name.to_string()]),
+                orderby: Some(format!("{} {}", name, direction)),
+                top: Some(1),
+                ..base.clone()
+            };
+            async move {
+                let response = self.client.fetch_entity_page(entity, None, &options).await?;
+                Ok::<_, ODataError>(
+                    response
+                        .value
+                        .first()
+                        .and_then(|r| r.get(name))
+                        .and_then(|v| v.as_str())
+                        .map(String::from),
+                )
+            }
+        };
+
+        let values = async {
+            let column = column?;
+            let options = QueryOptions {
+                apply: Some(match &filter {
+                    Some(f) => format!("filter({})/groupby(({}),aggregate($count as count))", f, column),
+                    None => format!("groupby(({}),aggregate($count as count))", column),
+                }),
+                ..Default::default()
+            };
+            Some(self.client.fetch_entity_page(entity, None, &options).await)
+        };
+
+        let (count, sample, created_min, created_max, modified_min, modified_max, values) = futures::join!(
+            self.client.fetch_entity_page(entity, None, &count_options),
+            self.client.fetch_entity_page(entity, None, &sample_options),
+            edge(AuditField::Created, "asc"),
+            edge(AuditField::Created, "desc"),
+            edge(AuditField::Modified, "asc"),
+            edge(AuditField::Modified, "desc"),
+            values,
+        );
+
+        // The sample doubles as an existence check for the entity set
+        let sample = match sample {
+            Ok(response) => response.value,
+            Err(e) => return CallToolResult::error(format!("Error profiling {}: {}", entity, e)),
+        };
+
+        let mut result = format!("# Profile: {}\n\n", entity);
+        if let Some(f) = &filter {
+            result.push_str(&format!("Filter: {}\n\n", f));
+        }
+
+        result.push_str(&match count {
+            Ok(response) => match response.count {
+                Some(n) => format!("- Total rows: {}\n", n),
+                None => "- Total rows: unknown (count not returned)\n".to_string(),
+            },
+            Err(e) => format!("- Total rows: unavailable ({})\n", e),
+        });
+
+        let range = |label: &str, field: AuditField, min: Result<Option<String>, ODataError>, max: Result<Option<String>, ODataError>| {
+            match (min, max) {
+                (Ok(Some(min)), Ok(Some(max))) => {
+                    format!("- {} ({}): {} .. {}\n", label, field.name(product), min, max)
+                }
+                (Ok(_), Ok(_)) => format!("- {} ({}): no values\n", label, field.name(product)),
+                (Err(e), _) | (_, Err(e)) => {
+                    format!("- {} ({}): unavailable ({})\n", label, field.name(product), e)
+                }
+            }
+        };
+        result.push_str(&range("Created", AuditField::Created, created_min, created_max));
+        result.push_str(&range("Modified", AuditField::Modified, modified_min, modified_max));
+
+        if let (Some(column), Some(values)) = (column, values) {
+            result.push_str(&format!("\n## Top values of {}\n\n", column));
+            match values {
+                Ok(response) => result.push_str(&format_top_values(column, response.value, top_values)),
+                Err(e) => result.push_str(&format!("Unavailable ($apply groupby failed: {})\n", e)),
+            }
+        }
+
+        result.push_str(&format!(
+            "\n## Sample ({} rows)\n\n{}",
+            sample.len(),
+            serde_json::to_string_pretty(&sample).unwrap_or_else(|_| "[]".to_string())
+        ));
+
+        CallToolResult::text(result)
+    }
+
+    /// Fetch two entity sets and join them locally
+    async fn start_change_tracking(&self, args: &HashMap<String, Value>, ctx: &ToolContext) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let options = QueryOptions {
+            select: args
+                .get("select")
+                .and_then(|v| v.as_str())
+                .map(|s| s.split(',').map(|f| f.trim().to_string()).collect()),
+            track_changes: true,
+            ..Default::default()
+        };
+
+        let fetched = self
+            .client
+            .fetch_pages_with(entity, None, &options, None, self.tool_deadline(args), |_, fetched| {
+                ctx.progress.report(
+                    fetched as f64,
+                    None,
+                    Some(format!("Read {} rows of {} for the change-tracking baseline", fetched, entity)),
+                );
+            })
+            .await;
+        let fetched = match fetched {
+            Ok(f) => f,
+            Err(e) => return CallToolResult::error(format!("Error starting change tracking on {}: {}", entity, e)),
+        };
+
+        if fetched.partial {
+            return CallToolResult::error(format!(
+                "Deadline reached after {} rows before the delta link was returned; retry with a larger timeout_seconds",
+                fetched.records.len()
+            ));
+        }
+        match fetched.delta_link {
+            Some(link) => {
+                let mut result = format!(
+                    "Change tracking started on {} ({} current rows)\n\ndelta_link: {}\n\nPass the delta link as 'cursor' to query_entity to get only the rows created, updated or deleted since now. Each change result carries a new delta link.\n",
+                    entity,
+                    fetched.records.len(),
+                    link
+                );
+                if !fetched.records.is_empty() {
+                    let preview: Vec<&Value> = fetched.records.iter().take(STORED_PREVIEW_ROWS).collect();
+                    result.push_str(&format!(
+                        "\nFirst {} rows:\n{}",
+                        preview.len(),
+                        serde_json::to_string_pretty(&preview).unwrap_or_default()
+                    ));
+                }
+                CallToolResult::text(result)
+            }
+            None => CallToolResult::error(format!(
+                "{} returned no delta link: change tracking is probably not enabled for this entity",
+                entity
+            )),
+        }
+    }
+
+    async fn get_changes(&self, args: &HashMap<String, Value>, ctx: &ToolContext) -> CallToolResult {
         let entity = match args.get("entity").and_then(|v| v.as_str()) {
             Some(e) => e,
             None => return CallToolResult::error("Missing required parameter: entity".to_string()),
         };
+        if self.config.entity(entity).is_some_and(|e| e.delta_enabled == Some(false)) {
+            return CallToolResult::error(format!(
+                "Delta sync is disabled for {} (delta_enabled = false in the config)",
+                entity
+            ));
+        }
+
+        if parse_bool_arg(args, "reset") {
+            if let Err(e) = self.delta.store().remove(entity) {
+                return CallToolResult::error(format!("Error resetting sync state of {}: {}", entity, e));
+            }
+        }
+
+        let pull = PullOptions {
+            select: args
+                .get("select")
+                .and_then(|v| v.as_str())
+                .map(|s| s.split(',').map(|f| f.trim().to_string()).collect()),
+            resume: parse_bool_arg(args, "resume"),
+            deadline: self.tool_deadline(args),
+            ..PullOptions::for_entity(entity, &self.config)
+        };
+        let pulled = self
+            .delta
+            .pull(entity, &pull, |_, fetched| {
+                ctx.progress.report(
+                    fetched as f64,
+                    None,
+                    Some(format!("Read {} changes of {}", fetched, entity)),
+                );
+                Ok(())
+            })
+            .await;
+        let changes = match pulled {
+            Ok(c) => c,
+            Err(SyncError::Interrupted { reason, resumable, rows }) => {
+                let (changed, deleted) = sync::split_changes(rows);
+                let mut result = format!(
+                    "partial: true\nPull of {} stopped: {}\n{} rows read so far are shown below; sync state was not advanced.\n",
+                    entity,
+                    reason,
+                    changed.len() + deleted.len()
+                );
+                if resumable {
+                    result.push_str("Call again with resume = true to continue after these rows.\n");
+                }
+                self.append_changes(&mut result, entity, args, changed, &deleted);
+                return CallToolResult::text(result);
+            }
+            Err(e) => {
+                return CallToolResult::error(format!(
+                    "Error getting changes of {}: {}\nIf the stored delta link has expired, call again with reset = true.",
+                    entity, e
+                ))
+            }
+        };
+
+        let field = &pull.watermark.field;
+        let mut result = if changes.initial_load_skipped {
+            format!(
+                "Tracking of {} started without an initial load (initial_load = false); the next call returns changes made from now on\n",
+                entity
+            )
+        } else if changes.initial {
+            format!(
+                "Initial sync of {}: {} rows ({})\n",
+                entity,
+                changes.resumed_rows + changes.changed.len(),
+                match (changes.mode, &changes.watermark) {
+                    (SyncMode::ChangeTracking, _) => "change tracking started".to_string(),
+                    (SyncMode::Watermark, Some(mark)) => format!("watermark {} = {}", field, mark),
+                    (SyncMode::Watermark, None) => format!("no {} values yet; next call reads all rows again", field),
+                }
+            )
+        } else if changes.mode == SyncMode::Watermark {
+            format!(
+                "Rows of {} modified since the last sync: {} (watermark {}; rows inside the {}s overlap window may repeat and deletions are not reported)\n",
+                entity,
+                changes.resumed_rows + changes.changed.len(),
+                changes.watermark.as_deref().unwrap_or("unchanged"),
+                self.config.delta_overlap_seconds
+            )
+        } else {
+            format!(
+                "Changes in {} since the last sync: {} new or updated, {} deleted\n",
+                entity,
+                changes.resumed_rows + changes.changed.len(),
+                changes.deleted.len()
+            )
+        };
+        if changes.resumed_rows > 0 {
+            result.push_str(&format!(
+                "Resumed from a checkpoint: {} rows were read by an earlier interrupted call and are not repeated.\n",
+                changes.resumed_rows
+            ));
+        }
+        self.append_changes(&mut result, entity, args, changes.changed, &changes.deleted);
+        CallToolResult::text(result)
+    }
+
+    fn sync_status(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let only = args.get("entity").and_then(|v| v.as_str());
+        let wanted = |name: &str| only.map_or(true, |o| o.eq_ignore_ascii_case(name));
+        let mut result = String::new();
+
+        match &self.scheduler {
+            None => result.push_str("Background sync: disabled ([sync] enabled = false)\n"),
+            Some(scheduler) => {
+                match scheduler.sink() {
+                    Some(sink) => result.push_str(&format!("Background sync (writing to {}):\n", sink.location())),
+                    None => result.push_str("Background sync (no sink configured, rows are not kept):\n"),
+                }
+                for (entity, status) in scheduler.status().into_iter().filter(|(e, _)| wanted(e)) {
+                    let last = match &status.last_result {
+                        None => "not run yet".to_string(),
+                        Some(Ok(run)) => format!(
+                            "{}{} changed, {} deleted ({})",
+                            if run.initial { "initial load, " } else { "" },
+                            run.changed,
+                            run.deleted,
+                            match run.mode {
+                                SyncMode::ChangeTracking => "change tracking",
+                                SyncMode::Watermark => "watermark",
+                            }
+                        ),
+                        Some(Err(e)) => format!("failed: {}", e),
+                    };
+                    result.push_str(&format!(
+                        "- {}: every {}s, {}runs {}, failures {}\n  last run: {} (started {}, finished {})\n  next run: {}\n",
+                        entity,
+                        status.interval.as_secs(),
+                        if status.running { "running now, " } else { "" },
+                        status.runs,
+                        status.failures,
+                        last,
+                        status.last_started.as_deref().unwrap_or("-"),
+                        status.last_finished.as_deref().unwrap_or("-"),
+                        status.next_run.as_deref().unwrap_or("-"),
+                    ));
+                }
+                if let Ok(states) = scheduler.sync().store().list() {
+                    append_sync_states(&mut result, "Background sync state", states, &wanted);
+                }
+            }
+        }
+
+        match self.delta.store().list() {
+            Ok(states) => append_sync_states(
+                &mut result,
+                &format!("get_changes state ({})", self.delta.store().location()),
+                states,
+                &wanted,
+            ),
+            Err(e) => result.push_str(&format!("\nget_changes state unavailable: {}\n", e)),
+        }
+        CallToolResult::text(result)
+    }
+
+    /// Append deleted ids and changed rows (or a preview when `store_as` is set) to a get_changes result
+    fn append_changes(
+        &self,
+        result: &mut String,
+        entity: &str,
+        args: &HashMap<String, Value>,
+        changed: Vec<Value>,
+        deleted: &[DeletedRecord],
+    ) {
+        let mut shown = &changed[..];
+        if let Some(name) = args.get("store_as").and_then(|v| v.as_str()) {
+            self.result_sets().insert(name, entity, changed.clone());
+            result.push_str(&format!("Stored {} rows as result set '{}'.\n", changed.len(), name));
+            shown = &changed[..changed.len().min(STORED_PREVIEW_ROWS)];
+        }
+
+        if !deleted.is_empty() {
+            let deleted: Vec<Value> = deleted
+                .iter()
+                .map(|d| serde_json::json!({ "id": d.id, "reason": d.reason }))
+                .collect();
+            result.push_str(&format!(
+                "\nDeleted:\n{}\n",
+                serde_json::to_string_pretty(&deleted).unwrap_or_default()
+            ));
+        }
+        if !shown.is_empty() {
+            result.push_str(&format!(
+                "\nNew or updated{}:\n{}\n",
+                if shown.len() < changed.len() { " (preview)" } else { "" },
+                serde_json::to_string_pretty(shown).unwrap_or_default()
+            ));
+        }
+    }
+
+    async fn count_entities(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let filter = args.get("filter").and_then(|v| v.as_str()).filter(|f| !f.trim().is_empty());
+        let cross_company = parse_bool_arg(args, "cross_company");
+
+        let count = match self.client.count_entities(entity, filter, cross_company).await {
+            Ok(c) => c,
+            Err(e) => return CallToolResult::error(format!("Error counting {}: {}", entity, e)),
+        };
+        if *self.client.product() != ProductType::Dataverse || count < DATAVERSE_COUNT_LIMIT {
+            return CallToolResult::text(format!("{}: {} records", entity, count));
+        }
+
+        // Dataverse stops counting at 5000: try an aggregate, then the table snapshot
+        if let Ok(exact) = self.client.aggregate_count(entity, filter).await {
+            return CallToolResult::text(format!("{}: {} records (aggregate count)", entity, exact));
+        }
+        if filter.is_none() {
+            if let Ok(Some(logical_name)) = self.client.entity_logical_name(entity).await {
+                if let Ok(Some(total)) = self.client.retrieve_total_record_count(&logical_name).await {
+                    return CallToolResult::text(format!(
+                        "{}: about {} records (RetrieveTotalRecordCount snapshot, may lag recent changes)",
+                        entity, total
+                    ));
+                }
+            }
+        }
+        CallToolResult::text(format!(
+            "{}: at least {} records (Dataverse caps $count at {}; the aggregate fallback also failed)",
+            entity, count, DATAVERSE_COUNT_LIMIT
+        ))
+    }
+
+    async fn batch_query(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let queries = match parse_array_arg(args, "queries") {
+            Ok(q) => q,
+            Err(e) => return CallToolResult::error(e),
+        };
+        if queries.is_empty() || queries.len() > BATCH_MAX_QUERIES {
+            return CallToolResult::error(format!(
+                "'queries' must hold between 1 and {} query specs",
+                BATCH_MAX_QUERIES
+            ));
+        }
+
+        let mut labels = Vec::with_capacity(queries.len());
+        let mut operations = Vec::with_capacity(queries.len());
+        for (i, query) in queries.iter().enumerate() {
+            let spec: HashMap<String, Value> = match query {
+                Value::Object(map) => map.clone().into_iter().collect(),
+                _ => return CallToolResult::error(format!("Query {} must be a JSON object", i + 1)),
+            };
+            let entity = match spec.get("entity").and_then(|v| v.as_str()) {
+                Some(e) => e,
+                None => return CallToolResult::error(format!("Query {} is missing 'entity'", i + 1)),
+            };
+            let list = |key: &str| {
+                spec.get(key)
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.split(',').map(|f| f.trim().to_string()).collect())
+            };
+            let id = spec.get("id").and_then(|v| v.as_str());
+            let expand = match parse_expand_arg(&spec, "expand") {
+                Ok(e) => e,
+                Err(e) => return CallToolResult::error(format!("Query {}: {}", i + 1, e)),
+            };
+            let aliases = match parse_alias_arg(&spec, "params") {
+                Ok(a) => a,
+                Err(e) => return CallToolResult::error(format!("Query {}: {}", i + 1, e)),
+            };
+            let options = QueryOptions {
+                select: list("select"),
+                filter: spec.get("filter").and_then(|v| v.as_str()).map(String::from),
+                orderby: spec.get("orderby").and_then(|v| v.as_str()).map(String::from),
+                top: id.is_none().then(|| parse_number_arg(&spec, "top").unwrap_or(50).min(1000)),
+                expand,
+                cross_company: parse_bool_arg(&spec, "cross_company"),
+                aliases,
+                ..Default::default()
+            };
+            let url = match id {
+                Some(id) => format!(
+                    "{}{}({}){}",
+                    self.client.endpoint(),
+                    entity,
+                    key_segment(id),
+                    options.to_query_string(self.client.product())
+                ),
+                None => self.client.entity_url(entity, &options),
+            };
+            labels.push(match id {
+                Some(id) => format!("{}({})", entity, id),
+                None => entity.to_string(),
+            });
+            operations.push(BatchOperation::get(url));
+        }
+
+        let responses = match self.client.execute_batch(&operations).await {
+            Ok(r) => r,
+            Err(e) => return CallToolResult::error(format!("Error executing batch: {}", e)),
+        };
+
+        let failed = responses.iter().filter(|r| !r.is_success()).count();
+        let mut result = format!(
+            "Batch of {} queries: {} succeeded, {} failed\n",
+            labels.len(),
+            responses.len() - failed,
+            failed
+        );
+        for (i, label) in labels.iter().enumerate() {
+            let Some(response) = responses.get(i) else {
+                result.push_str(&format!("\n### {}. {}\nNo response returned\n", i + 1, label));
+                continue;
+            };
+            let json = response.json();
+            if !response.is_success() {
+                let message = batch::error_message(response);
+                result.push_str(&format!(
+                    "\n### {}. {} - error {}\n{}\n",
+                    i + 1,
+                    label,
+                    response.status,
+                    message
+                ));
+                continue;
+            }
+            let rendered = match json {
+                Some(Value::Object(mut body)) => match body.remove("value") {
+                    Some(Value::Array(records)) => format!(
+                        "{} record(s)\n{}",
+                        records.len(),
+                        serde_json::to_string_pretty(&records).unwrap_or_default()
+                    ),
+                    Some(other) => {
+                        body.insert("value".to_string(), other);
+                        serde_json::to_string_pretty(&body).unwrap_or_default()
+                    }
+                    None => serde_json::to_string_pretty(&body).unwrap_or_default(),
+                },
+                _ => response.body.clone(),
+            };
+            result.push_str(&format!("\n### {}. {}\n{}\n", i + 1, label, rendered));
+        }
+        CallToolResult::text(result)
+    }
+
+    async fn join_queries(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let required = |name: &str| {
+            args.get(name)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| format!("Missing required parameter: {}", name))
+        };
+        let list = |name: &str| -> Option<Vec<String>> {
+            args.get(name).and_then(|v| v.as_str()).map(|s| {
+                s.split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect()
+            })
+        };
+
+        let (left_entity, right_entity) = match (required("left_entity"), required("right_entity")) {
+            (Ok(l), Ok(r)) => (l, r),
+            (Err(e), _) | (_, Err(e)) => return CallToolResult::error(e),
+        };
+        let (left_keys, right_keys) = match (list("left_key"), list("right_key")) {
+            (Some(l), Some(r)) if !l.is_empty() && l.len() == r.len() => (l, r),
+            (Some(_), Some(_)) => {
+                return CallToolResult::error(
+                    "left_key and right_key must list the same number of columns".to_string(),
+                )
+            }
+            _ => return CallToolResult::error("Missing required parameter: left_key/right_key".to_string()),
+        };
+        let join_type = match args.get("join_type").and_then(|v| v.as_str()) {
+            Some(t) => match JoinType::parse(t) {
+                Ok(t) => t,
+                Err(e) => return CallToolResult::error(e),
+            },
+            None => JoinType::Inner,
+        };
+        let right_prefix = args
+            .get("right_prefix")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .unwrap_or_else(|| format!("{}.", right_entity));
+        let max_rows = parse_number_arg(args, "max_rows")
+            .unwrap_or(JOIN_DEFAULT_ROWS)
+            .min(JOIN_MAX_ROWS);
+        let top = parse_number_arg(args, "top").unwrap_or(100).min(1000);
+        let cross_company = parse_bool_arg(args, "cross_company");
+
+        // Key columns must be selected or nothing would match
+        let side_options = |filter: &str, select: &str, keys: &[String]| QueryOptions {
+            filter: args.get(filter).and_then(|v| v.as_str()).map(String::from),
+            select: list(select).map(|mut fields| {
+                for key in keys {
+                    if !fields.contains(key) {
+                        fields.push(key.clone());
+                    }
+                }
+                fields
+            }),
+            cross_company,
+            ..Default::default()
+        };
+        let left_options = side_options("left_filter", "left_select", &left_keys);
+        let right_options = side_options("right_filter", "right_select", &right_keys);
+
+        let deadline = self.tool_deadline(args);
+        let (left, right) = futures::join!(
+            self.client.fetch_pages(left_entity, None, &left_options, Some(max_rows), deadline),
+            self.client.fetch_pages(right_entity, None, &right_options, Some(max_rows), deadline),
+        );
+        let (mut left, mut right) = match (left, right) {
+            (Ok(l), Ok(r)) => (l, r),
+            (Err(e), _) => return CallToolResult::error(format!("Error querying {}: {}", left_entity, e)),
+            (_, Err(e)) => return CallToolResult::error(format!("Error querying {}: {}", right_entity, e)),
+        };
+        left.records.truncate(max_rows);
+        right.records.truncate(max_rows);
+
+        let joined = join::join_records(
+            &left.records,
+            &right.records,
+            &left_keys,
+            &right_keys,
+            join_type,
+            &right_prefix,
+        );
+
+        let mut result = format!(
+            "Joined {} ({} rows) with {} ({} rows): {} rows\n",
+            left_entity,
+            left.records.len(),
+            right_entity,
+            right.records.len(),
+            joined.len()
+        );
+        for (entity, side) in [(left_entity, &left), (right_entity, &right)] {
+            if side.next_link.is_some() || side.partial {
+                result.push_str(&format!(
+                    "warning: {} was truncated at {} rows; narrow its filter or raise max_rows, the join may be incomplete\n",
+                    entity,
+                    side.records.len()
+                ));
+            }
+        }
+
+        let shown = &joined[..joined.len().min(top)];
+        result.push_str(&format!(
+            "Showing {} rows{}:\n\n{}",
+            shown.len(),
+            if joined.len() > shown.len() { " (more available, raise top)" } else { "" },
+            serde_json::to_string_pretty(shown).unwrap_or_else(|_| "[]".to_string())
+        ));
+        CallToolResult::text(result)
+    }
+
+    async fn create_entity(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let data = match parse_object_arg(args, "data") {
+            Ok(d) => d,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let model = self.validation_model(args).await;
+        if let Err(e) = check_payload(model.as_deref(), entity, &data, PayloadKind::Create) {
+            return CallToolResult::error(e);
+        }
+
+        match self.client.create_entity(entity, &Value::Object(data)).await {
+            Ok(created) => {
+                let mut result = format!("Created record in {}\n", entity);
+                if let Some(key) = &created.key {
+                    result.push_str(&format!("Key: {}\n", key));
+                }
+                if let Some(id) = &created.entity_id {
+                    result.push_str(&format!("URL: {}\n", id));
+                }
+                if let Some(record) = &created.record {
+                    result.push_str(&format!(
+                        "\n{}",
+                        serde_json::to_string_pretty(record).unwrap_or_default()
+                    ));
+                }
+                CallToolResult::text(result)
+            }
+            Err(e) => CallToolResult::error(format!("Error creating record in {}: {}", entity, e)),
+        }
+    }
+
+    async fn create_deep(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        if *self.client.product() == ProductType::Finops {
+            return CallToolResult::error(
+                "Deep insert is not supported by F&O OData; create the parent and related records with create_entity".to_string(),
+            );
+        }
+        let data = match parse_object_arg(args, "data") {
+            Ok(d) => d,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let plan = match DeepInsert::analyze(&data) {
+            Ok(p) => p,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let model = self.validation_model(args).await;
+        if let Err(e) = check_payload(model.as_deref(), entity, &data, PayloadKind::Create) {
+            return CallToolResult::error(e);
+        }
+
+        match self
+            .client
+            .create_deep(entity, &Value::Object(data), &plan.navigations)
+            .await
+        {
+            Ok(created) => {
+                let mut result = format!(
+                    "Created {} record(s) in one request: 1 in {}",
+                    plan.records, entity
+                );
+                for (path, count) in &plan.related {
+                    result.push_str(&format!(", {} via {}", count, path));
+                }
+                result.push('\n');
+                if plan.binds > 0 {
+                    result.push_str(&format!("Bound {} existing record(s)\n", plan.binds));
+                }
+                if let Some(key) = &created.key {
+                    result.push_str(&format!("Key: {}\n", key));
+                }
+                if let Some(id) = &created.entity_id {
+                    result.push_str(&format!("URL: {}\n", id));
+                }
+                if let Some(record) = &created.record {
+                    result.push_str(&format!(
+                        "\n{}",
+                        serde_json::to_string_pretty(record).unwrap_or_default()
+                    ));
+                }
+                CallToolResult::text(result)
+            }
+            Err(e) => CallToolResult::error(format!("Error creating records in {}: {}", entity, e)),
+        }
+    }
+
+    async fn update_entity(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let id = match args.get("id").and_then(|v| v.as_str()) {
+            Some(i) => i,
+            None => return CallToolResult::error("Missing required parameter: id".to_string()),
+        };
+        let data = match args.get("data") {
+            Some(_) => match parse_object_arg(args, "data") {
+                Ok(d) => d,
+                Err(e) => return CallToolResult::error(e),
+            },
+            None => serde_json::Map::new(),
+        };
+        let clear_fields: Vec<String> = args
+            .get("clear_fields")
+            .and_then(|v| v.as_str())
+            .map(|s| s.split(',').map(|f| f.trim().to_string()).collect())
+            .unwrap_or_default();
+        let etag = args.get("etag").and_then(|v| v.as_str());
+        let strategy = match args.get("on_conflict").and_then(|v| v.as_str()) {
+            Some(s) => match ConflictStrategy::parse(s) {
+                Ok(s) => s,
+                Err(e) => return CallToolResult::error(e),
+            },
+            None => self.config.conflict_strategy,
+        };
+
+        let payload = match UpdatePayload::build(data, &clear_fields, self.client.product()) {
+            Ok(p) => p,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let model = self.validation_model(args).await;
+        if let Err(e) = check_payload(model.as_deref(), entity, &payload.body, PayloadKind::Update) {
+            return CallToolResult::error(e);
+        }
+        if payload.body.is_empty() && payload.lookups_to_clear.is_empty() {
+            return CallToolResult::error("Nothing to update: provide 'data' and/or 'clear_fields'".to_string());
+        }
+
+        let key = key_segment(id);
+        let mut result = format!("Updated {}({})\n", entity, key);
+        let mut record = None;
+
+        if !payload.body.is_empty() {
+            let body = Value::Object(payload.body.clone());
+            let updated = match self.client.update_entity(entity, &key, &body, etag).await {
+                Err(ODataError::Conflict(message)) if strategy != ConflictStrategy::Fail => {
+                    self.resolve_conflict(strategy, entity, &key, &payload.body, message)
+                        .await
+                        .map(|(updated, resolution)| {
+                            result.push_str(&format!("Conflict: {}\n", resolution));
+                            updated
+                        })
+                }
+                other => other,
+            };
+            match updated {
+                Ok(updated) => record = updated,
+                Err(e) => return CallToolResult::error(format!("Error updating {}({}): {}", entity, key, e)),
+            }
+        }
+        for nav in &payload.lookups_to_clear {
+            if let Err(e) = self.client.disassociate(entity, &key, nav, None).await {
+                return CallToolResult::error(format!(
+                    "Error clearing lookup {} on {}({}): {}",
+                    nav, entity, key, e
+                ));
+            }
+            result.push_str(&format!("Cleared lookup: {}\n", nav));
+        }
+
+        if let Some(record) = record {
+            if let Some(new_etag) = record.get("@odata.etag").and_then(|v| v.as_str()) {
+                result.push_str(&format!("ETag: {}\n", new_etag));
+            }
+            result.push_str(&format!(
+                "\n{}",
+                serde_json::to_string_pretty(&record).unwrap_or_default()
+            ));
+        }
+        CallToolResult::text(result)
+    }
+
+    /// Retry an update that failed with a stale ETag, per `strategy`.
+    ///
+    /// Returns the updated record and a description of the resolution.
+    async fn resolve_conflict(
+        &self,
+        strategy: ConflictStrategy,
+        entity: &str,
+        key: &str,
+        body: &serde_json::Map<String, Value>,
+        message: String,
+    ) -> Result<(Option<Value>, String), ODataError> {
+        match strategy {
+            ConflictStrategy::Fail => Err(ODataError::Conflict(message)),
+            ConflictStrategy::Overwrite => {
+                tracing::warn!("Stale ETag on {}({}), overwriting", entity, key);
+                let updated = self
+                    .client
+                    .update_entity(entity, key, &Value::Object(body.clone()), None)
+                    .await?;
+                Ok((
+                    updated,
+                    "record had changed since it was read; overwritten with If-Match: *".to_string(),
+                ))
+            }
+            ConflictStrategy::RefetchMerge => {
+                tracing::warn!("Stale ETag on {}({}), re-reading and merging", entity, key);
+                let latest = self.client.get_entity(entity, key).await?;
+                let latest_etag = latest.get("@odata.etag").and_then(|v| v.as_str());
+
+                // Only fields whose latest value still differs from the requested one
+                let changed: serde_json::Map<String, Value> = body
+                    .iter()
+                    .filter(|(name, value)| latest.get(name.as_str()) != Some(*value))
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                if changed.is_empty() {
+                    return Ok((
+                        Some(latest),
+                        "record had changed, but already has the requested values; nothing reapplied".to_string(),
+                    ));
+                }
+
+                let fields: Vec<&str> = changed.keys().map(String::as_str).collect();
+                let resolution = format!(
+                    "record had changed; re-read it and reapplied {} on the latest version",
+                    fields.join(", ")
+                );
+                let updated = self
+                    .client
+                    .update_entity(entity, key, &Value::Object(changed), latest_etag)
+                    .await?;
+                Ok((updated, resolution))
+            }
+        }
+    }
+
+    async fn upsert_entity(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let key = match parse_object_arg(args, "keys").and_then(|k| alternate_key_segment(&k)) {
+            Ok(k) => k,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let data = match parse_object_arg(args, "data") {
+            Ok(d) => d,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let mode = match args.get("mode").and_then(|v| v.as_str()).unwrap_or("upsert") {
+            "upsert" => UpsertMode::Upsert,
+            "create_only" => UpsertMode::CreateOnly,
+            "update_only" => UpsertMode::UpdateOnly,
+            other => {
+                return CallToolResult::error(format!(
+                    "Invalid mode '{}': expected upsert, create_only or update_only",
+                    other
+                ))
+            }
+        };
+        let model = self.validation_model(args).await;
+        if let Err(e) = check_payload(model.as_deref(), entity, &data, PayloadKind::Create) {
+            return CallToolResult::error(e);
+        }
+
+        match self
+            .client
+            .upsert_entity(entity, &key, &Value::Object(data), mode)
+            .await
+        {
+            Ok(outcome) => {
+                let mut result = format!(
+                    "{} {}({})\n",
+                    if outcome.created { "Created" } else { "Updated" },
+                    entity,
+                    key
+                );
+                if let Some(record) = &outcome.record {
+                    result.push_str(&format!(
+                        "\n{}",
+                        serde_json::to_string_pretty(record).unwrap_or_default()
+                    ));
+                }
+                CallToolResult::text(result)
+            }
+            Err(ODataError::Conflict(_)) if mode == UpsertMode::CreateOnly => CallToolResult::error(format!(
+                "{}({}) already exists (create_only)",
+                entity, key
+            )),
+            Err(ODataError::NotFound(_)) if mode == UpsertMode::UpdateOnly => CallToolResult::error(format!(
+                "{}({}) does not exist (update_only)",
+                entity, key
+            )),
+            Err(e) => CallToolResult::error(format!("Error upserting {}({}): {}", entity, key, e)),
+        }
+    }
+
+    async fn set_record_state(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        if *self.client.product() == ProductType::Finops {
+            return CallToolResult::error("set_record_state is only available for Dataverse".to_string());
+        }
+        let (entity, id) = match (
+            args.get("entity").and_then(|v| v.as_str()),
+            args.get("id").and_then(|v| v.as_str()),
+        ) {
+            (Some(e), Some(i)) => (e, i),
+            _ => return CallToolResult::error("Missing required parameters: entity and id".to_string()),
+        };
+        let statecode = match args.get("statecode") {
+            Some(Value::String(s)) if s.eq_ignore_ascii_case("active") => 0,
+            Some(Value::String(s)) if s.eq_ignore_ascii_case("inactive") => 1,
+            Some(_) => match parse_number_arg(args, "statecode") {
+                Some(n) => n,
+                None => return CallToolResult::error("statecode must be a number, 'active' or 'inactive'".to_string()),
+            },
+            None => return CallToolResult::error("Missing required parameter: statecode".to_string()),
+        };
+        let mut body = serde_json::json!({ "statecode": statecode });
+        if args.contains_key("statuscode") {
+            match parse_number_arg(args, "statuscode") {
+                Some(n) => body["statuscode"] = Value::from(n),
+                None => return CallToolResult::error("statuscode must be a number".to_string()),
+            }
+        }
+
+        match self.client.update_entity(entity, &key_segment(id), &body, None).await {
+            Ok(_) => CallToolResult::text(format!(
+                "Set {}({}) to statecode {}{}",
+                entity,
+                id,
+                statecode,
+                body.get("statuscode")
+                    .map(|s| format!(", statuscode {}", s))
+                    .unwrap_or_default()
+            )),
+            Err(e) => CallToolResult::error(format!("Error setting state of {}({}): {}", entity, id, e)),
+        }
+    }
+
+    async fn assign_record(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        if *self.client.product() == ProductType::Finops {
+            return CallToolResult::error("assign_record is only available for Dataverse".to_string());
+        }
+        let (entity, id, owner) = match (
+            args.get("entity").and_then(|v| v.as_str()),
+            args.get("id").and_then(|v| v.as_str()),
+            args.get("owner").and_then(|v| v.as_str()).map(str::trim),
+        ) {
+            (Some(e), Some(i), Some(o)) if !o.is_empty() => (e, i, o),
+            _ => return CallToolResult::error("Missing required parameters: entity, id and owner".to_string()),
+        };
+        let owner_types: &[&str] = match args.get("owner_type").and_then(|v| v.as_str()) {
+            Some("systemuser") => &["systemusers"],
+            Some("team") => &["teams"],
+            Some(other) => {
+                return CallToolResult::error(format!(
+                    "Invalid owner_type '{}': expected 'systemuser' or 'team'",
+                    other
+                ))
+            }
+            None => &["systemusers", "teams"],
+        };
+
+        let (owner_set, owner_id, owner_name) = match self.resolve_owner(owner, owner_types).await {
+            Ok(o) => o,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let body = serde_json::json!({
+            "ownerid@odata.bind": format!("/{}({})", owner_set, owner_id)
+        });
+        match self.client.update_entity(entity, &key_segment(id), &body, None).await {
+            Ok(_) => CallToolResult::text(format!(
+                "Assigned {}({}) to {} {} ({})",
+                entity,
+                id,
+                if owner_set == "teams" { "team" } else { "user" },
+                owner_name,
+                owner_id
+            )),
+            Err(e) => CallToolResult::error(format!("Error assigning {}({}): {}", entity, id, e)),
+        }
+    }
+
+    /// Resolve an owner given as GUID or name to `(entity set, GUID, display name)`.
+    ///
+    /// Users match on email, domain name or full name, teams on name; the
+    /// match must be unique.
+    async fn resolve_owner(&self, owner: &str, owner_sets: &[&str]) -> Result<(&'static str, String, String), String> {
+        let literal = owner.replace('\'', "''");
+        let mut matches = Vec::new();
+        for set in owner_sets {
+            let (set, id_field, name_field, filter): (&'static str, &str, &str, String) = match *set {
+                "systemusers" => (
+                    "systemusers",
+                    "systemuserid",
+                    "fullname",
+                    if is_guid(owner) {
+                        format!("systemuserid eq {}", owner)
+                    } else {
+                        format!(
+                            "internalemailaddress eq '{0}' or domainname eq '{0}' or fullname eq '{0}'",
+                            literal
+                        )
+                    },
+                ),
+                _ => (
+                    "teams",
+                    "teamid",
+                    "name",
+                    if is_guid(owner) {
+                        format!("teamid eq {}", owner)
+                    } else {
+                        format!("name eq '{}'", literal)
+                    },
+                ),
+            };
+            let options = QueryOptions {
+                select: Some(vec![id_field.to_string(), name_field.to_string()]),
+                filter: Some(filter),
+                top: Some(2),
+                ..Default::default()
+            };
+            let page = self
+                .client
+                .fetch_entity_page(set, None, &options)
+                .await
+                .map_err(|e| format!("Error looking up owner '{}' in {}: {}", owner, set, e))?;
+            for record in page.value {
+                let id = record[id_field].as_str().unwrap_or_default().to_string();
+                let name = record[name_field].as_str().unwrap_or_default().to_string();
+                matches.push((set, id, name));
+            }
+        }
+
+        match matches.len() {
+            1 => Ok(matches.remove(0)),
+            0 => Err(format!("No user or team found for owner '{}'", owner)),
+            _ => Err(format!(
+                "Owner '{}' is ambiguous: {}. Pass the GUID or owner_type",
+                owner,
+                matches
+                    .iter()
+                    .map(|(set, id, name)| format!("{} {} ({})", set, name, id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// `bulk_create` and `bulk_update` (`update = true`)
+    async fn bulk_write(&self, args: &HashMap<String, Value>, update: bool, ctx: &ToolContext) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
+        };
+        let records = match parse_array_arg(args, "records") {
+            Ok(r) => r,
+            Err(e) => return CallToolResult::error(e),
+        };
+        if records.is_empty() || records.len() > BULK_MAX_RECORDS {
+            return CallToolResult::error(format!(
+                "'records' must hold between 1 and {} records",
+                BULK_MAX_RECORDS
+            ));
+        }
+        let key_field = args.get("key_field").and_then(|v| v.as_str());
+        if update && key_field.is_none() {
+            return CallToolResult::error("Missing required parameter: key_field".to_string());
+        }
+        let chunk_size = parse_number_arg(args, "chunk_size")
+            .unwrap_or(BULK_DEFAULT_CHUNK)
+            .clamp(1, batch::MAX_BATCH_REQUESTS);
+
+        let model = self.validation_model(args).await;
+        let kind = if update { PayloadKind::Update } else { PayloadKind::Create };
+
+        // Records that cannot be turned into a request, or do not match
+        // $metadata, fail up front
+        let mut report = BulkReport::default();
+        let mut operations = Vec::with_capacity(records.len());
+        for (i, record) in records.into_iter().enumerate() {
+            let Value::Object(mut body) = record else {
+                report.failed.push((i, 0, "Record is not a JSON object".to_string()));
+                continue;
+            };
+            let operation = match key_field {
+                Some(key_field) if update => {
+                    let key = match body.remove(key_field) {
+                        Some(Value::String(k)) => key_segment(&k),
+                        Some(Value::Number(n)) => n.to_string(),
+                        _ => {
+                            report.failed.push((i, 0, format!("Record has no '{}' key", key_field)));
+                            continue;
+                        }
+                    };
+                    let etag = body
+                        .remove("@odata.etag")
+                        .and_then(|v| v.as_str().map(String::from))
+                        .unwrap_or_else(|| "*".to_string());
+                    body.retain(|name, _| !name.starts_with("@odata."));
+                    if let Err(e) = check_payload(model.as_deref(), entity, &body, kind) {
+                        report.failed.push((i, 0, e));
+                        continue;
+                    }
+                    BatchOperation {
+                        method: "PATCH".to_string(),
+                        url: self.client.record_url(entity, &key),
+                        headers: vec![("If-Match".to_string(), etag)],
+                        body: Some(Value::Object(body)),
+                    }
+                }
+                _ => {
+                    if let Err(e) = check_payload(model.as_deref(), entity, &body, kind) {
+                        report.failed.push((i, 0, e));
+                        continue;
+                    }
+                    BatchOperation {
+                        method: "POST".to_string(),
+                        url: format!("{}{}", self.client.endpoint(), entity),
+                        headers: Vec::new(),
+                        body: Some(Value::Object(body)),
+                    }
+                }
+            };
+            operations.push((i, operation));
+        }
+
+        let total = operations.len();
+        let chunks: Vec<_> = operations.chunks(chunk_size).collect();
+        let chunk_count = chunks.len();
+        // Futures are collected first so the stream holds no borrowing closures
+        // (keeps the tool future Send for the socket transport)
+        let sends: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                let requests: Vec<BatchOperation> = chunk.iter().map(|(_, op)| op.clone()).collect();
+                let client = Arc::clone(&self.client);
+                async move { (chunk, client.execute_batch(&requests).await) }
+            })
+            .collect();
+        let mut results = futures::stream::iter(sends).buffered(self.config.concurrency.max(1));
+
+        let mut done = 0;
+        while let Some((chunk, outcome)) = results.next().await {
+            let indexes: Vec<usize> = chunk.iter().map(|(i, _)| *i).collect();
+            let mut chunk_report = BulkReport::default();
+            match outcome {
+                Ok(responses) => chunk_report.add_responses(0, indexes.len(), &responses),
+                Err(e) => chunk_report.add_batch_error(0, indexes.len(), &e.to_string()),
+            }
+            // Map positions within the chunk back to input indexes
+            report
+                .succeeded
+                .extend(chunk_report.succeeded.into_iter().map(|(p, id)| (indexes[p], id)));
+            report
+                .failed
+                .extend(chunk_report.failed.into_iter().map(|(p, s, m)| (indexes[p], s, m)));
+            done += indexes.len();
+            ctx.progress.report(
+                done as f64,
+                Some(total as f64),
+                Some(format!("{} of {} records written to {}", done, total, entity)),
+            );
+        }
+        report.failed.sort_by_key(|(i, _, _)| *i);
+
+        let verb = if update { "updated" } else { "created" };
+        let mut result = format!(
+            "Bulk {} in {}: {} succeeded, {} failed ({} batch request(s) of up to {})\n",
+            verb,
+            entity,
+            report.succeeded.len(),
+            report.failed.len(),
+            chunk_count,
+            chunk_size
+        );
+        if !report.failed.is_empty() {
+            result.push_str("\nFailures (record index is 0-based):\n");
+            for (i, status, message) in report.failed.iter().take(BULK_REPORT_MAX_FAILURES) {
+                match status {
+                    0 => result.push_str(&format!("- record {}: {}\n", i, message)),
+                    _ => result.push_str(&format!("- record {}: {} {}\n", i, status, message)),
+                }
+            }
+            if report.failed.len() > BULK_REPORT_MAX_FAILURES {
+                result.push_str(&format!(
+                    "(+{} more failures)\n",
+                    report.failed.len() - BULK_REPORT_MAX_FAILURES
+                ));
+            }
+        }
+        if !update {
+            let mut created = report.succeeded.clone();
+            created.sort_by_key(|(i, _)| *i);
+            let keys: Vec<String> = created
+                .iter()
+                .filter_map(|(i, id)| {
+                    id.as_deref()
+                        .and_then(key_from_entity_id)
+                        .map(|key| format!("{}: {}", i, key))
+                })
+                .collect();
+            if !keys.is_empty() {
+                result.push_str(&format!("\nCreated keys (record index: key):\n{}\n", keys.join("\n")));
+            }
+        }
 
-        let options = QueryOptions {
-            top: Some(1),
-            ..Default::default()
+        if report.succeeded.is_empty() {
+            CallToolResult::error(result)
+        } else {
+            CallToolResult::text(result)
+        }
+    }
+
+    /// `associate_records` (`associate = true`) and `disassociate_records`
+    async fn relate_records(&self, args: &HashMap<String, Value>, associate: bool) -> CallToolResult {
+        let mut required = vec!["entity", "id", "relationship", "related_ids"];
+        if associate {
+            required.push("related_entity");
+        }
+        let mut values = HashMap::new();
+        for name in required {
+            match args.get(name).and_then(|v| v.as_str()).filter(|v| !v.trim().is_empty()) {
+                Some(v) => values.insert(name, v.trim()),
+                None => return CallToolResult::error(format!("Missing required parameter: {}", name)),
+            };
+        }
+        let (entity, relationship) = (values["entity"], values["relationship"]);
+        let key = key_segment(values["id"]);
+        let related_ids: Vec<&str> = values["related_ids"]
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .collect();
+
+        let mut linked = Vec::new();
+        let mut errors = Vec::new();
+        for related_id in &related_ids {
+            let related_key = key_segment(related_id);
+            let outcome = if associate {
+                self.client
+                    .associate(entity, &key, relationship, values["related_entity"], &related_key)
+                    .await
+            } else {
+                self.client
+                    .disassociate(entity, &key, relationship, Some(&related_key))
+                    .await
+            };
+            match outcome {
+                Ok(()) => linked.push(*related_id),
+                Err(e) => errors.push(format!("- {}: {}", related_id, e)),
+            }
+        }
+
+        let verb = if associate { "Associated" } else { "Disassociated" };
+        let mut result = format!(
+            "{} {}/{} records with {}({}) via {}\n",
+            verb,
+            linked.len(),
+            related_ids.len(),
+            entity,
+            values["id"],
+            relationship
+        );
+        if !errors.is_empty() {
+            result.push_str(&format!("\nFailed:\n{}\n", errors.join("\n")));
+            if linked.is_empty() {
+                return CallToolResult::error(result);
+            }
+        }
+        CallToolResult::text(result)
+    }
+
+    async fn transaction(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let specs = match parse_array_arg(args, "operations") {
+            Ok(o) => o,
+            Err(e) => return CallToolResult::error(e),
         };
+        if specs.is_empty() || specs.len() > BATCH_MAX_QUERIES {
+            return CallToolResult::error(format!(
+                "'operations' must hold between 1 and {} operations",
+                BATCH_MAX_QUERIES
+            ));
+        }
 
-        match self.client.fetch_entity_page(entity, None, &options).await {
-            Ok(response) => {
-                if let Some(sample) = response.value.into_iter().next() {
-                    if let Value::Object(map) = &sample {
-                        let fields: Vec<String> = map.keys().cloned().collect();
-                        let result = format!(
-                            "Entity: {}\nFields ({}):\n{}\n\nSample record:\n{}",
-                            entity,
-                            fields.len(),
-                            fields.join(", "),
-                            serde_json::to_string_pretty(&sample).unwrap_or_default()
-                        );
-                        CallToolResult::text(result)
-                    } else {
-                        CallToolResult::text(serde_json::to_string_pretty(&sample).unwrap_or_default())
-                    }
-                } else {
-                    CallToolResult::text(format!("No records found in entity '{}'", entity))
+        let mut labels = Vec::with_capacity(specs.len());
+        let mut operations = Vec::with_capacity(specs.len());
+        for (i, spec) in specs.iter().enumerate() {
+            match self.transaction_operation(spec) {
+                Ok((label, operation)) => {
+                    labels.push(label);
+                    operations.push(operation);
                 }
+                Err(e) => return CallToolResult::error(format!("Operation {}: {}", i + 1, e)),
             }
-            Err(e) => CallToolResult::error(format!("Error fetching schema for {}: {}", entity, e)),
         }
+
+        let responses = match self.client.execute_changeset(&operations).await {
+            Ok(r) => r,
+            Err(e) => return CallToolResult::error(format!("Error executing transaction: {}", e)),
+        };
+
+        if let Some(failed) = responses.iter().find(|r| !r.is_success()) {
+            let index = failed
+                .content_id
+                .as_deref()
+                .and_then(|id| id.parse::<usize>().ok())
+                .filter(|i| (1..=labels.len()).contains(i));
+            let which = match index {
+                Some(i) => format!("operation {} ({})", i, labels[i - 1]),
+                None => "an operation".to_string(),
+            };
+            let message = batch::error_message(failed);
+            return CallToolResult::error(format!(
+                "Transaction rolled back, no changes were applied: {} failed with {}: {}",
+                which, failed.status, message
+            ));
+        }
+
+        let mut result = format!("Transaction committed: {} operation(s) applied\n", labels.len());
+        for (i, label) in labels.iter().enumerate() {
+            result.push_str(&format!("{}. {}", i + 1, label));
+            if let Some(id) = responses.get(i).and_then(|r| r.header("OData-EntityId")) {
+                result.push_str(&format!(" -> {}", id));
+            }
+            result.push('\n');
+        }
+        CallToolResult::text(result)
     }
 
-    async fn get_record(&self, args: &HashMap<String, Value>) -> CallToolResult {
+    /// One `transaction` spec as a changeset operation plus a readable label
+    fn transaction_operation(&self, spec: &Value) -> Result<(String, BatchOperation), String> {
+        let op = spec.get("op").and_then(|v| v.as_str()).ok_or("missing 'op'")?;
+        let entity = spec.get("entity").and_then(|v| v.as_str()).ok_or("missing 'entity'")?;
+        let data = match spec.get("data") {
+            Some(Value::Object(data)) => Some(Value::Object(data.clone())),
+            Some(_) => return Err("'data' must be a JSON object".to_string()),
+            None => None,
+        };
+        let if_match = (
+            "If-Match".to_string(),
+            spec.get("etag").and_then(|v| v.as_str()).unwrap_or("*").to_string(),
+        );
+        let record_url = || {
+            spec.get("id")
+                .and_then(|v| v.as_str())
+                .map(|id| (format!("{}({})", entity, id), format!("{}{}({})", self.client.endpoint(), entity, key_segment(id))))
+                .ok_or_else(|| format!("'{}' needs an 'id'", op))
+        };
+
+        let (label, operation) = match op.to_ascii_lowercase().as_str() {
+            "create" => (
+                format!("create {}", entity),
+                BatchOperation {
+                    method: "POST".to_string(),
+                    url: format!("{}{}", self.client.endpoint(), entity),
+                    headers: Vec::new(),
+                    body: Some(data.ok_or("'create' needs 'data'")?),
+                },
+            ),
+            "update" => {
+                let (label, url) = record_url()?;
+                (
+                    format!("update {}", label),
+                    BatchOperation {
+                        method: "PATCH".to_string(),
+                        url,
+                        headers: vec![if_match],
+                        body: Some(data.ok_or("'update' needs 'data'")?),
+                    },
+                )
+            }
+            "delete" => {
+                if self.config.tools.allow_delete == Some(false) {
+                    return Err("deletes are disabled (allow_delete = false)".to_string());
+                }
+                let (label, url) = record_url()?;
+                (
+                    format!("delete {}", label),
+                    BatchOperation {
+                        method: "DELETE".to_string(),
+                        url,
+                        headers: vec![if_match],
+                        body: None,
+                    },
+                )
+            }
+            other => return Err(format!("unknown op '{}': expected create, update or delete", other)),
+        };
+        Ok((label, operation))
+    }
+
+    async fn delete_entity(&self, args: &HashMap<String, Value>) -> CallToolResult {
         let entity = match args.get("entity").and_then(|v| v.as_str()) {
             Some(e) => e,
             None => return CallToolResult::error("Missing required parameter: entity".to_string()),
         };
-
         let id = match args.get("id").and_then(|v| v.as_str()) {
             Some(i) => i,
             None => return CallToolResult::error("Missing required parameter: id".to_string()),
         };
+        let etag = args.get("etag").and_then(|v| v.as_str());
+        let key = key_segment(id);
 
-        // Format key - GUIDs should be wrapped in quotes for OData
-        let key = if id.contains('-') && !id.starts_with('\'') {
-            format!("'{}'", id)
-        } else {
-            id.to_string()
+        match self.client.delete_entity(entity, &key, etag).await {
+            Ok(()) => CallToolResult::text(format!("Deleted {}({})", entity, key)),
+            Err(e) => CallToolResult::error(format!("Error deleting {}({}): {}", entity, key, e)),
+        }
+    }
+
+    async fn get_record(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let entity = match args.get("entity").and_then(|v| v.as_str()) {
+            Some(e) => e,
+            None => return CallToolResult::error("Missing required parameter: entity".to_string()),
         };
 
-        match self.client.get_entity(entity, &key).await {
+        let key = match args.get("id").map(parse_key_arg) {
+            Some(Ok(key)) => key,
+            Some(Err(e)) => return CallToolResult::error(e),
+            None => return CallToolResult::error("Missing required parameter: id".to_string()),
+        };
+
+        match self.client.get_entity(entity, &key.url_segment()).await {
             Ok(record) => {
                 let json = serde_json::to_string_pretty(&record).unwrap_or_default();
                 CallToolResult::text(json)
@@ -245,6 +2936,32 @@ impl D365McpServer {
         }
     }
 
+    /// Configured connections with their product and endpoint
+    fn list_connections(&self) -> CallToolResult {
+        let mut result = format!("{} connections:\n", self.connections.len() + 1);
+        for connection in self.all_connections() {
+            let default = if connection.connection == self.connection { " (default)" } else { "" };
+            let metadata = match connection.metadata.current() {
+                Some(model) => format!("{} entity sets", model.entity_sets.len()),
+                None => "$metadata not loaded yet".to_string(),
+            };
+            result.push_str(&format!(
+                "- {}{}: {:?} at {} (auth: {}; {})\n",
+                connection.connection,
+                default,
+                connection.client.product(),
+                connection.client.endpoint(),
+                connection.config.auth_type,
+                metadata
+            ));
+        }
+        result.push_str(&format!(
+            "\nPass connection=<name> to any tool to run it against that environment; without it, tools use '{}'. Result sets are shared between connections.",
+            self.connection
+        ));
+        CallToolResult::text(result)
+    }
+
     async fn get_environment_info(&self) -> CallToolResult {
         let info = format!(
             "D365 Environment Info:\n\
@@ -264,22 +2981,388 @@ impl D365McpServer {
         );
         CallToolResult::text(info)
     }
-}
 
-/// Extract entity set names from EDMX metadata XML
-fn extract_entity_sets_from_metadata(metadata: &str) -> Vec<String> {
-    let mut entities = Vec::new();
+    /// Get metadata: a filterable summary, or details for one entity
+    async fn get_metadata(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        // Parsed while streaming and cached rather than holding the raw document
+        let model = match self.metadata_model(parse_bool_arg(args, "refresh")).await {
+            Ok(m) => m,
+            Err(e) => return CallToolResult::error(format!("Failed to fetch metadata: {}", e)),
+        };
+
+        match args.get("entity").and_then(|v| v.as_str()) {
+            Some(entity) => describe_entity_type(&model, entity),
+            None => {
+                let filter = args.get("filter").and_then(|v| v.as_str());
+                let top = parse_number_arg(args, "top").unwrap_or(200);
+                CallToolResult::text(format_metadata_summary(&model, filter, top))
+            }
+        }
+    }
 
-    for line in metadata.lines() {
-        if line.contains("EntitySet") && line.contains("Name=") {
-            if let Some(start) = line.find("Name=\"") {
-                let rest = &line[start + 6..];
-                if let Some(end) = rest.find('"') {
-                    entities.push(rest[..end].to_string());
-                }
+    /// Reload the cached $metadata model
+    async fn refresh_metadata(&self) -> CallToolResult {
+        let model = match self.metadata_model(true).await {
+            Ok(m) => m,
+            Err(e) => return CallToolResult::error(format!("Failed to refresh metadata: {}", e)),
+        };
+        let mut result = format!(
+            "Metadata reloaded: {} entity sets, {} entity types, {} enum types\n",
+            model.entity_sets.len(),
+            model.entity_types.len(),
+            model.enum_types.len()
+        );
+        if let Some(info) = self.metadata.info() {
+            result.push_str(&format!("Source: {} ({})\n", info.source.describe(), info.fetched_at));
+            if let Some(path) = &info.path {
+                result.push_str(&format!("Cache/file: {}\n", path.display()));
+            }
+        }
+        CallToolResult::text(result)
+    }
+
+    async fn diff_metadata(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let name = args.get("snapshot").and_then(|v| v.as_str()).unwrap_or("baseline");
+        let path = match metadata_diff::snapshot_path(
+            &self.config.metadata.cache_dir.join(metadata_diff::SNAPSHOT_DIR_NAME),
+            name,
+            &cache_file_name(self.client.endpoint()),
+        ) {
+            Ok(p) => p,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let only: Vec<String> = args
+            .get("entities")
+            .and_then(|v| v.as_str())
+            .map(|s| s.split(',').map(|e| e.trim().to_string()).filter(|e| !e.is_empty()).collect())
+            .unwrap_or_default();
+        let refresh = args.get("refresh").map_or(true, |_| parse_bool_arg(args, "refresh"));
+
+        let model = match self.metadata_model(refresh).await {
+            Ok(m) => m,
+            Err(e) => return CallToolResult::error(format!("Failed to fetch metadata: {}", e)),
+        };
+        let snapshot = metadata_diff::Snapshot {
+            endpoint: self.client.endpoint().to_string(),
+            taken_at: time_window::format_utc(SystemTime::now()),
+            model: (*model).clone(),
+        };
+        let previous = match metadata_diff::read_snapshot(&path) {
+            Ok(p) => p,
+            Err(e) => return CallToolResult::error(e),
+        };
+
+        let Some(previous) = previous else {
+            return match metadata_diff::write_snapshot(&path, &snapshot) {
+                Ok(()) => CallToolResult::text(format!(
+                    "No snapshot '{}' yet; saved the current model ({} entity sets) to {}. Call diff_metadata again later to see what changed.",
+                    name,
+                    model.entity_sets.len(),
+                    path.display()
+                )),
+                Err(e) => CallToolResult::error(e),
+            };
+        };
+
+        let diff = MetadataDiff::compare(&previous.model, &model, &only);
+        let mut result = format!(
+            "Metadata changes since snapshot '{}' ({}){}:\n\n{}",
+            name,
+            previous.taken_at,
+            if only.is_empty() { String::new() } else { format!(" for {}", only.join(", ")) },
+            diff.render()
+        );
+        if parse_bool_arg(args, "update_snapshot") {
+            if let Err(e) = metadata_diff::write_snapshot(&path, &snapshot) {
+                return CallToolResult::error(e);
+            }
+            result.push_str(&format!("\nSnapshot '{}' updated to the current model.\n", name));
+        }
+        CallToolResult::text(result)
+    }
+
+    /// Rows of a stored set after the common `filter`, `orderby` and `select` arguments
+    fn stored_rows(&self, args: &HashMap<String, Value>) -> Result<(String, Vec<Value>), String> {
+        let name = args
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "Missing required parameter: name".to_string())?;
+        let set = self.result_sets().get(name).ok_or_else(|| {
+            format!("No result set named '{}'. Store one with query_entity's 'store_as'", name)
+        })?;
+
+        let mut rows: Vec<Value> = match args.get("filter").and_then(|v| v.as_str()) {
+            Some(filter) => {
+                let conditions = result_sets::parse_filter(filter)?;
+                set.records
+                    .iter()
+                    .filter(|r| result_sets::matches(r, &conditions))
+                    .cloned()
+                    .collect()
+            }
+            None => set.records.clone(),
+        };
+        if let Some(orderby) = args.get("orderby").and_then(|v| v.as_str()) {
+            result_sets::sort_records(&mut rows, orderby)?;
+        }
+        if let Some(select) = args.get("select").and_then(|v| v.as_str()) {
+            let fields: Vec<&str> = select.split(',').map(str::trim).collect();
+            rows = rows
+                .into_iter()
+                .map(|r| {
+                    let projected: serde_json::Map<String, Value> = fields
+                        .iter()
+                        .filter_map(|f| r.get(*f).map(|v| (f.to_string(), v.clone())))
+                        .collect();
+                    Value::Object(projected)
+                })
+                .collect();
+        }
+        Ok((set.entity.clone(), rows))
+    }
+
+    fn list_result_sets(&self) -> CallToolResult {
+        let sets = self.result_sets().list();
+        if sets.is_empty() {
+            return CallToolResult::text(
+                "No stored result sets. Use query_entity with 'store_as' to create one.".to_string(),
+            );
+        }
+        let mut text = String::from("| Name | Entity | Rows | Age |\n|------|--------|------|-----|\n");
+        for (name, set) in sets {
+            text.push_str(&format!(
+                "| {} | {} | {} | {}s |\n",
+                name,
+                set.entity,
+                set.records.len(),
+                set.stored_at.elapsed().as_secs()
+            ));
+        }
+        CallToolResult::text(text)
+    }
+
+    fn query_result_set(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let (entity, rows) = match self.stored_rows(args) {
+            Ok(r) => r,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let skip = parse_number_arg(args, "skip").unwrap_or(0);
+        let top = parse_number_arg(args, "top").unwrap_or(50).min(1000);
+        let page: Vec<&Value> = rows.iter().skip(skip).take(top).collect();
+
+        CallToolResult::text(format!(
+            "{} matching rows from {}. Showing {}{}:\n\n{}",
+            rows.len(),
+            entity,
+            page.len(),
+            if skip + page.len() < rows.len() { " (more available, use skip)" } else { "" },
+            serde_json::to_string_pretty(&page).unwrap_or_else(|_| "[]".to_string())
+        ))
+    }
+
+    fn aggregate_result_set(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let aggregates = match args.get("aggregates").and_then(|v| v.as_str()) {
+            Some(a) => match result_sets::parse_aggregates(a) {
+                Ok(a) => a,
+                Err(e) => return CallToolResult::error(e),
+            },
+            None => vec![result_sets::Aggregate::Count],
+        };
+        let group_by: Vec<String> = args
+            .get("group_by")
+            .and_then(|v| v.as_str())
+            .map(|s| s.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect())
+            .unwrap_or_default();
+
+        // orderby applies to the aggregated rows, not the input
+        let mut input_args = args.clone();
+        let orderby = input_args.remove("orderby");
+        let (entity, rows) = match self.stored_rows(&input_args) {
+            Ok(r) => r,
+            Err(e) => return CallToolResult::error(e),
+        };
+
+        let mut groups = result_sets::aggregate(&rows, &group_by, &aggregates);
+        if let Some(orderby) = orderby.as_ref().and_then(|v| v.as_str()) {
+            if let Err(e) = result_sets::sort_records(&mut groups, orderby) {
+                return CallToolResult::error(e);
+            }
+        }
+        let top = parse_number_arg(args, "top").unwrap_or(100).min(1000);
+
+        CallToolResult::text(format!(
+            "{} groups over {} rows from {}:\n\n{}",
+            groups.len(),
+            rows.len(),
+            entity,
+            serde_json::to_string_pretty(&groups[..groups.len().min(top)]).unwrap_or_else(|_| "[]".to_string())
+        ))
+    }
+
+    fn export_result_set(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let (_, rows) = match self.stored_rows(args) {
+            Ok(r) => r,
+            Err(e) => return CallToolResult::error(e),
+        };
+        let format = args.get("format").and_then(|v| v.as_str()).unwrap_or("csv");
+        let content = match format.to_ascii_lowercase().as_str() {
+            "csv" => result_sets::to_csv(&rows),
+            "json" => serde_json::to_string_pretty(&rows).unwrap_or_else(|_| "[]".to_string()),
+            "jsonl" => rows.iter().map(|r| format!("{}\n", r)).collect(),
+            other => {
+                return CallToolResult::error(format!(
+                    "Invalid format '{}': expected csv, json or jsonl",
+                    other
+                ))
             }
+        };
+
+        let Some(path) = args.get("path").and_then(|v| v.as_str()) else {
+            return CallToolResult::text(content);
+        };
+        let Some(dir) = &self.config.tools.export_dir else {
+            return CallToolResult::error(
+                "Writing exports to files is disabled: set export_dir under [tools] in the config, or omit 'path' to get the content".to_string(),
+            );
+        };
+        match result_sets::write_export(Path::new(dir), path, &content) {
+            Ok(written) => CallToolResult::text(format!(
+                "Exported {} rows as {} to {} ({} bytes)",
+                rows.len(),
+                format,
+                written.display(),
+                content.len()
+            )),
+            Err(e) => CallToolResult::error(e),
+        }
+    }
+
+    fn drop_result_set(&self, args: &HashMap<String, Value>) -> CallToolResult {
+        let name = match args.get("name").and_then(|v| v.as_str()) {
+            Some(n) => n,
+            None => return CallToolResult::error("Missing required parameter: name".to_string()),
+        };
+        if self.result_sets().remove(name) {
+            CallToolResult::text(format!("Dropped result set '{}'", name))
+        } else {
+            CallToolResult::error(format!("No result set named '{}'", name))
+        }
+    }
+}
+
+/// Append known D365 error hints to the text of an error result
+fn append_error_hints(mut result: CallToolResult) -> CallToolResult {
+    if result.is_error == Some(true) {
+        for content in &mut result.content {
+            content.text = error_hints::with_hints(&content.text);
+        }
+    }
+    result
+}
+
+/// Group a tool belongs to, for `[tools]` enable/disable configuration
+fn tool_group(name: &str) -> &'static str {
+    match name {
+        "query_entity" | "query_page" | "get_record" | "entity_profile" | "join_queries" | "batch_query" | "count_entities"
+        | "start_change_tracking" => "read",
+        "list_result_sets" | "query_result_set" | "aggregate_result_set" | "drop_result_set" => "read",
+        "list_entities" | "get_entity_schema" | "describe_entity" | "list_relationships" | "get_metadata"
+        | "refresh_metadata" | "diff_metadata" | "list_connections" => "metadata",
+        "get_environment_info" | "usage_stats" => "admin",
+        "create_entity" | "create_deep" | "update_entity" | "upsert_entity" | "delete_entity"
+        | "transaction" | "associate_records" | "disassociate_records" | "bulk_create"
+        | "bulk_update" | "set_record_state" | "assign_record" | "export_result_set" => "write",
+        "get_changes" | "sync_status" => "sync",
+        _ => "other",
+    }
+}
+
+/// Append one line per stored entity sync state
+fn append_sync_states(
+    result: &mut String,
+    title: &str,
+    states: Vec<(String, EntitySyncState)>,
+    wanted: &dyn Fn(&str) -> bool,
+) {
+    result.push_str(&format!("\n{}:\n", title));
+    let states: Vec<_> = states.into_iter().filter(|(e, _)| wanted(e)).collect();
+    if states.is_empty() {
+        result.push_str("- none\n");
+    }
+    for (entity, state) in states {
+        let position = match (&state.delta_link, &state.watermark) {
+            (Some(_), _) => "delta link stored".to_string(),
+            (None, Some(mark)) => format!(
+                "watermark {} = {}",
+                state.watermark_field.as_deref().unwrap_or("?"),
+                mark
+            ),
+            (None, None) => "no position yet".to_string(),
+        };
+        result.push_str(&format!(
+            "- {}: {}, last sync {} ({} rows)",
+            entity,
+            position,
+            state.last_sync.as_deref().unwrap_or("never"),
+            state.last_changes
+        ));
+        if let Some(checkpoint) = &state.checkpoint {
+            result.push_str(&format!(
+                ", checkpoint after {} rows saved {}",
+                checkpoint.rows, checkpoint.saved_at
+            ));
+        }
+        result.push('\n');
+    }
+}
+
+/// Add the `include_script` argument every tool accepts
+fn add_include_script_arg(tools: &mut [Tool]) {
+    for tool in tools {
+        tool.input_schema["properties"][INCLUDE_SCRIPT_ARG] = serde_json::json!({
+            "type": "string",
+            "description": "Set to 'true' to append the OData URL, a curl command and a PowerShell snippet reproducing this call"
+        });
+    }
+}
+
+/// Add the Dataverse `impersonate_user` argument every tool accepts
+fn add_impersonate_user_arg(tools: &mut [Tool]) {
+    for tool in tools {
+        tool.input_schema["properties"][IMPERSONATE_USER_ARG] = serde_json::json!({
+            "type": "string",
+            "description": "Run as this user (Azure AD object ID, or systemuser:<systemuserid>) with their security roles; 'none' skips the configured default"
+        });
+    }
+}
+
+/// Add the `connection` argument to every tool
+fn add_connection_arg(tools: &mut [Tool], default: &str, names: &[&str]) {
+    for tool in tools {
+        if tool.name == "list_connections" {
+            continue;
         }
+        tool.input_schema["properties"][CONNECTION_ARG] = serde_json::json!({
+            "type": "string",
+            "enum": names,
+            "description": format!("Connection (D365 environment) to run against; see list_connections (default: '{}')", default)
+        });
     }
+}
+
+/// Definition of `list_connections`, offered when several connections are configured
+fn list_connections_tool() -> Tool {
+    Tool {
+        name: "list_connections".to_string(),
+        description: "List the D365 environments this server is connected to (name, product, endpoint). Pass a name as 'connection' to any other tool, e.g. to compare Dataverse accounts with F&O customers.".to_string(),
+        input_schema: create_tool_schema(vec![]),
+        annotations: ToolAnnotations::read_only(),
+    }
+}
+
+/// Entity set names from the metadata model
+fn entity_set_names(model: &EdmModel) -> Vec<String> {
+    let mut entities: Vec<String> = model.entity_sets.iter().map(|s| s.name.clone()).collect();
 
     if entities.is_empty() {
         entities = vec![
@@ -294,63 +3377,388 @@ fn extract_entity_sets_from_metadata(metadata: &str) -> Vec<String> {
     entities
 }
 
-/// Parse a number argument from JSON (handles both string and number types)
+/// Parse a boolean argument from JSON (handles both string and bool types)
+fn parse_bool_arg(args: &HashMap<String, Value>, key: &str) -> bool {
+    args.get(key)
+        .and_then(|v| v.as_str().map(|s| s == "true").or_else(|| v.as_bool()))
+        .unwrap_or(false)
+}
+
+/// Parse a JSON object argument, given either as an object or as a JSON string
+fn parse_object_arg(args: &HashMap<String, Value>, key: &str) -> Result<serde_json::Map<String, Value>, String> {
+    let value = match args.get(key) {
+        Some(Value::String(text)) => serde_json::from_str(text)
+            .map_err(|e| format!("Parameter '{}' is not valid JSON: {}", key, e))?,
+        Some(value) => value.clone(),
+        None => return Err(format!("Missing required parameter: {}", key)),
+    };
+    match value {
+        Value::Object(map) => Ok(map),
+        _ => Err(format!("Parameter '{}' must be a JSON object", key)),
+    }
+}
+
+/// Parse a JSON array argument given either as an array or as a JSON string
+fn parse_array_arg(args: &HashMap<String, Value>, key: &str) -> Result<Vec<Value>, String> {
+    let value = match args.get(key) {
+        Some(Value::String(text)) => serde_json::from_str(text)
+            .map_err(|e| format!("Parameter '{}' is not valid JSON: {}", key, e))?,
+        Some(value) => value.clone(),
+        None => return Err(format!("Missing required parameter: {}", key)),
+    };
+    match value {
+        Value::Array(items) => Ok(items),
+        _ => Err(format!("Parameter '{}' must be a JSON array", key)),
+    }
+}
+
+/// Parse a record key given as text or as a JSON object of key parts
+fn parse_key_arg(value: &Value) -> Result<EntityKey, String> {
+    match value {
+        Value::String(text) if text.trim_start().starts_with('{') => {
+            let parts: Value = serde_json::from_str(text).map_err(|e| format!("Key is not valid JSON: {}", e))?;
+            EntityKey::from_json(&parts)
+        }
+        other => EntityKey::from_json(other),
+    }
+}
+
+/// Parse parameter aliases (`{"p1": "A & B"}`) into `@p1` OData literals
+fn parse_alias_arg(args: &HashMap<String, Value>, key: &str) -> Result<Vec<(String, String)>, String> {
+    if !args.contains_key(key) {
+        return Ok(Vec::new());
+    }
+    parse_object_arg(args, key)?
+        .iter()
+        .map(|(name, value)| {
+            let name = name.trim_start_matches('@');
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("Invalid parameter alias name '{}'", name));
+            }
+            odata_literal(value)
+                .map(|literal| (name.to_string(), literal))
+                .map_err(|e| format!("Parameter alias '{}': {}", name, e))
+        })
+        .collect()
+}
+
+/// Parse an `expand` argument given as `$expand` text or as structured JSON
+fn parse_expand_arg(args: &HashMap<String, Value>, key: &str) -> Result<Option<Vec<ExpandOption>>, String> {
+    let value = match args.get(key) {
+        Some(Value::String(text)) if text.trim_start().starts_with(['[', '{']) => serde_json::from_str(text)
+            .map_err(|e| format!("Parameter '{}' is not valid JSON: {}", key, e))?,
+        Some(Value::Null) | None => return Ok(None),
+        Some(value) => value.clone(),
+    };
+    let options = ExpandOption::from_json(&value)?;
+    Ok((!options.is_empty()).then_some(options))
+}
+
+/// Parse a number argument from JSON (handles both string and number types).
+/// Negative, fractional or out-of-range values are ignored like unparseable ones.
 fn parse_number_arg(args: &HashMap<String, Value>, key: &str) -> Option<usize> {
-    args.get(key).and_then(|v| {
-        v.as_i64()
-            .map(|n| n as usize)
-            .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+    args.get(key).and_then(|v| match v {
+        Value::Number(n) => n.as_u64().and_then(|n| usize::try_from(n).ok()),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
     })
 }
 
-impl D365McpServer {
-    /// Get metadata for a specific entity including properties and navigation properties
-    async fn get_metadata(&self, args: &HashMap<String, Value>) -> CallToolResult {
-        let entity = match args.get("entity").and_then(|v| v.as_str()) {
-            Some(e) => e,
-            None => return CallToolResult::error("Missing required argument: entity".to_string()),
+/// Render the most frequent values from a `groupby(...,aggregate($count as count))` result
+fn format_top_values(column: &str, mut groups: Vec<Value>, limit: usize) -> String {
+    let count_of = |v: &Value| v.get("count").and_then(|c| c.as_i64()).unwrap_or(0);
+    groups.sort_by_key(|g| std::cmp::Reverse(count_of(g)));
+
+    let mut text = String::from("| Value | Count |\n|-------|-------|\n");
+    for group in groups.iter().take(limit) {
+        let value = match group.get(column) {
+            None | Some(Value::Null) => "(empty)".to_string(),
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
         };
+        text.push_str(&format!("| {} | {} |\n", value, count_of(group)));
+    }
+    if groups.len() > limit {
+        text.push_str(&format!("\n... {} more distinct values\n", groups.len() - limit));
+    }
+    text
+}
 
-        // Fetch metadata
-        let metadata = match self.client.fetch_metadata().await {
-            Ok(m) => m,
-            Err(e) => return CallToolResult::error(format!("Failed to fetch metadata: {}", e)),
+/// Render a summary table of entity sets, optionally filtered by name
+fn format_metadata_summary(model: &EdmModel, filter: Option<&str>, top: usize) -> String {
+    let filter = filter.map(|f| f.to_lowercase());
+    let matching: Vec<_> = model
+        .entity_sets
+        .iter()
+        .filter(|s| match &filter {
+            Some(f) => s.name.to_lowercase().contains(f.as_str()),
+            None => true,
+        })
+        .collect();
+
+    let mut output = String::new();
+    output.push_str("## Metadata Summary\n\n");
+    output.push_str(&format!("- Entity sets: {}\n", model.entity_sets.len()));
+    output.push_str(&format!("- Entity types: {}\n", model.entity_types.len()));
+    output.push_str(&format!("- Enum types: {}\n", model.enum_types.len()));
+    if let Some(f) = &filter {
+        output.push_str(&format!("- Matching '{}': {}\n", f, matching.len()));
+    }
+    output.push('\n');
+
+    output.push_str("| Entity Set | Entity Type | Properties | Navigation |\n");
+    output.push_str("|------------|-------------|------------|------------|\n");
+    for set in matching.iter().take(top) {
+        let (props, navs) = model
+            .entity_type_for_set(set)
+            .map(|t| (model.properties_of(t).len(), t.navigation_properties.len()))
+            .unwrap_or((0, 0));
+        output.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            set.name,
+            unqualified(&set.entity_type),
+            props,
+            navs
+        ));
+    }
+
+    if matching.len() > top {
+        output.push_str(&format!(
+            "\n... {} more. Use 'filter' to narrow down or 'entity' for details.\n",
+            matching.len() - top
+        ));
+    }
+
+    output
+}
+
+/// Enum members listed per field by `describe_entity`
+const DESCRIBE_MAX_ENUM_MEMBERS: usize = 20;
+
+/// Full field reference of one entity for `describe_entity`
+fn format_entity_description(model: &EdmModel, entity: &str) -> Option<String> {
+    let entity_type = model.find_entity_type(entity)?;
+    let keys = model.keys_of(entity_type);
+    let set = model.entity_set_for_type(&entity_type.name);
+
+    let mut output = format!(
+        "## {} (type {}.{})\n\n",
+        set.map_or(entity, |s| s.name.as_str()),
+        entity_type.namespace,
+        entity_type.name
+    );
+    if let Some(base) = &entity_type.base_type {
+        output.push_str(&format!("Base type: {}\n", base));
+    }
+    output.push_str(&format!(
+        "Key: {}\n\n",
+        if keys.is_empty() { "(none declared)".to_string() } else { keys.join(", ") }
+    ));
+
+    let properties = model.properties_of(entity_type);
+    output.push_str(&format!("### Fields ({})\n", properties.len()));
+    output.push_str("| Field | Type | Required | Facets |\n");
+    output.push_str("|-------|------|----------|--------|\n");
+    let mut enums = Vec::new();
+    for property in &properties {
+        let mut facets = Vec::new();
+        if let Some(max) = &property.max_length {
+            facets.push(format!("max length {}", max));
+        }
+        match (property.precision, &property.scale) {
+            (Some(p), Some(s)) => facets.push(format!("precision {}, scale {}", p, s)),
+            (Some(p), None) => facets.push(format!("precision {}", p)),
+            _ => {}
+        }
+        if keys.contains(&property.name) {
+            facets.push("key".to_string());
+        }
+        let enum_type = model.enum_type(&property.edm_type).filter(|_| !property.edm_type.starts_with("Edm."));
+        if let Some(e) = enum_type {
+            facets.push(format!("enum {}", e.name));
+            if !enums.contains(&e) {
+                enums.push(e);
+            }
+        }
+        output.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            property.name,
+            property.edm_type,
+            if property.nullable { "no (nullable)" } else { "yes" },
+            facets.join("; ")
+        ));
+    }
+
+    for e in enums {
+        let members: Vec<String> = e
+            .members
+            .iter()
+            .take(DESCRIBE_MAX_ENUM_MEMBERS)
+            .map(|m| format!("{} = {}", m.name, m.value))
+            .collect();
+        output.push_str(&format!(
+            "\n### Enum {}{}\n{}{}\n",
+            e.name,
+            if e.is_flags { " (flags)" } else { "" },
+            members.join(", "),
+            if e.members.len() > DESCRIBE_MAX_ENUM_MEMBERS {
+                format!(", ... {} more", e.members.len() - DESCRIBE_MAX_ENUM_MEMBERS)
+            } else {
+                String::new()
+            }
+        ));
+        output.push_str(&format!(
+            "Filter with {}.{}'{}'\n",
+            e.namespace,
+            e.name,
+            e.members.first().map_or("Value", |m| m.name.as_str())
+        ));
+    }
+
+    if !entity_type.navigation_properties.is_empty() {
+        output.push_str(&format!(
+            "\n### Navigation Properties ({})\n",
+            entity_type.navigation_properties.len()
+        ));
+        output.push_str("| Name | Target | Cardinality | Target Set |\n");
+        output.push_str("|------|--------|-------------|------------|\n");
+        for nav in &entity_type.navigation_properties {
+            output.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                nav.name,
+                unqualified(&nav.target),
+                match (nav.collection, nav.nullable) {
+                    (true, _) => "many",
+                    (false, true) => "zero or one",
+                    (false, false) => "one",
+                },
+                model.entity_set_for_type(&nav.target).map_or("-", |s| s.name.as_str())
+            ));
+        }
+    }
+    Some(output)
+}
+
+/// Render the relationships of an entity; `None` if it is not in the model
+fn format_relationships(model: &EdmModel, entity: &str, kind: Option<&str>) -> Option<String> {
+    let entity_type = model.find_entity_type(entity)?;
+    let relationships: Vec<_> = model
+        .relationships(entity_type)
+        .into_iter()
+        .filter(|r| kind.map_or(true, |k| r.kind.label() == k))
+        .collect();
+    let set = model
+        .entity_set_for_type(&entity_type.name)
+        .map_or(entity_type.name.as_str(), |s| s.name.as_str());
+
+    let mut output = format!("# Relationships of {} ({})\n\n", set, entity_type.name);
+    if relationships.is_empty() {
+        output.push_str("No relationships found.\n");
+        return Some(output);
+    }
+    output.push_str("| Navigation | Kind | Target Set | Lookup Field | Link With |\n");
+    output.push_str("|------------|------|------------|--------------|-----------|\n");
+    for r in &relationships {
+        let target_set = r.target_set.unwrap_or_else(|| unqualified(&r.navigation.target));
+        let link = match r.kind {
+            RelationshipKind::ManyToOne | RelationshipKind::OneToOne => {
+                format!("`\"{}@odata.bind\": \"/{}(<key>)\"`", r.navigation.name, target_set)
+            }
+            RelationshipKind::OneToMany => match r.partner {
+                Some(partner) => format!(
+                    "`\"{}@odata.bind\": \"/{}(<key>)\"` on the {} record",
+                    partner.name, set, target_set
+                ),
+                None => "deep insert (create_deep)".to_string(),
+            },
+            RelationshipKind::ManyToMany => format!("associate_records relationship={}", r.navigation.name),
         };
+        output.push_str(&format!(
+            "| {} | {} | {} | {} | {} |\n",
+            r.navigation.name,
+            r.kind.label(),
+            target_set,
+            if r.lookup_fields.is_empty() { "-".to_string() } else { r.lookup_fields.join(", ") },
+            link
+        ));
+    }
+    output.push_str(&format!(
+        "\nExpand related records with $expand=<Navigation>, e.g. $expand={}($select=...)\n",
+        relationships[0].navigation.name
+    ));
+    Some(output)
+}
 
-        // Parse entity information
-        match crate::odata::ODataClient::parse_entity_from_metadata(&metadata, entity) {
-            Ok((properties, nav_properties, key_fields)) => {
-                let mut output = String::new();
-                
-                output.push_str(&format!("## Entity: {}\n\n", entity));
-                
-                // Key fields
-                if !key_fields.is_empty() {
-                    output.push_str("### Key Fields\n");
-                    for key in &key_fields {
-                        output.push_str(&format!("- {}\n", key));
-                    }
-                    output.push('\n');
-                }
-                
-                // Properties
-                output.push_str(&format!("### Properties ({} fields)\n", properties.len()));
-                for prop in &properties {
-                    output.push_str(&format!("- {}\n", prop));
-                }
-                output.push('\n');
-                
-                // Navigation properties (expandable)
-                if !nav_properties.is_empty() {
-                    output.push_str(&format!("### Navigation Properties (expandable via $expand) ({} fields)\n", nav_properties.len()));
-                    for nav in &nav_properties {
-                        output.push_str(&format!("- {}\n", nav));
-                    }
-                }
-                
-                CallToolResult::text(output)
+/// Check a write payload against the entity's type in $metadata. Passes
+/// when there is no model or the entity set is not in it.
+fn check_payload(
+    model: Option<&EdmModel>,
+    entity: &str,
+    payload: &serde_json::Map<String, Value>,
+    kind: PayloadKind,
+) -> Result<(), String> {
+    let Some((model, entity_type)) = model.and_then(|m| validate::entity_type_for_payload(m, entity).map(|t| (m, t)))
+    else {
+        return Ok(());
+    };
+    validate::validate_payload(model, entity_type, payload, kind).map_err(|errors| {
+        format!(
+            "Payload does not match {} in $metadata:\n- {}\nPass skip_validation=true to send it anyway",
+            entity_type.name,
+            errors.join("\n- ")
+        )
+    })
+}
+
+/// Render key fields, properties and navigation properties of one entity
+fn describe_entity_type(model: &EdmModel, entity: &str) -> CallToolResult {
+    let entity_type = match model.find_entity_type(entity) {
+        Some(t) => t,
+        None => {
+            return CallToolResult::error(format!(
+                "Failed to parse entity metadata: Not found: Entity '{}' not found in metadata",
+                entity
+            ))
+        }
+    };
+
+    let mut output = String::new();
+    
+    output.push_str(&format!("## Entity: {}\n\n", entity));
+    
+    // Key fields
+    let keys = model.keys_of(entity_type);
+    if !keys.is_empty() {
+        output.push_str("### Key Fields\n");
+        for key in keys {
+            output.push_str(&format!("- {}\n", key));
+        }
+        output.push('\n');
+    }
+    
+    // Properties
+    let properties = model.properties_of(entity_type);
+    output.push_str(&format!("### Properties ({} fields)\n", properties.len()));
+    for property in properties {
+        output.push_str(&format!("- {}: {}\n", property.name, property.edm_type.replace("Edm.", "")));
+    }
+    output.push('\n');
+    
+    // Navigation properties (expandable)
+    if !entity_type.navigation_properties.is_empty() {
+        output.push_str(&format!(
+            "### Navigation Properties (expandable via $expand) ({} fields)\n",
+            entity_type.navigation_properties.len()
+        ));
+        for nav in &entity_type.navigation_properties {
+            let target = unqualified(&nav.target);
+            if nav.collection {
+                output.push_str(&format!("- {} -> [{}]\n", nav.name, target));
+            } else {
+                output.push_str(&format!("- {} -> {}\n", nav.name, target));
             }
-            Err(e) => CallToolResult::error(format!("Failed to parse entity metadata: {}", e)),
         }
     }
+    
+    CallToolResult::text(output)
 }
@@ -0,0 +1,252 @@
+//! MCP sessions
+//!
+//! Each HTTP client gets its own session (`Mcp-Session-Id`) with state that
+//! must not leak between agents, such as stored result sets. Stdio and socket
+//! connections get an unregistered session holding only the negotiated
+//! protocol version; they share the server-wide result sets. HTTP requests
+//! without a session header each get a throwaway session with its own.
+
+use crate::mcp::logging::LogLevel;
+use crate::mcp::protocol::ProtocolVersion;
+use crate::mcp::result_sets::ResultSetStore;
+use futures::future::AbortHandle;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// State kept per session
+#[derive(Debug)]
+pub struct Session {
+    /// Own stored result sets; `None` uses the server-wide store
+    pub result_sets: Option<Arc<ResultSetStore>>,
+    protocol_version: Mutex<ProtocolVersion>,
+    /// Level set by `logging/setLevel`
+    log_level: Mutex<Option<LogLevel>>,
+    /// Running tool calls by JSON request id, for `notifications/cancelled`
+    in_flight: Mutex<HashMap<String, AbortHandle>>,
+    last_used: Mutex<Instant>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            result_sets: Some(Arc::new(ResultSetStore::new())),
+            ..Self::connection()
+        }
+    }
+
+    /// Unregistered session with its own result sets, for an HTTP request
+    /// that names no session
+    pub fn temporary() -> Self {
+        Self::new()
+    }
+
+    /// Session of a stdio or socket connection
+    pub fn connection() -> Self {
+        Self {
+            result_sets: None,
+            protocol_version: Mutex::new(ProtocolVersion::default()),
+            log_level: Mutex::new(None),
+            in_flight: Mutex::new(HashMap::new()),
+            last_used: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        *self.protocol_version.lock().unwrap()
+    }
+
+    pub fn set_protocol_version(&self, version: ProtocolVersion) {
+        *self.protocol_version.lock().unwrap() = version;
+    }
+
+    pub fn log_level(&self) -> Option<LogLevel> {
+        *self.log_level.lock().unwrap()
+    }
+
+    pub fn set_log_level(&self, level: LogLevel) {
+        *self.log_level.lock().unwrap() = Some(level);
+    }
+
+    /// Run `future` as request `id`, so it can be cancelled; `None` once cancelled
+    pub async fn cancellable<F: Future>(&self, id: &Value, future: F) -> Option<F::Output> {
+        let (future, handle) = futures::future::abortable(future);
+        self.in_flight.lock().unwrap().insert(id.to_string(), handle);
+        let output = future.await.ok();
+        self.in_flight.lock().unwrap().remove(&id.to_string());
+        output
+    }
+
+    /// Abort running request `id`; false if it is not running
+    pub fn cancel(&self, id: &Value) -> bool {
+        match self.in_flight.lock().unwrap().remove(&id.to_string()) {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_used.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used.lock().unwrap().elapsed()
+    }
+}
+
+/// Open sessions by id
+#[derive(Debug)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+    /// Sessions unused for longer are dropped
+    idle_timeout: Duration,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDLE_TIMEOUT)
+    }
+}
+
+/// Idle time after which a session is forgotten
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+impl Sessions {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            idle_timeout,
+        }
+    }
+
+    /// Start a session and return its id; idle sessions are dropped first
+    pub fn open(&self) -> String {
+        let id = new_session_id();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|id, session| {
+            let keep = session.idle_for() < self.idle_timeout;
+            if !keep {
+                tracing::info!("Session {} expired", id);
+            }
+            keep
+        });
+        sessions.insert(id.clone(), Arc::new(Session::new()));
+        tracing::info!("Session {} opened ({} open)", id, sessions.len());
+        id
+    }
+
+    /// The open session `id`, marked as used
+    pub fn get(&self, id: &str) -> Option<Arc<Session>> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(id).filter(|s| s.idle_for() < self.idle_timeout)?;
+        session.touch();
+        Some(session.clone())
+    }
+
+    pub fn close(&self, id: &str) -> bool {
+        let closed = self.sessions.lock().unwrap().remove(id).is_some();
+        if closed {
+            tracing::info!("Session {} closed", id);
+        }
+        closed
+    }
+}
+
+/// 128 random bits, hex encoded
+fn new_session_id() -> String {
+    use ring::rand::SecureRandom;
+
+    let mut bytes = [0u8; 16];
+    ring::rand::SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+tokio::task_local! {
+    static CURRENT: Arc<Session>;
+}
+
+/// Run `future` inside `session`, if any
+pub async fn in_session<F: Future>(session: Option<Arc<Session>>, future: F) -> F::Output {
+    match session {
+        Some(session) => CURRENT.scope(session, future).await,
+        None => future.await,
+    }
+}
+
+/// Session of the running request
+pub fn current() -> Option<Arc<Session>> {
+    CURRENT.try_with(Arc::clone).ok()
+}
+
+/// Protocol version negotiated by the running request's session
+pub fn protocol_version() -> ProtocolVersion {
+    current().map(|s| s.protocol_version()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_get_close() {
+        let sessions = Sessions::default();
+        let a = sessions.open();
+        let b = sessions.open();
+        assert_eq!(a.len(), 32);
+        assert_ne!(a, b);
+
+        let result_sets = |id: &str| sessions.get(id).unwrap().result_sets.clone().unwrap();
+        result_sets(&a).insert("accounts", "accounts", Vec::new());
+        assert!(result_sets(&b).get("accounts").is_none());
+
+        assert!(sessions.close(&a));
+        assert!(sessions.get(&a).is_none());
+        assert!(!sessions.close(&a));
+
+        let temporary = Session::temporary().result_sets.unwrap();
+        temporary.insert("accounts", "accounts", Vec::new());
+        assert!(Session::temporary().result_sets.unwrap().get("accounts").is_none());
+        assert!(sessions.get(&b).unwrap().result_sets.clone().unwrap().get("accounts").is_none());
+    }
+
+    #[test]
+    fn test_idle_sessions_expire() {
+        let sessions = Sessions::new(Duration::ZERO);
+        let id = sessions.open();
+        assert!(sessions.get(&id).is_none());
+        sessions.open();
+        assert_eq!(sessions.sessions.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running_request() {
+        let session = Arc::new(Session::connection());
+        let id = Value::from(5);
+        assert!(!session.cancel(&id));
+
+        let running = tokio::spawn({
+            let session = session.clone();
+            async move { session.cancellable(&Value::from(5), std::future::pending::<()>()).await }
+        });
+        while !session.cancel(&id) {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(running.await.unwrap(), None);
+        assert_eq!(session.cancellable(&id, async { 1 }).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_in_session_scope() {
+        assert!(current().is_none());
+        let session = Arc::new(Session::new());
+        let inside = in_session(Some(session.clone()), async { current() }).await;
+        assert!(Arc::ptr_eq(&inside.unwrap(), &session));
+    }
+}
@@ -0,0 +1,608 @@
+//! MCP transports
+//!
+//! Runs the JSON-RPC message loop over stdio, a local socket or TCP; HTTP
+//! lives in [`crate::mcp::http`]. Socket transports accept many connections
+//! against one shared handler, so a warm server (and its cached token)
+//! survives editor/agent restarts and can be supervised on its own.
+
+use crate::mcp::framing::{self, Framing, MessageTooLarge};
+use crate::mcp::handler::McpHandler;
+use crate::mcp::logging;
+use crate::mcp::protocol::{JsonRpcNotification, JsonRpcRequest, JsonRpcResponse};
+use crate::mcp::session::{self, Session};
+use crate::odata::metadata_cache::ModelWatch;
+use futures::StreamExt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncWrite, BufReader};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+/// Serve MCP over the process stdin/stdout
+pub async fn serve_stdio(handler: Arc<McpHandler>) -> std::io::Result<()> {
+    let reader = BufReader::new(tokio::io::stdin());
+    let writer = tokio::io::stdout();
+    serve_connection(handler, reader, writer).await
+}
+
+/// Run the message loop for a single connection until EOF or shutdown.
+///
+/// Each request runs in its own task, so a slow tool call does not hold up
+/// `ping` or `tools/list`; only the writes to `writer` are serialized. Once
+/// reading stops, requests still running are finished before returning.
+pub async fn serve_connection<R, W>(
+    handler: Arc<McpHandler>,
+    mut reader: R,
+    writer: W,
+) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    tracing::debug!("Waiting for input...");
+
+    let writer: SharedWriter<W> = Arc::new(Mutex::new(writer));
+    let mut tool_changes = handler.tool_changes();
+    // Unsolicited messages use the framing of the client's latest message
+    let mut connection_framing = Framing::default();
+    // Holds the protocol version and log level negotiated on this connection
+    let session = Arc::new(Session::connection());
+    let mut logs = logging::subscribe();
+    let mut requests = JoinSet::new();
+
+    // A stream keeps a partly read message when another branch wins the select
+    let max_bytes = handler.max_message_bytes();
+    let messages = futures::stream::unfold(&mut reader, move |reader| async move {
+        framing::read_message(reader, max_bytes).await.transpose().map(|message| (message, reader))
+    });
+    tokio::pin!(messages);
+
+    loop {
+        let message = tokio::select! {
+            message = messages.next() => match message {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => match MessageTooLarge::from_io(&e) {
+                    // The oversized message was skipped; the connection stays usable
+                    Some(too_large) => {
+                        tracing::warn!("Dropped incoming message: {}", too_large);
+                        let message = format!("Invalid Request: {}", too_large);
+                        let error_response = JsonRpcResponse::error(None, -32600, &message);
+                        let _ = send_message(&writer, &error_response, connection_framing).await;
+                        continue;
+                    }
+                    None => return Err(e),
+                },
+                None => break,
+            },
+            _ = handler.shutting_down() => {
+                tracing::info!("Shutting down; no further requests are read");
+                break;
+            }
+            Some(finished) = requests.join_next(), if !requests.is_empty() => {
+                if let Err(e) = finished {
+                    tracing::error!("Request task failed: {}", e);
+                }
+                continue;
+            }
+            log = logging::next_at(&mut logs, || handler.client_log_level(Some(&session))) => {
+                let _ = send_message(&writer, &log.notification(), connection_framing).await;
+                continue;
+            }
+            changed = tools_changed(&mut tool_changes) => {
+                if changed {
+                    let notification = JsonRpcNotification::new("notifications/tools/list_changed", serde_json::json!({}));
+                    let _ = send_message(&writer, &notification, connection_framing).await;
+                } else {
+                    tool_changes = None;
+                }
+                continue;
+            }
+        };
+
+        tracing::debug!(
+            "Read {} bytes ({:?} framing): {:?}",
+            message.body.len(),
+            message.framing,
+            message.body
+        );
+
+        // Reply in the same framing the client used
+        let framing = message.framing;
+        connection_framing = framing;
+
+        let message = match serde_json::from_str::<serde_json::Value>(&message.body) {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::debug!("Parse error: {}", e);
+                let error_response = JsonRpcResponse::error(None, -32700, &format!("Parse error: {}", e));
+                let _ = send_message(&writer, &error_response, framing).await;
+                continue;
+            }
+        };
+
+        // A JSON-RPC batch is answered with one array of the non-notification responses
+        if let serde_json::Value::Array(batch) = message {
+            if batch.is_empty() {
+                let error_response = JsonRpcResponse::error(None, -32600, "Invalid request: empty batch");
+                let _ = send_message(&writer, &error_response, framing).await;
+                continue;
+            }
+            let (handler, writer) = (handler.clone(), writer.clone());
+            requests.spawn(session::in_session(Some(session.clone()), Box::pin(async move {
+                let responses = futures::future::join_all(batch.into_iter().map(|element| {
+                    let (handler, writer) = (handler.clone(), writer.clone());
+                    async move {
+                        match handler.parse_request(element) {
+                            Ok(request) => {
+                                let is_notification = request.id.is_none();
+                                let response = dispatch(&handler, request, &writer, framing).await;
+                                (!is_notification && !response.is_cancelled()).then_some(response)
+                            }
+                            Err(error_response) => Some(*error_response),
+                        }
+                    }
+                }))
+                .await;
+                let responses: Vec<JsonRpcResponse> = responses.into_iter().flatten().collect();
+                if !responses.is_empty() {
+                    let _ = send_message(&writer, &responses, framing).await;
+                }
+            })));
+            continue;
+        }
+
+        let request = match handler.parse_request(message) {
+            Ok(req) => {
+                tracing::debug!("Parsed request: method={}, has_id={}", req.method, req.id.is_some());
+                req
+            }
+            Err(error_response) => {
+                tracing::debug!("Invalid request: {:?}", error_response.error);
+                let _ = send_message(&writer, &error_response, framing).await;
+                continue;
+            }
+        };
+
+        // Notifications don't have an id and should NOT receive a response
+        let is_notification = request.id.is_none();
+
+        let (handler, writer) = (handler.clone(), writer.clone());
+        // Boxed: the handler future is large and the session scope would copy it
+        requests.spawn(session::in_session(Some(session.clone()), Box::pin(async move {
+            let response = dispatch(&handler, request, &writer, framing).await;
+            if is_notification {
+                tracing::debug!("Notification handled, no response needed");
+            } else if !response.is_cancelled() {
+                let _ = send_message(&writer, &response, framing).await;
+            }
+        })));
+    }
+
+    tracing::debug!("Input closed, finishing {} running requests", requests.len());
+    while let Some(finished) = requests.join_next().await {
+        if let Err(e) = finished {
+            tracing::error!("Request task failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Connection writer shared by the tasks of its requests
+type SharedWriter<W> = Arc<Mutex<W>>;
+
+/// Handle one request, writing its notifications while it runs
+async fn dispatch<W: AsyncWrite + Unpin>(
+    handler: &McpHandler,
+    request: JsonRpcRequest,
+    writer: &SharedWriter<W>,
+    framing: Framing,
+) -> JsonRpcResponse {
+    // Server-initiated notifications are written while their request is still running
+    let (notify_tx, mut notify_rx) = tokio::sync::mpsc::unbounded_channel::<JsonRpcNotification>();
+    let handle = handler.handle_request(request, Some(notify_tx));
+    tokio::pin!(handle);
+    let response = loop {
+        tokio::select! {
+            response = &mut handle => break response,
+            Some(notification) = notify_rx.recv() => {
+                let _ = send_message(writer, &notification, framing).await;
+            }
+        }
+    };
+    while let Ok(notification) = notify_rx.try_recv() {
+        let _ = send_message(writer, &notification, framing).await;
+    }
+    response
+}
+
+/// Wait for the next tool list change; false once changes can no longer happen
+pub(crate) async fn tools_changed(changes: &mut Option<ModelWatch>) -> bool {
+    match changes {
+        Some(changes) => changes.changed().await.is_ok(),
+        None => std::future::pending().await,
+    }
+}
+
+async fn send_message<W: AsyncWrite + Unpin, T: serde::Serialize>(
+    writer: &SharedWriter<W>,
+    message: &T,
+    framing: Framing,
+) -> std::io::Result<()> {
+    let json = serde_json::to_string(message)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    tracing::debug!("Sending: {}", json);
+    framing::write_message(&mut *writer.lock().await, &json, framing).await
+}
+
+/// Address of a socket listener, as given to `--listen`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// Unix domain socket path, or Windows named pipe name
+    Local(String),
+    Tcp(SocketAddr),
+}
+
+impl ListenAddr {
+    /// `unix:<path>`, `pipe:<name>` or `tcp:<host>:<port>`
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.split_once(':') {
+            Some(("unix" | "pipe", path)) if !path.is_empty() => Ok(ListenAddr::Local(path.to_string())),
+            Some(("tcp", addr)) => addr
+                .parse()
+                .map(ListenAddr::Tcp)
+                .map_err(|e| format!("Invalid TCP address '{}': {} (e.g. tcp:127.0.0.1:9000)", addr, e)),
+            _ => Err(format!(
+                "Invalid listen address '{}': use unix:<path>, pipe:<name> or tcp:<host>:<port>",
+                text
+            )),
+        }
+    }
+}
+
+/// Serve MCP on a `--listen` address
+pub async fn serve_listen(handler: Arc<McpHandler>, addr: &ListenAddr) -> std::io::Result<()> {
+    match addr {
+        ListenAddr::Local(path) => serve_local_socket(handler, path).await,
+        ListenAddr::Tcp(addr) => serve_tcp(handler, *addr).await,
+    }
+}
+
+/// Serve MCP on a TCP port, one task per connection. There is no
+/// authentication, so only loopback addresses are accepted.
+pub async fn serve_tcp(handler: Arc<McpHandler>, addr: SocketAddr) -> std::io::Result<()> {
+    if !addr.ip().is_loopback() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "refusing to serve TCP on non-loopback {} without authentication; use --transport http with MCP_HTTP_TOKEN",
+                addr
+            ),
+        ));
+    }
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Listening on tcp {}", listener.local_addr()?);
+    serve_tcp_listener(handler, listener).await
+}
+
+async fn serve_tcp_listener(handler: Arc<McpHandler>, listener: tokio::net::TcpListener) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = handler.shutting_down() => return Ok(()),
+        };
+        tracing::info!("Accepted TCP connection from {}", peer);
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let (read_half, write_half) = stream.into_split();
+            if let Err(e) = serve_connection(handler, BufReader::new(read_half), write_half).await {
+                tracing::warn!("TCP connection error: {}", e);
+            }
+        });
+    }
+}
+
+/// Serve MCP on a Unix domain socket, one task per connection. The socket
+/// is readable by the owner only and removed when serving ends.
+#[cfg(unix)]
+pub async fn serve_unix_socket(handler: Arc<McpHandler>, path: &str) -> std::io::Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use tokio::net::UnixListener;
+
+    // A stale socket from a previous run would make bind fail; anything
+    // else at the path is left alone
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path),
+            ))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let _socket = SocketFile(path);
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    tracing::info!("Listening on unix socket {}", path);
+
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = handler.shutting_down() => return Ok(()),
+        };
+        tracing::info!("Accepted socket connection");
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let (read_half, write_half) = stream.into_split();
+            if let Err(e) = serve_connection(handler, BufReader::new(read_half), write_half).await {
+                tracing::warn!("Socket connection error: {}", e);
+            }
+        });
+    }
+}
+
+/// Removes the socket file when the listener goes away
+#[cfg(unix)]
+struct SocketFile<'a>(&'a str);
+
+#[cfg(unix)]
+impl Drop for SocketFile<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(self.0) {
+            tracing::warn!("Failed to remove socket {}: {}", self.0, e);
+        }
+    }
+}
+
+/// Serve MCP on a Windows named pipe (e.g. `\\.\pipe\d365-odata-mcp`)
+#[cfg(windows)]
+pub async fn serve_named_pipe(handler: Arc<McpHandler>, name: &str) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new().first_pipe_instance(true).create(name)?;
+    tracing::info!("Listening on named pipe {}", name);
+
+    loop {
+        tokio::select! {
+            connected = server.connect() => connected?,
+            _ = handler.shutting_down() => return Ok(()),
+        }
+        let connected = server;
+        // Create the next instance before serving so new clients can connect
+        server = ServerOptions::new().create(name)?;
+
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let (read_half, write_half) = tokio::io::split(connected);
+            if let Err(e) = serve_connection(handler, BufReader::new(read_half), write_half).await {
+                tracing::warn!("Pipe connection error: {}", e);
+            }
+        });
+    }
+}
+
+/// Serve MCP on the platform's local socket type
+pub async fn serve_local_socket(handler: Arc<McpHandler>, path: &str) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        serve_unix_socket(handler, path).await
+    }
+    #[cfg(windows)]
+    {
+        serve_named_pipe(handler, path).await
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (handler, path);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Local socket transport is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve `input` as one connection and return everything written back
+    async fn run_connection(handler: Arc<McpHandler>, input: &str) -> String {
+        use tokio::io::AsyncReadExt;
+
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        serve_connection(handler, BufReader::new(input.as_bytes()), server)
+            .await
+            .unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        output
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection_replies_and_skips_notifications() {
+        let handler = Arc::new(McpHandler::new(None));
+        let input = concat!(
+            "{\"jsonrpc\":\"2.0\",\"method\":\"notifications/initialized\"}\n",
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"}\n",
+        );
+        let output = run_connection(handler, input).await;
+        assert_eq!(output, "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{}}\n");
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection_skips_oversized_message() {
+        let handler = Arc::new(McpHandler::new(None).with_max_message_bytes(64));
+        let input = format!(
+            "{{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\",\"params\":{{\"pad\":\"{}\"}}}}\n{{\"jsonrpc\":\"2.0\",\n\"id\":2,\"method\":\"ping\"}}",
+            "x".repeat(100)
+        );
+        let lines: Vec<serde_json::Value> = run_connection(handler, &input)
+            .await
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["error"]["code"], -32600);
+        assert_eq!(lines[1]["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection_batch() {
+        let handler = Arc::new(McpHandler::new(None));
+        let input = concat!(
+            "[{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"},",
+            "{\"jsonrpc\":\"2.0\",\"method\":\"notifications/initialized\"},",
+            "{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"ping\"},",
+            "{\"id\":3}]\n",
+            "[{\"jsonrpc\":\"2.0\",\"method\":\"notifications/initialized\"}]\n",
+            "[]\n",
+        );
+        let lines: Vec<serde_json::Value> = run_connection(handler, input)
+            .await
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        // Batches run in their own task, so the empty batch error may come first
+        let batch = lines.iter().find_map(|l| l.as_array()).unwrap();
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch[0]["id"], 1);
+        assert_eq!(batch[1]["id"], 2);
+        assert_eq!(batch[2]["error"]["code"], -32600);
+        assert!(lines.iter().any(|l| l["error"]["code"] == -32600));
+    }
+
+    #[tokio::test]
+    async fn test_serve_tcp_refuses_non_loopback() {
+        let handler = Arc::new(McpHandler::new(None));
+        let error = serve_tcp(handler, "0.0.0.0:0".parse().unwrap()).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("d365-socket-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("not-a-socket");
+        std::fs::write(&file, "keep").unwrap();
+        let handler = Arc::new(McpHandler::new(None));
+        let error = serve_unix_socket(handler.clone(), file.to_str().unwrap()).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep");
+
+        let socket = dir.join("mcp.sock");
+        let path = socket.to_str().unwrap().to_string();
+        let serving = tokio::spawn({
+            let handler = handler.clone();
+            async move { serve_unix_socket(handler, &path).await }
+        });
+        while !socket.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        handler.shutdown().await;
+        serving.await.unwrap().unwrap();
+        assert!(!socket.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection_stops_on_shutdown() {
+        let handler = Arc::new(McpHandler::new(None));
+        // Input that never ends, as when the client keeps stdin open
+        let (_client_input, input) = tokio::io::duplex(1024);
+        let (_client_output, output) = tokio::io::duplex(1024);
+        let serving = tokio::spawn(serve_connection(handler.clone(), BufReader::new(input), output));
+
+        handler.shutdown().await;
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), serving).await;
+        assert!(result.expect("connection kept reading after shutdown").unwrap().is_ok());
+
+        let call = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(1.into()),
+            method: "tools/call".to_string(),
+            params: Some(serde_json::json!({ "name": "list_entities" })),
+        };
+        let response = Box::pin(handler.handle_request(call, None)).await;
+        assert_eq!(response.error.unwrap().code, -32000);
+    }
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            ListenAddr::parse("unix:/run/d365.sock").unwrap(),
+            ListenAddr::Local("/run/d365.sock".to_string())
+        );
+        assert_eq!(
+            ListenAddr::parse("tcp:127.0.0.1:9000").unwrap(),
+            ListenAddr::Tcp("127.0.0.1:9000".parse().unwrap())
+        );
+        assert!(ListenAddr::parse("tcp:localhost").is_err());
+        assert!(ListenAddr::parse("/run/d365.sock").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_round_trip() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_tcp_listener(Arc::new(McpHandler::new(None)), listener));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (read_half, mut write_half) = stream.into_split();
+        write_half
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"ping\"}\n")
+            .await
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(read_half).read_line(&mut line).await.unwrap();
+        assert_eq!(line.trim(), "{\"jsonrpc\":\"2.0\",\"id\":3,\"result\":{}}");
+
+        server.abort();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_round_trip() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("d365-mcp-test-{}.sock", std::process::id()));
+        let path_str = path.to_string_lossy().into_owned();
+        let handler = Arc::new(McpHandler::new(None));
+        let server_path = path_str.clone();
+        let server = tokio::spawn(async move { serve_unix_socket(handler, &server_path).await });
+
+        // Wait for the listener to come up
+        let mut stream = None;
+        for _ in 0..50 {
+            if let Ok(s) = tokio::net::UnixStream::connect(&path).await {
+                stream = Some(s);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let stream = stream.expect("socket listener did not start");
+        let (read_half, mut write_half) = stream.into_split();
+
+        write_half
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"ping\"}\n")
+            .await
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(read_half).read_line(&mut line).await.unwrap();
+        assert_eq!(line.trim(), "{\"jsonrpc\":\"2.0\",\"id\":7,\"result\":{}}");
+
+        server.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,393 @@
+//! OData `$batch` requests
+//!
+//! Builds `multipart/mixed` batch bodies and splits the multipart response
+//! back into one result per sub-request, so several small reads cost a
+//! single HTTP round trip. Writes can be grouped in a changeset, which the
+//! service applies atomically.
+
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Most sub-requests the services accept in one batch
+pub const MAX_BATCH_REQUESTS: usize = 1000;
+
+static BOUNDARY_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// One sub-request of a batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchOperation {
+    pub method: String,
+    /// Absolute URL of the sub-request
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Value>,
+}
+
+impl BatchOperation {
+    pub fn get(url: impl Into<String>) -> Self {
+        Self {
+            method: "GET".to_string(),
+            url: url.into(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+}
+
+/// Response to one sub-request
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResponse {
+    /// `Content-ID` of the changeset operation this answers, if any
+    pub content_id: Option<String>,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Raw response body (usually JSON, empty for 204)
+    pub body: String,
+}
+
+impl BatchResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Body parsed as JSON, if it is JSON
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.body).ok()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Outcome of a bulk write sent as several batches
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BulkReport {
+    /// Input index and entity URL (`OData-EntityId`) of each record written
+    pub succeeded: Vec<(usize, Option<String>)>,
+    /// Input index, HTTP status (0 if the whole batch failed) and error message
+    pub failed: Vec<(usize, u16, String)>,
+}
+
+impl BulkReport {
+    /// Fold the responses of a batch whose first record has input index `offset`
+    pub fn add_responses(&mut self, offset: usize, count: usize, responses: &[BatchResponse]) {
+        for i in 0..count {
+            match responses.get(i) {
+                Some(response) if response.is_success() => self
+                    .succeeded
+                    .push((offset + i, response.header("OData-EntityId").map(String::from))),
+                Some(response) => self.failed.push((offset + i, response.status, error_message(response))),
+                None => self.failed.push((offset + i, 0, "No response returned".to_string())),
+            }
+        }
+    }
+
+    /// Mark every record of a batch that could not be sent as failed
+    pub fn add_batch_error(&mut self, offset: usize, count: usize, error: &str) {
+        self.failed
+            .extend((offset..offset + count).map(|i| (i, 0, error.to_string())));
+    }
+}
+
+/// The OData error message of a failed sub-response, or its raw body
+pub fn error_message(response: &BatchResponse) -> String {
+    response
+        .json()
+        .and_then(|j| j["error"]["message"].as_str().map(String::from))
+        .unwrap_or_else(|| response.body.clone())
+}
+
+/// A unique multipart boundary, e.g. `batch_18f3c2a9b1_1`
+pub fn new_boundary(prefix: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let seq = BOUNDARY_SEQ.fetch_add(1, Ordering::Relaxed);
+    format!("{}_{:x}_{}", prefix, nanos, seq)
+}
+
+/// Build a batch body holding one changeset; operation `i` gets `Content-ID: i+1`
+pub fn build_changeset_batch(
+    batch_boundary: &str,
+    changeset_boundary: &str,
+    operations: &[BatchOperation],
+) -> Result<String, String> {
+    let mut body = format!(
+        "--{}\r\nContent-Type: multipart/mixed; boundary={}\r\n\r\n",
+        batch_boundary, changeset_boundary
+    );
+    for (i, operation) in operations.iter().enumerate() {
+        body.push_str(&format!("--{}\r\n", changeset_boundary));
+        body.push_str("Content-Type: application/http\r\n");
+        body.push_str("Content-Transfer-Encoding: binary\r\n");
+        body.push_str(&format!("Content-ID: {}\r\n\r\n", i + 1));
+        push_http_request(&mut body, operation)?;
+    }
+    body.push_str(&format!("--{}--\r\n", changeset_boundary));
+    body.push_str(&format!("--{}--\r\n", batch_boundary));
+    Ok(body)
+}
+
+/// Build a `multipart/mixed` batch body of independent sub-requests
+pub fn build_batch(boundary: &str, operations: &[BatchOperation]) -> Result<String, String> {
+    let mut body = String::new();
+    for operation in operations {
+        body.push_str(&format!("--{}\r\n", boundary));
+        body.push_str("Content-Type: application/http\r\n");
+        body.push_str("Content-Transfer-Encoding: binary\r\n\r\n");
+        push_http_request(&mut body, operation)?;
+    }
+    body.push_str(&format!("--{}--\r\n", boundary));
+    Ok(body)
+}
+
+/// Append one sub-request. Its request line and headers are written as-is,
+/// so a CR or LF in them (e.g. in a client-supplied ETag) would start new
+/// headers or a new part and is rejected.
+fn push_http_request(body: &mut String, operation: &BatchOperation) -> Result<(), String> {
+    let target = request_target(&operation.url);
+    let has_line_break = |text: &str| text.contains(['\r', '\n']);
+    if has_line_break(&operation.method) || has_line_break(&target) {
+        return Err(format!("Batch request line contains a line break: {} {}", operation.method, target.trim()));
+    }
+    if let Some((name, _)) = operation
+        .headers
+        .iter()
+        .find(|(name, value)| has_line_break(name) || has_line_break(value))
+    {
+        return Err(format!("Batch header {} contains a line break", name.trim()));
+    }
+    body.push_str(&format!("{} {} HTTP/1.1\r\n", operation.method, target));
+    body.push_str("Accept: application/json\r\n");
+    for (name, value) in &operation.headers {
+        body.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    match &operation.body {
+        Some(json) => {
+            body.push_str("Content-Type: application/json\r\n\r\n");
+            body.push_str(&json.to_string());
+            body.push_str("\r\n");
+        }
+        None => body.push_str("\r\n"),
+    }
+    Ok(())
+}
+
+/// The URL as it must appear in a request line: spaces and quotes in
+/// `$filter` are percent-encoded like reqwest does for top-level requests
+fn request_target(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .map(|u| u.to_string())
+        .unwrap_or_else(|_| url.replace(' ', "%20"))
+}
+
+/// Split a batch response into sub-responses, in request order.
+///
+/// `content_type` is the response's `Content-Type` header, which carries the
+/// boundary. Nested multipart parts are flattened into the list.
+pub fn parse_batch_response(content_type: &str, body: &str) -> Result<Vec<BatchResponse>, String> {
+    let boundary = boundary_of(content_type)
+        .ok_or_else(|| format!("Batch response has no multipart boundary: {}", content_type))?;
+    let mut responses = Vec::new();
+    parse_multipart(&boundary, &body.replace("\r\n", "\n"), &mut responses)?;
+    Ok(responses)
+}
+
+fn boundary_of(content_type: &str) -> Option<String> {
+    content_type.split(';').find_map(|param| {
+        let (name, value) = param.trim().split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn parse_multipart(boundary: &str, body: &str, responses: &mut Vec<BatchResponse>) -> Result<(), String> {
+    let delimiter = format!("--{}", boundary);
+    for part in body.split(delimiter.as_str()).skip(1) {
+        if part.starts_with("--") {
+            break;
+        }
+        let (part_headers, content) = part
+            .trim_start_matches('\n')
+            .split_once("\n\n")
+            .ok_or_else(|| "Malformed batch part: missing headers".to_string())?;
+        let part_headers = parse_headers(part_headers);
+        let part_header = |name: &str| {
+            part_headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.clone())
+        };
+        let part_type = part_header("Content-Type").unwrap_or_default();
+
+        if part_type.to_ascii_lowercase().starts_with("multipart/mixed") {
+            let nested = boundary_of(&part_type)
+                .ok_or_else(|| "Nested batch part has no boundary".to_string())?;
+            parse_multipart(&nested, content, responses)?;
+        } else {
+            let mut response = parse_http_response(content)?;
+            response.content_id = part_header("Content-ID").or_else(|| response.header("Content-ID").map(String::from));
+            responses.push(response);
+        }
+    }
+    Ok(())
+}
+
+fn parse_http_response(text: &str) -> Result<BatchResponse, String> {
+    let text = text.trim_start();
+    let (head, body) = text.split_once("\n\n").unwrap_or((text, ""));
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| "Malformed batch part: missing HTTP status line".to_string())?;
+    Ok(BatchResponse {
+        content_id: None,
+        status,
+        headers: parse_headers(&lines.collect::<Vec<_>>().join("\n")),
+        body: body.trim().to_string(),
+    })
+}
+
+fn parse_headers(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_batch() {
+        let body = build_batch(
+            "batch_1",
+            &[BatchOperation::get("https://org/api/data/v9.2/accounts?$top=1")],
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            "--batch_1\r\nContent-Type: application/http\r\nContent-Transfer-Encoding: binary\r\n\r\n\
+             GET https://org/api/data/v9.2/accounts?$top=1 HTTP/1.1\r\nAccept: application/json\r\n\r\n\
+             --batch_1--\r\n"
+        );
+    }
+
+    #[test]
+    fn test_build_batch_rejects_line_breaks() {
+        let update = BatchOperation {
+            method: "PATCH".to_string(),
+            url: "https://org/api/data/v9.2/accounts(1)".to_string(),
+            headers: vec![("If-Match".to_string(), "*\r\n\r\nDELETE https://org/api/data/v9.2/accounts(2) HTTP/1.1".to_string())],
+            body: None,
+        };
+        let error = build_changeset_batch("batch_1", "changeset_1", &[update]).unwrap_err();
+        assert!(error.contains("If-Match"), "{}", error);
+
+        let get = BatchOperation::get("accounts?$filter=name eq 'A'\nX-Injected: 1");
+        assert!(build_batch("batch_1", &[get]).is_err());
+    }
+
+    #[test]
+    fn test_request_target_encodes_spaces() {
+        assert_eq!(
+            request_target("https://org/api/data/v9.2/accounts?$filter=name eq 'A'"),
+            "https://org/api/data/v9.2/accounts?$filter=name%20eq%20%27A%27"
+        );
+    }
+
+    #[test]
+    fn test_parse_batch_response() {
+        let body = "--batchresponse_a\r\n\
+            Content-Type: application/http\r\n\
+            Content-Transfer-Encoding: binary\r\n\r\n\
+            HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n\
+            {\"value\":[{\"name\":\"Contoso\"}]}\r\n\
+            --batchresponse_a\r\n\
+            Content-Type: application/http\r\n\r\n\
+            HTTP/1.1 404 Not Found\r\n\r\n\
+            {\"error\":{\"message\":\"missing\"}}\r\n\
+            --batchresponse_a--\r\n";
+        let responses =
+            parse_batch_response("multipart/mixed; boundary=batchresponse_a", body).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].is_success());
+        assert_eq!(responses[0].json().unwrap()["value"][0]["name"], "Contoso");
+        assert_eq!(responses[0].header("content-type"), Some("application/json"));
+        assert_eq!(responses[1].status, 404);
+
+        assert!(parse_batch_response("application/json", "{}").is_err());
+    }
+
+    #[test]
+    fn test_changeset_round_trip() {
+        let update = BatchOperation {
+            method: "PATCH".to_string(),
+            url: "https://org/api/data/v9.2/accounts(1)".to_string(),
+            headers: vec![("If-Match".to_string(), "*".to_string())],
+            body: Some(serde_json::json!({ "name": "A" })),
+        };
+        let body = build_changeset_batch("batch_1", "changeset_1", &[update]).unwrap();
+        assert!(body.starts_with("--batch_1\r\nContent-Type: multipart/mixed; boundary=changeset_1\r\n\r\n--changeset_1\r\n"));
+        assert!(body.contains("Content-ID: 1\r\n\r\nPATCH https://org/api/data/v9.2/accounts(1) HTTP/1.1\r\n"));
+        assert!(body.contains("If-Match: *\r\nContent-Type: application/json\r\n\r\n{\"name\":\"A\"}\r\n"));
+        assert!(body.ends_with("--changeset_1--\r\n--batch_1--\r\n"));
+
+        let response = "--batchresponse_1\r\n\
+            Content-Type: multipart/mixed; boundary=changesetresponse_1\r\n\r\n\
+            --changesetresponse_1\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: 1\r\n\r\n\
+            HTTP/1.1 204 No Content\r\nOData-EntityId: https://org/api/data/v9.2/accounts(1)\r\n\r\n\r\n\
+            --changesetresponse_1\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: 2\r\n\r\n\
+            HTTP/1.1 412 Precondition Failed\r\n\r\n{}\r\n\
+            --changesetresponse_1--\r\n\
+            --batchresponse_1--\r\n";
+        let responses = parse_batch_response("multipart/mixed; boundary=batchresponse_1", response).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].status, 204);
+        assert_eq!(responses[0].header("OData-EntityId"), Some("https://org/api/data/v9.2/accounts(1)"));
+        assert_eq!(responses[1].content_id.as_deref(), Some("2"));
+        assert!(!responses[1].is_success());
+    }
+
+    #[test]
+    fn test_bulk_report() {
+        let ok = BatchResponse {
+            content_id: None,
+            status: 204,
+            headers: vec![("OData-EntityId".to_string(), "https://org/accounts(1)".to_string())],
+            body: String::new(),
+        };
+        let bad = BatchResponse {
+            content_id: None,
+            status: 400,
+            headers: Vec::new(),
+            body: r#"{"error":{"message":"bad name"}}"#.to_string(),
+        };
+        let mut report = BulkReport::default();
+        report.add_responses(10, 3, &[ok, bad]);
+        report.add_batch_error(13, 2, "timeout");
+        assert_eq!(report.succeeded, vec![(10, Some("https://org/accounts(1)".to_string()))]);
+        assert_eq!(report.failed[0], (11, 400, "bad name".to_string()));
+        assert_eq!(report.failed[1], (12, 0, "No response returned".to_string()));
+        assert_eq!(report.failed.len(), 4);
+    }
+
+    #[test]
+    fn test_boundaries_are_unique() {
+        assert_ne!(new_boundary("batch"), new_boundary("batch"));
+    }
+}
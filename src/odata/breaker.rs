@@ -0,0 +1,136 @@
+//! Circuit breaker around the OData endpoint
+//!
+//! When the environment keeps answering with 5xx or not at all, every tool
+//! call retrying on its own only adds load. After `failure_threshold`
+//! consecutive failures the circuit opens and requests fail fast for the
+//! cool-down. Then requests go through again; one more failure reopens it at
+//! once, a success closes it.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default consecutive failures that open the circuit
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// Default time the circuit stays open
+pub const DEFAULT_COOL_DOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Consecutive-failure circuit breaker
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// Failures that open the circuit; 0 never opens it
+    failure_threshold: u32,
+    cool_down: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOL_DOWN)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cool_down: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cool_down,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// `Err` with the time left while the circuit is open
+    pub fn check(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let Some(open_until) = state.open_until else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if now < open_until {
+            return Err(open_until - now);
+        }
+        // Half-open: the next failure reopens the circuit
+        state.open_until = None;
+        state.consecutive_failures = self.failure_threshold.saturating_sub(1);
+        tracing::info!("Circuit half-open; letting requests through to the OData endpoint");
+        Ok(())
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.consecutive_failures > 0 {
+            tracing::debug!("OData endpoint recovered after {} failures", state.consecutive_failures);
+        }
+        state.consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&self) {
+        if self.failure_threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.failure_threshold && state.open_until.is_none() {
+            tracing::warn!(
+                "OData endpoint failed {} times in a row; failing fast for {}s",
+                state.consecutive_failures,
+                self.cool_down.as_secs()
+            );
+            state.open_until = Some(Instant::now() + self.cool_down);
+        }
+    }
+
+    /// Consecutive failures so far
+    pub fn failures(&self) -> u32 {
+        self.state.lock().unwrap().consecutive_failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_threshold() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        let remaining = breaker.check().unwrap_err();
+        assert!(remaining <= Duration::from_secs(60) && remaining > Duration::from_secs(50));
+    }
+
+    #[test]
+    fn test_half_open_after_cool_down() {
+        let breaker = CircuitBreaker::new(2, Duration::ZERO);
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        assert_eq!(breaker.failures(), 1);
+        // One failure reopens it; a success closes it
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_success();
+        assert_eq!(breaker.failures(), 0);
+    }
+
+    #[test]
+    fn test_threshold_zero_never_opens() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            breaker.record_failure();
+        }
+        assert!(breaker.check().is_ok());
+    }
+}
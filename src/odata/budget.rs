@@ -0,0 +1,315 @@
+//! Service-protection budget tracking
+//!
+//! Dataverse enforces per-user service protection limits over a sliding
+//! 5-minute window: 6000 requests and 20 minutes of combined execution time.
+//! The client records every request here and slows down once usage crosses a
+//! soft limit, instead of waiting for the platform to answer with 429s.
+//!
+//! Dataverse also reports what is left of both limits on every response
+//! (`x-ms-ratelimit-*-remaining-xrm-requests`). That count includes requests
+//! made by other clients of the same user, so once it falls below the soft
+//! limit, requests are spread over the window at the pace it still allows.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Length of the service-protection sliding window
+pub const WINDOW: Duration = Duration::from_secs(300);
+
+/// Response header with the requests left in the window
+pub const REMAINING_REQUESTS_HEADER: &str = "x-ms-ratelimit-burst-remaining-xrm-requests";
+
+/// Response header with the execution time left in the window, in milliseconds
+pub const REMAINING_TIME_HEADER: &str = "x-ms-ratelimit-time-remaining-xrm-requests";
+
+/// Budget limits per window
+#[derive(Debug, Clone)]
+pub struct BudgetLimits {
+    /// Maximum requests per window (Dataverse: 6000)
+    pub max_requests: u32,
+    /// Maximum combined execution time per window (Dataverse: 1200s)
+    pub max_execution: Duration,
+    /// Percentage of either limit at which requests start being delayed
+    pub soft_limit_percent: u8,
+    /// Whether to delay requests once the soft limit is reached (otherwise only warn)
+    pub enforce: bool,
+}
+
+impl Default for BudgetLimits {
+    fn default() -> Self {
+        Self {
+            max_requests: 6000,
+            max_execution: Duration::from_secs(1200),
+            soft_limit_percent: 90,
+            enforce: true,
+        }
+    }
+}
+
+/// Point-in-time view of budget consumption
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetSnapshot {
+    pub window_seconds: u64,
+    pub requests_in_window: u32,
+    pub max_requests: u32,
+    pub execution_seconds_in_window: f64,
+    pub max_execution_seconds: u64,
+    pub soft_limit_percent: u8,
+    pub total_requests: u64,
+    pub total_throttled: u64,
+    pub total_soft_delays: u64,
+    /// Requests left as last reported by the service
+    pub reported_requests_remaining: Option<u32>,
+    /// Execution time left as last reported by the service
+    pub reported_execution_seconds_remaining: Option<f64>,
+}
+
+/// Remaining budget from the latest response that carried it
+#[derive(Debug, Clone, Copy)]
+struct Reported {
+    requests: Option<u32>,
+    execution: Option<Duration>,
+    at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    /// (completed at, execution time) per request in the window
+    entries: VecDeque<(Instant, Duration)>,
+    execution_in_window: Duration,
+    total_requests: u64,
+    total_throttled: u64,
+    total_soft_delays: u64,
+    reported: Option<Reported>,
+}
+
+impl BudgetState {
+    fn evict(&mut self, now: Instant) {
+        while let Some(&(at, elapsed)) = self.entries.front() {
+            if now.duration_since(at) < WINDOW {
+                break;
+            }
+            self.entries.pop_front();
+            self.execution_in_window = self.execution_in_window.saturating_sub(elapsed);
+        }
+        if self.reported.is_some_and(|r| now.duration_since(r.at) >= WINDOW) {
+            self.reported = None;
+        }
+    }
+
+    /// Pause between requests that spreads the reported remaining budget
+    /// over the window, once it is below `1 - percent` of the limits
+    fn reported_pace(&self, limits: &BudgetLimits, percent: u32) -> Option<Duration> {
+        let reported = self.reported?;
+        let reserve = f64::from(100 - percent) / 100.0;
+        let mut requests_left: Option<f64> = None;
+        if let Some(left) = reported.requests {
+            if f64::from(left) <= f64::from(limits.max_requests) * reserve {
+                requests_left = Some(f64::from(left));
+            }
+        }
+        if let Some(left) = reported.execution {
+            if left.as_secs_f64() <= limits.max_execution.as_secs_f64() * reserve {
+                // Remaining time in requests of the average duration seen so far
+                let average = match self.entries.len() {
+                    0 => Duration::from_secs(1),
+                    n => (self.execution_in_window / n as u32).max(Duration::from_millis(1)),
+                };
+                let left = left.as_secs_f64() / average.as_secs_f64();
+                requests_left = Some(requests_left.map_or(left, |r| r.min(left)));
+            }
+        }
+        requests_left.map(|left| WINDOW.div_f64(left.max(1.0)))
+    }
+}
+
+/// Sliding-window tracker of request count and execution time
+#[derive(Debug)]
+pub struct ServiceProtectionBudget {
+    limits: BudgetLimits,
+    state: Mutex<BudgetState>,
+}
+
+impl ServiceProtectionBudget {
+    pub fn new(limits: BudgetLimits) -> Self {
+        Self {
+            limits,
+            state: Mutex::new(BudgetState::default()),
+        }
+    }
+
+    /// Record a completed request and how long the service took
+    pub fn record(&self, elapsed: Duration) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.evict(now);
+        state.entries.push_back((now, elapsed));
+        state.execution_in_window += elapsed;
+        state.total_requests += 1;
+    }
+
+    /// Record the remaining budget reported on a response
+    pub fn record_reported(&self, requests: Option<u32>, execution: Option<Duration>) {
+        if requests.is_none() && execution.is_none() {
+            return;
+        }
+        self.state.lock().unwrap().reported = Some(Reported {
+            requests,
+            execution,
+            at: Instant::now(),
+        });
+    }
+
+    /// Record that the service throttled a request (429)
+    pub fn record_throttled(&self) {
+        self.state.lock().unwrap().total_throttled += 1;
+    }
+
+    /// How long to wait before the next request to stay under the soft limit.
+    ///
+    /// Returns `None` when there is headroom. Over the soft limit of this
+    /// client's own requests, the delay is the time until enough of the
+    /// oldest entries leave the window; below the soft limit of the budget
+    /// reported by the service, it is the pace that budget allows. The
+    /// longer of the two applies.
+    pub fn soft_limit_delay(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.evict(now);
+
+        let percent = u32::from(self.limits.soft_limit_percent.min(100));
+        let soft_requests = (self.limits.max_requests * percent / 100).max(1) as usize;
+        let soft_execution = self.limits.max_execution * percent / 100;
+
+        let over_requests = state.entries.len() >= soft_requests;
+        let over_execution = state.execution_in_window >= soft_execution;
+        let pace = state.reported_pace(&self.limits, percent);
+        if !over_requests && !over_execution && pace.is_none() {
+            return None;
+        }
+
+        state.total_soft_delays += 1;
+        if !self.limits.enforce {
+            return None;
+        }
+
+        // Wait until the oldest entry expires; one expiry frees one request slot
+        let expiry = (over_requests || over_execution)
+            .then(|| state.entries.front().map(|&(at, _)| WINDOW.saturating_sub(now.duration_since(at))))
+            .flatten();
+        expiry.max(pace).filter(|d| !d.is_zero())
+    }
+
+    /// Whether usage is over the soft limit (for warnings in enforce=false mode)
+    pub fn is_over_soft_limit(&self) -> bool {
+        let snapshot = self.snapshot();
+        let percent = f64::from(self.limits.soft_limit_percent);
+        let requests_pct = f64::from(snapshot.requests_in_window) * 100.0 / f64::from(snapshot.max_requests.max(1));
+        let exec_pct = snapshot.execution_seconds_in_window * 100.0 / (snapshot.max_execution_seconds.max(1) as f64);
+        requests_pct >= percent || exec_pct >= percent
+    }
+
+    /// Current consumption
+    pub fn snapshot(&self) -> BudgetSnapshot {
+        let mut state = self.state.lock().unwrap();
+        state.evict(Instant::now());
+        BudgetSnapshot {
+            window_seconds: WINDOW.as_secs(),
+            requests_in_window: state.entries.len() as u32,
+            max_requests: self.limits.max_requests,
+            execution_seconds_in_window: state.execution_in_window.as_secs_f64(),
+            max_execution_seconds: self.limits.max_execution.as_secs(),
+            soft_limit_percent: self.limits.soft_limit_percent,
+            total_requests: state.total_requests,
+            total_throttled: state.total_throttled,
+            total_soft_delays: state.total_soft_delays,
+            reported_requests_remaining: state.reported.and_then(|r| r.requests),
+            reported_execution_seconds_remaining: state.reported.and_then(|r| r.execution).map(|d| d.as_secs_f64()),
+        }
+    }
+}
+
+/// Parse a remaining-budget header value such as `4,999` or `1,199,961.00`
+pub fn parse_remaining(value: &str) -> Option<f64> {
+    value.trim().replace(',', "").parse().ok().filter(|n: &f64| n.is_finite() && *n >= 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_requests: u32, max_execution_secs: u64) -> BudgetLimits {
+        BudgetLimits {
+            max_requests,
+            max_execution: Duration::from_secs(max_execution_secs),
+            soft_limit_percent: 50,
+            enforce: true,
+        }
+    }
+
+    #[test]
+    fn test_no_delay_with_headroom() {
+        let budget = ServiceProtectionBudget::new(limits(10, 100));
+        budget.record(Duration::from_millis(100));
+        assert!(budget.soft_limit_delay().is_none());
+        assert_eq!(budget.snapshot().requests_in_window, 1);
+    }
+
+    #[test]
+    fn test_delay_when_request_soft_limit_reached() {
+        let budget = ServiceProtectionBudget::new(limits(10, 100));
+        for _ in 0..5 {
+            budget.record(Duration::from_millis(10));
+        }
+        let delay = budget.soft_limit_delay().expect("should delay");
+        assert!(delay <= WINDOW);
+        assert!(budget.is_over_soft_limit());
+        assert_eq!(budget.snapshot().total_soft_delays, 1);
+    }
+
+    #[test]
+    fn test_delay_when_execution_soft_limit_reached() {
+        let budget = ServiceProtectionBudget::new(limits(1000, 10));
+        budget.record(Duration::from_secs(6));
+        assert!(budget.soft_limit_delay().is_some());
+    }
+
+    #[test]
+    fn test_reported_budget_paces_requests() {
+        let budget = ServiceProtectionBudget::new(limits(6000, 1200));
+        budget.record(Duration::from_millis(100));
+        budget.record_reported(Some(5000), Some(Duration::from_secs(1000)));
+        assert!(budget.soft_limit_delay().is_none());
+
+        // Other clients used most of the budget: spread the rest over the window
+        budget.record_reported(Some(100), None);
+        assert_eq!(budget.soft_limit_delay(), Some(Duration::from_secs(3)));
+        budget.record_reported(Some(0), None);
+        assert_eq!(budget.soft_limit_delay(), Some(WINDOW));
+
+        // 10s left at 100ms per request is 100 requests
+        budget.record_reported(None, Some(Duration::from_secs(10)));
+        assert_eq!(budget.soft_limit_delay(), Some(Duration::from_secs(3)));
+        assert_eq!(budget.snapshot().reported_execution_seconds_remaining, Some(10.0));
+    }
+
+    #[test]
+    fn test_parse_remaining() {
+        assert_eq!(parse_remaining("4,999"), Some(4999.0));
+        assert_eq!(parse_remaining(" 1,199,961.00 "), Some(1_199_961.0));
+        assert_eq!(parse_remaining("-1"), None);
+        assert_eq!(parse_remaining("n/a"), None);
+    }
+
+    #[test]
+    fn test_warn_only_mode() {
+        let mut l = limits(2, 100);
+        l.enforce = false;
+        let budget = ServiceProtectionBudget::new(l);
+        budget.record(Duration::from_millis(10));
+        assert!(budget.soft_limit_delay().is_none());
+        assert_eq!(budget.snapshot().total_soft_delays, 1);
+    }
+}
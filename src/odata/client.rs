@@ -3,14 +3,30 @@
 //! HTTP client for Microsoft Dynamics 365 OData APIs
 //! Supports both Dataverse and Finance & Operations endpoints
 
-use crate::auth::AzureAdAuth;
-use crate::config::config::ProductType;
-use reqwest::{Client, Response, StatusCode};
+use crate::auth::{AzureAdAuth, TokenProvider};
+use crate::config::config::{ConnectionSettings, ProductType};
+use crate::odata::expand::ExpandOption;
+use crate::odata::impersonation;
+use crate::odata::key::EntityKey;
+use crate::odata::metadata::{suggest_names, EdmModel, EdmModelBuilder, MetadataSummary};
+use crate::odata::batch::{self, BatchOperation, BatchResponse, MAX_BATCH_REQUESTS};
+use crate::odata::breaker::CircuitBreaker;
+use crate::odata::budget::{self, BudgetLimits, BudgetSnapshot, ServiceProtectionBudget};
+use crate::odata::correlation::{self, RequestIds};
+use crate::odata::cursor::PageCursor;
+use crate::odata::etag_cache::{self, ConditionalCache};
+use crate::odata::partition::{self, Partitioning};
+use crate::odata::time_window::{parse_http_date, parse_utc};
+use futures::StreamExt;
+use crate::odata::script::{self, RecordedRequest};
+use crate::odata::spill::{FetchedRecords, RecordSpill, SpillLimits};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::time::sleep;
 
 /// OData client errors
@@ -33,6 +49,15 @@ pub enum ODataError {
 
     #[error("Not found: {0}")]
     NotFound(String),
+
+    #[error("Conflict (412 Precondition Failed): {0}")]
+    Conflict(String),
+
+    #[error("Endpoint unhealthy: {0}")]
+    Unavailable(String),
+
+    #[error("Spill file error: {0}")]
+    SpillError(#[from] std::io::Error),
 }
 
 /// Query options for OData requests
@@ -40,51 +65,77 @@ pub enum ODataError {
 pub struct QueryOptions {
     pub select: Option<Vec<String>>,
     pub filter: Option<String>,
+    pub apply: Option<String>, // $apply aggregation pipeline
+    pub search: Option<String>, // $search full-text terms (Dataverse)
     pub top: Option<usize>,
     pub skip: Option<usize>,
     pub orderby: Option<String>,
-    pub expand: Option<Vec<String>>,
+    pub expand: Option<Vec<ExpandOption>>,
     pub cross_company: bool, // F&O only
     pub count: bool,         // Include @odata.count in response
+    /// Parameter aliases as (name without `@`, OData literal), e.g. `("p1", "'A & B'")`
+    pub aliases: Vec<(String, String)>,
+    /// Records per server-driven page (`Prefer: odata.maxpagesize`), overriding the client default
+    pub max_page_size: Option<usize>,
+    /// Request a delta link for later change queries (`Prefer: odata.track-changes`)
+    pub track_changes: bool,
 }
 
 impl QueryOptions {
-    /// Build query string from options
+    /// Build query string from options.
+    ///
+    /// Option values are percent-encoded, so `&`, `#`, `+` and non-ASCII
+    /// text in filters cannot break or extend the query string.
     pub fn to_query_string(&self, product: &ProductType) -> String {
         let mut params = Vec::new();
+        let mut push = |name: &str, value: &str| {
+            params.push(format!("{}={}", name, encode_query_value(value)));
+        };
 
         if let Some(ref select) = self.select {
-            params.push(format!("$select={}", select.join(",")));
+            push("$select", &select.join(","));
         }
 
         if let Some(ref filter) = self.filter {
-            params.push(format!("$filter={}", filter));
+            push("$filter", filter);
+        }
+
+        if let Some(ref apply) = self.apply {
+            push("$apply", apply);
+        }
+
+        if let Some(ref search) = self.search {
+            push("$search", search);
         }
 
         if let Some(top) = self.top {
-            params.push(format!("$top={}", top));
+            push("$top", &top.to_string());
         }
 
         if let Some(skip) = self.skip {
-            params.push(format!("$skip={}", skip));
+            push("$skip", &skip.to_string());
         }
 
         if let Some(ref orderby) = self.orderby {
-            params.push(format!("$orderby={}", orderby));
+            push("$orderby", orderby);
         }
 
         if let Some(ref expand) = self.expand {
-            params.push(format!("$expand={}", expand.join(",")));
+            push("$expand", &ExpandOption::join(expand));
         }
 
         // Include count in response
         if self.count {
-            params.push("$count=true".to_string());
+            push("$count", "true");
         }
 
         // F&O specific: cross-company query
         if self.cross_company && *product == ProductType::Finops {
-            params.push("cross-company=true".to_string());
+            push("cross-company", "true");
+        }
+
+        for (name, literal) in &self.aliases {
+            push(&format!("@{}", name.trim_start_matches('@')), literal);
         }
 
         if params.is_empty() {
@@ -95,6 +146,34 @@ impl QueryOptions {
     }
 }
 
+/// Percent-encode a query option value, keeping the characters OData
+/// expressions use literally (`$ ' ( ) , / : ; = @ * !`)
+pub fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            b'$' | b'\'' | b'(' | b')' | b',' | b'/' | b':' | b';' | b'=' | b'@' | b'*' | b'!' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Render a JSON value as an OData literal: strings are quoted with `'`
+/// doubled, numbers, booleans and null are bare
+pub fn odata_literal(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(format!("'{}'", s.replace('\'', "''"))),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Null => Ok("null".to_string()),
+        _ => Err("OData literals must be strings, numbers, booleans or null".to_string()),
+    }
+}
+
 /// OData response with paging support
 #[derive(Debug, Deserialize)]
 pub struct ODataResponse {
@@ -114,6 +193,124 @@ pub struct ODataResponse {
     pub value: Vec<Value>,
 }
 
+/// Send failures worth another attempt: the request may never have reached the service
+fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_request()
+}
+
+/// Random wait between zero and `delay_ms` milliseconds
+fn full_jitter(delay_ms: u64) -> Duration {
+    use ring::rand::SecureRandom;
+
+    let mut bytes = [0u8; 8];
+    let random = match ring::rand::SystemRandom::new().fill(&mut bytes) {
+        Ok(()) => u64::from_le_bytes(bytes),
+        Err(_) => delay_ms / 2,
+    };
+    Duration::from_millis(random % delay_ms.saturating_add(1))
+}
+
+/// HTTP client for the OData endpoint
+fn build_http_client(insecure_ssl: bool, settings: &ConnectionSettings) -> Client {
+    let mut builder = Client::builder()
+        .timeout(settings.request_timeout)
+        .danger_accept_invalid_certs(insecure_ssl)
+        .pool_max_idle_per_host(settings.pool_max_idle_per_host.unwrap_or(usize::MAX));
+    if let Some(timeout) = settings.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = settings.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(timeout);
+    }
+    builder = match settings.http2 {
+        Some(true) => builder.http2_prior_knowledge(),
+        Some(false) => builder.http1_only(),
+        None => builder,
+    };
+    builder.build().unwrap()
+}
+
+/// Only the next link of a page, read without building its records
+#[derive(Deserialize)]
+struct PageLink {
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+/// Deserialize a page body
+fn parse_page(body: &[u8]) -> Result<ODataResponse, ODataError> {
+    serde_json::from_slice(body)
+        .map_err(|e| ODataError::ParseError(format!("Failed to parse OData response: {}", e)))
+}
+
+/// Result of a multi-page fetch that may stop early
+#[derive(Debug, Clone, Default)]
+pub struct PagedFetch {
+    pub records: Vec<Value>,
+    /// `@odata.count` from the first page, if requested
+    pub count: Option<i64>,
+    /// Link to continue fetching from, if more data remains
+    pub next_link: Option<String>,
+    /// True when fetching stopped because the deadline was reached
+    pub partial: bool,
+    /// `@odata.deltaLink` from the last page when change tracking was requested
+    pub delta_link: Option<String>,
+}
+
+/// Rows read from a [`PageCursor`] position
+#[derive(Debug, Default)]
+pub struct PageWindow {
+    pub records: Vec<Value>,
+    /// `@odata.count` when the window starts the query and a count was requested
+    pub count: Option<i64>,
+    /// Where the next window starts, if more rows remain
+    pub next: Option<PageCursor>,
+}
+
+/// Result of creating a record
+#[derive(Debug, Clone, Default)]
+pub struct CreatedEntity {
+    /// `OData-EntityId` / `Location` URL of the new record
+    pub entity_id: Option<String>,
+    /// Key segment of the new record, e.g. `00000000-0000-0000-0000-000000000001`
+    pub key: Option<String>,
+    /// The created record, when the service returned a representation
+    pub record: Option<Value>,
+}
+
+/// Body of an outgoing request
+enum RequestBody<'a> {
+    Json(&'a Value),
+    Raw { content_type: String, text: String },
+}
+
+/// Create/update behavior of an upsert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpsertMode {
+    /// Create the record if missing, otherwise update it
+    #[default]
+    Upsert,
+    /// Only create (`If-None-Match: *`); fails with a conflict if it exists
+    CreateOnly,
+    /// Only update (`If-Match: *`); fails with not found if it is missing
+    UpdateOnly,
+}
+
+/// Result of an upsert
+#[derive(Debug, Clone)]
+pub struct UpsertOutcome {
+    /// True if a new record was created
+    pub created: bool,
+    /// The record, when the service returned a representation
+    pub record: Option<Value>,
+}
+
+/// Highest count Dataverse returns from `/$count` or `$count=true`
+pub const DATAVERSE_COUNT_LIMIT: u64 = 5000;
+
+/// Downloaded pages that may wait for parsing in [`ODataClient::fetch_all_pages`]
+pub const PREFETCH_PAGES: usize = 2;
+
 /// Entity metadata information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EntityInfo {
@@ -126,26 +323,45 @@ pub struct EntityInfo {
 /// OData client for D365 APIs
 #[derive(Debug)]
 pub struct ODataClient {
-    auth: Arc<AzureAdAuth>,
+    auth: Arc<dyn TokenProvider>,
     endpoint: String,
     product: ProductType,
     http_client: Client,
+    insecure_ssl: bool,
     max_retries: u32,
     retry_delay_ms: u64,
+    /// Longest time one request spends waiting between retries
+    retry_deadline: Option<Duration>,
+    budget: ServiceProtectionBudget,
+    breaker: CircuitBreaker,
+    /// Bounds the requests in flight across all callers; `None` is unbounded
+    request_permits: Option<Arc<Semaphore>>,
+    /// `Accept` header for JSON requests
+    accept_json: &'static str,
+    /// Default `Prefer: odata.maxpagesize` for queries
+    page_size: Option<usize>,
+    /// Records [`Self::fetch_all_pages_bounded`] keeps in memory
+    spill_limits: SpillLimits,
+    /// Bodies of single-record GETs and $metadata, revalidated by ETag
+    responses: ConditionalCache<bytes::Bytes>,
+    /// Parsed $metadata, revalidated by ETag
+    models: ConditionalCache<EdmModel>,
+    /// Entity set names from $metadata, once loaded; used to explain 404s
+    entity_sets: RwLock<Arc<Vec<String>>>,
 }
 
 impl ODataClient {
     /// Create a new OData client
     ///
     /// # Arguments
-    /// * `auth` - Azure AD auth helper
+    /// * `auth` - Token source, usually an [`crate::auth::OAuth2Auth`]
     /// * `endpoint` - Service root URL (e.g., "https://org.crm.dynamics.com/api/data/v9.2/")
     /// * `product` - Product type (Dataverse or F&O)
     /// * `max_retries` - Maximum retry attempts for failed requests
     /// * `retry_delay_ms` - Initial delay between retries in milliseconds
     /// * `insecure_ssl` - Skip SSL certificate verification
     pub fn new(
-        auth: Arc<AzureAdAuth>,
+        auth: Arc<dyn TokenProvider>,
         endpoint: String,
         product: ProductType,
         max_retries: u32,
@@ -159,27 +375,230 @@ impl ODataClient {
             format!("{}/", endpoint)
         };
 
-        let http_client = if insecure_ssl {
-            Client::builder()
-                .timeout(Duration::from_secs(120))  // Longer timeout for large $metadata
-                .danger_accept_invalid_certs(true)
-                .build()
-                .unwrap()
-        } else {
-            Client::builder()
-                .timeout(Duration::from_secs(120))  // Longer timeout for large $metadata
-                .build()
-                .unwrap()
-        };
+        let http_client = build_http_client(insecure_ssl, &ConnectionSettings::default());
 
         Self {
             auth,
             endpoint,
             product,
             http_client,
+            insecure_ssl,
             max_retries,
             retry_delay_ms,
+            retry_deadline: None,
+            budget: ServiceProtectionBudget::new(BudgetLimits::default()),
+            breaker: CircuitBreaker::default(),
+            request_permits: None,
+            accept_json: "application/json",
+            page_size: None,
+            spill_limits: SpillLimits::default(),
+            responses: ConditionalCache::new(etag_cache::DEFAULT_CAPACITY),
+            models: ConditionalCache::new(1),
+            entity_sets: RwLock::new(Arc::new(Vec::new())),
+        }
+    }
+
+    /// Record the entity sets of the service, so a 404 for an unknown
+    /// entity set names the closest existing ones
+    pub fn set_entity_sets(&self, names: Vec<String>) {
+        *self.entity_sets.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(names);
+    }
+
+    /// Replace a 404 for an entity set missing from $metadata with
+    /// "did you mean" suggestions; other results pass through
+    fn explain_not_found<T>(&self, entity: &str, result: Result<T, ODataError>) -> Result<T, ODataError> {
+        let Err(ODataError::NotFound(body)) = result else {
+            return result;
+        };
+        let known = Arc::clone(&self.entity_sets.read().unwrap_or_else(|e| e.into_inner()));
+        let name = entity.split(['(', '/', '?']).next().unwrap_or(entity);
+        if known.is_empty() || known.iter().any(|k| k == name) {
+            return Err(ODataError::NotFound(body));
+        }
+        let suggestions = suggest_names(name, known.iter().map(String::as_str), 5);
+        let message = if suggestions.is_empty() {
+            format!("Entity set '{}' does not exist in $metadata. Use list_entities to see entity set names", name)
+        } else {
+            format!(
+                "Entity set '{}' does not exist. Did you mean {}? Entity set names are case-sensitive",
+                name,
+                suggestions.join(" / ")
+            )
+        };
+        Err(ODataError::NotFound(message))
+    }
+
+    /// Use the `[connection]` timeouts, pool, HTTP version and circuit breaker settings
+    pub fn with_connection_settings(mut self, settings: &ConnectionSettings) -> Self {
+        self.http_client = build_http_client(self.insecure_ssl, settings);
+        self.breaker = CircuitBreaker::new(settings.breaker_failures, settings.breaker_cool_down);
+        self.responses = ConditionalCache::new(settings.etag_cache_entries);
+        self.models = ConditionalCache::new(settings.etag_cache_entries.min(1));
+        self
+    }
+
+    /// Ask for server-driven pages of `page_size` records
+    /// (`Prefer: odata.maxpagesize`); 0 keeps the service default
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = (page_size > 0).then_some(page_size);
+        self
+    }
+
+    /// `Prefer` preferences for a query: page size and change tracking
+    fn query_preferences(&self, options: &QueryOptions) -> Vec<String> {
+        let mut prefer: Vec<String> = options
+            .max_page_size
+            .filter(|size| *size > 0)
+            .or(self.page_size)
+            .map(|size| format!("odata.maxpagesize={}", size))
+            .into_iter()
+            .collect();
+        if options.track_changes {
+            prefer.push("odata.track-changes".to_string());
+        }
+        prefer
+    }
+
+    /// Give up retrying a request once its waits would exceed `deadline`;
+    /// `None` retries up to `max_retries` however long the service asks to wait
+    pub fn with_retry_deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.retry_deadline = deadline;
+        self
+    }
+
+    /// Whether waiting `wait` more keeps a request's retries within the retry deadline
+    fn within_retry_deadline(&self, started: Instant, wait: Duration) -> bool {
+        self.retry_deadline.map_or(true, |deadline| started.elapsed() + wait <= deadline)
+    }
+
+    /// Spill [`Self::fetch_all_pages_bounded`] results to disk past `limits`
+    pub fn with_spill_limits(mut self, limits: SpillLimits) -> Self {
+        self.spill_limits = limits;
+        self
+    }
+
+    /// Send at most `max` requests at once, across tool calls, batches and
+    /// partitioned reads; 0 leaves them unbounded
+    pub fn with_max_concurrent_requests(mut self, max: usize) -> Self {
+        self.request_permits = (max > 0).then(|| Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Use custom service-protection budget limits
+    pub fn with_budget_limits(mut self, limits: BudgetLimits) -> Self {
+        self.budget = ServiceProtectionBudget::new(limits);
+        self
+    }
+
+    /// Ask the service to send Int64/Decimal values as strings so large
+    /// amounts are never rounded by a JSON number parser downstream
+    pub fn with_ieee754_compatible(mut self, enabled: bool) -> Self {
+        self.accept_json = if enabled {
+            "application/json;IEEE754Compatible=true"
+        } else {
+            "application/json"
+        };
+        self
+    }
+
+    /// Current service-protection budget consumption
+    pub fn budget_snapshot(&self) -> BudgetSnapshot {
+        self.budget.snapshot()
+    }
+
+    /// Send a request through the circuit breaker and the service-protection budget.
+    ///
+    /// Fails fast while the circuit is open. On Dataverse, waits first if
+    /// usage is over the soft limit, and records the remaining budget the
+    /// response reports. Every request's execution time is recorded for the
+    /// sliding window; 5xx responses, timeouts and connection errors count
+    /// as endpoint failures.
+    async fn send_tracked(&self, request: RequestBuilder) -> Result<Response, ODataError> {
+        if let Err(remaining) = self.breaker.check() {
+            return Err(ODataError::Unavailable(format!(
+                "{} failed repeatedly; requests are paused for another {}s",
+                self.endpoint,
+                remaining.as_secs().max(1)
+            )));
+        }
+
+        // Moved into the response body below, so the slot stays taken until
+        // the body has been read or dropped
+        let permit = match &self.request_permits {
+            Some(permits) => {
+                let permit = match Arc::clone(permits).try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        tracing::debug!("All request slots in use, waiting");
+                        Arc::clone(permits)
+                            .acquire_owned()
+                            .await
+                            .map_err(|e| ODataError::Unavailable(e.to_string()))?
+                    }
+                };
+                Some(permit)
+            }
+            None => None,
+        };
+
+        if self.product == ProductType::Dataverse {
+            if let Some(delay) = self.budget.soft_limit_delay() {
+                tracing::warn!(
+                    "Service protection soft limit reached, delaying request by {:.1}s",
+                    delay.as_secs_f64()
+                );
+                sleep(delay).await;
+            } else if self.budget.is_over_soft_limit() {
+                tracing::warn!("Service protection soft limit reached");
+            }
+        }
+
+        let client_request_id = correlation::new_request_id();
+        let mut request = request;
+        for name in correlation::CLIENT_REQUEST_ID_HEADERS {
+            request = request.header(name, &client_request_id);
+        }
+
+        let started = Instant::now();
+        let mut response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::debug!("Request failed ({}), client-request-id: {}", e, client_request_id);
+                if e.is_timeout() || e.is_connect() {
+                    self.breaker.record_failure();
+                }
+                return Err(e.into());
+            }
+        };
+        self.budget.record(started.elapsed());
+        let ids = RequestIds::new(client_request_id, response.headers());
+        tracing::debug!("{} {} ({})", response.status(), response.url(), ids);
+        response.extensions_mut().insert(ids);
+        if let Some(permit) = permit {
+            response = hold_until_read(response, permit);
+        }
+        if response.status().is_server_error() {
+            self.breaker.record_failure();
+        } else {
+            self.breaker.record_success();
+        }
+        if self.product == ProductType::Dataverse {
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(budget::parse_remaining)
+            };
+            self.budget.record_reported(
+                header(budget::REMAINING_REQUESTS_HEADER).map(|n| n as u32),
+                header(budget::REMAINING_TIME_HEADER).map(|ms| Duration::from_secs_f64(ms / 1000.0)),
+            );
         }
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            self.budget.record_throttled();
+        }
+        Ok(response)
     }
 
     /// Get the resource URL for token acquisition
@@ -187,64 +606,213 @@ impl ODataClient {
         AzureAdAuth::resource_from_endpoint(&self.endpoint)
     }
 
+    /// Impersonation header of the current tool call; Dataverse only
+    fn caller_header(&self) -> Option<(&'static str, String)> {
+        if self.product == ProductType::Dataverse {
+            impersonation::current_header()
+        } else {
+            None
+        }
+    }
+
+    /// Drop the cached token after a 401 and acquire a new one, so a rotated
+    /// secret or a revoked token does not fail the rest of the session
+    async fn reauthenticate(&self, response: &Response) -> Result<String, ODataError> {
+        tracing::warn!(
+            "Token rejected (401{}), acquiring a new one and retrying once",
+            token_rejection(response).map(|r| format!(": {}", r)).unwrap_or_default()
+        );
+        self.auth.clear_cache().await;
+        Ok(self.auth.get_token(&self.resource()).await?)
+    }
+
     /// Execute HTTP request with retry logic
     async fn execute_with_retry(
         &self,
         url: &str,
         token: &str,
     ) -> Result<Response, ODataError> {
+        self.execute_with_retry_prefer(url, token, &[]).await
+    }
+
+    /// Execute HTTP request with retry logic and additional `Prefer` preferences
+    async fn execute_with_retry_prefer(
+        &self,
+        url: &str,
+        token: &str,
+        prefer: &[String],
+    ) -> Result<Response, ODataError> {
+        self.execute_request(Method::GET, url, token, prefer, None, &[]).await
+    }
+
+    /// Execute an HTTP request with retry logic.
+    ///
+    /// 429s are always retried since the service did not process the request;
+    /// server errors are only retried for idempotent methods, so a POST is
+    /// never sent twice. Transport errors (connect failures, resets,
+    /// timeouts) are retried for GETs only, since a write may have been
+    /// applied before the connection broke. A 401 is retried once with a
+    /// newly acquired token. Backoff waits a random time up to the
+    /// exponential delay (full jitter), so concurrent callers do not retry
+    /// in lockstep. Retries stop early when their waits would exceed the
+    /// retry deadline; a 429 then reports the wait the service still asks for.
+    async fn execute_request(
+        &self,
+        method: Method,
+        url: &str,
+        token: &str,
+        prefer: &[String],
+        body: Option<&Value>,
+        headers: &[(&str, String)],
+    ) -> Result<Response, ODataError> {
+        self.execute_request_body(method, url, token, prefer, body.map(RequestBody::Json), headers)
+            .await
+    }
+
+    /// [`Self::execute_request`] for bodies that are not plain JSON (e.g. `$batch`)
+    async fn execute_request_body(
+        &self,
+        method: Method,
+        url: &str,
+        token: &str,
+        prefer: &[String],
+        body: Option<RequestBody<'_>>,
+        headers: &[(&str, String)],
+    ) -> Result<Response, ODataError> {
+        let mut prefer_values = vec!["odata.include-annotations=*".to_string()];
+        prefer_values.extend(prefer.iter().cloned());
+        let prefer_header = prefer_values.join(",");
+        let retry_server_errors = method != Method::POST;
+        let retry_transport_errors = method == Method::GET;
+
+        let mut sent_headers = vec![
+            ("Accept".to_string(), self.accept_json.to_string()),
+            ("OData-MaxVersion".to_string(), "4.0".to_string()),
+            ("OData-Version".to_string(), "4.0".to_string()),
+            ("Prefer".to_string(), prefer_header.clone()),
+        ];
+        sent_headers.extend(headers.iter().map(|(n, v)| (n.to_string(), v.clone())));
+        let caller = self.caller_header();
+        sent_headers.extend(caller.iter().map(|(n, v)| (n.to_string(), v.clone())));
+        let recorded_body = match &body {
+            Some(RequestBody::Json(json)) => Some((*json).clone()),
+            Some(RequestBody::Raw { content_type, text }) => {
+                sent_headers.push(("Content-Type".to_string(), content_type.clone()));
+                Some(Value::String(text.clone()))
+            }
+            None => None,
+        };
+        script::record(RecordedRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: sent_headers,
+            body: recorded_body,
+        });
+
+        let mut token = token.to_string();
+        let mut reauthenticated = false;
         let mut attempt = 0;
         let mut delay = self.retry_delay_ms;
+        let started = Instant::now();
 
         loop {
             attempt += 1;
 
-            let response = self
+            let mut request = self
                 .http_client
-                .get(url)
+                .request(method.clone(), url)
                 .header("Authorization", format!("Bearer {}", token))
-                .header("Accept", "application/json")
+                .header("Accept", self.accept_json)
                 .header("OData-MaxVersion", "4.0")
                 .header("OData-Version", "4.0")
-                .header("Prefer", "odata.include-annotations=*")
-                .send()
-                .await?;
+                .header("Prefer", &prefer_header);
+            for (name, value) in headers.iter().chain(caller.as_ref()) {
+                request = request.header(*name, value);
+            }
+            match &body {
+                Some(RequestBody::Json(json)) => request = request.json(json),
+                Some(RequestBody::Raw { content_type, text }) => {
+                    request = request.header("Content-Type", content_type).body(text.clone())
+                }
+                None => {}
+            }
+            let response = match self.send_tracked(request).await {
+                Ok(response) => response,
+                Err(ODataError::HttpError(e))
+                    if retry_transport_errors
+                        && is_transient(&e)
+                        && attempt < self.max_retries
+                        && self.within_retry_deadline(started, Duration::from_millis(delay)) =>
+                {
+                    tracing::warn!(
+                        "Transport error ({}), attempt {}/{}, retrying...",
+                        e,
+                        attempt,
+                        self.max_retries
+                    );
+                    sleep(full_jitter(delay)).await;
+                    delay *= 2;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
 
             match response.status() {
-                StatusCode::OK | StatusCode::CREATED | StatusCode::NO_CONTENT => {
+                StatusCode::OK
+                | StatusCode::CREATED
+                | StatusCode::ACCEPTED
+                | StatusCode::NO_CONTENT
+                | StatusCode::NOT_MODIFIED => {
                     return Ok(response);
                 }
                 StatusCode::TOO_MANY_REQUESTS => {
-                    // Get Retry-After header if available
-                    let retry_after = response
-                        .headers()
-                        .get("Retry-After")
-                        .and_then(|v| v.to_str().ok())
-                        .and_then(|v| v.parse::<u64>().ok())
-                        .unwrap_or(delay / 1000);
+                    let wait = retry_after(&response).unwrap_or(Duration::from_millis(delay));
+                    let wait_secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
 
                     if attempt >= self.max_retries {
-                        return Err(ODataError::RateLimited(retry_after));
+                        return Err(ODataError::RateLimited(wait_secs));
+                    }
+                    if !self.within_retry_deadline(started, wait) {
+                        tracing::warn!(
+                            "Rate limited (429), Retry-After of {} seconds exceeds the retry deadline",
+                            wait_secs
+                        );
+                        return Err(ODataError::RateLimited(wait_secs));
                     }
 
                     tracing::warn!(
                         "Rate limited (429), attempt {}/{}, retrying after {} seconds",
                         attempt,
                         self.max_retries,
-                        retry_after
+                        wait_secs
                     );
 
-                    sleep(Duration::from_secs(retry_after)).await;
+                    // Frees the request slot while waiting
+                    drop(response);
+                    sleep(wait).await;
                     delay *= 2; // Exponential backoff
                 }
+                // The service did not process the request, so even a POST
+                // is safe to send again
+                StatusCode::UNAUTHORIZED if !reauthenticated => {
+                    token = self.reauthenticate(&response).await?;
+                    reauthenticated = true;
+                    attempt -= 1;
+                }
                 StatusCode::NOT_FOUND => {
                     let body = response.text().await.unwrap_or_default();
                     return Err(ODataError::NotFound(body));
                 }
+                StatusCode::PRECONDITION_FAILED => {
+                    let body = response.text().await.unwrap_or_default();
+                    return Err(ODataError::Conflict(body));
+                }
                 status if status.is_server_error() => {
-                    if attempt >= self.max_retries {
-                        let body = response.text().await.unwrap_or_default();
-                        return Err(ODataError::ServerError(status.as_u16(), body));
+                    if !retry_server_errors
+                        || attempt >= self.max_retries
+                        || !self.within_retry_deadline(started, Duration::from_millis(delay))
+                    {
+                        return Err(server_error(response).await);
                     }
 
                     tracing::warn!(
@@ -254,40 +822,81 @@ impl ODataClient {
                         self.max_retries
                     );
 
-                    sleep(Duration::from_millis(delay)).await;
+                    drop(response);
+                    sleep(full_jitter(delay)).await;
                     delay *= 2;
                 }
-                status => {
-                    let body = response.text().await.unwrap_or_default();
-                    return Err(ODataError::ServerError(status.as_u16(), body));
-                }
+                _ => return Err(server_error(response).await),
             }
         }
     }
 
-    /// Fetch $metadata XML
-    pub async fn fetch_metadata(&self) -> Result<String, ODataError> {
-        let url = format!("{}$metadata", self.endpoint);
-        let token = self.auth.get_token(&self.resource()).await?;
-
-        let response = self
-            .http_client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Accept", "application/xml")
-            .send()
-            .await?;
+    /// Send the $metadata request and check its status; with an ETag it
+    /// is conditional and may answer `304 Not Modified`
+    async fn metadata_response(&self, if_none_match: Option<&str>) -> Result<Response, ODataError> {
+        let url = self.metadata_url();
+        let mut token = self.auth.get_token(&self.resource()).await?;
+
+        let mut headers = vec![("Accept".to_string(), "application/xml".to_string())];
+        headers.extend(if_none_match.map(|etag| ("If-None-Match".to_string(), etag.to_string())));
+        script::record(RecordedRequest {
+            method: "GET".to_string(),
+            url: url.clone(),
+            headers,
+            body: None,
+        });
+        let mut reauthenticated = false;
+        let response = loop {
+            let mut request = self
+                .http_client
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Accept", "application/xml");
+            if let Some(etag) = if_none_match {
+                request = request.header("If-None-Match", etag);
+            }
+            let response = self.send_tracked(request).await?;
+            if response.status() != StatusCode::UNAUTHORIZED || reauthenticated {
+                break response;
+            }
+            token = self.reauthenticate(&response).await?;
+            reauthenticated = true;
+        };
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(ODataError::ServerError(status.as_u16(), body));
+        if !response.status().is_success() && response.status() != StatusCode::NOT_MODIFIED {
+            return Err(server_error(response).await);
         }
 
-        // Get response as bytes to handle large XML and encoding issues
-        let bytes = response.bytes().await.map_err(|e| {
-            ODataError::ParseError(format!("Failed to read metadata bytes: {}", e))
-        })?;
+        Ok(response)
+    }
+
+    fn metadata_url(&self) -> String {
+        format!("{}$metadata", self.endpoint)
+    }
+
+    /// Fetch $metadata XML
+    pub async fn fetch_metadata(&self) -> Result<String, ODataError> {
+        let url = self.metadata_url();
+        let cached = self.responses.get(&url);
+        let response = self.metadata_response(cached.as_ref().map(|c| c.etag.as_str())).await?;
+        let bytes = match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => {
+                tracing::debug!("$metadata not modified, using the cached document");
+                cached.value
+            }
+            _ => {
+                let etag = response_etag(&response);
+                // Get response as bytes to handle large XML and encoding issues
+                let bytes = response.bytes().await.map_err(|e| {
+                    ODataError::ParseError(format!("Failed to read metadata bytes: {}", e))
+                })?;
+                match etag {
+                    Some(etag) => self.responses.insert(&url, &etag, bytes.clone()),
+                    None => self.responses.remove(&url),
+                }
+                bytes
+            }
+        };
 
         // Convert bytes to string, handling potential encoding issues
         let xml = String::from_utf8_lossy(&bytes).to_string();
@@ -295,6 +904,47 @@ impl ODataClient {
         Ok(xml)
     }
 
+    /// Stream $metadata and parse it chunk by chunk into a typed model,
+    /// without buffering the whole document
+    pub async fn fetch_metadata_model(&self) -> Result<EdmModel, ODataError> {
+        let url = self.metadata_url();
+        let cached = self.models.get(&url);
+        let mut response = self.metadata_response(cached.as_ref().map(|c| c.etag.as_str())).await?;
+        if let (StatusCode::NOT_MODIFIED, Some(cached)) = (response.status(), cached) {
+            tracing::info!("$metadata not modified, reusing the parsed model");
+            return Ok(cached.value);
+        }
+        let etag = response_etag(&response);
+        let mut builder = EdmModelBuilder::default();
+        let mut total_bytes = 0usize;
+
+        while let Some(chunk) = response.chunk().await.map_err(|e| {
+            ODataError::ParseError(format!("Failed to read metadata stream: {}", e))
+        })? {
+            total_bytes += chunk.len();
+            builder.feed(&chunk);
+        }
+
+        let model = builder.finish();
+        tracing::info!(
+            "Parsed $metadata ({} bytes): {} entity sets, {} entity types, {} enum types",
+            total_bytes,
+            model.entity_sets.len(),
+            model.entity_types.len(),
+            model.enum_types.len()
+        );
+        match etag {
+            Some(etag) => self.models.insert(&url, &etag, model.clone()),
+            None => self.models.remove(&url),
+        }
+        Ok(model)
+    }
+
+    /// Stream $metadata into a compact summary
+    pub async fn fetch_metadata_summary(&self) -> Result<MetadataSummary, ODataError> {
+        self.fetch_metadata_model().await.map(MetadataSummary::from)
+    }
+
     /// Fetch entity data with paging support
     ///
     /// # Arguments
@@ -307,6 +957,25 @@ impl ODataClient {
         next_link: Option<&str>,
         options: &QueryOptions,
     ) -> Result<ODataResponse, ODataError> {
+        let body = self.fetch_entity_page_body(entity, next_link, options).await?;
+        let odata_response = parse_page(&body)?;
+
+        tracing::debug!(
+            "Fetched {} records, next_link: {:?}",
+            odata_response.value.len(),
+            odata_response.next_link.is_some()
+        );
+
+        Ok(odata_response)
+    }
+
+    /// Raw JSON body of a page, as [`fetch_entity_page`](Self::fetch_entity_page) reads it
+    async fn fetch_entity_page_body(
+        &self,
+        entity: &str,
+        next_link: Option<&str>,
+        options: &QueryOptions,
+    ) -> Result<bytes::Bytes, ODataError> {
         let url = match next_link {
             Some(link) => link.to_string(),
             None => {
@@ -318,66 +987,818 @@ impl ODataClient {
         tracing::debug!("Fetching: {}", url);
 
         let token = self.auth.get_token(&self.resource()).await?;
-        let response = self.execute_with_retry(&url, &token).await?;
-
-        let odata_response: ODataResponse = response.json().await.map_err(|e| {
-            ODataError::ParseError(format!("Failed to parse OData response: {}", e))
-        })?;
-
-        tracing::debug!(
-            "Fetched {} records, next_link: {:?}",
-            odata_response.value.len(),
-            odata_response.next_link.is_some()
-        );
+        let response = self
+            .execute_with_retry_prefer(&url, &token, &self.query_preferences(options))
+            .await;
+        let response = match next_link {
+            Some(_) => response?,
+            None => self.explain_not_found(entity, response)?,
+        };
 
-        Ok(odata_response)
+        Ok(response.bytes().await?)
     }
 
-    /// Fetch all pages for an entity
-    pub async fn fetch_all_pages(
+    /// Fetch a query page using `Prefer: respond-async`.
+    ///
+    /// If the service accepts the request asynchronously (202 + `Location`
+    /// status monitor), the monitor is polled until the result is ready or
+    /// `max_wait` elapses. `on_poll` is called with the poll count and elapsed
+    /// time after every pending poll. A synchronous 200 is returned as-is.
+    pub async fn fetch_entity_page_async<F>(
         &self,
         entity: &str,
         options: &QueryOptions,
-    ) -> Result<Vec<Value>, ODataError> {
-        let mut all_records = Vec::new();
-        let mut next_link: Option<String> = None;
-        let mut page = 0;
+        poll_interval: Duration,
+        max_wait: Duration,
+        mut on_poll: F,
+    ) -> Result<ODataResponse, ODataError>
+    where
+        F: FnMut(u32, Duration),
+    {
+        let url = self.entity_url(entity, options);
+        tracing::debug!("Fetching (respond-async): {}", url);
+
+        let mut prefer = vec!["respond-async".to_string()];
+        prefer.extend(self.query_preferences(options));
+        let token = self.auth.get_token(&self.resource()).await?;
+        let response = self.execute_with_retry_prefer(&url, &token, &prefer).await;
+        let response = self.explain_not_found(entity, response)?;
+
+        if response.status() != StatusCode::ACCEPTED {
+            return parse_odata_response(response).await;
+        }
+
+        let monitor_url = response
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+            .ok_or_else(|| {
+                ODataError::ParseError("202 Accepted without a Location status monitor".to_string())
+            })?;
+        tracing::info!("Request accepted asynchronously, monitor: {}", monitor_url);
+
+        let started = Instant::now();
+        let mut polls = 0u32;
+        let mut wait = retry_after(&response).unwrap_or(poll_interval);
+        // Frees the request slot while polling
+        drop(response);
 
         loop {
-            page += 1;
-            let response = self
-                .fetch_entity_page(entity, next_link.as_deref(), options)
-                .await?;
+            if started.elapsed() + wait > max_wait {
+                return Err(ODataError::ServerError(
+                    408,
+                    format!(
+                        "Async operation still running after {}s; status monitor: {}",
+                        started.elapsed().as_secs(),
+                        monitor_url
+                    ),
+                ));
+            }
+            sleep(wait).await;
+
+            // Token may have been refreshed during a long wait
+            let mut token = self.auth.get_token(&self.resource()).await?;
+            let mut reauthenticated = false;
+            let response = loop {
+                let mut request = self
+                    .http_client
+                    .get(&monitor_url)
+                    .header("Authorization", format!("Bearer {}", token))
+                    .header("Accept", self.accept_json);
+                if let Some((name, value)) = self.caller_header() {
+                    request = request.header(name, value);
+                }
+                let response = self.send_tracked(request).await?;
+                if response.status() != StatusCode::UNAUTHORIZED || reauthenticated {
+                    break response;
+                }
+                token = self.reauthenticate(&response).await?;
+                reauthenticated = true;
+            };
 
-            tracing::info!("Page {}: fetched {} records", page, response.value.len());
+            match response.status() {
+                StatusCode::ACCEPTED => {
+                    polls += 1;
+                    on_poll(polls, started.elapsed());
+                    wait = retry_after(&response).unwrap_or(poll_interval);
+                }
+                status if status.is_success() => {
+                    let is_http_envelope = response
+                        .headers()
+                        .get("Content-Type")
+                        .and_then(|v| v.to_str().ok())
+                        .is_some_and(|ct| ct.starts_with("application/http"));
 
-            all_records.extend(response.value);
+                    if !is_http_envelope {
+                        return parse_odata_response(response).await;
+                    }
 
-            match response.next_link {
-                Some(link) => next_link = Some(link),
-                None => break,
+                    // Result wraps the original response as an HTTP message
+                    let ids = request_ids(&response);
+                    let text = response.text().await?;
+                    let (status, body) = parse_http_envelope(&text)?;
+                    if !(200..300).contains(&status) {
+                        return Err(ODataError::ServerError(status, ids.annotate(&body)));
+                    }
+                    return serde_json::from_str(&body).map_err(|e| {
+                        ODataError::ParseError(format!("Failed to parse OData response: {}", e))
+                    });
+                }
+                _ => return Err(server_error(response).await),
             }
         }
+    }
 
-        tracing::info!("Total records fetched: {}", all_records.len());
-        Ok(all_records)
+    /// Build the full request URL for an entity query
+    pub fn entity_url(&self, entity: &str, options: &QueryOptions) -> String {
+        format!("{}{}{}", self.endpoint, entity, options.to_query_string(&self.product))
     }
 
-    /// Get single entity by key
-    pub async fn get_entity(
+    /// Fetch all pages for an entity.
+    ///
+    /// Pages are downloaded and deserialized in a pipeline: the request for
+    /// the next page starts as soon as its `@odata.nextLink` has been read
+    /// from the body, while the full body is still being parsed on a
+    /// blocking thread. At most [`PREFETCH_PAGES`] bodies wait to be parsed.
+    ///
+    /// Every record is held in memory; see [`Self::fetch_all_pages_bounded`]
+    /// for entities that may not fit.
+    pub async fn fetch_all_pages(
         &self,
         entity: &str,
-        key: &str,
-    ) -> Result<Value, ODataError> {
-        let url = format!("{}{}({})", self.endpoint, entity, key);
-        let token = self.auth.get_token(&self.resource()).await?;
-        let response = self.execute_with_retry(&url, &token).await?;
-
-        let value: Value = response.json().await.map_err(|e| {
-            ODataError::ParseError(format!("Failed to parse entity: {}", e))
-        })?;
-
-        Ok(value)
+        options: &QueryOptions,
+    ) -> Result<Vec<Value>, ODataError> {
+        let spill = RecordSpill::new(entity, SpillLimits::unbounded());
+        Ok(self.collect_pages(entity, options, spill).await?.into_vec()?)
+    }
+
+    /// Fetch all pages for an entity, keeping at most the configured
+    /// `[spill]` limits in memory. Past them, the records are written to a
+    /// JSON Lines file that is returned instead.
+    pub async fn fetch_all_pages_bounded(
+        &self,
+        entity: &str,
+        options: &QueryOptions,
+    ) -> Result<FetchedRecords, ODataError> {
+        let spill = RecordSpill::new(entity, self.spill_limits.clone());
+        self.collect_pages(entity, options, spill).await
+    }
+
+    /// Pipeline of [`Self::fetch_all_pages`], collecting the records into `spill`
+    async fn collect_pages(
+        &self,
+        entity: &str,
+        options: &QueryOptions,
+        spill: RecordSpill,
+    ) -> Result<FetchedRecords, ODataError> {
+        let (pages, mut bodies) = tokio::sync::mpsc::channel::<bytes::Bytes>(PREFETCH_PAGES);
+
+        let download = async move {
+            let mut link: Option<String> = None;
+            loop {
+                let body = self.fetch_entity_page_body(entity, link.as_deref(), options).await?;
+                let next = serde_json::from_slice::<PageLink>(&body)
+                    .map_err(|e| ODataError::ParseError(format!("Failed to parse OData response: {}", e)))?
+                    .next_link;
+                // The parser stopped after an error; it reports that one
+                if pages.send(body).await.is_err() {
+                    return Ok::<_, ODataError>(());
+                }
+                match next {
+                    Some(next) => link = Some(next),
+                    None => return Ok(()),
+                }
+            }
+        };
+
+        let parse = async {
+            let mut spill = spill;
+            let mut page = 0;
+            while let Some(body) = bodies.recv().await {
+                // Parsing and spill file writes both block
+                let (parsed, fetched) = tokio::task::spawn_blocking(move || {
+                    let records = parse_page(&body)?.value;
+                    let fetched = records.len();
+                    spill.push_page(records, body.len())?;
+                    Ok::<_, ODataError>((spill, fetched))
+                })
+                .await
+                .map_err(|e| ODataError::ParseError(format!("Failed to parse OData response: {}", e)))??;
+                spill = parsed;
+                page += 1;
+                tracing::info!("Page {}: fetched {} records", page, fetched);
+            }
+            let records = tokio::task::spawn_blocking(move || spill.finish())
+                .await
+                .map_err(|e| ODataError::ParseError(format!("Failed to finish the spill file: {}", e)))??;
+            Ok::<_, ODataError>(records)
+        };
+
+        let (downloaded, records) = futures::join!(download, parse);
+        downloaded?;
+        let records = records?;
+        tracing::info!("Total records fetched: {}", records.len());
+        Ok(records)
+    }
+
+    /// Follow `@odata.nextLink` until the data, `max_records` or the deadline runs out.
+    ///
+    /// When the deadline is hit the records fetched so far are returned with
+    /// `partial` set and `next_link` pointing at the page that was not completed.
+    ///
+    /// # Arguments
+    /// * `entity` - Entity set name
+    /// * `start_link` - Optional link to resume from (a previous `next_link`)
+    /// * `options` - Query options (ignored when resuming)
+    /// * `max_records` - Stop once this many records have been collected
+    /// * `deadline` - Stop and return partial results at this instant
+    pub async fn fetch_pages(
+        &self,
+        entity: &str,
+        start_link: Option<&str>,
+        options: &QueryOptions,
+        max_records: Option<usize>,
+        deadline: Option<Instant>,
+    ) -> Result<PagedFetch, ODataError> {
+        self.fetch_pages_with(entity, start_link, options, max_records, deadline, |_, _| {})
+            .await
+    }
+
+    /// Like [`fetch_pages`](Self::fetch_pages), calling `on_page` with each
+    /// page's records and the number of records fetched so far, as pages arrive
+    pub async fn fetch_pages_with<F>(
+        &self,
+        entity: &str,
+        start_link: Option<&str>,
+        options: &QueryOptions,
+        max_records: Option<usize>,
+        deadline: Option<Instant>,
+        mut on_page: F,
+    ) -> Result<PagedFetch, ODataError>
+    where
+        F: FnMut(&[Value], usize),
+    {
+        let mut fetched = PagedFetch::default();
+        let mut next_link: Option<String> = start_link.map(String::from);
+        let mut page = 0;
+
+        loop {
+            page += 1;
+            let request = self.fetch_entity_page(entity, next_link.as_deref(), options);
+
+            let response = match deadline {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), request).await {
+                        Ok(result) => result?,
+                        Err(_) => {
+                            tracing::warn!("Deadline reached after {} records", fetched.records.len());
+                            fetched.partial = true;
+                            fetched.next_link =
+                                Some(next_link.unwrap_or_else(|| self.entity_url(entity, options)));
+                            break;
+                        }
+                    }
+                }
+                None => request.await?,
+            };
+
+            tracing::info!("Page {}: fetched {} records", page, response.value.len());
+
+            if page == 1 {
+                fetched.count = response.count;
+            }
+            on_page(&response.value, fetched.records.len() + response.value.len());
+            fetched.records.extend(response.value);
+            fetched.next_link = response.next_link;
+            fetched.delta_link = response.delta_link;
+
+            let limit_reached = max_records.is_some_and(|max| fetched.records.len() >= max);
+            match &fetched.next_link {
+                Some(link) if !limit_reached => next_link = Some(link.clone()),
+                _ => break,
+            }
+        }
+
+        tracing::info!("Total records fetched: {}", fetched.records.len());
+        Ok(fetched)
+    }
+
+    /// Partition filters of `by` for a read of `entity` with `options`.
+    /// Time buckets look up the first and last created timestamp first.
+    pub async fn partition_filters(
+        &self,
+        entity: &str,
+        options: &QueryOptions,
+        by: &Partitioning,
+    ) -> Result<Vec<String>, ODataError> {
+        match by {
+            Partitioning::KeyRange { field, partitions } => Ok(partition::key_range_filters(field, *partitions)),
+            Partitioning::Created { partitions } => {
+                let field = by.field(&self.product);
+                let mut edges = Vec::with_capacity(2);
+                for direction in ["asc", "desc"] {
+                    let edge = QueryOptions {
+                        select: Some(vec![field.clone()]),
+                        filter: Some(partition::with_partition(options.filter.as_deref(), &format!("{} ne null", field))),
+                        orderby: Some(format!("{} {}", field, direction)),
+                        top: Some(1),
+                        cross_company: options.cross_company,
+                        aliases: options.aliases.clone(),
+                        ..Default::default()
+                    };
+                    let response = self.fetch_entity_page(entity, None, &edge).await?;
+                    edges.push(
+                        response
+                            .value
+                            .first()
+                            .and_then(|r| r.get(&field))
+                            .and_then(|v| v.as_str())
+                            .and_then(parse_utc),
+                    );
+                }
+                let range = match (edges[0], edges[1]) {
+                    (Some(first), Some(last)) => Some((first, last)),
+                    _ => None,
+                };
+                Ok(partition::time_bucket_filters(&field, range, *partitions))
+            }
+        }
+    }
+
+    /// Pages of every partition of a read, up to `concurrency` partitions at
+    /// a time. Pages of different partitions arrive interleaved; a failed
+    /// page ends its partition with the error.
+    pub fn partitioned_pages<'a>(
+        &'a self,
+        entity: &'a str,
+        options: &QueryOptions,
+        filters: Vec<String>,
+        concurrency: usize,
+    ) -> impl futures::Stream<Item = Result<ODataResponse, ODataError>> + 'a {
+        let partitions: Vec<QueryOptions> = filters
+            .iter()
+            .map(|filter| QueryOptions {
+                filter: Some(partition::with_partition(options.filter.as_deref(), filter)),
+                ..options.clone()
+            })
+            .collect();
+        futures::stream::iter(partitions)
+            .map(move |options| {
+                Box::pin(futures::stream::try_unfold(Some(None::<String>), move |link| {
+                    let options = options.clone();
+                    async move {
+                        let Some(link) = link else {
+                            return Ok(None);
+                        };
+                        let response = self.fetch_entity_page(entity, link.as_deref(), &options).await?;
+                        let next = response.next_link.clone().map(Some);
+                        Ok(Some((response, next)))
+                    }
+                }))
+            })
+            .flatten_unordered(concurrency.max(1))
+    }
+
+    /// Read every record of `entity`, split by `by` and fetched `concurrency`
+    /// partitions at a time. Records come in no particular order.
+    pub async fn fetch_partitioned(
+        &self,
+        entity: &str,
+        options: &QueryOptions,
+        by: &Partitioning,
+        concurrency: usize,
+    ) -> Result<Vec<Value>, ODataError> {
+        let filters = self.partition_filters(entity, options, by).await?;
+        tracing::info!("Reading {} in {} partitions", entity, filters.len());
+        let mut pages = std::pin::pin!(self.partitioned_pages(entity, options, filters, concurrency));
+        let mut records = Vec::new();
+        while let Some(page) = pages.next().await {
+            records.extend(page?.value);
+        }
+        tracing::info!("Total records fetched: {}", records.len());
+        Ok(records)
+    }
+
+    /// Read up to `limit` records starting at `cursor`, or at the start of
+    /// the query when there is none, following `@odata.nextLink` as needed.
+    ///
+    /// Service pages larger than `limit` are split: the returned cursor points
+    /// into the same page, which is fetched again to continue.
+    pub async fn fetch_page_window(
+        &self,
+        entity: &str,
+        cursor: Option<&PageCursor>,
+        options: &QueryOptions,
+        limit: usize,
+    ) -> Result<PageWindow, ODataError> {
+        let mut window = PageWindow::default();
+        let mut link = match cursor {
+            Some(cursor) => cursor.link.clone(),
+            None => self.entity_url(entity, options),
+        };
+        let mut offset = cursor.map_or(0, |c| c.offset);
+        let mut first = cursor.is_none();
+
+        while window.records.len() < limit {
+            let response = self
+                .fetch_entity_page(entity, if first { None } else { Some(&link) }, options)
+                .await?;
+            if first {
+                window.count = response.count;
+                first = false;
+            }
+
+            let page_len = response.value.len();
+            let wanted = limit - window.records.len();
+            window.records.extend(response.value.into_iter().skip(offset).take(wanted));
+            let consumed = (offset + wanted).min(page_len);
+            if consumed < page_len {
+                window.next = Some(PageCursor::new(entity, &link, consumed));
+                break;
+            }
+            match response.next_link {
+                Some(next) if window.records.len() < limit => {
+                    link = next;
+                    offset = 0;
+                }
+                Some(next) => window.next = Some(PageCursor::new(entity, &next, 0)),
+                None => break,
+            }
+        }
+
+        tracing::info!("Read {} records from {}", window.records.len(), entity);
+        Ok(window)
+    }
+
+    /// Get single entity by key.
+    ///
+    /// A record fetched before is requested with `If-None-Match`, and a
+    /// `304 Not Modified` is served from the cache.
+    pub async fn get_entity(
+        &self,
+        entity: &str,
+        key: &str,
+    ) -> Result<Value, ODataError> {
+        let url = format!("{}{}({})", self.endpoint, entity, key);
+        // Impersonated callers may see different fields
+        let cache_key = match self.caller_header() {
+            Some((_, caller)) => format!("{} {}", caller, url),
+            None => url.clone(),
+        };
+        let cached = self.responses.get(&cache_key);
+        let headers: Vec<(&str, String)> = cached.iter().map(|c| ("If-None-Match", c.etag.clone())).collect();
+
+        let token = self.auth.get_token(&self.resource()).await?;
+        let response = self.execute_request(Method::GET, &url, &token, &[], None, &headers).await;
+        let response = match self.explain_not_found(entity, response) {
+            Ok(response) => response,
+            Err(e) => {
+                self.responses.remove(&cache_key);
+                return Err(e);
+            }
+        };
+
+        let body = match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => {
+                tracing::debug!("{} not modified, using the cached record", url);
+                cached.value
+            }
+            _ => {
+                let etag = response_etag(&response);
+                let body = response.bytes().await?;
+                match etag {
+                    Some(etag) => self.responses.insert(&cache_key, &etag, body.clone()),
+                    None => self.responses.remove(&cache_key),
+                }
+                body
+            }
+        };
+
+        let value: Value = serde_json::from_slice(&body).map_err(|e| {
+            ODataError::ParseError(format!("Failed to parse entity: {}", e))
+        })?;
+
+        Ok(value)
+    }
+
+    /// Count records with `GET <entity>/$count`, optionally filtered.
+    ///
+    /// Dataverse caps this count at [`DATAVERSE_COUNT_LIMIT`].
+    pub async fn count_entities(
+        &self,
+        entity: &str,
+        filter: Option<&str>,
+        cross_company: bool,
+    ) -> Result<u64, ODataError> {
+        let options = QueryOptions {
+            filter: filter.map(String::from),
+            cross_company,
+            ..Default::default()
+        };
+        let url = format!(
+            "{}{}/$count{}",
+            self.endpoint,
+            entity,
+            options.to_query_string(&self.product)
+        );
+        let token = self.auth.get_token(&self.resource()).await?;
+        let response = self.execute_with_retry(&url, &token).await;
+        let text = self.explain_not_found(entity, response)?.text().await?;
+        parse_count(&text)
+    }
+
+    /// Count with `$apply=aggregate($count as count)`, which is not capped
+    /// like `/$count` (but fails beyond Dataverse's aggregate record limit)
+    pub async fn aggregate_count(&self, entity: &str, filter: Option<&str>) -> Result<u64, ODataError> {
+        let options = QueryOptions {
+            apply: Some(match filter {
+                Some(f) => format!("filter({})/aggregate($count as count)", f),
+                None => "aggregate($count as count)".to_string(),
+            }),
+            ..Default::default()
+        };
+        let page = self.fetch_entity_page(entity, None, &options).await?;
+        page.value
+            .first()
+            .and_then(|row| row.get("count"))
+            .and_then(|c| c.as_u64().or_else(|| c.as_str().and_then(|s| s.parse().ok())))
+            .ok_or_else(|| ODataError::ParseError("Aggregate response has no count".to_string()))
+    }
+
+    /// Total row count of a Dataverse table from `RetrieveTotalRecordCount`.
+    ///
+    /// This is a recent snapshot (not filtered, possibly a few hours old) and
+    /// takes the table's logical name, e.g. `account`.
+    pub async fn retrieve_total_record_count(&self, logical_name: &str) -> Result<Option<u64>, ODataError> {
+        let url = format!(
+            "{}RetrieveTotalRecordCount(EntityNames=@p1)?@p1=['{}']",
+            self.endpoint,
+            logical_name.replace('\'', "''")
+        );
+        let token = self.auth.get_token(&self.resource()).await?;
+        let response = self.execute_with_retry(&url, &token).await?;
+        let value: Value = response.json().await.map_err(|e| {
+            ODataError::ParseError(format!("Failed to parse RetrieveTotalRecordCount: {}", e))
+        })?;
+        Ok(value["EntityRecordCountCollection"]["Values"]
+            .get(0)
+            .and_then(|v| v.as_u64()))
+    }
+
+    /// Logical name of a Dataverse entity set, e.g. `account` for `accounts`
+    pub async fn entity_logical_name(&self, entity_set: &str) -> Result<Option<String>, ODataError> {
+        let options = QueryOptions {
+            select: Some(vec!["LogicalName".to_string()]),
+            filter: Some(format!("EntitySetName eq '{}'", entity_set.replace('\'', "''"))),
+            ..Default::default()
+        };
+        let page = self.fetch_entity_page("EntityDefinitions", None, &options).await?;
+        Ok(page
+            .value
+            .first()
+            .and_then(|e| e["LogicalName"].as_str())
+            .map(String::from))
+    }
+
+    /// Create a record with `POST <entity>` and `Prefer: return=representation`
+    pub async fn create_entity(&self, entity: &str, body: &Value) -> Result<CreatedEntity, ODataError> {
+        self.create_entity_expanded(entity, body, &[]).await
+    }
+
+    /// Create a record and related records in one POST (deep insert).
+    ///
+    /// `navigations` are expanded in the returned representation so the keys
+    /// of the new related records come back with the parent.
+    pub async fn create_deep(
+        &self,
+        entity: &str,
+        body: &Value,
+        navigations: &[String],
+    ) -> Result<CreatedEntity, ODataError> {
+        self.create_entity_expanded(entity, body, navigations).await
+    }
+
+    async fn create_entity_expanded(
+        &self,
+        entity: &str,
+        body: &Value,
+        expand: &[String],
+    ) -> Result<CreatedEntity, ODataError> {
+        let mut url = format!("{}{}", self.endpoint, entity);
+        if !expand.is_empty() {
+            url.push_str(&format!("?$expand={}", expand.join(",")));
+        }
+        let token = self.auth.get_token(&self.resource()).await?;
+        let prefer = ["return=representation".to_string()];
+        let response = self
+            .execute_request(Method::POST, &url, &token, &prefer, Some(body), &[])
+            .await?;
+
+        let entity_id = ["OData-EntityId", "Location"].iter().find_map(|name| {
+            response
+                .headers()
+                .get(*name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        });
+        let text = response.text().await?;
+        let record = if text.trim().is_empty() {
+            None
+        } else {
+            Some(serde_json::from_str(&text).map_err(|e| {
+                ODataError::ParseError(format!("Failed to parse created entity: {}", e))
+            })?)
+        };
+
+        Ok(CreatedEntity {
+            key: entity_id.as_deref().and_then(key_from_entity_id),
+            entity_id,
+            record,
+        })
+    }
+
+    /// Update a record with `PATCH <entity>(<key>)`.
+    ///
+    /// Sends `If-Match: <etag>` for optimistic concurrency, or `If-Match: *`
+    /// without one so a missing record is never created by accident. A stale
+    /// ETag fails with [`ODataError::Conflict`]. Returns the updated record
+    /// when the service sends a representation.
+    pub async fn update_entity(
+        &self,
+        entity: &str,
+        key: &str,
+        body: &Value,
+        etag: Option<&str>,
+    ) -> Result<Option<Value>, ODataError> {
+        let url = format!("{}{}({})", self.endpoint, entity, key);
+        let token = self.auth.get_token(&self.resource()).await?;
+        let prefer = ["return=representation".to_string()];
+        let headers = [("If-Match", etag.unwrap_or("*").to_string())];
+        let response = self
+            .execute_request(Method::PATCH, &url, &token, &prefer, Some(body), &headers)
+            .await?;
+
+        let text = response.text().await?;
+        if text.trim().is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| ODataError::ParseError(format!("Failed to parse updated entity: {}", e)))
+    }
+
+    /// Upsert a record with `PATCH <entity>(<key>)`, typically addressed by
+    /// alternate keys (see [`alternate_key_segment`])
+    pub async fn upsert_entity(
+        &self,
+        entity: &str,
+        key: &str,
+        body: &Value,
+        mode: UpsertMode,
+    ) -> Result<UpsertOutcome, ODataError> {
+        let url = format!("{}{}({})", self.endpoint, entity, key);
+        let token = self.auth.get_token(&self.resource()).await?;
+        let prefer = ["return=representation".to_string()];
+        let headers: Vec<(&str, String)> = match mode {
+            UpsertMode::Upsert => vec![],
+            UpsertMode::CreateOnly => vec![("If-None-Match", "*".to_string())],
+            UpsertMode::UpdateOnly => vec![("If-Match", "*".to_string())],
+        };
+        let response = self
+            .execute_request(Method::PATCH, &url, &token, &prefer, Some(body), &headers)
+            .await?;
+
+        let created = response.status() == StatusCode::CREATED;
+        let text = response.text().await?;
+        let record = if text.trim().is_empty() {
+            None
+        } else {
+            Some(serde_json::from_str(&text).map_err(|e| {
+                ODataError::ParseError(format!("Failed to parse upserted entity: {}", e))
+            })?)
+        };
+        Ok(UpsertOutcome { created, record })
+    }
+
+    /// Send sub-requests in one `$batch` round trip.
+    ///
+    /// Returns one response per operation, in order; a failed sub-request is
+    /// reported in its own response rather than failing the whole call.
+    pub async fn execute_batch(&self, operations: &[BatchOperation]) -> Result<Vec<BatchResponse>, ODataError> {
+        if operations.len() > MAX_BATCH_REQUESTS {
+            return Err(ODataError::ParseError(format!(
+                "A batch holds at most {} requests, got {}",
+                MAX_BATCH_REQUESTS,
+                operations.len()
+            )));
+        }
+        let boundary = batch::new_boundary("batch");
+        let body = RequestBody::Raw {
+            content_type: format!("multipart/mixed; boundary={}", boundary),
+            text: batch::build_batch(&boundary, operations).map_err(ODataError::ParseError)?,
+        };
+        self.send_batch(body).await
+    }
+
+    /// Send write operations as one `$batch` changeset, applied atomically.
+    ///
+    /// On failure the service rolls back every operation; the failing one is
+    /// identified by its `Content-ID` (1-based operation index).
+    pub async fn execute_changeset(&self, operations: &[BatchOperation]) -> Result<Vec<BatchResponse>, ODataError> {
+        if operations.len() > MAX_BATCH_REQUESTS {
+            return Err(ODataError::ParseError(format!(
+                "A changeset holds at most {} operations, got {}",
+                MAX_BATCH_REQUESTS,
+                operations.len()
+            )));
+        }
+        let boundary = batch::new_boundary("batch");
+        let changeset = batch::new_boundary("changeset");
+        let body = RequestBody::Raw {
+            content_type: format!("multipart/mixed; boundary={}", boundary),
+            text: batch::build_changeset_batch(&boundary, &changeset, operations).map_err(ODataError::ParseError)?,
+        };
+        self.send_batch(body).await
+    }
+
+    async fn send_batch(&self, body: RequestBody<'_>) -> Result<Vec<BatchResponse>, ODataError> {
+        let url = format!("{}$batch", self.endpoint);
+        let token = self.auth.get_token(&self.resource()).await?;
+        let response = self
+            .execute_request_body(Method::POST, &url, &token, &[], Some(body), &[])
+            .await?;
+
+        let content_type = response
+            .headers()
+            .get("Content-Type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let text = response.text().await?;
+        batch::parse_batch_response(&content_type, &text).map_err(ODataError::ParseError)
+    }
+
+    /// Delete a record with `DELETE <entity>(<key>)`.
+    ///
+    /// Sends `If-Match: <etag>` when given, otherwise `If-Match: *`.
+    pub async fn delete_entity(&self, entity: &str, key: &str, etag: Option<&str>) -> Result<(), ODataError> {
+        let url = format!("{}{}({})", self.endpoint, entity, key);
+        let token = self.auth.get_token(&self.resource()).await?;
+        let headers = [("If-Match", etag.unwrap_or("*").to_string())];
+        self.execute_request(Method::DELETE, &url, &token, &[], None, &headers)
+            .await?;
+        Ok(())
+    }
+
+    /// Remove a relationship with `DELETE .../<nav>/$ref`.
+    ///
+    /// Without `related_key` this clears a single-valued navigation property
+    /// (lookup); with it, the related record is removed from a
+    /// collection-valued (N:N or 1:N) navigation property via
+    /// `DELETE <entity>(<key>)/<nav>(<related_key>)/$ref`.
+    pub async fn disassociate(
+        &self,
+        entity: &str,
+        key: &str,
+        navigation_property: &str,
+        related_key: Option<&str>,
+    ) -> Result<(), ODataError> {
+        let mut url = self.navigation_url(entity, key, navigation_property);
+        if let Some(related_key) = related_key {
+            url.push_str(&format!("({})", related_key));
+        }
+        url.push_str("/$ref");
+        let token = self.auth.get_token(&self.resource()).await?;
+        self.execute_request(Method::DELETE, &url, &token, &[], None, &[]).await?;
+        Ok(())
+    }
+
+    /// Add a related record to a collection-valued navigation property with
+    /// `POST <entity>(<key>)/<nav>/$ref` and `{"@odata.id": <related record URL>}`
+    pub async fn associate(
+        &self,
+        entity: &str,
+        key: &str,
+        navigation_property: &str,
+        related_entity: &str,
+        related_key: &str,
+    ) -> Result<(), ODataError> {
+        let url = format!("{}/$ref", self.navigation_url(entity, key, navigation_property));
+        let body = serde_json::json!({ "@odata.id": self.record_url(related_entity, related_key) });
+        let token = self.auth.get_token(&self.resource()).await?;
+        self.execute_request(Method::POST, &url, &token, &[], Some(&body), &[]).await?;
+        Ok(())
+    }
+
+    /// URL of a record, e.g. `.../accounts(<key>)`
+    pub fn record_url(&self, entity: &str, key: &str) -> String {
+        format!("{}{}({})", self.endpoint, entity, key)
+    }
+
+    /// URL of a record's navigation property, e.g. `.../accounts(<key>)/contact_customer_accounts`
+    pub fn navigation_url(&self, entity: &str, key: &str, navigation_property: &str) -> String {
+        format!("{}/{}", self.record_url(entity, key), navigation_property)
     }
 
     /// Get endpoint URL
@@ -385,6 +1806,11 @@ impl ODataClient {
         &self.endpoint
     }
 
+    /// Token audience for this endpoint, e.g. `https://org.crm.dynamics.com`
+    pub fn resource_url(&self) -> String {
+        self.resource()
+    }
+
     /// Get product type
     pub fn product(&self) -> &ProductType {
         &self.product
@@ -392,18 +1818,16 @@ impl ODataClient {
 
     /// Parse $metadata XML to extract entity information for a specific entity
     /// Returns: (properties, navigation_properties, key_fields)
+    #[allow(clippy::type_complexity)]
     pub fn parse_entity_from_metadata(
         metadata_xml: &str,
         entity_name: &str,
     ) -> Result<(Vec<String>, Vec<String>, Vec<String>), ODataError> {
-        use std::collections::HashSet;
-
         let mut properties = Vec::new();
         let mut nav_properties = Vec::new();
         let mut key_fields = Vec::new();
         let mut in_entity = false;
         let mut in_key = false;
-        let mut entity_type_name = String::new();
 
         // Simple XML parsing for entity properties
         for line in metadata_xml.lines() {
@@ -412,7 +1836,6 @@ impl ODataClient {
             // Look for EntityType definition
             if trimmed.contains("<EntityType ") && trimmed.contains(&format!("Name=\"{}\"", entity_name)) {
                 in_entity = true;
-                entity_type_name = entity_name.to_string();
             }
             // Also check for EntityType that matches without exact name (for partial matches)
             if !in_entity && trimmed.contains("<EntityType ") {
@@ -423,7 +1846,6 @@ impl ODataClient {
                         // Match entity name at start (e.g., "CustomersV3" matches "CustomersV3Type")
                         if name.starts_with(entity_name) || entity_name.starts_with(name) {
                             in_entity = true;
-                            entity_type_name = name.to_string();
                         }
                     }
                 }
@@ -454,16 +1876,10 @@ impl ODataClient {
                         if let Some(end) = trimmed[name_start..].find('"') {
                             let name = &trimmed[name_start..name_start + end];
                             // Get type if available
-                            let prop_type = if let Some(type_start) = trimmed.find("Type=\"") {
+                            let prop_type = trimmed.find("Type=\"").and_then(|type_start| {
                                 let ts = type_start + 6;
-                                if let Some(te) = trimmed[ts..].find('"') {
-                                    Some(trimmed[ts..ts + te].to_string())
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            };
+                                trimmed[ts..].find('"').map(|te| trimmed[ts..ts + te].to_string())
+                            });
 
                             let prop_str = match prop_type {
                                 Some(t) => format!("{}: {}", name, t.replace("Edm.", "")),
@@ -481,16 +1897,10 @@ impl ODataClient {
                         if let Some(end) = trimmed[name_start..].find('"') {
                             let name = &trimmed[name_start..name_start + end];
                             // Get type/target if available
-                            let nav_type = if let Some(type_start) = trimmed.find("Type=\"") {
+                            let nav_type = trimmed.find("Type=\"").and_then(|type_start| {
                                 let ts = type_start + 6;
-                                if let Some(te) = trimmed[ts..].find('"') {
-                                    Some(trimmed[ts..ts + te].to_string())
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            };
+                                trimmed[ts..].find('"').map(|te| trimmed[ts..ts + te].to_string())
+                            });
 
                             let nav_str = match nav_type {
                                 Some(t) => {
@@ -499,7 +1909,7 @@ impl ODataClient {
                                         .replace("Collection(", "")
                                         .replace(")", "")
                                         .split('.')
-                                        .last()
+                                        .next_back()
                                         .unwrap_or(&t)
                                         .to_string();
                                     if t.contains("Collection") {
@@ -536,9 +1946,187 @@ impl ODataClient {
     }
 }
 
+/// Parse a JSON OData response body
+async fn parse_odata_response(response: Response) -> Result<ODataResponse, ODataError> {
+    response.json().await.map_err(|e| {
+        ODataError::ParseError(format!("Failed to parse OData response: {}", e))
+    })
+}
+
+/// `ETag` header of a response, if present
+fn response_etag(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get("ETag")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// `response` with `permit` moved into its body.
+///
+/// `bytes()`, `text()` and `json()` take the body out of the response and
+/// drop its extensions, so the permit has to travel with the body itself.
+fn hold_until_read(response: Response, permit: OwnedSemaphorePermit) -> Response {
+    use http_body_util::BodyExt;
+    use reqwest::ResponseBuilderExt;
+
+    let url = response.url().clone();
+    let (parts, body) = hyper::Response::<reqwest::Body>::from(response).into_parts();
+    let body = reqwest::Body::wrap(body.map_frame(move |frame| {
+        let _held = &permit;
+        frame
+    }));
+    let mut builder = hyper::Response::builder()
+        .status(parts.status)
+        .version(parts.version)
+        .url(url);
+    if let Some(headers) = builder.headers_mut() {
+        *headers = parts.headers;
+    }
+    if let Some(extensions) = builder.extensions_mut() {
+        extensions.extend(parts.extensions);
+    }
+    builder
+        .body(body)
+        .map(Response::from)
+        .expect("parts of a received response are valid")
+}
+
+/// IDs [`ODataClient::send_tracked`] recorded for a response
+fn request_ids(response: &Response) -> RequestIds {
+    response.extensions().get::<RequestIds>().cloned().unwrap_or_default()
+}
+
+/// `ServerError` of a failed response, naming its request IDs for support cases
+async fn server_error(response: Response) -> ODataError {
+    let status = response.status().as_u16();
+    let ids = request_ids(&response);
+    let body = response.text().await.unwrap_or_default();
+    ODataError::ServerError(status, ids.annotate(&body))
+}
+
+/// Wait asked for by the `Retry-After` header, if present
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get("Retry-After")?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+/// `Retry-After` value as a wait from `now`: delay seconds or an HTTP-date
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = parse_http_date(value)?;
+    Some(at.duration_since(now).unwrap_or_default())
+}
+
+/// Reason the service gives for rejecting the token, from `WWW-Authenticate`
+fn token_rejection(response: &Response) -> Option<String> {
+    let header = response.headers().get("WWW-Authenticate")?.to_str().ok()?;
+    bearer_error(header)
+}
+
+/// `error_description` (or `error`) of a `Bearer` challenge, e.g.
+/// `Bearer error="invalid_token", error_description="The token is expired"`
+fn bearer_error(challenge: &str) -> Option<String> {
+    let param = |name: &str| {
+        let start = challenge.find(&format!("{}=\"", name))? + name.len() + 2;
+        let len = challenge[start..].find('"')?;
+        Some(challenge[start..start + len].to_string())
+    };
+    param("error_description").or_else(|| param("error"))
+}
+
+/// Key segment for a record ID typed by a user, e.g. in `accounts(<key>)`.
+///
+/// See [`EntityKey`]: GUIDs and numbers are used bare, strings are quoted with
+/// `'` doubled, and composite keys (`a='x',b=1`) have each part escaped.
+/// Malformed composite keys are passed through unchanged.
+pub fn key_segment(id: &str) -> String {
+    EntityKey::parse(id)
+        .map(|key| key.to_string())
+        .unwrap_or_else(|_| id.trim().to_string())
+}
+
+/// Parse a plain-text `/$count` body (may start with a byte order mark)
+fn parse_count(text: &str) -> Result<u64, ODataError> {
+    let trimmed = text.trim_start_matches('\u{feff}').trim();
+    trimmed
+        .parse()
+        .map_err(|_| ODataError::ParseError(format!("Invalid $count response: {}", trimmed)))
+}
+
+/// Whether `text` is a GUID such as `00000000-0000-0000-0000-000000000000`
+pub fn is_guid(text: &str) -> bool {
+    text.len() == 36
+        && text.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Build an alternate-key segment, e.g. `accountnumber='A-1',address1_postalcode='98052'`.
+///
+/// Strings are quoted with `'` doubled; numbers and booleans are bare.
+pub fn alternate_key_segment(keys: &serde_json::Map<String, Value>) -> Result<String, String> {
+    if keys.is_empty() {
+        return Err("At least one alternate key is required".to_string());
+    }
+    keys.iter()
+        .map(|(name, value)| {
+            if value.is_null() {
+                return Err(format!("Alternate key '{}' must be a string, number or boolean", name));
+            }
+            let literal = odata_literal(value)
+                .map_err(|_| format!("Alternate key '{}' must be a string, number or boolean", name))?;
+            Ok(format!("{}={}", name, literal))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|parts| parts.join(","))
+}
+
+/// Key segment from an entity URL: `.../accounts(<key>)` gives `<key>`
+pub fn key_from_entity_id(url: &str) -> Option<String> {
+    let url = url.trim_end_matches('/');
+    let open = url.rfind('(')?;
+    url.ends_with(')')
+        .then(|| url[open + 1..url.len() - 1].to_string())
+}
+
+/// Split an `application/http` envelope into status code and body
+fn parse_http_envelope(text: &str) -> Result<(u16, String), ODataError> {
+    let status = text
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| ODataError::ParseError("Invalid application/http status line".to_string()))?;
+
+    let body = text
+        .split_once("\r\n\r\n")
+        .or_else(|| text.split_once("\n\n"))
+        .map(|(_, body)| body.trim().to_string())
+        .unwrap_or_default();
+
+    Ok((status, body))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::StaticToken;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_bearer_error() {
+        assert_eq!(
+            bearer_error(r#"Bearer error="invalid_token", error_description="The token is expired""#).as_deref(),
+            Some("The token is expired")
+        );
+        assert_eq!(bearer_error(r#"Bearer error="invalid_token""#).as_deref(), Some("invalid_token"));
+        assert_eq!(bearer_error(r#"Bearer authorization_uri="https://login.microsoftonline.com/x""#), None);
+    }
 
     #[test]
     fn test_query_options_empty() {
@@ -551,18 +2139,300 @@ mod tests {
         let options = QueryOptions {
             select: Some(vec!["name".to_string(), "email".to_string()]),
             filter: Some("status eq 'active'".to_string()),
+            apply: None,
+            search: None,
             top: Some(10),
             skip: None,
             orderby: Some("name asc".to_string()),
-            expand: None,
+            expand: Some(vec![ExpandOption {
+                top: Some(5),
+                ..ExpandOption::new("contacts")
+            }]),
             cross_company: false,
+            count: false,
+            aliases: Vec::new(),
+            max_page_size: None,
+            track_changes: false,
         };
 
         let query = options.to_query_string(&ProductType::Dataverse);
+        assert!(query.contains("$expand=contacts($top=5)"));
         assert!(query.contains("$select=name,email"));
-        assert!(query.contains("$filter=status eq 'active'"));
+        assert!(query.contains("$filter=status%20eq%20'active'"));
         assert!(query.contains("$top=10"));
-        assert!(query.contains("$orderby=name asc"));
+        assert!(query.contains("$orderby=name%20asc"));
+    }
+
+    #[test]
+    fn test_query_options_search() {
+        let options = QueryOptions {
+            search: Some("contoso".to_string()),
+            top: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(options.to_query_string(&ProductType::Dataverse), "?$search=contoso&$top=5");
+    }
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("\u{feff}1234\r\n").unwrap(), 1234);
+        assert!(parse_count("{\"error\":{}}").is_err());
+    }
+
+    #[test]
+    fn test_full_jitter() {
+        for _ in 0..100 {
+            assert!(full_jitter(1000) <= Duration::from_millis(1000));
+        }
+        assert_eq!(full_jitter(0), Duration::ZERO);
+        let distinct: std::collections::HashSet<_> = (0..20).map(|_| full_jitter(1_000_000)).collect();
+        assert!(distinct.len() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_requests() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (in_flight_server, peak_server) = (Arc::clone(&in_flight), Arc::clone(&peak));
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (in_flight, peak) = (Arc::clone(&in_flight_server), Arc::clone(&peak_server));
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    let response = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}";
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+
+        let auth = Arc::new(StaticToken::new("token"));
+        let client = ODataClient::new(auth, format!("http://{}/", addr), ProductType::Finops, 1, 1, false)
+            .with_max_concurrent_requests(2);
+        let keys: Vec<String> = (0..6).map(|i| i.to_string()).collect();
+        let results = futures::future::join_all(keys.iter().map(|key| client.get_entity("Customers", key))).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_requests_held_until_body_read() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sending = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (sending_server, peak_server) = (Arc::clone(&sending), Arc::clone(&peak));
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (sending, peak) = (Arc::clone(&sending_server), Arc::clone(&peak_server));
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    // Headers go out at once; the body follows later
+                    let headers = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n";
+                    stream.write_all(headers.as_bytes()).await.unwrap();
+                    stream.flush().await.unwrap();
+                    let now = sending.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    sending.fetch_sub(1, Ordering::SeqCst);
+                    stream.write_all(b"{}").await.unwrap();
+                });
+            }
+        });
+
+        let auth = Arc::new(StaticToken::new("token"));
+        let client = ODataClient::new(auth, format!("http://{}/", addr), ProductType::Finops, 1, 1, false)
+            .with_max_concurrent_requests(1);
+        let keys: Vec<String> = (0..4).map(|i| i.to_string()).collect();
+        let results = futures::future::join_all(keys.iter().map(|key| client.get_entity("Customers", key))).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = UNIX_EPOCH + Duration::from_secs(784_111_700);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now),
+            Some(Duration::from_secs(77))
+        );
+        let later = now + Duration::from_secs(600);
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", later), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_page_link_and_body() {
+        let body = br#"{"@odata.context":"x","value":[{"n":1},{"n":2.50}],"@odata.nextLink":"https://org/next"}"#;
+        let link: PageLink = serde_json::from_slice(body).unwrap();
+        assert_eq!(link.next_link.as_deref(), Some("https://org/next"));
+        let page = parse_page(body).unwrap();
+        assert_eq!(page.value.len(), 2);
+        assert_eq!(page.value[1]["n"].to_string(), "2.50");
+        assert!(parse_page(b"<html>").is_err());
+    }
+
+    #[test]
+    fn test_query_values_are_encoded() {
+        let options = QueryOptions {
+            filter: Some("name eq 'A & B #1+2' or name eq 'Zoë'".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.to_query_string(&ProductType::Dataverse),
+            "?$filter=name%20eq%20'A%20%26%20B%20%231%2B2'%20or%20name%20eq%20'Zo%C3%AB'"
+        );
+    }
+
+    #[test]
+    fn test_parameter_aliases() {
+        let options = QueryOptions {
+            filter: Some("name eq @p1 and revenue gt @p2".to_string()),
+            aliases: vec![
+                ("p1".to_string(), odata_literal(&serde_json::json!("O'Brien & Co")).unwrap()),
+                ("@p2".to_string(), odata_literal(&serde_json::json!(1000)).unwrap()),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            options.to_query_string(&ProductType::Dataverse),
+            "?$filter=name%20eq%20@p1%20and%20revenue%20gt%20@p2&@p1='O''Brien%20%26%20Co'&@p2=1000"
+        );
+    }
+
+    #[test]
+    fn test_query_preferences() {
+        let auth = Arc::new(StaticToken::new("token"));
+        let client = ODataClient::new(auth, "https://org/".to_string(), ProductType::Dataverse, 1, 1, false)
+            .with_page_size(500);
+        assert_eq!(client.query_preferences(&QueryOptions::default()), vec!["odata.maxpagesize=500"]);
+
+        let options = QueryOptions {
+            max_page_size: Some(50),
+            ..Default::default()
+        };
+        assert_eq!(client.query_preferences(&options), vec!["odata.maxpagesize=50"]);
+
+        let options = QueryOptions {
+            track_changes: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            client.query_preferences(&options),
+            vec!["odata.maxpagesize=500", "odata.track-changes"]
+        );
+        assert!(client.with_page_size(0).query_preferences(&QueryOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_explain_not_found() {
+        let auth = Arc::new(StaticToken::new("token"));
+        let client = ODataClient::new(auth, "https://org/".to_string(), ProductType::Finops, 1, 1, false);
+        let not_found = || Err::<(), _>(ODataError::NotFound("raw body".to_string()));
+
+        // Without metadata the service's answer is kept
+        assert!(matches!(client.explain_not_found("Customers", not_found()), Err(ODataError::NotFound(b)) if b == "raw body"));
+
+        client.set_entity_sets(vec!["CustomersV3".to_string(), "CustCustomersV3".to_string()]);
+        match client.explain_not_found("Customers", not_found()) {
+            Err(ODataError::NotFound(message)) => {
+                assert!(message.contains("Did you mean CustomersV3 / CustCustomersV3?"), "{}", message)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // A known entity set: the record is what is missing
+        assert!(matches!(client.explain_not_found("CustomersV3(1)", not_found()), Err(ODataError::NotFound(b)) if b == "raw body"));
+        assert!(client.explain_not_found("Customers", Ok(())).is_ok());
+    }
+
+    #[test]
+    fn test_query_options_apply() {
+        let options = QueryOptions {
+            apply: Some("groupby((statecode),aggregate($count as count))".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.to_query_string(&ProductType::Dataverse),
+            "?$apply=groupby((statecode),aggregate($count%20as%20count))"
+        );
+    }
+
+    #[test]
+    fn test_key_segment() {
+        assert_eq!(key_segment("7a1b2c3d-0000-4000-8000-00000000000A"), "7a1b2c3d-0000-4000-8000-00000000000A");
+        assert_eq!(key_segment("5637144577"), "5637144577");
+        assert_eq!(key_segment("US-001"), "'US-001'");
+        assert_eq!(key_segment("O'Brien"), "'O''Brien'");
+        assert_eq!(key_segment("'C1'"), "'C1'");
+        assert_eq!(key_segment("dataAreaId='usmf',CustomerAccount='C1'"), "dataAreaId='usmf',CustomerAccount='C1'");
+    }
+
+    #[test]
+    fn test_alternate_key_segment() {
+        let keys = serde_json::json!({ "accountnumber": "O'Neil-1", "address1_postalcode": 98052 });
+        assert_eq!(
+            alternate_key_segment(keys.as_object().unwrap()).unwrap(),
+            "accountnumber='O''Neil-1',address1_postalcode=98052"
+        );
+        assert!(alternate_key_segment(&serde_json::Map::new()).is_err());
+        let bad = serde_json::json!({ "k": null });
+        assert!(alternate_key_segment(bad.as_object().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_key_from_entity_id() {
+        assert_eq!(
+            key_from_entity_id("https://org.crm.dynamics.com/api/data/v9.2/accounts(7a1b2c3d-0000-0000-0000-000000000001)").as_deref(),
+            Some("7a1b2c3d-0000-0000-0000-000000000001")
+        );
+        assert_eq!(
+            key_from_entity_id("https://fo.example.com/data/CustomersV3(dataAreaId='usmf',CustomerAccount='C1')").as_deref(),
+            Some("dataAreaId='usmf',CustomerAccount='C1'")
+        );
+        assert_eq!(key_from_entity_id("https://org/api/data/v9.2/accounts"), None);
+    }
+
+    #[test]
+    fn test_large_numbers_round_trip_exactly() {
+        let body = r#"{"AmountCur":12345678901234567.89,"RecId":5637144577123456789}"#;
+        let value: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(serde_json::to_string(&value).unwrap(), body);
+    }
+
+    #[test]
+    fn test_parse_http_envelope() {
+        let text = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{\"value\":[]}";
+        let (status, body) = parse_http_envelope(text).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, "{\"value\":[]}");
+
+        let (status, _) = parse_http_envelope("HTTP/1.1 500 Internal Server Error\n\n{}").unwrap();
+        assert_eq!(status, 500);
+        assert!(parse_http_envelope("garbage").is_err());
     }
 
     #[test]
@@ -0,0 +1,107 @@
+//! Request correlation IDs
+//!
+//! Every request carries a fresh UUID in `client-request-id` (Dataverse) and
+//! `x-ms-client-request-id` (F&O and Azure front ends). The service answers
+//! with its own request or activity ID. Microsoft support asks for both IDs
+//! to find a request in their logs, so failures name them.
+
+use reqwest::header::HeaderMap;
+use std::fmt;
+
+/// Request headers carrying the client request ID
+pub const CLIENT_REQUEST_ID_HEADERS: [&str; 2] = ["client-request-id", "x-ms-client-request-id"];
+
+/// Response headers carrying the service's ID of a request, in order of preference
+const SERVICE_REQUEST_ID_HEADERS: [&str; 4] = ["x-ms-service-request-id", "req_id", "x-ms-activity-id", "ms-dyn-aid"];
+
+/// IDs of one request, kept in the response's extensions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestIds {
+    /// ID sent in `client-request-id`
+    pub client: String,
+    /// ID the service reported, if any
+    pub service: Option<String>,
+}
+
+impl RequestIds {
+    /// IDs of a request sent as `client`, given the response headers
+    pub fn new(client: String, headers: &HeaderMap) -> Self {
+        let service = SERVICE_REQUEST_ID_HEADERS.iter().find_map(|name| {
+            headers
+                .get(*name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(String::from)
+        });
+        Self { client, service }
+    }
+
+    /// `message` followed by the IDs, for errors passed on to users
+    pub fn annotate(&self, message: &str) -> String {
+        if message.is_empty() {
+            format!("[{}]", self)
+        } else {
+            format!("{} [{}]", message, self)
+        }
+    }
+}
+
+impl fmt::Display for RequestIds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client-request-id: {}", self.client)?;
+        if let Some(service) = &self.service {
+            write!(f, ", service request id: {}", service)?;
+        }
+        Ok(())
+    }
+}
+
+/// Random (version 4) UUID, e.g. `1b4e28ba-2fa1-4d2b-883f-0016d3cca427`
+pub fn new_request_id() -> String {
+    use ring::rand::SecureRandom;
+
+    let mut bytes = [0u8; 16];
+    ring::rand::SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_new_request_id_is_uuid_v4() {
+        let id = new_request_id();
+        let groups: Vec<&str> = id.split('-').collect();
+        assert_eq!(groups.iter().map(|g| g.len()).collect::<Vec<_>>(), [8, 4, 4, 4, 12]);
+        assert!(groups[2].starts_with('4'));
+        assert!(matches!(&groups[3][..1], "8" | "9" | "a" | "b"));
+        assert_ne!(id, new_request_id());
+    }
+
+    #[test]
+    fn test_service_id_and_annotation() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ms-activity-id", HeaderValue::from_static("act-1"));
+        let ids = RequestIds::new("c-1".to_string(), &headers);
+        assert_eq!(ids.service.as_deref(), Some("act-1"));
+
+        headers.insert("x-ms-service-request-id", HeaderValue::from_static("svc-1"));
+        let ids = RequestIds::new("c-1".to_string(), &headers);
+        assert_eq!(
+            ids.annotate("Internal error"),
+            "Internal error [client-request-id: c-1, service request id: svc-1]"
+        );
+        assert_eq!(
+            RequestIds::new("c-2".to_string(), &HeaderMap::new()).annotate(""),
+            "[client-request-id: c-2]"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! Opaque cursors for reading a result set page by page
+//!
+//! A cursor names a position in a query result: the URL of a service page
+//! (the first query URL or an `@odata.nextLink`) and how many rows of that
+//! page were already returned. It travels as URL-safe base64 of JSON so
+//! clients pass it back unchanged instead of editing the link.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Position of the next unread row of a query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageCursor {
+    /// Entity set being read
+    pub entity: String,
+    /// Service page holding the next row
+    pub link: String,
+    /// Rows of that page already returned
+    #[serde(default)]
+    pub offset: usize,
+}
+
+impl PageCursor {
+    pub fn new(entity: &str, link: &str, offset: usize) -> Self {
+        Self {
+            entity: entity.to_string(),
+            link: link.to_string(),
+            offset,
+        }
+    }
+
+    /// Opaque text form handed to clients
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    /// Parse a cursor from [`encode`](Self::encode)
+    pub fn decode(text: &str) -> Result<Self, String> {
+        let invalid = || "Invalid cursor: pass the cursor from a previous query_page result unchanged".to_string();
+        let json = URL_SAFE_NO_PAD.decode(text.trim()).map_err(|_| invalid())?;
+        serde_json::from_slice(&json).map_err(|_| invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = PageCursor::new("accounts", "https://org/api/data/v9.2/accounts?$skiptoken=%3Ccookie%3E", 20);
+        let text = cursor.encode();
+        assert!(text.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(PageCursor::decode(&text).unwrap(), cursor);
+    }
+
+    #[test]
+    fn test_cursor_rejects_edited_text() {
+        assert!(PageCursor::decode("https://org/api/data/v9.2/accounts").is_err());
+        assert!(PageCursor::decode(&URL_SAFE_NO_PAD.encode("{\"link\":1}")).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Knowledge base of well-known D365 / OData errors
+//!
+//! Maps error codes and message fragments found in service responses to
+//! short, actionable hints for the agent.
+
+/// A known error pattern and the hint to show for it
+struct ErrorHint {
+    /// Case-insensitive fragment searched for in the error text
+    pattern: &'static str,
+    hint: &'static str,
+}
+
+const HINTS: &[ErrorHint] = &[
+    ErrorHint {
+        pattern: "0x80040265",
+        hint: "A plug-in or custom business logic rejected the operation. The message text comes from that code; fix the data it complains about or ask the owner of the customization.",
+    },
+    ErrorHint {
+        pattern: "0x80060891",
+        hint: "The entity set name in the URL was not found. Names are case-sensitive and Dataverse uses the plural EntitySet name (e.g. 'accounts'); check list_entities.",
+    },
+    ErrorHint {
+        pattern: "0x80040217",
+        hint: "The record does not exist (or was deleted). Verify the key and that you are querying the right entity set.",
+    },
+    ErrorHint {
+        pattern: "0x80040220",
+        hint: "The application user is missing a security privilege for this table/operation. Assign a security role that grants it.",
+    },
+    ErrorHint {
+        pattern: "0x80040237",
+        hint: "A record with the same key or duplicate-detection value already exists. Query for it first or update it instead of creating.",
+    },
+    ErrorHint {
+        pattern: "0x80072322",
+        hint: "Service protection limit hit: too many requests in the 5-minute window. Wait for Retry-After, batch requests, or reduce concurrency.",
+    },
+    ErrorHint {
+        pattern: "0x80072321",
+        hint: "Service protection limit hit: combined execution time exceeded in the 5-minute window. Simplify queries ($select fewer columns, narrower $filter) or spread them out.",
+    },
+    ErrorHint {
+        pattern: "0x80072326",
+        hint: "Service protection limit hit: too many concurrent requests. Lower the 'concurrency' setting.",
+    },
+    ErrorHint {
+        pattern: "Endpoint unhealthy",
+        hint: "The D365 environment kept failing (5xx or timeouts), so requests are paused briefly instead of adding load. Wait and retry; check the environment's health if it persists.",
+    },
+    ErrorHint {
+        pattern: "Could not find a property named",
+        hint: "A field in $select/$filter/$orderby does not exist. Property names are case-sensitive and lookups use _name_value; check get_metadata for exact names.",
+    },
+    ErrorHint {
+        pattern: "Ledger dimension",
+        hint: "F&O ledger dimension validation failed. Supply the dimension as a display value in the configured integration format (e.g. '110110-001-022') and make sure each segment value is active for the legal entity.",
+    },
+    ErrorHint {
+        pattern: "financial dimension",
+        hint: "F&O financial dimension validation failed. Check that the dimension values exist and are not suspended for this legal entity (dataAreaId).",
+    },
+    ErrorHint {
+        pattern: "No resources were found when selecting for update",
+        hint: "F&O could not find the record to update. Include dataAreaId in the key and check cross-company settings.",
+    },
+    ErrorHint {
+        pattern: "Write failed for table row",
+        hint: "F&O table validation rejected the write. The nested messages name the failing field; mandatory fields and number sequences are common causes.",
+    },
+    ErrorHint {
+        pattern: "412 Precondition Failed",
+        hint: "The record changed since it was read (ETag mismatch). Re-read it with get_record and retry with its current @odata.etag.",
+    },
+    ErrorHint {
+        pattern: "(401)",
+        hint: "Authentication failed. Check TENANT_ID/CLIENT_ID/CLIENT_SECRET and that the app user exists in the environment.",
+    },
+    ErrorHint {
+        pattern: "(403)",
+        hint: "Access denied. The app user exists but lacks permission; check its security roles.",
+    },
+];
+
+/// Hints matching the given error text, in knowledge-base order
+pub fn hints_for(error_text: &str) -> Vec<&'static str> {
+    let lower = error_text.to_lowercase();
+    HINTS
+        .iter()
+        .filter(|h| lower.contains(&h.pattern.to_lowercase()))
+        .map(|h| h.hint)
+        .collect()
+}
+
+/// Append matching hints to an error message
+pub fn with_hints(error_text: &str) -> String {
+    let hints = hints_for(error_text);
+    if hints.is_empty() {
+        return error_text.to_string();
+    }
+
+    let mut text = error_text.to_string();
+    text.push_str("\n\nHint:");
+    for hint in hints {
+        text.push_str(&format!("\n- {}", hint));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_protection_hint() {
+        let error = r#"Server error (429): {"error":{"code":"0x80072322","message":"Number of requests exceeded the limit"}}"#;
+        let hints = hints_for(error);
+        assert_eq!(hints.len(), 1);
+        assert!(hints[0].contains("too many requests"));
+    }
+
+    #[test]
+    fn test_with_hints_appends_and_passes_through() {
+        assert_eq!(with_hints("Error: something else"), "Error: something else");
+
+        let text = with_hints("Error: Not found: {\"code\":\"0x80060891\"}");
+        assert!(text.starts_with("Error: Not found"));
+        assert!(text.contains("\n\nHint:\n- The entity set name"));
+    }
+
+    #[test]
+    fn test_message_fragment_is_case_insensitive() {
+        assert_eq!(hints_for("LEDGER DIMENSION is not valid").len(), 1);
+    }
+}
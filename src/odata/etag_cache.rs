@@ -0,0 +1,138 @@
+//! ETag-validated cache of GET responses
+//!
+//! Agents look up the same reference records again and again within a
+//! session. Responses that carry an `ETag` are kept here by URL; the next
+//! GET sends `If-None-Match`, and a `304 Not Modified` is answered from the
+//! cache without transferring the body again. The service still decides
+//! whether the value is current, so a hit is never stale.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Default responses kept
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// A cached value and the ETag it was returned with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cached<T> {
+    pub etag: String,
+    pub value: T,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    cached: Cached<T>,
+    /// Order of last use, for evicting the least recently used entry
+    used: u64,
+}
+
+/// Cache of values by key (usually the request URL), validated by ETag
+#[derive(Debug)]
+pub struct ConditionalCache<T> {
+    /// Entries kept; 0 disables the cache
+    capacity: usize,
+    entries: Mutex<HashMap<String, Entry<T>>>,
+    clock: AtomicU64,
+}
+
+impl<T: Clone> ConditionalCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Cached value of `key`, to revalidate with `If-None-Match`
+    pub fn get(&self, key: &str) -> Option<Cached<T>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?;
+        entry.used = self.clock.fetch_add(1, Ordering::Relaxed);
+        Some(entry.cached.clone())
+    }
+
+    /// Remember `value` of `key` with its ETag, evicting the least recently used entry when full
+    pub fn insert(&self, key: &str, etag: &str, value: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(key) && entries.len() >= self.capacity {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key.to_string(),
+            Entry {
+                cached: Cached {
+                    etag: etag.to_string(),
+                    value,
+                },
+                used: self.clock.fetch_add(1, Ordering::Relaxed),
+            },
+        );
+    }
+
+    /// Forget `key`, e.g. after the record was changed or deleted
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_and_replace() {
+        let cache = ConditionalCache::new(4);
+        assert!(cache.get("a").is_none());
+        cache.insert("a", "W/\"1\"", "one".to_string());
+        assert_eq!(
+            cache.get("a"),
+            Some(Cached {
+                etag: "W/\"1\"".to_string(),
+                value: "one".to_string()
+            })
+        );
+        cache.insert("a", "W/\"2\"", "two".to_string());
+        assert_eq!(cache.get("a").unwrap().etag, "W/\"2\"");
+        assert_eq!(cache.len(), 1);
+        cache.remove("a");
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = ConditionalCache::new(2);
+        cache.insert("a", "1", 1);
+        cache.insert("b", "2", 2);
+        cache.get("a");
+        cache.insert("c", "3", 3);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn test_capacity_zero_disables() {
+        let cache = ConditionalCache::new(0);
+        cache.insert("a", "1", 1);
+        assert!(cache.get("a").is_none());
+    }
+}
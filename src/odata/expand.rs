@@ -0,0 +1,256 @@
+//! Structured `$expand` options
+//!
+//! `$expand` items may carry their own query options, e.g.
+//! `contact_customer_accounts($select=fullname;$filter=statecode eq 0;$top=5)`.
+//! [`ExpandOption`] models one item so nested options are rendered with the
+//! right separators instead of being concatenated by hand.
+
+use serde_json::Value;
+use std::fmt;
+
+/// One `$expand` item with its nested query options
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExpandOption {
+    /// Navigation property to expand
+    pub property: String,
+    pub select: Option<Vec<String>>,
+    pub filter: Option<String>,
+    pub orderby: Option<String>,
+    pub top: Option<usize>,
+    /// Nested expands of the related records
+    pub expand: Vec<ExpandOption>,
+}
+
+impl ExpandOption {
+    /// Expand a navigation property without nested options
+    pub fn new(property: impl Into<String>) -> Self {
+        Self {
+            property: property.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Parse `$expand` text, e.g. `primarycontactid,contacts($select=fullname;$top=5)`
+    pub fn parse_list(text: &str) -> Result<Vec<Self>, String> {
+        split_top_level(text, ',')?
+            .into_iter()
+            .filter(|item| !item.is_empty())
+            .map(Self::parse_item)
+            .collect()
+    }
+
+    fn parse_item(text: &str) -> Result<Self, String> {
+        let (property, options) = match text.split_once('(') {
+            Some((property, rest)) => {
+                let inner = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("Unbalanced parentheses in $expand item '{}'", text))?;
+                (property.trim(), Some(inner))
+            }
+            None => (text.trim(), None),
+        };
+        if property.is_empty() {
+            return Err(format!("Missing navigation property in $expand item '{}'", text));
+        }
+
+        let mut option = Self::new(property);
+        for part in split_top_level(options.unwrap_or_default(), ';')? {
+            if part.is_empty() {
+                continue;
+            }
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid option '{}' in $expand of {}", part, property))?;
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "$select" => option.select = Some(split_fields(value)),
+                "$filter" => option.filter = Some(value.to_string()),
+                "$orderby" => option.orderby = Some(value.to_string()),
+                "$top" => {
+                    option.top = Some(value.parse().map_err(|_| {
+                        format!("Invalid $top '{}' in $expand of {}", value, property)
+                    })?)
+                }
+                "$expand" => option.expand = Self::parse_list(value)?,
+                other => {
+                    return Err(format!(
+                        "Unsupported option '{}' in $expand of {}: use $select, $filter, $orderby, $top or $expand",
+                        other, property
+                    ))
+                }
+            }
+        }
+        Ok(option)
+    }
+
+    /// Read the JSON form: a string (same syntax as [`Self::parse_list`]), an
+    /// object `{"property", "select", "filter", "orderby", "top", "expand"}`,
+    /// or an array of either
+    pub fn from_json(value: &Value) -> Result<Vec<Self>, String> {
+        match value {
+            Value::String(text) => Self::parse_list(text),
+            Value::Array(items) => items.iter().try_fold(Vec::new(), |mut all, item| {
+                all.extend(Self::from_json(item)?);
+                Ok(all)
+            }),
+            Value::Object(fields) => {
+                let property = fields
+                    .get("property")
+                    .and_then(|v| v.as_str())
+                    .filter(|p| !p.trim().is_empty())
+                    .ok_or("Each expand object needs a 'property'")?;
+                let text = |name: &str| fields.get(name).and_then(|v| v.as_str()).map(String::from);
+                let select = match fields.get("select") {
+                    Some(Value::String(s)) => Some(split_fields(s)),
+                    Some(Value::Array(items)) => Some(
+                        items
+                            .iter()
+                            .filter_map(|v| v.as_str().map(String::from))
+                            .collect(),
+                    ),
+                    _ => None,
+                };
+                let top = match fields.get("top") {
+                    Some(v) => Some(
+                        v.as_u64()
+                            .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+                            .ok_or_else(|| format!("Invalid top in expand of {}", property))?
+                            as usize,
+                    ),
+                    None => None,
+                };
+                Ok(vec![Self {
+                    property: property.trim().to_string(),
+                    select,
+                    filter: text("filter"),
+                    orderby: text("orderby"),
+                    top,
+                    expand: match fields.get("expand") {
+                        Some(nested) => Self::from_json(nested)?,
+                        None => Vec::new(),
+                    },
+                }])
+            }
+            _ => Err("'expand' must be a string, an object or an array".to_string()),
+        }
+    }
+
+    /// Render a list as the value of `$expand`
+    pub fn join(options: &[Self]) -> String {
+        options
+            .iter()
+            .map(|o| o.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl fmt::Display for ExpandOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = Vec::new();
+        if let Some(select) = &self.select {
+            options.push(format!("$select={}", select.join(",")));
+        }
+        if let Some(filter) = &self.filter {
+            options.push(format!("$filter={}", filter));
+        }
+        if let Some(orderby) = &self.orderby {
+            options.push(format!("$orderby={}", orderby));
+        }
+        if let Some(top) = self.top {
+            options.push(format!("$top={}", top));
+        }
+        if !self.expand.is_empty() {
+            options.push(format!("$expand={}", Self::join(&self.expand)));
+        }
+
+        if options.is_empty() {
+            write!(f, "{}", self.property)
+        } else {
+            write!(f, "{}({})", self.property, options.join(";"))
+        }
+    }
+}
+
+fn split_fields(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+/// Split on `separator` outside parentheses and string literals
+fn split_top_level(text: &str, separator: char) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let (mut depth, mut in_string, mut start) = (0i32, false, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '\'' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => {
+                depth -= 1;
+                if depth < 0 {
+                    return Err(format!("Unbalanced parentheses in '{}'", text));
+                }
+            }
+            c if c == separator && depth == 0 && !in_string => {
+                parts.push(text[start..i].trim());
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(format!("Unbalanced parentheses in '{}'", text));
+    }
+    parts.push(text[start..].trim());
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_nested_options() {
+        let option = ExpandOption {
+            property: "contact_customer_accounts".to_string(),
+            select: Some(vec!["fullname".to_string(), "emailaddress1".to_string()]),
+            top: Some(5),
+            expand: vec![ExpandOption::new("owninguser")],
+            ..Default::default()
+        };
+        assert_eq!(
+            option.to_string(),
+            "contact_customer_accounts($select=fullname,emailaddress1;$top=5;$expand=owninguser)"
+        );
+    }
+
+    #[test]
+    fn test_parse_list_round_trip() {
+        let text = "primarycontactid,contacts($select=fullname;$filter=name eq 'a;b(c)';$expand=owninguser($select=fullname))";
+        let options = ExpandOption::parse_list(text).unwrap();
+        assert_eq!(options.len(), 2);
+        assert_eq!(options[1].filter.as_deref(), Some("name eq 'a;b(c)'"));
+        assert_eq!(options[1].expand[0].select, Some(vec!["fullname".to_string()]));
+        assert_eq!(ExpandOption::join(&options), text);
+
+        assert!(ExpandOption::parse_list("contacts($select=a").is_err());
+        assert!(ExpandOption::parse_list("contacts($skip=1)").is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let value = json!([
+            "primarycontactid",
+            { "property": "contacts", "select": ["fullname"], "top": "3",
+              "expand": { "property": "owninguser", "select": "fullname" } }
+        ]);
+        assert_eq!(
+            ExpandOption::join(&ExpandOption::from_json(&value).unwrap()),
+            "primarycontactid,contacts($select=fullname;$top=3;$expand=owninguser($select=fullname))"
+        );
+        assert!(ExpandOption::from_json(&json!({ "select": "a" })).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Dataverse impersonation
+//!
+//! Requests sent while an [`as_caller`] scope is active carry the caller
+//! header, so Dataverse runs them with that user's security roles and records
+//! them as that user in audit history. The service account needs the
+//! "Act on Behalf of Another User" privilege. F&O has no equivalent.
+
+use std::future::Future;
+
+/// Argument accepted by every tool on Dataverse
+pub const IMPERSONATE_USER_ARG: &str = "impersonate_user";
+
+/// User a request runs as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    /// Azure AD object ID (`CallerObjectId`)
+    ObjectId(String),
+    /// Dataverse `systemuserid` (`MSCRMCallerID`)
+    SystemUser(String),
+}
+
+impl Caller {
+    /// `<object id>`, `aad:<object id>` or `systemuser:<systemuserid>`
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (kind, id) = match text.split_once(':') {
+            Some((kind, id)) => (kind.to_ascii_lowercase(), id.trim()),
+            None => ("aad".to_string(), text),
+        };
+        let id = id.trim_start_matches('{').trim_end_matches('}');
+        if !crate::odata::client::is_guid(id) {
+            return Err(format!(
+                "impersonate_user '{}' must be an Azure AD object ID or systemuser:<systemuserid> (GUIDs)",
+                text
+            ));
+        }
+        match kind.as_str() {
+            "aad" | "objectid" => Ok(Caller::ObjectId(id.to_string())),
+            "systemuser" | "systemuserid" => Ok(Caller::SystemUser(id.to_string())),
+            _ => Err(format!(
+                "Unknown impersonate_user prefix '{}'; use aad:<object id> or systemuser:<systemuserid>",
+                kind
+            )),
+        }
+    }
+
+    /// Header name and value
+    pub fn header(&self) -> (&'static str, String) {
+        match self {
+            Caller::ObjectId(id) => ("CallerObjectId", id.clone()),
+            Caller::SystemUser(id) => ("MSCRMCallerID", id.clone()),
+        }
+    }
+}
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+/// Run `future` with its requests sent as `caller`
+pub async fn as_caller<F: Future>(caller: Option<Caller>, future: F) -> F::Output {
+    match caller {
+        Some(caller) => CALLER.scope(caller, future).await,
+        None => future.await,
+    }
+}
+
+/// Caller header of the active scope, if any
+pub(crate) fn current_header() -> Option<(&'static str, String)> {
+    CALLER.try_with(Caller::header).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "9c0a7b7e-1f7b-4c3e-9a57-5b2d0f1d8e11";
+
+    #[test]
+    fn test_parse_caller() {
+        assert_eq!(Caller::parse(ID).unwrap(), Caller::ObjectId(ID.to_string()));
+        assert_eq!(
+            Caller::parse(&format!("systemuser:{{{}}}", ID)).unwrap().header(),
+            ("MSCRMCallerID", ID.to_string())
+        );
+        assert!(Caller::parse("someone@contoso.com").is_err());
+        assert!(Caller::parse(&format!("team:{}", ID)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_as_caller_scope() {
+        assert_eq!(current_header(), None);
+        let header = as_caller(Some(Caller::ObjectId(ID.to_string())), async { current_header() }).await;
+        assert_eq!(header, Some(("CallerObjectId", ID.to_string())));
+        assert_eq!(as_caller(None, async { current_header() }).await, None);
+    }
+}
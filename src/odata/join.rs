@@ -0,0 +1,132 @@
+//! Client-side joins of two record sets
+//!
+//! For entities that cannot be related with `$expand` (cross-entity F&O
+//! joins, unrelated tables), records are matched on key columns locally.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Join semantics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinType {
+    /// Only left rows with at least one match
+    Inner,
+    /// Every left row; right columns are absent when there is no match
+    Left,
+}
+
+impl JoinType {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "inner" => Ok(JoinType::Inner),
+            "left" => Ok(JoinType::Left),
+            other => Err(format!("Invalid join_type '{}': expected 'inner' or 'left'", other)),
+        }
+    }
+}
+
+/// Join `left` and `right` where every `left_keys[i]` equals `right_keys[i]`.
+///
+/// Right columns are added to each left row under `right_prefix`. A left row
+/// matching several right rows yields one output row per match. Rows with a
+/// null or missing key never match. String keys compare case-insensitively
+/// so GUIDs in different casing still join.
+pub fn join_records(
+    left: &[Value],
+    right: &[Value],
+    left_keys: &[String],
+    right_keys: &[String],
+    join_type: JoinType,
+    right_prefix: &str,
+) -> Vec<Value> {
+    let mut index: HashMap<Vec<String>, Vec<&Value>> = HashMap::new();
+    for record in right {
+        if let Some(key) = composite_key(record, right_keys) {
+            index.entry(key).or_default().push(record);
+        }
+    }
+
+    let mut joined = Vec::new();
+    for record in left {
+        let matches = composite_key(record, left_keys).and_then(|key| index.get(&key));
+        match matches {
+            Some(matches) => {
+                for matched in matches {
+                    joined.push(merge(record, Some(matched), right_prefix));
+                }
+            }
+            None if join_type == JoinType::Left => joined.push(merge(record, None, right_prefix)),
+            None => {}
+        }
+    }
+    joined
+}
+
+fn composite_key(record: &Value, keys: &[String]) -> Option<Vec<String>> {
+    keys.iter()
+        .map(|k| match record.get(k)? {
+            Value::Null => None,
+            Value::String(s) => Some(s.to_lowercase()),
+            other => Some(other.to_string()),
+        })
+        .collect()
+}
+
+fn merge(left: &Value, right: Option<&Value>, right_prefix: &str) -> Value {
+    let mut row: Map<String, Value> = left.as_object().cloned().unwrap_or_default();
+    if let Some(Value::Object(right)) = right {
+        for (name, value) in right {
+            if !name.starts_with("@odata.") {
+                row.insert(format!("{}{}", right_prefix, name), value.clone());
+            }
+        }
+    }
+    Value::Object(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn keys(k: &str) -> Vec<String> {
+        vec![k.to_string()]
+    }
+
+    #[test]
+    fn test_inner_and_left_join() {
+        let orders = vec![
+            json!({ "SalesId": "S1", "CustAccount": "C1" }),
+            json!({ "SalesId": "S2", "CustAccount": "C9" }),
+            json!({ "SalesId": "S3", "CustAccount": null }),
+        ];
+        let customers = vec![json!({ "CustomerAccount": "c1", "Name": "Contoso", "@odata.etag": "x" })];
+
+        let inner = join_records(&orders, &customers, &keys("CustAccount"), &keys("CustomerAccount"), JoinType::Inner, "cust.");
+        assert_eq!(inner, vec![json!({ "SalesId": "S1", "CustAccount": "C1", "cust.CustomerAccount": "c1", "cust.Name": "Contoso" })]);
+
+        let left = join_records(&orders, &customers, &keys("CustAccount"), &keys("CustomerAccount"), JoinType::Left, "cust.");
+        assert_eq!(left.len(), 3);
+        assert_eq!(left[1], json!({ "SalesId": "S2", "CustAccount": "C9" }));
+    }
+
+    #[test]
+    fn test_composite_key_and_multiple_matches() {
+        let left = vec![json!({ "a": 1, "b": "x" })];
+        let right = vec![
+            json!({ "a": 1, "b": "X", "n": 1 }),
+            json!({ "a": 1, "b": "x", "n": 2 }),
+            json!({ "a": 2, "b": "x", "n": 3 }),
+        ];
+        let pair = vec!["a".to_string(), "b".to_string()];
+        let joined = join_records(&left, &right, &pair, &pair, JoinType::Inner, "r.");
+        assert_eq!(joined.len(), 2);
+        assert_eq!(joined[1]["r.n"], 2);
+    }
+
+    #[test]
+    fn test_join_type_parse() {
+        assert_eq!(JoinType::parse("LEFT").unwrap(), JoinType::Left);
+        assert!(JoinType::parse("outer").is_err());
+    }
+}
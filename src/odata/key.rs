@@ -0,0 +1,219 @@
+//! Entity keys
+//!
+//! Renders the key segment of `entity(<key>)` URLs: GUIDs and numbers bare,
+//! strings quoted with `'` doubled, and F&O composite keys such as
+//! `dataAreaId='usmf',ItemNumber='A0001'` with every part escaped.
+
+use crate::odata::client::{encode_query_value, is_guid};
+use serde_json::Value;
+use std::fmt;
+
+/// Key of a single record
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntityKey {
+    Guid(String),
+    Number(String),
+    /// String key, stored unescaped
+    String(String),
+    /// Literal passed through as written, e.g. `true` or an F&O enum value
+    /// `Microsoft.Dynamics.DataEntities.NoYes'Yes'`
+    Literal(String),
+    /// Named key parts, e.g. `dataAreaId='usmf',ItemNumber='A0001'`
+    Composite(Vec<(String, EntityKey)>),
+}
+
+impl EntityKey {
+    /// Parse key text as a user would type it: `<guid>`, `42`, `ABC`,
+    /// `'O''Brien'` or `dataAreaId='usmf',ItemNumber='A0001'`
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err("Key is empty".to_string());
+        }
+        if let Some(value) = unquote(text) {
+            return Ok(EntityKey::String(value));
+        }
+        if has_top_level_equals(text) {
+            return split_parts(text)?
+                .into_iter()
+                .map(|part| {
+                    let (name, value) = part
+                        .split_once('=')
+                        .ok_or_else(|| format!("Invalid key part '{}': expected name=value", part))?;
+                    let name = name.trim();
+                    if name.is_empty() {
+                        return Err(format!("Invalid key part '{}': missing name", part));
+                    }
+                    Ok((name.to_string(), Self::parse_part_value(value.trim())?))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(EntityKey::Composite);
+        }
+        Ok(Self::scalar(text))
+    }
+
+    /// Key from JSON: a string (parsed like [`Self::parse`]), a number, or an
+    /// object of key parts, e.g. `{"dataAreaId": "usmf", "ItemNumber": "A0001"}`
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::String(text) => Self::parse(text),
+            Value::Number(n) => Ok(EntityKey::Number(n.to_string())),
+            Value::Object(parts) if !parts.is_empty() => parts
+                .iter()
+                .map(|(name, value)| {
+                    let part = match value {
+                        Value::String(s) if is_guid(s) => EntityKey::Guid(s.clone()),
+                        Value::String(s) => EntityKey::String(s.clone()),
+                        Value::Number(n) => EntityKey::Number(n.to_string()),
+                        Value::Bool(b) => EntityKey::Literal(b.to_string()),
+                        _ => return Err(format!("Key part '{}' must be a string, number or boolean", name)),
+                    };
+                    Ok((name.clone(), part))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(EntityKey::Composite),
+            _ => Err("Key must be a string, a number or an object of key parts".to_string()),
+        }
+    }
+
+    /// The key as it goes in a URL path: rendered, then percent-encoded so
+    /// `#`, `?`, `%` and non-ASCII characters in string keys are safe
+    pub fn url_segment(&self) -> String {
+        encode_query_value(&self.to_string())
+    }
+
+    fn scalar(text: &str) -> Self {
+        if is_guid(text) {
+            EntityKey::Guid(text.to_string())
+        } else if text.parse::<f64>().is_ok() {
+            EntityKey::Number(text.to_string())
+        } else {
+            EntityKey::String(text.to_string())
+        }
+    }
+
+    fn parse_part_value(text: &str) -> Result<Self, String> {
+        if text.is_empty() {
+            return Err("Key part has no value".to_string());
+        }
+        if let Some(value) = unquote(text) {
+            return Ok(EntityKey::String(value));
+        }
+        if text == "true" || text == "false" || text == "null" || text.ends_with('\'') {
+            return Ok(EntityKey::Literal(text.to_string()));
+        }
+        if text.contains('\'') {
+            return Err(format!("Unbalanced quotes in key value {}", text));
+        }
+        Ok(Self::scalar(text))
+    }
+}
+
+impl fmt::Display for EntityKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntityKey::Guid(v) | EntityKey::Number(v) | EntityKey::Literal(v) => write!(f, "{}", v),
+            EntityKey::String(v) => write!(f, "'{}'", v.replace('\'', "''")),
+            EntityKey::Composite(parts) => {
+                for (i, (name, value)) in parts.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}={}", name, value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Value of a complete `'...'` literal, with `''` unescaped
+fn unquote(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('\'')?.strip_suffix('\'')?;
+    // Every quote inside must be doubled, otherwise this is not one literal
+    let mut chars = inner.chars();
+    let mut value = String::with_capacity(inner.len());
+    while let Some(c) = chars.next() {
+        if c == '\'' && chars.next() != Some('\'') {
+            return None;
+        }
+        value.push(c);
+    }
+    Some(value)
+}
+
+/// Whether `text` has an `=` outside string literals
+fn has_top_level_equals(text: &str) -> bool {
+    let mut in_string = false;
+    text.chars().any(|c| {
+        if c == '\'' {
+            in_string = !in_string;
+        }
+        c == '=' && !in_string
+    })
+}
+
+/// Split composite key text on commas outside string literals
+fn split_parts(text: &str) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let (mut in_string, mut start) = (false, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '\'' => in_string = !in_string,
+            ',' if !in_string => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if in_string {
+        return Err(format!("Unbalanced quotes in key {}", text));
+    }
+    parts.push(text[start..].trim());
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_scalar_keys() {
+        let guid = "5e0b2c1a-0000-0000-0000-000000000001";
+        assert_eq!(EntityKey::parse(guid).unwrap().to_string(), guid);
+        assert_eq!(EntityKey::parse("42").unwrap().to_string(), "42");
+        assert_eq!(EntityKey::parse("O'Brien").unwrap().to_string(), "'O''Brien'");
+        assert_eq!(EntityKey::parse("'O''Brien'").unwrap(), EntityKey::String("O'Brien".to_string()));
+        assert!(EntityKey::parse("  ").is_err());
+    }
+
+    #[test]
+    fn test_composite_keys() {
+        let key = EntityKey::parse("dataAreaId='usmf', ItemNumber='A,1=2''x'").unwrap();
+        assert_eq!(
+            key,
+            EntityKey::Composite(vec![
+                ("dataAreaId".to_string(), EntityKey::String("usmf".to_string())),
+                ("ItemNumber".to_string(), EntityKey::String("A,1=2'x".to_string())),
+            ])
+        );
+        assert_eq!(key.to_string(), "dataAreaId='usmf',ItemNumber='A,1=2''x'");
+
+        let key = EntityKey::parse("dataAreaId=usmf,LineNum=1,Blocked=Microsoft.Dynamics.DataEntities.NoYes'Yes'").unwrap();
+        assert_eq!(
+            key.to_string(),
+            "dataAreaId='usmf',LineNum=1,Blocked=Microsoft.Dynamics.DataEntities.NoYes'Yes'"
+        );
+        assert!(EntityKey::parse("dataAreaId='usmf").is_err());
+    }
+
+    #[test]
+    fn test_from_json_and_url_segment() {
+        let key = EntityKey::from_json(&json!({ "dataAreaId": "usmf", "ItemNumber": "A#1 ü" })).unwrap();
+        assert_eq!(key.to_string(), "ItemNumber='A#1 ü',dataAreaId='usmf'");
+        assert_eq!(key.url_segment(), "ItemNumber='A%231%20%C3%BC',dataAreaId='usmf'");
+        assert!(EntityKey::from_json(&json!([1])).is_err());
+    }
+}
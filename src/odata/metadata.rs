@@ -0,0 +1,881 @@
+//! Streamed $metadata parsing
+//!
+//! F&O `$metadata` documents run to tens of MB. Instead of buffering the
+//! whole XML, response chunks are fed through an incremental tag scanner
+//! that keeps only what the tools need: entity sets, entity types with keys,
+//! typed properties and navigation properties, and enum types. The result
+//! is an [`EdmModel`]; [`MetadataSummary`] is its flattened form.
+
+use serde::{Deserialize, Serialize};
+
+/// A structural XML event produced by [`XmlScanner`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum XmlEvent {
+    /// Start (or self-closing) tag with its local name and attributes
+    Start {
+        name: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    /// End tag with its local name
+    End { name: String },
+}
+
+/// Incremental XML tag scanner
+///
+/// Accepts arbitrary byte chunks (tags and UTF-8 sequences may be split
+/// across chunks) and emits tag events. Text, comments, CDATA and
+/// processing instructions are skipped.
+#[derive(Debug, Default)]
+pub struct XmlScanner {
+    buf: Vec<u8>,
+}
+
+impl XmlScanner {
+    /// Feed a chunk and emit every tag completed by it
+    pub fn feed<F: FnMut(XmlEvent)>(&mut self, chunk: &[u8], mut emit: F) {
+        self.buf.extend_from_slice(chunk);
+        let mut pos = 0;
+
+        while let Some(rel_start) = self.buf[pos..].iter().position(|&b| b == b'<') {
+            let start = pos + rel_start;
+            let rest = &self.buf[start..];
+
+            let end = if rest.starts_with(b"<!--") {
+                find(rest, b"-->").map(|i| start + i + 3)
+            } else if rest.starts_with(b"<![CDATA[") {
+                find(rest, b"]]>").map(|i| start + i + 3)
+            } else if rest.starts_with(b"<?") {
+                find(rest, b"?>").map(|i| start + i + 2)
+            } else {
+                find_tag_end(rest).map(|i| start + i + 1)
+            };
+
+            let end = match end {
+                Some(e) => e,
+                None => {
+                    // Incomplete tag, wait for more data
+                    pos = start;
+                    break;
+                }
+            };
+
+            let tag = &self.buf[start..end];
+            if !tag.starts_with(b"<!") && !tag.starts_with(b"<?") {
+                if let Some(event) = parse_tag(&String::from_utf8_lossy(&tag[1..tag.len() - 1])) {
+                    emit(event);
+                }
+            }
+            pos = end;
+        }
+
+        if self.buf[pos..].iter().all(|&b| b != b'<') {
+            pos = self.buf.len();
+        }
+        self.buf.drain(..pos);
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Find the closing '>' of a tag, ignoring '>' inside quoted attribute values
+fn find_tag_end(tag: &[u8]) -> Option<usize> {
+    let mut quote: Option<u8> = None;
+    for (i, &b) in tag.iter().enumerate() {
+        match quote {
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None if b == b'"' || b == b'\'' => quote = Some(b),
+            None if b == b'>' => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn parse_tag(inner: &str) -> Option<XmlEvent> {
+    if let Some(name) = inner.strip_prefix('/') {
+        return Some(XmlEvent::End {
+            name: local_name(name.trim()).to_string(),
+        });
+    }
+
+    let (inner, self_closing) = match inner.strip_suffix('/') {
+        Some(i) => (i, true),
+        None => (inner, false),
+    };
+
+    let inner = inner.trim();
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = local_name(&inner[..name_end]).to_string();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut attrs = Vec::new();
+    let mut rest = &inner[name_end..];
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        let after = rest[eq + 1..].trim_start();
+        let quote = match after.chars().next() {
+            Some(q @ ('"' | '\'')) => q,
+            _ => break,
+        };
+        let value_end = match after[1..].find(quote) {
+            Some(e) => e + 1,
+            None => break,
+        };
+        attrs.push((key, unescape(&after[1..value_end])));
+        rest = &after[value_end + 1..];
+    }
+
+    Some(XmlEvent::Start {
+        name,
+        attrs,
+        self_closing,
+    })
+}
+
+fn unescape(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_string();
+    }
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+}
+
+/// Typed model of a $metadata document
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct EdmModel {
+    pub entity_sets: Vec<EntitySet>,
+    pub entity_types: Vec<EntityType>,
+    pub enum_types: Vec<EnumType>,
+}
+
+/// Entity set exposed by the service
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EntitySet {
+    pub name: String,
+    /// Qualified entity type name, e.g. `Microsoft.Dynamics.CRM.account`
+    pub entity_type: String,
+}
+
+/// Entity type with its structural and navigation members
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct EntityType {
+    pub name: String,
+    /// Schema namespace the type is declared in
+    pub namespace: String,
+    /// Qualified base type; keys and properties may be inherited from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_type: Option<String>,
+    pub is_abstract: bool,
+    pub keys: Vec<String>,
+    pub properties: Vec<Property>,
+    pub navigation_properties: Vec<NavigationProperty>,
+}
+
+/// Structural property
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Property {
+    pub name: String,
+    /// EDM or enum type, e.g. `Edm.String` or `Collection(Edm.Int32)`
+    pub edm_type: String,
+    /// `Nullable` facet; absent means nullable
+    pub nullable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<String>,
+}
+
+/// Relationship to another entity type
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NavigationProperty {
+    pub name: String,
+    /// Qualified target type without the `Collection(...)` wrapper
+    pub target: String,
+    pub collection: bool,
+    pub nullable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partner: Option<String>,
+    /// Local fields that hold the target's key, e.g. `_parentaccountid_value`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraints: Vec<ReferentialConstraint>,
+}
+
+/// `ReferentialConstraint` of a navigation property
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReferentialConstraint {
+    /// Field on the declaring type
+    pub property: String,
+    /// Field on the target type
+    pub referenced_property: String,
+}
+
+/// Enumeration type, e.g. an F&O `NoYes`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct EnumType {
+    pub name: String,
+    pub namespace: String,
+    pub is_flags: bool,
+    pub members: Vec<EnumMember>,
+}
+
+/// Named value of an [`EnumType`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EnumMember {
+    pub name: String,
+    /// Explicit `Value`; members without one count up from the previous value
+    pub value: i64,
+}
+
+impl EdmModel {
+    /// Parse a complete XML document
+    pub fn parse(xml: &str) -> Self {
+        let mut builder = EdmModelBuilder::default();
+        builder.feed(xml.as_bytes());
+        builder.finish()
+    }
+
+    /// Find an entity type by entity set name, type name, or name prefix
+    /// (e.g. `CustomersV3` matches `CustomerV3`, `accounts` matches `account`)
+    pub fn find_entity_type(&self, name: &str) -> Option<&EntityType> {
+        if let Some(set) = self.entity_sets.iter().find(|s| s.name.eq_ignore_ascii_case(name)) {
+            if let Some(t) = self.entity_type_for_set(set) {
+                return Some(t);
+            }
+        }
+
+        self.entity_types
+            .iter()
+            .find(|t| t.name.eq_ignore_ascii_case(name))
+            .or_else(|| {
+                self.entity_types
+                    .iter()
+                    .find(|t| t.name.starts_with(name) || name.starts_with(t.name.as_str()))
+            })
+    }
+
+    /// Entity type backing an entity set
+    pub fn entity_type_for_set(&self, set: &EntitySet) -> Option<&EntityType> {
+        self.entity_type(&set.entity_type)
+    }
+
+    /// Entity type by qualified or unqualified name
+    pub fn entity_type(&self, type_name: &str) -> Option<&EntityType> {
+        let name = unqualified(type_name);
+        self.entity_types.iter().find(|t| t.name == name)
+    }
+
+    /// Entity set whose type is `type_name`
+    pub fn entity_set_for_type(&self, type_name: &str) -> Option<&EntitySet> {
+        let name = unqualified(type_name);
+        self.entity_sets.iter().find(|s| unqualified(&s.entity_type) == name)
+    }
+
+    /// Enum type by qualified or unqualified name
+    pub fn enum_type(&self, type_name: &str) -> Option<&EnumType> {
+        let name = unqualified(type_name);
+        self.enum_types.iter().find(|t| t.name == name)
+    }
+
+    /// Keys of a type, taken from the nearest base type that declares them
+    pub fn keys_of<'a>(&'a self, entity_type: &'a EntityType) -> &'a [String] {
+        self.lineage(entity_type)
+            .find(|t| !t.keys.is_empty())
+            .map(|t| t.keys.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Properties of a type including inherited ones, base type first
+    pub fn properties_of<'a>(&'a self, entity_type: &'a EntityType) -> Vec<&'a Property> {
+        let mut types: Vec<&EntityType> = self.lineage(entity_type).collect();
+        types.reverse();
+        types.iter().flat_map(|t| t.properties.iter()).collect()
+    }
+
+    /// Navigation properties of a type including inherited ones
+    pub fn navigation_properties_of<'a>(&'a self, entity_type: &'a EntityType) -> Vec<&'a NavigationProperty> {
+        self.lineage(entity_type)
+            .flat_map(|t| t.navigation_properties.iter())
+            .collect()
+    }
+
+    /// Relationships of a type through its navigation properties
+    pub fn relationships<'a>(&'a self, entity_type: &'a EntityType) -> Vec<Relationship<'a>> {
+        self.navigation_properties_of(entity_type)
+            .into_iter()
+            .map(|navigation| {
+                let target = self.entity_type(&navigation.target);
+                let partner = navigation.partner.as_deref().and_then(|partner| {
+                    target.and_then(|t| self.navigation_properties_of(t).into_iter().find(|n| n.name == partner))
+                });
+                let kind = match (navigation.collection, partner.map(|p| p.collection)) {
+                    (true, Some(true)) => RelationshipKind::ManyToMany,
+                    (true, _) => RelationshipKind::OneToMany,
+                    (false, Some(false)) => RelationshipKind::OneToOne,
+                    (false, _) => RelationshipKind::ManyToOne,
+                };
+                // The lookup fields live on the "many" side
+                let lookup = match kind {
+                    RelationshipKind::OneToMany => partner.map(|p| p.constraints.as_slice()),
+                    _ => Some(navigation.constraints.as_slice()),
+                };
+                Relationship {
+                    navigation,
+                    kind,
+                    target_set: self.entity_set_for_type(&navigation.target).map(|s| s.name.as_str()),
+                    partner,
+                    lookup_fields: lookup
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|c| c.property.as_str())
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// The type followed by its base types; stops at unknown or repeated types
+    fn lineage<'a>(&'a self, entity_type: &'a EntityType) -> impl Iterator<Item = &'a EntityType> {
+        let mut seen = Vec::new();
+        std::iter::successors(Some(entity_type), |t| {
+            t.base_type.as_deref().and_then(|base| self.entity_type(base))
+        })
+        .take_while(move |t| {
+            let first = !seen.contains(&t.name);
+            seen.push(t.name.clone());
+            first
+        })
+    }
+}
+
+/// Cardinality of a relationship seen from the declaring type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelationshipKind {
+    OneToOne,
+    /// Lookup on the declaring type
+    ManyToOne,
+    /// Lookup on the target type pointing back
+    OneToMany,
+    ManyToMany,
+}
+
+impl RelationshipKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RelationshipKind::OneToOne => "1:1",
+            RelationshipKind::ManyToOne => "N:1",
+            RelationshipKind::OneToMany => "1:N",
+            RelationshipKind::ManyToMany => "N:N",
+        }
+    }
+}
+
+/// Navigation property with its cardinality and the fields behind it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relationship<'a> {
+    pub navigation: &'a NavigationProperty,
+    pub kind: RelationshipKind,
+    pub target_set: Option<&'a str>,
+    /// Navigation property on the target type leading back
+    pub partner: Option<&'a NavigationProperty>,
+    /// Fields holding the related key: on the declaring type for N:1 and
+    /// 1:1, on the target type for 1:N (e.g. `_parentcustomerid_value`)
+    pub lookup_fields: Vec<&'a str>,
+}
+
+/// Builds an [`EdmModel`] from streamed chunks
+#[derive(Debug, Default)]
+pub struct EdmModelBuilder {
+    scanner: XmlScanner,
+    model: EdmModel,
+    namespace: String,
+    current: Option<EntityType>,
+    current_enum: Option<EnumType>,
+    in_key: bool,
+    in_navigation: bool,
+}
+
+impl EdmModelBuilder {
+    /// Feed the next chunk of the document
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let mut events = Vec::new();
+        self.scanner.feed(chunk, |e| events.push(e));
+        for event in events {
+            self.handle(event);
+        }
+    }
+
+    /// Finish parsing and return the model
+    pub fn finish(mut self) -> EdmModel {
+        self.close_entity_type();
+        self.close_enum_type();
+        self.model
+    }
+
+    fn close_entity_type(&mut self) {
+        if let Some(current) = self.current.take() {
+            self.model.entity_types.push(current);
+        }
+    }
+
+    fn close_enum_type(&mut self) {
+        if let Some(current) = self.current_enum.take() {
+            self.model.enum_types.push(current);
+        }
+    }
+
+    fn handle(&mut self, event: XmlEvent) {
+        match event {
+            XmlEvent::Start {
+                name,
+                attrs,
+                self_closing,
+            } => match name.as_str() {
+                "Schema" => self.namespace = attr(&attrs, "Namespace").unwrap_or_default().to_string(),
+                "EntityType" => {
+                    self.current = Some(EntityType {
+                        name: attr(&attrs, "Name").unwrap_or_default().to_string(),
+                        namespace: self.namespace.clone(),
+                        base_type: attr(&attrs, "BaseType").map(String::from),
+                        is_abstract: attr(&attrs, "Abstract") == Some("true"),
+                        ..Default::default()
+                    });
+                    if self_closing {
+                        self.close_entity_type();
+                    }
+                }
+                "EnumType" => {
+                    self.current_enum = Some(EnumType {
+                        name: attr(&attrs, "Name").unwrap_or_default().to_string(),
+                        namespace: self.namespace.clone(),
+                        is_flags: attr(&attrs, "IsFlags") == Some("true"),
+                        members: Vec::new(),
+                    });
+                    if self_closing {
+                        self.close_enum_type();
+                    }
+                }
+                "Member" => {
+                    if let (Some(current), Some(n)) = (self.current_enum.as_mut(), attr(&attrs, "Name")) {
+                        let value = attr(&attrs, "Value")
+                            .and_then(|v| v.parse().ok())
+                            .unwrap_or_else(|| current.members.last().map_or(0, |m| m.value + 1));
+                        current.members.push(EnumMember {
+                            name: n.to_string(),
+                            value,
+                        });
+                    }
+                }
+                "Key" if !self_closing => self.in_key = true,
+                "PropertyRef" if self.in_key => {
+                    if let (Some(current), Some(n)) = (self.current.as_mut(), attr(&attrs, "Name")) {
+                        current.keys.push(n.to_string());
+                    }
+                }
+                "Property" => {
+                    if let (Some(current), Some(n)) = (self.current.as_mut(), attr(&attrs, "Name")) {
+                        current.properties.push(Property {
+                            name: n.to_string(),
+                            edm_type: attr(&attrs, "Type").unwrap_or_default().to_string(),
+                            nullable: attr(&attrs, "Nullable") != Some("false"),
+                            max_length: attr(&attrs, "MaxLength").map(String::from),
+                            precision: attr(&attrs, "Precision").and_then(|p| p.parse().ok()),
+                            scale: attr(&attrs, "Scale").map(String::from),
+                        });
+                    }
+                }
+                "NavigationProperty" => {
+                    if let (Some(current), Some(n)) = (self.current.as_mut(), attr(&attrs, "Name")) {
+                        let t = attr(&attrs, "Type").unwrap_or_default();
+                        let (target, collection) = match t.strip_prefix("Collection(").and_then(|t| t.strip_suffix(')')) {
+                            Some(inner) => (inner, true),
+                            None => (t, false),
+                        };
+                        current.navigation_properties.push(NavigationProperty {
+                            name: n.to_string(),
+                            target: target.to_string(),
+                            collection,
+                            nullable: attr(&attrs, "Nullable") != Some("false"),
+                            partner: attr(&attrs, "Partner").map(String::from),
+                            constraints: Vec::new(),
+                        });
+                        self.in_navigation = !self_closing;
+                    }
+                }
+                "ReferentialConstraint" if self.in_navigation => {
+                    let nav = self.current.as_mut().and_then(|c| c.navigation_properties.last_mut());
+                    if let (Some(nav), Some(property), Some(referenced)) =
+                        (nav, attr(&attrs, "Property"), attr(&attrs, "ReferencedProperty"))
+                    {
+                        nav.constraints.push(ReferentialConstraint {
+                            property: property.to_string(),
+                            referenced_property: referenced.to_string(),
+                        });
+                    }
+                }
+                "EntitySet" => {
+                    if let Some(n) = attr(&attrs, "Name") {
+                        self.model.entity_sets.push(EntitySet {
+                            name: n.to_string(),
+                            entity_type: attr(&attrs, "EntityType").unwrap_or_default().to_string(),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            XmlEvent::End { name } => match name.as_str() {
+                "EntityType" => self.close_entity_type(),
+                "EnumType" => self.close_enum_type(),
+                "Key" => self.in_key = false,
+                "NavigationProperty" => self.in_navigation = false,
+                _ => {}
+            },
+        }
+    }
+}
+
+/// Entity set of a [`MetadataSummary`]
+pub type EntitySetSummary = EntitySet;
+
+/// Entity type with its members as (name, type) pairs
+#[derive(Debug, Clone, Serialize, PartialEq, Default)]
+pub struct EntityTypeSummary {
+    pub name: String,
+    pub keys: Vec<String>,
+    /// (name, EDM type)
+    pub properties: Vec<(String, String)>,
+    /// (name, target type)
+    pub navigation_properties: Vec<(String, String)>,
+}
+
+/// Compact view of a $metadata document
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct MetadataSummary {
+    pub entity_sets: Vec<EntitySetSummary>,
+    pub entity_types: Vec<EntityTypeSummary>,
+}
+
+impl From<EdmModel> for MetadataSummary {
+    fn from(model: EdmModel) -> Self {
+        let entity_types = model
+            .entity_types
+            .into_iter()
+            .map(|t| EntityTypeSummary {
+                name: t.name,
+                keys: t.keys,
+                properties: t.properties.into_iter().map(|p| (p.name, p.edm_type)).collect(),
+                navigation_properties: t
+                    .navigation_properties
+                    .into_iter()
+                    .map(|n| {
+                        let target = if n.collection { format!("Collection({})", n.target) } else { n.target };
+                        (n.name, target)
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            entity_sets: model.entity_sets,
+            entity_types,
+        }
+    }
+}
+
+impl MetadataSummary {
+    /// Build a summary from a complete XML document
+    pub fn parse(xml: &str) -> Self {
+        EdmModel::parse(xml).into()
+    }
+
+    /// Find an entity type by entity set name, type name, or name prefix
+    /// (e.g. `CustomersV3` matches `CustomerV3`, `accounts` matches `account`)
+    pub fn find_entity_type(&self, name: &str) -> Option<&EntityTypeSummary> {
+        if let Some(set) = self.entity_sets.iter().find(|s| s.name.eq_ignore_ascii_case(name)) {
+            let type_name = unqualified(&set.entity_type);
+            if let Some(t) = self.entity_types.iter().find(|t| t.name == type_name) {
+                return Some(t);
+            }
+        }
+
+        self.entity_types
+            .iter()
+            .find(|t| t.name.eq_ignore_ascii_case(name))
+            .or_else(|| {
+                self.entity_types
+                    .iter()
+                    .find(|t| t.name.starts_with(name) || name.starts_with(t.name.as_str()))
+            })
+    }
+
+    /// Entity type backing an entity set
+    pub fn entity_type_for_set(&self, set: &EntitySetSummary) -> Option<&EntityTypeSummary> {
+        let type_name = unqualified(&set.entity_type);
+        self.entity_types.iter().find(|t| t.name == type_name)
+    }
+}
+
+/// Up to `limit` candidates that look like `name`: the same name in another
+/// case, names containing it (or contained in it), then names within a small
+/// edit distance. Closest first.
+pub fn suggest_names<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>, limit: usize) -> Vec<&'a str> {
+    let wanted = name.to_lowercase();
+    let max_distance = (wanted.chars().count() / 3).max(2);
+    let mut scored: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            let score = if lower == wanted {
+                0
+            } else if lower.contains(&wanted) || wanted.contains(&lower) {
+                1 + lower.len().abs_diff(wanted.len())
+            } else {
+                let distance = edit_distance(&lower, &wanted);
+                if distance > max_distance {
+                    return None;
+                }
+                100 + distance
+            };
+            Some((score, candidate))
+        })
+        .collect();
+    scored.sort();
+    scored.into_iter().take(limit).map(|(_, c)| c).collect()
+}
+
+/// Levenshtein distance over chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb { previous } else { 1 + previous.min(row[j]).min(current) };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// Strip the namespace from a qualified type name
+pub fn unqualified(type_name: &str) -> &str {
+    type_name.rsplit('.').next().unwrap_or(type_name)
+}
+
+/// Builds a [`MetadataSummary`] from streamed chunks
+#[derive(Debug, Default)]
+pub struct MetadataSummaryBuilder {
+    model: EdmModelBuilder,
+}
+
+impl MetadataSummaryBuilder {
+    /// Feed the next chunk of the document
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.model.feed(chunk);
+    }
+
+    /// Finish parsing and return the summary
+    pub fn finish(self) -> MetadataSummary {
+        self.model.finish().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<edmx:Edmx Version="4.0" xmlns:edmx="http://docs.oasis-open.org/odata/ns/edmx">
+  <edmx:DataServices>
+    <Schema Namespace="Microsoft.Dynamics.CRM">
+      <!-- accounts <EntityType Name="ignored"> -->
+      <EntityType Name="account">
+        <Key><PropertyRef Name="accountid" /></Key>
+        <Property Name="accountid" Type="Edm.Guid" />
+        <Property Name="name" Type="Edm.String" MaxLength="160" />
+        <NavigationProperty Name="contact_customer_accounts" Type="Collection(Microsoft.Dynamics.CRM.contact)" />
+        <Annotation Term="Org.OData.Core.V1.Description" String="a &gt; b" />
+      </EntityType>
+      <EntityType Name="contact">
+        <Key><PropertyRef Name="contactid" /></Key>
+        <Property Name="contactid" Type="Edm.Guid" />
+      </EntityType>
+      <EntityContainer Name="System">
+        <EntitySet Name="accounts" EntityType="Microsoft.Dynamics.CRM.account" />
+        <EntitySet Name="contacts" EntityType="Microsoft.Dynamics.CRM.contact" />
+      </EntityContainer>
+    </Schema>
+  </edmx:DataServices>
+</edmx:Edmx>"#;
+
+    #[test]
+    fn test_parse_summary() {
+        let summary = MetadataSummary::parse(SAMPLE);
+        assert_eq!(summary.entity_types.len(), 2);
+        assert_eq!(summary.entity_sets.len(), 2);
+
+        let account = summary.find_entity_type("accounts").unwrap();
+        assert_eq!(account.name, "account");
+        assert_eq!(account.keys, vec!["accountid"]);
+        assert_eq!(account.properties.len(), 2);
+        assert_eq!(account.properties[1], ("name".to_string(), "Edm.String".to_string()));
+        assert_eq!(account.navigation_properties.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_model() {
+        let xml = r#"<edmx:Edmx><edmx:DataServices>
+<Schema Namespace="Microsoft.Dynamics.DataEntities">
+  <EnumType Name="NoYes"><Member Name="No" Value="0" /><Member Name="Yes" Value="1" /></EnumType>
+  <EnumType Name="Flags" IsFlags="true"><Member Name="A" /><Member Name="B" /></EnumType>
+  <EntityType Name="base" Abstract="true">
+    <Key><PropertyRef Name="id" /></Key>
+    <Property Name="id" Type="Edm.Guid" Nullable="false" />
+  </EntityType>
+  <EntityType Name="CustomerV3" BaseType="Microsoft.Dynamics.DataEntities.base">
+    <Property Name="CreditLimit" Type="Edm.Decimal" Precision="32" Scale="6" />
+    <Property Name="OnHold" Type="Microsoft.Dynamics.DataEntities.NoYes" Nullable="false" />
+    <NavigationProperty Name="Group" Type="Microsoft.Dynamics.DataEntities.CustomerGroup" Partner="Customers" />
+  </EntityType>
+  <EntityContainer Name="Resources">
+    <EntitySet Name="CustomersV3" EntityType="Microsoft.Dynamics.DataEntities.CustomerV3" />
+  </EntityContainer>
+</Schema></edmx:DataServices></edmx:Edmx>"#;
+        let model = EdmModel::parse(xml);
+
+        let customer = model.find_entity_type("CustomersV3").unwrap();
+        assert_eq!(customer.namespace, "Microsoft.Dynamics.DataEntities");
+        assert_eq!(model.keys_of(customer), ["id".to_string()]);
+        let properties: Vec<&str> = model.properties_of(customer).iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(properties, vec!["id", "CreditLimit", "OnHold"]);
+
+        let credit = &customer.properties[0];
+        assert!(credit.nullable);
+        assert_eq!((credit.precision, credit.scale.as_deref()), (Some(32), Some("6")));
+        assert!(!customer.properties[1].nullable);
+
+        let group = &customer.navigation_properties[0];
+        assert!(!group.collection);
+        assert_eq!(group.partner.as_deref(), Some("Customers"));
+
+        let no_yes = model.enum_type(&customer.properties[1].edm_type).unwrap();
+        assert_eq!(no_yes.members[1], EnumMember { name: "Yes".to_string(), value: 1 });
+        let flags = model.enum_type("Flags").unwrap();
+        assert!(flags.is_flags);
+        assert_eq!(flags.members.iter().map(|m| m.value).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(model.entity_set_for_type("CustomerV3").unwrap().name, "CustomersV3");
+    }
+
+    #[test]
+    fn test_streamed_byte_by_byte_matches_whole() {
+        let mut builder = MetadataSummaryBuilder::default();
+        for byte in SAMPLE.as_bytes() {
+            builder.feed(std::slice::from_ref(byte));
+        }
+        let streamed = builder.finish();
+        let whole = MetadataSummary::parse(SAMPLE);
+        assert_eq!(streamed.entity_types, whole.entity_types);
+        assert_eq!(streamed.entity_sets, whole.entity_sets);
+    }
+
+    #[test]
+    fn test_scanner_handles_quoted_gt_and_entities() {
+        let mut events = Vec::new();
+        XmlScanner::default().feed(br#"<a:Tag x="1 > 0" y='&amp;'/>"#, |e| events.push(e));
+        assert_eq!(
+            events,
+            vec![XmlEvent::Start {
+                name: "Tag".to_string(),
+                attrs: vec![
+                    ("x".to_string(), "1 > 0".to_string()),
+                    ("y".to_string(), "&".to_string())
+                ],
+                self_closing: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_suggest_names() {
+        let sets = ["CustCustomersV3", "CustomersV3", "CustomerGroups", "VendorsV2", "accounts"];
+        assert_eq!(suggest_names("Customers", sets, 3), vec!["CustomersV3", "CustCustomersV3"]);
+        assert_eq!(suggest_names("Accounts", sets, 3), vec!["accounts"]);
+        assert_eq!(suggest_names("VendorV2", sets, 3), vec!["VendorsV2"]);
+        assert_eq!(suggest_names("acounts", sets, 3), vec!["accounts"]);
+        assert!(suggest_names("SalesOrderLines", sets, 3).is_empty());
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_unqualified() {
+        assert_eq!(unqualified("Microsoft.Dynamics.CRM.account"), "account");
+        assert_eq!(unqualified("account"), "account");
+    }
+
+    #[test]
+    fn test_relationships() {
+        let model = EdmModel::parse(
+            r#"<Schema Namespace="mscrm">
+  <EntityType Name="account">
+    <Key><PropertyRef Name="accountid" /></Key>
+    <Property Name="accountid" Type="Edm.Guid" />
+    <Property Name="_primarycontactid_value" Type="Edm.Guid" />
+    <NavigationProperty Name="primarycontactid" Type="mscrm.contact" Partner="account_primary_contact">
+      <ReferentialConstraint Property="_primarycontactid_value" ReferencedProperty="contactid" />
+    </NavigationProperty>
+    <NavigationProperty Name="contact_customer_accounts" Type="Collection(mscrm.contact)" Partner="parentcustomerid_account" />
+    <NavigationProperty Name="accountleads_association" Type="Collection(mscrm.lead)" Partner="accountleads_association" />
+  </EntityType>
+  <EntityType Name="contact">
+    <Key><PropertyRef Name="contactid" /></Key>
+    <NavigationProperty Name="parentcustomerid_account" Type="mscrm.account" Partner="contact_customer_accounts">
+      <ReferentialConstraint Property="_parentcustomerid_value" ReferencedProperty="accountid" />
+    </NavigationProperty>
+    <NavigationProperty Name="account_primary_contact" Type="Collection(mscrm.account)" Partner="primarycontactid" />
+  </EntityType>
+  <EntityType Name="lead">
+    <NavigationProperty Name="accountleads_association" Type="Collection(mscrm.account)" Partner="accountleads_association" />
+  </EntityType>
+  <EntityContainer Name="System">
+    <EntitySet Name="accounts" EntityType="mscrm.account" />
+    <EntitySet Name="contacts" EntityType="mscrm.contact" />
+  </EntityContainer>
+</Schema>"#,
+        );
+        let account = model.entity_type("account").unwrap();
+        let relationships = model.relationships(account);
+        let summary: Vec<(&str, &str, Option<&str>, Vec<&str>)> = relationships
+            .iter()
+            .map(|r| (r.navigation.name.as_str(), r.kind.label(), r.target_set, r.lookup_fields.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("primarycontactid", "N:1", Some("contacts"), vec!["_primarycontactid_value"]),
+                ("contact_customer_accounts", "1:N", Some("contacts"), vec!["_parentcustomerid_value"]),
+                ("accountleads_association", "N:N", None, vec![]),
+            ]
+        );
+        assert_eq!(relationships[1].partner.unwrap().name, "parentcustomerid_account");
+    }
+}
@@ -0,0 +1,337 @@
+//! Shared $metadata model
+//!
+//! The parsed [`EdmModel`] is fetched once and shared by every tool. With a
+//! cache directory the model is also written to disk, keyed by endpoint, and
+//! reused across restarts until its TTL runs out; when a fetch fails an
+//! expired copy is used rather than none. `[metadata] file` loads an EDMX
+//! file instead of calling the service at all.
+//!
+//! Holders of a [`ModelWatch`] are woken when a load yields a model that
+//! differs from the previous one, e.g. to re-announce tools built from it.
+
+use crate::config::MetadataSettings;
+use crate::odata::metadata::EdmModel;
+use crate::odata::time_window::{format_utc, parse_utc};
+use crate::odata::{ODataClient, ODataError};
+use crate::sync::state::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{watch, Mutex};
+
+/// Receiver of model changes
+pub type ModelWatch = watch::Receiver<Option<Arc<EdmModel>>>;
+
+/// Where the current model came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelSource {
+    Service,
+    DiskCache,
+    File,
+}
+
+impl ModelSource {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ModelSource::Service => "fetched from the service",
+            ModelSource::DiskCache => "loaded from the disk cache",
+            ModelSource::File => "loaded from the metadata file",
+        }
+    }
+}
+
+/// Origin and age of the current model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub source: ModelSource,
+    /// When the model was fetched from the service, or read from the file (UTC)
+    pub fetched_at: String,
+    /// Cache file or metadata file the model was read from or written to
+    pub path: Option<PathBuf>,
+}
+
+/// Disk cache file
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    endpoint: String,
+    fetched_at: String,
+    model: EdmModel,
+}
+
+/// Lazily loaded, shared $metadata model
+pub struct MetadataCache {
+    client: Arc<ODataClient>,
+    /// Held while loading so concurrent callers wait for one download
+    fetch: Mutex<()>,
+    current: watch::Sender<Option<Arc<EdmModel>>>,
+    info: std::sync::Mutex<Option<ModelInfo>>,
+    /// EDMX file used instead of the service
+    file: Option<PathBuf>,
+    /// Disk cache directory; none keeps the model in memory only
+    cache_dir: Option<PathBuf>,
+    /// Age after which a model is fetched again; zero never expires
+    ttl: Duration,
+}
+
+impl MetadataCache {
+    /// In-memory cache that fetches once per process
+    pub fn new(client: Arc<ODataClient>) -> Self {
+        Self {
+            client,
+            fetch: Mutex::new(()),
+            current: watch::Sender::new(None),
+            info: std::sync::Mutex::new(None),
+            file: None,
+            cache_dir: None,
+            ttl: Duration::ZERO,
+        }
+    }
+
+    /// Apply the `[metadata]` settings: offline file, disk cache and TTL
+    pub fn with_settings(mut self, settings: &MetadataSettings) -> Self {
+        self.file = settings.file.clone();
+        self.ttl = Duration::from_secs(settings.cache_ttl_seconds);
+        self.cache_dir = (settings.cache_ttl_seconds > 0).then(|| settings.cache_dir.clone());
+        self
+    }
+
+    /// The model if it has been loaded, without fetching
+    pub fn current(&self) -> Option<Arc<EdmModel>> {
+        self.current.borrow().clone()
+    }
+
+    /// Where the current model came from
+    pub fn info(&self) -> Option<ModelInfo> {
+        self.info.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Watch for loads that change the model
+    pub fn subscribe(&self) -> ModelWatch {
+        self.current.subscribe()
+    }
+
+    /// The model, loaded on first use and again once it is older than the
+    /// TTL; `refresh` reloads it from the file or the service
+    pub async fn model(&self, refresh: bool) -> Result<Arc<EdmModel>, ODataError> {
+        let _loading = self.fetch.lock().await;
+        if !refresh {
+            if let (Some(model), Some(info)) = (self.current(), self.info()) {
+                if self.is_fresh(&info) {
+                    return Ok(model);
+                }
+            }
+        }
+
+        let (model, info) = self.load(refresh).await?;
+        let model = Arc::new(model);
+        *self.info.lock().unwrap_or_else(|e| e.into_inner()) = Some(info);
+        self.store(Arc::clone(&model));
+        Ok(model)
+    }
+
+    fn is_fresh(&self, info: &ModelInfo) -> bool {
+        info.source == ModelSource::File || self.ttl.is_zero() || age(&info.fetched_at) < self.ttl
+    }
+
+    async fn load(&self, refresh: bool) -> Result<(EdmModel, ModelInfo), ODataError> {
+        if let Some(file) = &self.file {
+            let path = file.clone();
+            let model = tokio::task::spawn_blocking(move || read_edmx(&path))
+                .await
+                .map_err(|e| ODataError::ParseError(e.to_string()))??;
+            tracing::info!("Loaded $metadata from {}", file.display());
+            let info = ModelInfo {
+                source: ModelSource::File,
+                fetched_at: format_utc(SystemTime::now()),
+                path: Some(file.clone()),
+            };
+            return Ok((model, info));
+        }
+
+        let cache_path = self.cache_path();
+        let cached = match &cache_path {
+            Some(path) => {
+                let (path, endpoint) = (path.clone(), self.client.endpoint().to_string());
+                tokio::task::spawn_blocking(move || read_cache(&path, &endpoint))
+                    .await
+                    .unwrap_or(None)
+            }
+            None => None,
+        };
+        let disk_info = |entry: &CacheEntry| ModelInfo {
+            source: ModelSource::DiskCache,
+            fetched_at: entry.fetched_at.clone(),
+            path: cache_path.clone(),
+        };
+
+        let cached = match cached {
+            Some(entry) if !refresh && age(&entry.fetched_at) < self.ttl => {
+                let info = disk_info(&entry);
+                return Ok((entry.model, info));
+            }
+            other => other,
+        };
+
+        match self.client.fetch_metadata_model().await {
+            Ok(model) => {
+                let entry = CacheEntry {
+                    endpoint: self.client.endpoint().to_string(),
+                    fetched_at: format_utc(SystemTime::now()),
+                    model,
+                };
+                if let Some(path) = &cache_path {
+                    if let Err(e) = write_cache(path, &entry) {
+                        tracing::warn!("Cannot write metadata cache {}: {}", path.display(), e);
+                    }
+                }
+                let info = ModelInfo {
+                    source: ModelSource::Service,
+                    fetched_at: entry.fetched_at,
+                    path: cache_path,
+                };
+                Ok((entry.model, info))
+            }
+            Err(e) => match cached {
+                Some(entry) => {
+                    tracing::warn!(
+                        "Fetching $metadata failed ({}); using the cached model from {}",
+                        e,
+                        entry.fetched_at
+                    );
+                    let info = disk_info(&entry);
+                    Ok((entry.model, info))
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// `<cache_dir>/<host>-<hash of endpoint>.json`
+    fn cache_path(&self) -> Option<PathBuf> {
+        let dir = self.cache_dir.as_ref()?;
+        Some(dir.join(cache_file_name(self.client.endpoint())))
+    }
+
+    /// Replace the model; watchers are only woken when it changed
+    fn store(&self, model: Arc<EdmModel>) {
+        self.client
+            .set_entity_sets(model.entity_sets.iter().map(|s| s.name.clone()).collect());
+        self.current.send_if_modified(|current| {
+            let changed = current.as_deref() != Some(&*model);
+            *current = Some(model);
+            changed
+        });
+    }
+}
+
+/// Time since a UTC timestamp; unreadable timestamps count as expired
+fn age(fetched_at: &str) -> Duration {
+    parse_utc(fetched_at)
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .unwrap_or(Duration::MAX)
+}
+
+/// `<host>-<hash of endpoint>.json`
+pub(crate) fn cache_file_name(endpoint: &str) -> String {
+    let host = endpoint
+        .split("://")
+        .last()
+        .unwrap_or(endpoint)
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    let host: String = host
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    format!("{}-{:016x}.json", host, fnv1a(endpoint.trim_end_matches('/').as_bytes()))
+}
+
+/// Stable 64-bit FNV-1a hash, so cache file names survive toolchain updates
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn read_edmx(path: &Path) -> Result<EdmModel, ODataError> {
+    let xml = std::fs::read(path)
+        .map_err(|e| ODataError::ParseError(format!("cannot read metadata file {}: {}", path.display(), e)))?;
+    let model = EdmModel::parse(&String::from_utf8_lossy(&xml));
+    if model.entity_types.is_empty() {
+        return Err(ODataError::ParseError(format!(
+            "{} contains no entity types; expected an EDMX $metadata document",
+            path.display()
+        )));
+    }
+    Ok(model)
+}
+
+/// The cached entry for `endpoint`; unreadable files are ignored
+fn read_cache(path: &Path, endpoint: &str) -> Option<CacheEntry> {
+    let text = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<CacheEntry>(&text) {
+        Ok(entry) if entry.endpoint == endpoint => Some(entry),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!("Ignoring unreadable metadata cache {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn write_cache(path: &Path, entry: &CacheEntry) -> std::io::Result<()> {
+    let json = serde_json::to_vec(entry).map_err(std::io::Error::other)?;
+    write_atomic(path, &json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_file_name() {
+        let name = cache_file_name("https://org.crm.dynamics.com/api/data/v9.2/");
+        assert!(name.starts_with("org.crm.dynamics.com-"));
+        assert_eq!(name, cache_file_name("https://org.crm.dynamics.com/api/data/v9.2"));
+        assert_ne!(name, cache_file_name("https://org.crm.dynamics.com/api/data/v9.1/"));
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let dir = std::env::temp_dir().join(format!("d365-metadata-cache-{}", std::process::id()));
+        let path = dir.join("entry.json");
+        let model = EdmModel::parse(
+            r#"<Schema Namespace="ns"><EntityType Name="account"><Key><PropertyRef Name="accountid" /></Key>
+<Property Name="accountid" Type="Edm.Guid" Nullable="false" /></EntityType></Schema>"#,
+        );
+        let entry = CacheEntry {
+            endpoint: "https://org/api/data/v9.2/".to_string(),
+            fetched_at: format_utc(SystemTime::now()),
+            model: model.clone(),
+        };
+        write_cache(&path, &entry).unwrap();
+
+        let read = read_cache(&path, "https://org/api/data/v9.2/").unwrap();
+        assert_eq!(read.model, model);
+        assert!(age(&read.fetched_at) < Duration::from_secs(60));
+        assert!(read_cache(&path, "https://other/api/data/v9.2/").is_none());
+        assert_eq!(age("not a time"), Duration::MAX);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_edmx_requires_entity_types() {
+        let dir = std::env::temp_dir().join(format!("d365-metadata-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("metadata.xml");
+        std::fs::write(&path, "<html>login page</html>").unwrap();
+        assert!(read_edmx(&path).is_err());
+        std::fs::write(&path, r#"<Schema Namespace="ns"><EntityType Name="account" /></Schema>"#).unwrap();
+        assert_eq!(read_edmx(&path).unwrap().entity_types[0].name, "account");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,266 @@
+//! Metadata drift detection
+//!
+//! A snapshot of the entity model is kept on disk per endpoint; comparing
+//! the current model with it shows the entity sets and fields a
+//! customization or update added, removed or retyped since the snapshot.
+
+use crate::odata::metadata::{EdmModel, Property};
+use crate::sync::state::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directory under the metadata cache directory holding snapshots
+pub const SNAPSHOT_DIR_NAME: &str = "snapshots";
+
+/// Saved model
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub endpoint: String,
+    /// When the snapshot was taken (UTC)
+    pub taken_at: String,
+    pub model: EdmModel,
+}
+
+/// Field whose type or facets differ between two models
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetypedField {
+    pub name: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// Field changes of one entity set
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EntityDiff {
+    pub entity_set: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub retyped: Vec<RetypedField>,
+    /// Key fields before and after, when they changed
+    pub keys_changed: Option<(Vec<String>, Vec<String>)>,
+}
+
+/// Differences between a snapshot and the current model
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MetadataDiff {
+    pub added_entity_sets: Vec<String>,
+    pub removed_entity_sets: Vec<String>,
+    pub changed: Vec<EntityDiff>,
+}
+
+impl MetadataDiff {
+    /// Compare two models by entity set; `only` limits the comparison to
+    /// the named entity sets
+    pub fn compare(before: &EdmModel, after: &EdmModel, only: &[String]) -> Self {
+        let fields = |model: &EdmModel| -> BTreeMap<String, (Vec<String>, BTreeMap<String, String>)> {
+            model
+                .entity_sets
+                .iter()
+                .filter(|s| only.is_empty() || only.contains(&s.name))
+                .map(|s| {
+                    let described = model.entity_type_for_set(s).map(|t| {
+                        let props = model
+                            .properties_of(t)
+                            .into_iter()
+                            .map(|p| (p.name.clone(), describe_type(p)))
+                            .collect();
+                        (model.keys_of(t).to_vec(), props)
+                    });
+                    (s.name.clone(), described.unwrap_or_default())
+                })
+                .collect()
+        };
+        let (before, after) = (fields(before), fields(after));
+
+        let mut diff = MetadataDiff {
+            added_entity_sets: after.keys().filter(|s| !before.contains_key(*s)).cloned().collect(),
+            removed_entity_sets: before.keys().filter(|s| !after.contains_key(*s)).cloned().collect(),
+            changed: Vec::new(),
+        };
+        for (set, (old_keys, old_fields)) in &before {
+            let Some((new_keys, new_fields)) = after.get(set) else {
+                continue;
+            };
+            let entity = EntityDiff {
+                entity_set: set.clone(),
+                added: new_fields.keys().filter(|f| !old_fields.contains_key(*f)).cloned().collect(),
+                removed: old_fields.keys().filter(|f| !new_fields.contains_key(*f)).cloned().collect(),
+                retyped: old_fields
+                    .iter()
+                    .filter_map(|(name, before)| {
+                        let after = new_fields.get(name).filter(|after| *after != before)?;
+                        Some(RetypedField { name: name.clone(), before: before.clone(), after: after.clone() })
+                    })
+                    .collect(),
+                keys_changed: (old_keys != new_keys).then(|| (old_keys.clone(), new_keys.clone())),
+            };
+            if entity != (EntityDiff { entity_set: set.clone(), ..Default::default() }) {
+                diff.changed.push(entity);
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_entity_sets.is_empty() && self.removed_entity_sets.is_empty() && self.changed.is_empty()
+    }
+
+    /// Human-readable report
+    pub fn render(&self) -> String {
+        if self.is_empty() {
+            return "No changes.\n".to_string();
+        }
+        let mut out = String::new();
+        if !self.added_entity_sets.is_empty() {
+            out.push_str(&format!("Added entity sets: {}\n", self.added_entity_sets.join(", ")));
+        }
+        if !self.removed_entity_sets.is_empty() {
+            out.push_str(&format!("Removed entity sets: {}\n", self.removed_entity_sets.join(", ")));
+        }
+        for entity in &self.changed {
+            out.push_str(&format!("\n{}:\n", entity.entity_set));
+            if let Some((before, after)) = &entity.keys_changed {
+                out.push_str(&format!("  key changed: ({}) -> ({})\n", before.join(", "), after.join(", ")));
+            }
+            for field in &entity.removed {
+                out.push_str(&format!("  - {}\n", field));
+            }
+            for field in &entity.added {
+                out.push_str(&format!("  + {}\n", field));
+            }
+            for field in &entity.retyped {
+                out.push_str(&format!("  ~ {}: {} -> {}\n", field.name, field.before, field.after));
+            }
+        }
+        out
+    }
+}
+
+/// Type with the facets that break extracts when they change
+fn describe_type(property: &Property) -> String {
+    let mut text = property.edm_type.clone();
+    let mut facets = Vec::new();
+    if let Some(max) = &property.max_length {
+        facets.push(max.clone());
+    }
+    if let Some(precision) = property.precision {
+        facets.push(precision.to_string());
+    }
+    if let Some(scale) = &property.scale {
+        facets.push(scale.clone());
+    }
+    if !facets.is_empty() {
+        text.push_str(&format!("({})", facets.join(",")));
+    }
+    if !property.nullable {
+        text.push_str(" not null");
+    }
+    text
+}
+
+/// `<dir>/<name>/<cache file name>`; snapshot names are limited to
+/// letters, digits, `-` and `_`
+pub fn snapshot_path(dir: &Path, name: &str, cache_file_name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!(
+            "Invalid snapshot name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(dir.join(name).join(cache_file_name))
+}
+
+/// The snapshot at `path`; none when there is no file yet
+pub fn read_snapshot(path: &Path) -> Result<Option<Snapshot>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| format!("Unreadable snapshot {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Cannot read snapshot {}: {}", path.display(), e)),
+    }
+}
+
+pub fn write_snapshot(path: &Path, snapshot: &Snapshot) -> Result<(), String> {
+    let json = serde_json::to_vec(snapshot).map_err(|e| e.to_string())?;
+    write_atomic(path, &json).map_err(|e| format!("Cannot write snapshot {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(fields: &str, key: &str) -> EdmModel {
+        EdmModel::parse(&format!(
+            r#"<Schema Namespace="ns">
+  <EntityType Name="CustomerV3"><Key><PropertyRef Name="{key}" /></Key>{fields}</EntityType>
+  <EntityType Name="VendorV2"><Property Name="VendorAccount" Type="Edm.String" /></EntityType>
+  <EntityContainer Name="Resources">
+    <EntitySet Name="CustomersV3" EntityType="ns.CustomerV3" />
+    <EntitySet Name="VendorsV2" EntityType="ns.VendorV2" />
+  </EntityContainer>
+</Schema>"#
+        ))
+    }
+
+    #[test]
+    fn test_compare() {
+        let before = model(
+            r#"<Property Name="CustomerAccount" Type="Edm.String" Nullable="false" MaxLength="20" />
+<Property Name="CreditLimit" Type="Edm.Decimal" /><Property Name="Fax" Type="Edm.String" />"#,
+            "CustomerAccount",
+        );
+        let after = model(
+            r#"<Property Name="CustomerAccount" Type="Edm.String" Nullable="false" MaxLength="30" />
+<Property Name="CreditLimit" Type="Edm.Double" /><Property Name="Email" Type="Edm.String" />"#,
+            "CustomerAccount",
+        );
+        assert!(MetadataDiff::compare(&before, &before, &[]).is_empty());
+
+        let diff = MetadataDiff::compare(&before, &after, &[]);
+        assert_eq!(diff.changed.len(), 1);
+        let customers = &diff.changed[0];
+        assert_eq!(customers.entity_set, "CustomersV3");
+        assert_eq!(customers.added, vec!["Email"]);
+        assert_eq!(customers.removed, vec!["Fax"]);
+        assert_eq!(
+            customers.retyped,
+            vec![
+                RetypedField { name: "CreditLimit".into(), before: "Edm.Decimal".into(), after: "Edm.Double".into() },
+                RetypedField {
+                    name: "CustomerAccount".into(),
+                    before: "Edm.String(20) not null".into(),
+                    after: "Edm.String(30) not null".into()
+                },
+            ]
+        );
+        assert!(diff.render().contains("  ~ CreditLimit: Edm.Decimal -> Edm.Double\n"));
+        assert!(MetadataDiff::compare(&before, &after, &["VendorsV2".to_string()]).is_empty());
+
+        let rekeyed = model(r#"<Property Name="CustomerAccount" Type="Edm.String" />"#, "dataAreaId");
+        let diff = MetadataDiff::compare(&before, &rekeyed, &[]);
+        assert!(diff.changed[0].keys_changed.is_some());
+        let diff = MetadataDiff::compare(&before, &EdmModel::default(), &[]);
+        assert_eq!(diff.removed_entity_sets, vec!["CustomersV3", "VendorsV2"]);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let dir = std::env::temp_dir().join(format!("d365-metadata-snapshot-{}", std::process::id()));
+        assert!(snapshot_path(&dir, "../x", "org.json").is_err());
+        let path = snapshot_path(&dir, "baseline", "org.json").unwrap();
+        assert!(read_snapshot(&path).unwrap().is_none());
+
+        let snapshot = Snapshot {
+            endpoint: "https://org/data/".to_string(),
+            taken_at: "2024-01-31T08:00:00Z".to_string(),
+            model: model("", "CustomerAccount"),
+        };
+        write_snapshot(&path, &snapshot).unwrap();
+        let read = read_snapshot(&path).unwrap().unwrap();
+        assert_eq!(read.model, snapshot.model);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
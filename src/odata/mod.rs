@@ -2,6 +2,37 @@
 //!
 //! HTTP client and schema utilities for D365 OData APIs
 
+pub mod batch;
+pub mod breaker;
+pub mod budget;
 pub mod client;
+pub mod correlation;
+pub mod cursor;
+pub mod error_hints;
+pub mod etag_cache;
+pub mod expand;
+pub mod join;
+pub mod impersonation;
+pub mod key;
+pub mod metadata;
+pub mod metadata_cache;
+pub mod metadata_diff;
+pub mod partition;
+pub mod payload;
+pub mod script;
+pub mod spill;
+pub mod time_window;
+pub mod transform;
+pub mod validate;
 
-pub use client::{EntityInfo, ODataClient, ODataError, ODataResponse, QueryOptions};
+pub use client::{
+    alternate_key_segment, encode_query_value, is_guid, key_from_entity_id, key_segment,
+    odata_literal, CreatedEntity, EntityInfo, ODataClient, ODataError, ODataResponse, PagedFetch,
+    PageWindow,    QueryOptions, UpsertMode, UpsertOutcome, DATAVERSE_COUNT_LIMIT,
+};
+pub use cursor::PageCursor;
+pub use expand::ExpandOption;
+pub use key::EntityKey;
+pub use partition::Partitioning;
+pub use metadata::{EdmModel, MetadataSummary};
+pub use payload::{DeepInsert, UpdatePayload};
@@ -0,0 +1,158 @@
+//! Partitioned extraction of large entities
+//!
+//! A full read of a large entity follows `@odata.nextLink` one page at a
+//! time. Splitting it into disjoint `$filter` ranges lets several ranges be
+//! read at once. Every split ends with open ranges and a `null` partition,
+//! so together the partitions cover every row, including rows added while
+//! the extract runs.
+
+use crate::config::ProductType;
+use crate::odata::time_window::{format_utc, AuditField};
+use std::time::{Duration, SystemTime};
+
+/// First characters of string keys, in sort order, used to place key boundaries
+const KEY_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// How an extract is split into partitions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Partitioning {
+    /// Equal time buckets of the created timestamp, between its first and last value
+    Created { partitions: usize },
+    /// Ranges of a string key field by first character
+    KeyRange { field: String, partitions: usize },
+}
+
+impl Partitioning {
+    /// `created` splits by creation time; any other value names a string key field
+    pub fn parse(by: &str, partitions: usize) -> Self {
+        let partitions = partitions.max(1);
+        match by.trim() {
+            by if by.eq_ignore_ascii_case("created") => Partitioning::Created { partitions },
+            field => Partitioning::KeyRange {
+                field: field.to_string(),
+                partitions,
+            },
+        }
+    }
+
+    /// Field the ranges are taken on
+    pub fn field(&self, product: &ProductType) -> String {
+        match self {
+            Partitioning::Created { .. } => AuditField::Created.name(product).to_string(),
+            Partitioning::KeyRange { field, .. } => field.clone(),
+        }
+    }
+}
+
+/// Filters of `partitions` time buckets between `first` and `last`.
+/// Without a range (no timestamped rows) only the open ranges remain.
+pub fn time_bucket_filters(field: &str, range: Option<(SystemTime, SystemTime)>, partitions: usize) -> Vec<String> {
+    let bounds: Vec<String> = match range {
+        Some((first, last)) if last > first && partitions > 1 => {
+            let span = last.duration_since(first).unwrap_or_default();
+            let step = Duration::from_secs((span.as_secs() / partitions as u64).max(1));
+            let mut bounds: Vec<String> = (1..partitions as u32)
+                .map(|i| format_utc(first + step * i))
+                .collect();
+            bounds.dedup();
+            bounds
+        }
+        _ => Vec::new(),
+    };
+    range_filters(field, &bounds)
+}
+
+/// Filters of up to `partitions` ranges of a string key by first character
+pub fn key_range_filters(field: &str, partitions: usize) -> Vec<String> {
+    let partitions = partitions.clamp(1, KEY_ALPHABET.len());
+    let bounds: Vec<String> = (1..partitions)
+        .map(|i| {
+            let c = KEY_ALPHABET[i * KEY_ALPHABET.len() / partitions] as char;
+            format!("'{}'", c)
+        })
+        .collect();
+    range_filters(field, &bounds)
+}
+
+/// `field lt b1`, `field ge b1 and field lt b2`, ..., `field ge bn`, `field eq null`
+fn range_filters(field: &str, bounds: &[String]) -> Vec<String> {
+    let mut filters = Vec::with_capacity(bounds.len() + 2);
+    match bounds.first() {
+        Some(first) => filters.push(format!("{} lt {}", field, first)),
+        None => filters.push(format!("{} ne null", field)),
+    }
+    for pair in bounds.windows(2) {
+        filters.push(format!("{} ge {} and {} lt {}", field, pair[0], field, pair[1]));
+    }
+    if let Some(last) = bounds.last() {
+        filters.push(format!("{} ge {}", field, last));
+    }
+    filters.push(format!("{} eq null", field));
+    filters
+}
+
+/// AND a partition filter onto the query's own filter
+pub fn with_partition(filter: Option<&str>, partition: &str) -> String {
+    match filter {
+        Some(filter) => format!("({}) and ({})", filter, partition),
+        None => partition.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::odata::time_window::parse_utc;
+
+    #[test]
+    fn test_parse_partitioning() {
+        assert_eq!(Partitioning::parse("Created", 4), Partitioning::Created { partitions: 4 });
+        assert_eq!(
+            Partitioning::parse("CustomerAccount", 0),
+            Partitioning::KeyRange {
+                field: "CustomerAccount".to_string(),
+                partitions: 1
+            }
+        );
+        assert_eq!(Partitioning::parse("created", 2).field(&ProductType::Finops), "CreatedDateTime");
+    }
+
+    #[test]
+    fn test_time_bucket_filters() {
+        let first = parse_utc("2024-01-01T00:00:00Z").unwrap();
+        let last = parse_utc("2024-01-05T00:00:00Z").unwrap();
+        let filters = time_bucket_filters("createdon", Some((first, last)), 4);
+        assert_eq!(
+            filters,
+            [
+                "createdon lt 2024-01-02T00:00:00Z",
+                "createdon ge 2024-01-02T00:00:00Z and createdon lt 2024-01-03T00:00:00Z",
+                "createdon ge 2024-01-03T00:00:00Z and createdon lt 2024-01-04T00:00:00Z",
+                "createdon ge 2024-01-04T00:00:00Z",
+                "createdon eq null",
+            ]
+        );
+
+        assert_eq!(
+            time_bucket_filters("createdon", None, 4),
+            ["createdon ne null", "createdon eq null"]
+        );
+        assert_eq!(time_bucket_filters("createdon", Some((first, first)), 4).len(), 2);
+    }
+
+    #[test]
+    fn test_key_range_filters() {
+        let filters = key_range_filters("ItemId", 3);
+        assert_eq!(
+            filters,
+            [
+                "ItemId lt 'C'",
+                "ItemId ge 'C' and ItemId lt 'O'",
+                "ItemId ge 'O'",
+                "ItemId eq null",
+            ]
+        );
+        assert_eq!(key_range_filters("ItemId", 100).len(), KEY_ALPHABET.len() + 1);
+        assert_eq!(with_partition(Some("a eq 1 or b eq 2"), "ItemId lt 'C'"), "(a eq 1 or b eq 2) and (ItemId lt 'C')");
+    }
+}
@@ -0,0 +1,226 @@
+//! Write payload construction
+//!
+//! Separates "leave this field alone" from "clear this field" for PATCH
+//! updates. Omitted fields are never sent; cleared fields become JSON nulls,
+//! except Dataverse lookups, which must be cleared through their `$ref`.
+//! Also validates nested documents for deep insert.
+
+use crate::config::ProductType;
+use serde_json::{Map, Value};
+
+/// A PATCH body plus the lookups that must be disassociated separately
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpdatePayload {
+    /// Fields to send in the PATCH body; cleared fields are `null`
+    pub body: Map<String, Value>,
+    /// Single-valued navigation properties to clear with `DELETE .../$ref`
+    pub lookups_to_clear: Vec<String>,
+}
+
+impl UpdatePayload {
+    /// Build the payload from the fields to set and the fields to clear.
+    ///
+    /// On Dataverse a cleared field written as `_<nav>_value` or
+    /// `<nav>@odata.bind` is a lookup and is disassociated; every other
+    /// cleared field is sent as `null`. A field may not be both set and cleared.
+    pub fn build(
+        mut body: Map<String, Value>,
+        clear_fields: &[String],
+        product: &ProductType,
+    ) -> Result<Self, String> {
+        let mut lookups_to_clear = Vec::new();
+
+        for field in clear_fields.iter().map(|f| f.trim()).filter(|f| !f.is_empty()) {
+            let lookup = match product {
+                ProductType::Dataverse => lookup_navigation(field),
+                ProductType::Finops => None,
+            };
+
+            let set_names: &[&str] = match lookup {
+                Some(nav) => &[field, nav],
+                None => &[field],
+            };
+            if let Some(conflict) = body.keys().find(|k| {
+                set_names.contains(&k.as_str())
+                    || lookup.is_some_and(|nav| k.as_str() == format!("{}@odata.bind", nav))
+            }) {
+                return Err(format!(
+                    "Field '{}' is both set in the data and listed in clear_fields",
+                    conflict
+                ));
+            }
+
+            match lookup {
+                Some(nav) => {
+                    if !lookups_to_clear.iter().any(|n| n == nav) {
+                        lookups_to_clear.push(nav.to_string());
+                    }
+                }
+                None => {
+                    body.insert(field.to_string(), Value::Null);
+                }
+            }
+        }
+
+        Ok(Self {
+            body,
+            lookups_to_clear,
+        })
+    }
+}
+
+/// Shape of a deep-insert document: the related records created with the parent
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeepInsert {
+    /// Records created, including the parent
+    pub records: usize,
+    /// Top-level navigation properties holding new related records
+    pub navigations: Vec<String>,
+    /// Related record counts by navigation path, e.g. `contact_customer_accounts/Contact_Tasks`
+    pub related: Vec<(String, usize)>,
+    /// Existing records bound with `@odata.bind`
+    pub binds: usize,
+}
+
+impl DeepInsert {
+    /// Validate a nested document and describe what it creates.
+    ///
+    /// Nested objects are single-valued navigations, arrays of objects are
+    /// collection-valued navigations. `@odata.bind` values must be entity
+    /// references (a string, or an array of strings for collections).
+    pub fn analyze(body: &Map<String, Value>) -> Result<Self, String> {
+        let mut plan = DeepInsert {
+            records: 1,
+            ..Default::default()
+        };
+        plan.walk("", body)?;
+        Ok(plan)
+    }
+
+    fn walk(&mut self, prefix: &str, record: &Map<String, Value>) -> Result<(), String> {
+        for (name, value) in record {
+            if name.ends_with("@odata.bind") {
+                match value {
+                    Value::String(_) => self.binds += 1,
+                    Value::Array(refs) if refs.iter().all(Value::is_string) => self.binds += refs.len(),
+                    _ => {
+                        return Err(format!(
+                            "'{}{}' must be an entity reference like '/contacts(<id>)'",
+                            prefix, name
+                        ))
+                    }
+                }
+                continue;
+            }
+            let children: Vec<&Map<String, Value>> = match value {
+                Value::Object(child) => vec![child],
+                Value::Array(items) if !items.is_empty() && items.iter().any(Value::is_object) => items
+                    .iter()
+                    .map(|item| {
+                        item.as_object().ok_or_else(|| {
+                            format!("'{}{}' mixes records with other values", prefix, name)
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+                _ => continue,
+            };
+
+            let path = format!("{}{}", prefix, name);
+            if prefix.is_empty() {
+                self.navigations.push(name.clone());
+            }
+            self.records += children.len();
+            self.related.push((path.clone(), children.len()));
+            for child in children {
+                self.walk(&format!("{}/", path), child)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Navigation property name for a Dataverse lookup reference, if `field` is one
+fn lookup_navigation(field: &str) -> Option<&str> {
+    if let Some(nav) = field.strip_suffix("@odata.bind") {
+        return Some(nav);
+    }
+    field
+        .strip_prefix('_')
+        .and_then(|f| f.strip_suffix("_value"))
+        .filter(|nav| !nav.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_clear_fields_become_nulls_and_refs() {
+        let payload = UpdatePayload::build(
+            object(json!({ "name": "Contoso" })),
+            &[
+                "telephone1".to_string(),
+                "_primarycontactid_value".to_string(),
+                "parentaccountid@odata.bind".to_string(),
+            ],
+            &ProductType::Dataverse,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Value::Object(payload.body),
+            json!({ "name": "Contoso", "telephone1": null })
+        );
+        assert_eq!(payload.lookups_to_clear, vec!["primarycontactid", "parentaccountid"]);
+    }
+
+    #[test]
+    fn test_finops_has_no_ref_disassociation() {
+        let payload = UpdatePayload::build(
+            Map::new(),
+            &["_CustomerGroup_value".to_string()],
+            &ProductType::Finops,
+        )
+        .unwrap();
+        assert!(payload.lookups_to_clear.is_empty());
+        assert_eq!(payload.body.get("_CustomerGroup_value"), Some(&Value::Null));
+    }
+
+    #[test]
+    fn test_deep_insert_analyze() {
+        let body = object(json!({
+            "name": "Contoso",
+            "primarycontactid": { "firstname": "Ann" },
+            "contact_customer_accounts": [
+                { "firstname": "Bob", "Contact_Tasks": [{ "subject": "Call" }] },
+                { "firstname": "Eve", "parentcustomerid_account@odata.bind": "/accounts(1)" }
+            ],
+            "transactioncurrencyid@odata.bind": "/transactioncurrencies(2)"
+        }));
+        let plan = DeepInsert::analyze(&body).unwrap();
+        assert_eq!(plan.records, 5);
+        assert_eq!(plan.navigations, vec!["contact_customer_accounts", "primarycontactid"]);
+        assert_eq!(plan.binds, 2);
+        assert!(plan
+            .related
+            .contains(&("contact_customer_accounts/Contact_Tasks".to_string(), 1)));
+
+        let bad = object(json!({ "contact_customer_accounts": [{ "firstname": "Bob" }, 3] }));
+        assert!(DeepInsert::analyze(&bad).is_err());
+    }
+
+    #[test]
+    fn test_set_and_clear_same_field_is_rejected() {
+        let result = UpdatePayload::build(
+            object(json!({ "primarycontactid@odata.bind": "/contacts(1)" })),
+            &["_primarycontactid_value".to_string()],
+            &ProductType::Dataverse,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Reproducible scripts for tool calls
+//!
+//! Records the OData requests made while a tool runs and renders them as a
+//! raw URL, a curl command and a PowerShell `Invoke-RestMethod` snippet, so a
+//! finding from an MCP session can be moved into a scheduled script.
+
+use serde_json::Value;
+use std::cell::RefCell;
+use std::future::Future;
+
+/// Headers that are copied into scripts (authorization is stubbed separately)
+const SCRIPT_HEADERS: &[&str] = &[
+    "Accept",
+    "OData-MaxVersion",
+    "OData-Version",
+    "Prefer",
+    "If-Match",
+    "If-None-Match",
+    "Content-Type",
+    "CallerObjectId",
+    "MSCRMCallerID",
+];
+
+/// One OData request as sent by the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Value>,
+}
+
+tokio::task_local! {
+    static RECORDED: RefCell<Vec<RecordedRequest>>;
+}
+
+/// Run `future`, collecting every request the client sends from this task
+pub async fn record_requests<F: Future>(future: F) -> (F::Output, Vec<RecordedRequest>) {
+    RECORDED
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = future.await;
+            let requests = RECORDED.with(|r| r.take());
+            (output, requests)
+        })
+        .await
+}
+
+/// Record a request if a recording scope is active
+pub(crate) fn record(request: RecordedRequest) {
+    let _ = RECORDED.try_with(|r| r.borrow_mut().push(request));
+}
+
+/// Render recorded requests as URL, curl and PowerShell snippets.
+///
+/// `resource` is the token audience, e.g. `https://org.crm.dynamics.com`.
+pub fn render_scripts(requests: &[RecordedRequest], resource: &str) -> String {
+    if requests.is_empty() {
+        return "No D365 requests were made by this call.".to_string();
+    }
+
+    let mut text = String::from("## Reproduce this call\n");
+    for (i, request) in requests.iter().enumerate() {
+        text.push_str(&format!("\n### Request {}: {} {}\n", i + 1, request.method, request.url));
+        text.push_str(&format!("\nOData URL:\n```\n{}\n```\n", request.url));
+        text.push_str(&format!("\ncurl:\n```bash\n{}\n```\n", curl_command(request, resource)));
+        text.push_str(&format!(
+            "\nPowerShell:\n```powershell\n{}\n```\n",
+            powershell_snippet(request, resource)
+        ));
+    }
+    text
+}
+
+fn script_headers(request: &RecordedRequest) -> impl Iterator<Item = &(String, String)> {
+    request
+        .headers
+        .iter()
+        .filter(|(name, _)| SCRIPT_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)))
+}
+
+fn curl_command(request: &RecordedRequest, resource: &str) -> String {
+    let mut lines = vec![
+        "# Token acquisition stub: replace with your own flow (client credentials, managed identity, ...)".to_string(),
+        format!(
+            "TOKEN=$(az account get-access-token --resource {} --query accessToken -o tsv)",
+            resource
+        ),
+        format!("curl -X {} {} \\", request.method, shell_quote(&request.url)),
+        "  -H \"Authorization: Bearer $TOKEN\" \\".to_string(),
+    ];
+    for (name, value) in script_headers(request) {
+        lines.push(format!("  -H {} \\", shell_quote(&format!("{}: {}", name, value))));
+    }
+    match &request.body {
+        // Raw bodies (e.g. $batch) carry their own Content-Type header
+        Some(Value::String(text)) => lines.push(format!("  --data-binary {} \\", shell_quote(text))),
+        Some(body) => {
+            lines.push("  -H 'Content-Type: application/json' \\".to_string());
+            lines.push(format!("  --data {} \\", shell_quote(&body.to_string())));
+        }
+        None => {}
+    }
+    let last = lines.len() - 1;
+    lines[last] = lines[last].trim_end_matches(" \\").to_string();
+    lines.join("\n")
+}
+
+fn powershell_snippet(request: &RecordedRequest, resource: &str) -> String {
+    let mut lines = vec![
+        "# Token acquisition stub: replace with your own flow (client credentials, managed identity, ...)".to_string(),
+        format!("$token = (Get-AzAccessToken -ResourceUrl {}).Token", ps_quote(resource)),
+        "$headers = @{".to_string(),
+        "    Authorization = \"Bearer $token\"".to_string(),
+    ];
+    for (name, value) in script_headers(request) {
+        lines.push(format!("    {} = {}", ps_quote(name), ps_quote(value)));
+    }
+    lines.push("}".to_string());
+
+    let method = capitalize(&request.method);
+    match &request.body {
+        Some(Value::String(text)) => {
+            lines.push(format!("$body = @'\n{}\n'@", text));
+            lines.push(format!(
+                "Invoke-RestMethod -Method {} -Uri {} -Headers $headers -Body $body",
+                method,
+                ps_quote(&request.url)
+            ));
+        }
+        Some(body) => {
+            lines.push(format!("$body = @'\n{}\n'@", serde_json::to_string_pretty(body).unwrap_or_default()));
+            lines.push(format!(
+                "Invoke-RestMethod -Method {} -Uri {} -Headers $headers -ContentType 'application/json' -Body $body",
+                method,
+                ps_quote(&request.url)
+            ));
+        }
+        None => lines.push(format!(
+            "Invoke-RestMethod -Method {} -Uri {} -Headers $headers",
+            method,
+            ps_quote(&request.url)
+        )),
+    }
+    lines.join("\n")
+}
+
+/// Single-quote for POSIX shells
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Single-quote for PowerShell (no interpolation, `'` doubled)
+fn ps_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+fn capitalize(method: &str) -> String {
+    let lower = method.to_ascii_lowercase();
+    let mut chars = lower.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request() -> RecordedRequest {
+        RecordedRequest {
+            method: "PATCH".to_string(),
+            url: "https://org.crm.dynamics.com/api/data/v9.2/accounts(1)?$select=name".to_string(),
+            headers: vec![
+                ("Authorization".to_string(), "Bearer secret".to_string()),
+                ("If-Match".to_string(), "*".to_string()),
+            ],
+            body: Some(json!({ "name": "O'Brien" })),
+        }
+    }
+
+    #[test]
+    fn test_curl_command() {
+        let curl = curl_command(&request(), "https://org.crm.dynamics.com");
+        assert!(curl.contains("curl -X PATCH 'https://org.crm.dynamics.com/api/data/v9.2/accounts(1)?$select=name' \\"));
+        assert!(curl.contains("-H 'If-Match: *'"));
+        assert!(curl.ends_with("--data '{\"name\":\"O'\\''Brien\"}'"));
+        assert!(!curl.contains("secret"));
+    }
+
+    #[test]
+    fn test_powershell_snippet() {
+        let ps = powershell_snippet(&request(), "https://org.crm.dynamics.com");
+        assert!(ps.contains("Get-AzAccessToken -ResourceUrl 'https://org.crm.dynamics.com'"));
+        assert!(ps.contains("'If-Match' = '*'"));
+        assert!(ps.contains("Invoke-RestMethod -Method Patch -Uri"));
+        assert!(!ps.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_record_requests_scope() {
+        record(request()); // outside a scope: ignored
+        let ((), recorded) = record_requests(async { record(request()) }).await;
+        assert_eq!(recorded, vec![request()]);
+    }
+}
@@ -0,0 +1,288 @@
+//! Memory-bounded collection of fetched records
+//!
+//! Reading every page of a large entity into one `Vec` can exhaust memory.
+//! [`RecordSpill`] keeps records in memory until a record or byte limit is
+//! crossed, then writes everything to a JSON Lines file and appends the
+//! remaining pages there. The caller gets either the records or the file.
+
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Default records held in memory before spilling
+pub const DEFAULT_MAX_RECORDS: usize = 100_000;
+
+/// Default page bytes held in memory before spilling
+pub const DEFAULT_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+/// When fetched records move from memory to a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpillLimits {
+    /// Records kept in memory
+    pub max_records: usize,
+    /// Bytes of page bodies kept in memory
+    pub max_bytes: usize,
+    /// Directory of spill files
+    pub dir: PathBuf,
+}
+
+impl Default for SpillLimits {
+    fn default() -> Self {
+        Self {
+            max_records: DEFAULT_MAX_RECORDS,
+            max_bytes: DEFAULT_MAX_BYTES,
+            dir: crate::config::paths::default_spill_dir(),
+        }
+    }
+}
+
+impl SpillLimits {
+    /// Limits that never spill
+    pub fn unbounded() -> Self {
+        Self {
+            max_records: usize::MAX,
+            max_bytes: usize::MAX,
+            dir: PathBuf::new(),
+        }
+    }
+}
+
+/// Records of a bounded fetch
+#[derive(Debug)]
+pub enum FetchedRecords {
+    /// Everything fit within the limits
+    InMemory(Vec<Value>),
+    /// Records were written to a JSON Lines file
+    Spilled(SpillFile),
+}
+
+impl FetchedRecords {
+    /// Number of records
+    pub fn len(&self) -> usize {
+        match self {
+            FetchedRecords::InMemory(records) => records.len(),
+            FetchedRecords::Spilled(file) => file.records,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All records in memory, reading back a spill file
+    pub fn into_vec(self) -> io::Result<Vec<Value>> {
+        match self {
+            FetchedRecords::InMemory(records) => Ok(records),
+            FetchedRecords::Spilled(file) => file.records()?.collect(),
+        }
+    }
+}
+
+/// JSON Lines file of spilled records, deleted when dropped unless kept
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    /// Records in the file
+    pub records: usize,
+    /// Size of the file
+    pub bytes: u64,
+    keep: bool,
+}
+
+impl SpillFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the records back, one per line
+    pub fn records(&self) -> io::Result<impl Iterator<Item = io::Result<Value>>> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        Ok(reader.lines().map(|line| {
+            let line = line?;
+            serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }))
+    }
+
+    /// Keep the file after this handle is dropped and return its path
+    pub fn keep(mut self) -> PathBuf {
+        self.keep = true;
+        self.path.clone()
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Collects pages of records, spilling to a file past the limits
+#[derive(Debug)]
+pub struct RecordSpill {
+    limits: SpillLimits,
+    /// Name of the spill file, without extension
+    name: String,
+    records: Vec<Value>,
+    bytes: usize,
+    file: Option<SpillWriter>,
+}
+
+/// Open spill file; dropping it on an error removes the file
+#[derive(Debug)]
+struct SpillWriter {
+    writer: BufWriter<File>,
+    spilled: SpillFile,
+}
+
+impl RecordSpill {
+    /// Collect records of `entity` within `limits`
+    pub fn new(entity: &str, limits: SpillLimits) -> Self {
+        let entity: String = entity
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+            .collect();
+        Self {
+            limits,
+            name: format!("{}-{}", entity, crate::odata::correlation::new_request_id()),
+            records: Vec::new(),
+            bytes: 0,
+            file: None,
+        }
+    }
+
+    /// Add a page of records that arrived in a body of `page_bytes`
+    pub fn push_page(&mut self, records: Vec<Value>, page_bytes: usize) -> io::Result<()> {
+        if self.file.is_none() {
+            self.bytes = self.bytes.saturating_add(page_bytes);
+            self.records.extend(records);
+            if self.records.len() > self.limits.max_records || self.bytes > self.limits.max_bytes {
+                self.spill()?;
+            }
+            return Ok(());
+        }
+        let file = self.file.as_mut().expect("spill file is open");
+        for record in &records {
+            write_line(&mut file.writer, record)?;
+        }
+        file.spilled.records += records.len();
+        Ok(())
+    }
+
+    /// Records collected so far
+    pub fn len(&self) -> usize {
+        match &self.file {
+            Some(file) => file.spilled.records,
+            None => self.records.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Finish collecting; an open spill file is flushed and handed over
+    pub fn finish(self) -> io::Result<FetchedRecords> {
+        let Some(file) = self.file else {
+            return Ok(FetchedRecords::InMemory(self.records));
+        };
+        let SpillWriter { writer, mut spilled } = file;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        spilled.bytes = fs::metadata(&spilled.path)?.len();
+        Ok(FetchedRecords::Spilled(spilled))
+    }
+
+    /// Move the records held in memory to a new spill file
+    fn spill(&mut self) -> io::Result<()> {
+        fs::create_dir_all(&self.limits.dir)?;
+        let path = self.limits.dir.join(format!("{}.jsonl", self.name));
+        tracing::info!(
+            "Fetched {} records ({} bytes), beyond the in-memory limit; spilling to {}",
+            self.records.len(),
+            self.bytes,
+            path.display()
+        );
+        let mut file = SpillWriter {
+            writer: BufWriter::new(File::create(&path)?),
+            spilled: SpillFile {
+                path,
+                records: self.records.len(),
+                bytes: 0,
+                keep: false,
+            },
+        };
+        for record in &self.records {
+            write_line(&mut file.writer, record)?;
+        }
+        self.file = Some(file);
+        self.records = Vec::new();
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+fn write_line(writer: &mut impl Write, record: &Value) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn limits(name: &str, max_records: usize, max_bytes: usize) -> SpillLimits {
+        SpillLimits {
+            max_records,
+            max_bytes,
+            dir: std::env::temp_dir().join(format!("d365-spill-{}-{}", std::process::id(), name)),
+        }
+    }
+
+    #[test]
+    fn test_stays_in_memory_within_limits() {
+        let mut spill = RecordSpill::new("accounts", limits("memory", 3, 1000));
+        spill.push_page(vec![json!({"n": 1}), json!({"n": 2})], 100).unwrap();
+        spill.push_page(vec![json!({"n": 3})], 100).unwrap();
+        match spill.finish().unwrap() {
+            FetchedRecords::InMemory(records) => assert_eq!(records.len(), 3),
+            FetchedRecords::Spilled(_) => panic!("spilled within the limits"),
+        }
+    }
+
+    #[test]
+    fn test_spills_past_record_limit() {
+        let mut spill = RecordSpill::new("my/entity", limits("records", 2, usize::MAX));
+        spill.push_page(vec![json!({"n": 1}), json!({"n": 2})], 10).unwrap();
+        spill.push_page(vec![json!({"n": 3})], 10).unwrap();
+        spill.push_page(vec![json!({"n": 4}), json!({"n": 5})], 10).unwrap();
+        assert_eq!(spill.len(), 5);
+
+        let FetchedRecords::Spilled(file) = spill.finish().unwrap() else {
+            panic!("expected a spill file");
+        };
+        assert_eq!(file.records, 5);
+        assert!(file.bytes > 0);
+        let name = file.path().file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("my_entity-") && name.ends_with(".jsonl"));
+        let path = file.path().to_path_buf();
+        let records = FetchedRecords::Spilled(file).into_vec().unwrap();
+        assert_eq!(records.iter().map(|r| r["n"].as_i64().unwrap()).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+        assert!(!path.exists(), "spill file is removed when dropped");
+    }
+
+    #[test]
+    fn test_spills_past_byte_limit_and_keep() {
+        let mut spill = RecordSpill::new("accounts", limits("bytes", usize::MAX, 150));
+        spill.push_page(vec![json!({"n": 1})], 100).unwrap();
+        spill.push_page(vec![json!({"n": 2})], 100).unwrap();
+        let FetchedRecords::Spilled(file) = spill.finish().unwrap() else {
+            panic!("expected a spill file");
+        };
+        let path = file.keep();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
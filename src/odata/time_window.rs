@@ -0,0 +1,215 @@
+//! Relative time windows for audit-field filters
+//!
+//! Turns arguments like `modified_within = "7d"` into UTC `$filter`
+//! expressions on the product's audit fields.
+
+use crate::config::ProductType;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Which audit timestamp a window applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditField {
+    Modified,
+    Created,
+}
+
+impl AuditField {
+    /// Audit field name for the product
+    pub fn name(self, product: &ProductType) -> &'static str {
+        match (self, product) {
+            (AuditField::Modified, ProductType::Dataverse) => "modifiedon",
+            (AuditField::Created, ProductType::Dataverse) => "createdon",
+            (AuditField::Modified, ProductType::Finops) => "ModifiedDateTime",
+            (AuditField::Created, ProductType::Finops) => "CreatedDateTime",
+        }
+    }
+}
+
+/// Parse a window such as "30m", "24h", "7d" or "2w"
+pub fn parse_window(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("Invalid time window '{}': missing unit (m, h, d or w)", text))?;
+    let (amount, unit) = text.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid time window '{}': expected e.g. '24h' or '7d'", text))?;
+
+    let unit_secs = match unit.trim().to_ascii_lowercase().as_str() {
+        "m" | "min" | "mins" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hours" => 3600,
+        "d" | "day" | "days" => 86_400,
+        "w" | "week" | "weeks" => 604_800,
+        _ => {
+            return Err(format!(
+                "Invalid time window '{}': unit must be m, h, d or w",
+                text
+            ))
+        }
+    };
+
+    if amount == 0 {
+        return Err(format!("Invalid time window '{}': must be greater than zero", text));
+    }
+    Ok(Duration::from_secs(amount.saturating_mul(unit_secs)))
+}
+
+/// Format a time as an OData `DateTimeOffset` literal in UTC (`2024-01-31T08:00:00Z`)
+pub fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+/// Parse an OData `DateTimeOffset` value such as `2024-01-31T08:00:00Z`,
+/// `2024-01-31T08:00:00.123Z` or `2024-01-31T10:00:00+02:00`. Fractional
+/// seconds are dropped.
+pub fn parse_utc(text: &str) -> Option<SystemTime> {
+    let text = text.trim();
+    let (date, time) = text.split_once('T')?;
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+
+    let (clock, offset_secs) = if let Some(clock) = time.strip_suffix('Z') {
+        (clock, 0)
+    } else {
+        let sign_at = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(sign_at);
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        let secs = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+        (clock, if offset.starts_with('-') { -secs } else { secs })
+    };
+    let clock = clock.split('.').next()?;
+    let mut clock_parts = clock.splitn(3, ':');
+    let hour: i64 = clock_parts.next()?.parse().ok()?;
+    let minute: i64 = clock_parts.next()?.parse().ok()?;
+    let second: i64 = clock_parts.next().unwrap_or("0").parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_secs;
+    u64::try_from(secs).ok().map(|s| UNIX_EPOCH + Duration::from_secs(s))
+}
+
+/// Parse an HTTP-date (`Sun, 06 Nov 1994 08:49:37 GMT`), as sent in
+/// `Retry-After`. The obsolete RFC 850 and asctime forms are not accepted.
+pub fn parse_http_date(text: &str) -> Option<SystemTime> {
+    let (_, rest) = text.trim().split_once(", ")?;
+    let mut parts = rest.split_ascii_whitespace();
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let clock = parts.next()?;
+    if parts.next() != Some("GMT") || parts.next().is_some() {
+        return None;
+    }
+    let date = format!("{:04}-{:02}-{:02}T{}Z", year, month, day, clock);
+    parse_utc(&date)
+}
+
+/// Month names of HTTP-dates
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// `<field> ge <now - window>` for the product's audit field
+pub fn within_filter(field: AuditField, product: &ProductType, window: Duration, now: SystemTime) -> String {
+    let since = now.checked_sub(window).unwrap_or(UNIX_EPOCH);
+    format!("{} ge {}", field.name(product), format_utc(since))
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) civil date to days since 1970-01-01
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("24h").unwrap(), Duration::from_secs(86_400));
+        assert_eq!(parse_window("7d").unwrap(), Duration::from_secs(604_800));
+        assert_eq!(parse_window("30 min").unwrap(), Duration::from_secs(1800));
+        assert!(parse_window("7").is_err());
+        assert!(parse_window("0d").is_err());
+        assert!(parse_window("3y").is_err());
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let t = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(format_utc(t), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let t = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(t));
+        assert_eq!(parse_http_date(" Sun, 06 Nov 1994 08:49:37 GMT "), Some(t));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("120"), None);
+    }
+
+    #[test]
+    fn test_parse_utc() {
+        let t = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(parse_utc("2024-02-29T12:34:56Z"), Some(t));
+        assert_eq!(parse_utc("2024-02-29T12:34:56.789Z"), Some(t));
+        assert_eq!(parse_utc("2024-02-29T14:34:56+02:00"), Some(t));
+        assert_eq!(parse_utc("2024-02-29T07:34:56-05:00"), Some(t));
+        assert_eq!(parse_utc("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(parse_utc("2024-02-29"), None);
+        assert_eq!(parse_utc("2024-13-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn test_within_filter_per_product() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        let week = Duration::from_secs(604_800);
+        assert_eq!(
+            within_filter(AuditField::Modified, &ProductType::Dataverse, week, now),
+            "modifiedon ge 2024-02-22T12:34:56Z"
+        );
+        assert_eq!(
+            within_filter(AuditField::Created, &ProductType::Finops, week, now),
+            "CreatedDateTime ge 2024-02-22T12:34:56Z"
+        );
+    }
+}
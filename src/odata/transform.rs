@@ -0,0 +1,214 @@
+//! Record shaping for tool output
+//!
+//! Post-processing applied to fetched records before they are rendered,
+//! e.g. flattening `$expand`ed navigation properties into plain columns or
+//! slimming away annotations.
+
+use serde_json::{Map, Value};
+
+/// Dataverse annotation carrying the display text of a value
+const FORMATTED_VALUE: &str = "@OData.Community.Display.V1.FormattedValue";
+
+/// Maximum items listed when summarizing an expanded collection
+const SUMMARY_MAX_ITEMS: usize = 10;
+
+/// How expanded collection-valued navigation properties are flattened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollectionMode {
+    /// Replace the collection with its item count
+    #[default]
+    Count,
+    /// Replace the collection with a `; `-separated list of each item's first text field
+    Summary,
+}
+
+impl CollectionMode {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "count" => Ok(CollectionMode::Count),
+            "summary" => Ok(CollectionMode::Summary),
+            other => Err(format!(
+                "Invalid flatten_collections '{}': expected 'count' or 'summary'",
+                other
+            )),
+        }
+    }
+}
+
+/// Flatten expanded navigation properties into prefixed columns.
+///
+/// Single-valued navs become `nav.field` columns (recursively for nested
+/// expands); collections become counts or summaries. `@odata.*` annotations
+/// of expanded records are dropped.
+pub fn flatten_record(record: &Value, mode: CollectionMode) -> Value {
+    let Value::Object(fields) = record else {
+        return record.clone();
+    };
+    let mut flat = Map::new();
+    flatten_into(&mut flat, "", fields, mode);
+    Value::Object(flat)
+}
+
+fn flatten_into(flat: &mut Map<String, Value>, prefix: &str, fields: &Map<String, Value>, mode: CollectionMode) {
+    for (name, value) in fields {
+        if !prefix.is_empty() && name.starts_with("@odata.") {
+            continue;
+        }
+        let column = format!("{}{}", prefix, name);
+        match value {
+            Value::Object(nested) => flatten_into(flat, &format!("{}.", column), nested, mode),
+            Value::Array(items) if items.iter().all(Value::is_object) && !items.is_empty() => {
+                flat.insert(column, collection_value(items, mode));
+            }
+            _ => {
+                flat.insert(column, value.clone());
+            }
+        }
+    }
+}
+
+fn collection_value(items: &[Value], mode: CollectionMode) -> Value {
+    match mode {
+        CollectionMode::Count => Value::from(items.len()),
+        CollectionMode::Summary => {
+            let mut labels: Vec<String> = items
+                .iter()
+                .take(SUMMARY_MAX_ITEMS)
+                .map(|item| item_label(item).unwrap_or_else(|| "?".to_string()))
+                .collect();
+            if items.len() > SUMMARY_MAX_ITEMS {
+                labels.push(format!("(+{} more)", items.len() - SUMMARY_MAX_ITEMS));
+            }
+            Value::String(labels.join("; "))
+        }
+    }
+}
+
+/// First non-annotation text value of an expanded item
+fn item_label(item: &Value) -> Option<String> {
+    item.as_object()?
+        .iter()
+        .filter(|(name, _)| !name.contains('@'))
+        .find_map(|(_, value)| value.as_str().map(String::from))
+}
+
+/// Strip a record down to what an LLM needs.
+///
+/// Drops `@odata.*` and other annotations, null fields, and the raw GUIDs of
+/// Dataverse lookups. A lookup's formatted value is kept under the lookup
+/// name (`_primarycontactid_value` becomes `primarycontactid`); other
+/// formatted values are kept as `<field>@formatted`. Applies to expanded
+/// records too.
+pub fn slim_record(record: &Value) -> Value {
+    match record {
+        Value::Object(fields) => {
+            let mut slim = Map::new();
+            for (name, value) in fields {
+                if value.is_null() {
+                    continue;
+                }
+                if let Some(field) = name.strip_suffix(FORMATTED_VALUE) {
+                    let key = match lookup_name(field) {
+                        Some(lookup) if !fields.contains_key(lookup) => lookup.to_string(),
+                        _ => format!("{}@formatted", field),
+                    };
+                    slim.insert(key, value.clone());
+                    continue;
+                }
+                if name.contains('@') {
+                    continue;
+                }
+                let formatted_key = format!("{}{}", name, FORMATTED_VALUE);
+                if lookup_name(name).is_some() && fields.contains_key(&formatted_key) {
+                    continue;
+                }
+                slim.insert(name.clone(), slim_record(value));
+            }
+            Value::Object(slim)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(slim_record).collect()),
+        other => other.clone(),
+    }
+}
+
+/// `primarycontactid` for a Dataverse lookup column `_primarycontactid_value`
+fn lookup_name(field: &str) -> Option<&str> {
+    field
+        .strip_prefix('_')
+        .and_then(|f| f.strip_suffix("_value"))
+        .filter(|f| !f.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_flatten_single_valued_and_nested() {
+        let record = json!({
+            "name": "Contoso",
+            "primarycontactid": {
+                "@odata.etag": "W/\"1\"",
+                "fullname": "Ann Lee",
+                "parentcustomerid_account": { "name": "Parent Co" }
+            },
+            "tags": ["a", "b"]
+        });
+        let flat = flatten_record(&record, CollectionMode::Count);
+        assert_eq!(
+            flat,
+            json!({
+                "name": "Contoso",
+                "primarycontactid.fullname": "Ann Lee",
+                "primarycontactid.parentcustomerid_account.name": "Parent Co",
+                "tags": ["a", "b"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_slim_record() {
+        let record = json!({
+            "@odata.etag": "W/\"123\"",
+            "name": "Contoso",
+            "telephone1": null,
+            "statecode": 0,
+            "statecode@OData.Community.Display.V1.FormattedValue": "Active",
+            "_primarycontactid_value": "7a1b...",
+            "_primarycontactid_value@OData.Community.Display.V1.FormattedValue": "Ann Lee",
+            "_primarycontactid_value@Microsoft.Dynamics.CRM.lookuplogicalname": "contact",
+            "_ownerid_value": "9c2d...",
+            "contact_customer_accounts": [{ "@odata.etag": "x", "fullname": "Bob", "mobilephone": null }]
+        });
+        assert_eq!(
+            slim_record(&record),
+            json!({
+                "name": "Contoso",
+                "statecode": 0,
+                "statecode@formatted": "Active",
+                "primarycontactid": "Ann Lee",
+                "_ownerid_value": "9c2d...",
+                "contact_customer_accounts": [{ "fullname": "Bob" }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_flatten_collections() {
+        let record = json!({
+            "contact_customer_accounts": [
+                { "@odata.etag": "x", "fullname": "Ann" },
+                { "fullname": "Bob" }
+            ]
+        });
+        assert_eq!(
+            flatten_record(&record, CollectionMode::Count)["contact_customer_accounts"],
+            2
+        );
+        assert_eq!(
+            flatten_record(&record, CollectionMode::Summary)["contact_customer_accounts"],
+            "Ann; Bob"
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! Write payload validation against $metadata
+//!
+//! Create and update payloads are checked against the entity type before
+//! they are sent, so a wrong field name or value type is reported as
+//! "field X expects Edm.DateTimeOffset" instead of an opaque 400 from the
+//! service. The same rules are published as a JSON Schema per entity.
+//!
+//! Only what the metadata states is enforced: missing non-nullable fields
+//! are not reported, since the service fills many of them with defaults.
+
+use crate::odata::client::is_guid;
+use crate::odata::metadata::{suggest_names, unqualified, EdmModel, EntityType, Property};
+use crate::odata::time_window::parse_utc;
+use serde_json::{json, Map, Value};
+
+/// Invalid fields listed in one error
+const MAX_REPORTED_ERRORS: usize = 20;
+
+/// What the payload is for; update payloads may not set key fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    Create,
+    Update,
+}
+
+impl PayloadKind {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "create" => Ok(PayloadKind::Create),
+            "update" => Ok(PayloadKind::Update),
+            other => Err(format!("Invalid payload kind '{}': expected create or update", other)),
+        }
+    }
+}
+
+/// Entity type behind an entity set; exact set names only, so a typo is
+/// left to the service instead of being validated against the wrong type
+pub fn entity_type_for_payload<'a>(model: &'a EdmModel, entity: &str) -> Option<&'a EntityType> {
+    model
+        .entity_sets
+        .iter()
+        .find(|s| s.name == entity)
+        .and_then(|s| model.entity_type_for_set(s))
+}
+
+/// JSON Schema of one property value
+pub fn property_schema(model: &EdmModel, property: &Property) -> Value {
+    let mut schema = match property.edm_type.as_str() {
+        "Edm.Guid" => json!({ "type": "string", "format": "uuid" }),
+        "Edm.Byte" | "Edm.SByte" | "Edm.Int16" | "Edm.Int32" | "Edm.Int64" => json!({ "type": "integer" }),
+        "Edm.Decimal" | "Edm.Double" | "Edm.Single" => json!({ "type": "number" }),
+        "Edm.Boolean" => json!({ "type": "boolean" }),
+        "Edm.DateTimeOffset" => json!({ "type": "string", "format": "date-time" }),
+        "Edm.Date" => json!({ "type": "string", "format": "date" }),
+        other => match model.enum_type(other).filter(|_| !other.starts_with("Edm.")) {
+            Some(e) => json!({ "type": "string", "enum": e.members.iter().map(|m| &m.name).collect::<Vec<_>>() }),
+            None => json!({ "type": "string" }),
+        },
+    };
+    if let Some(max) = property.max_length.as_deref().and_then(|m| m.parse::<u64>().ok()) {
+        schema["maxLength"] = json!(max);
+    }
+    schema["description"] = json!(format!("{} ({})", property.name, unqualified(&property.edm_type)));
+    schema
+}
+
+/// JSON Schema of a create or update payload for an entity type.
+///
+/// Nullable fields also accept `null`; navigation properties accept nested
+/// records (deep insert) and `<nav>@odata.bind` references.
+pub fn payload_schema(model: &EdmModel, entity_type: &EntityType, kind: PayloadKind) -> Value {
+    let keys = model.keys_of(entity_type);
+    let mut properties = Map::new();
+    for property in model.properties_of(entity_type) {
+        if kind == PayloadKind::Update && keys.contains(&property.name) {
+            continue;
+        }
+        let mut schema = match collection_item(&property.edm_type) {
+            Some(item) => {
+                let item = Property { edm_type: item.to_string(), ..property.clone() };
+                json!({ "type": "array", "items": property_schema(model, &item) })
+            }
+            None => property_schema(model, property),
+        };
+        if property.nullable {
+            let ty = schema["type"].clone();
+            schema["type"] = json!([ty, "null"]);
+        }
+        properties.insert(property.name.clone(), schema);
+    }
+    for nav in model.navigation_properties_of(entity_type) {
+        let target = unqualified(&nav.target);
+        let schema = if nav.collection {
+            json!({ "type": "array", "items": { "type": "object" }, "description": format!("New {} records (deep insert)", target) })
+        } else {
+            json!({ "type": "object", "description": format!("New {} record (deep insert)", target) })
+        };
+        properties.insert(nav.name.clone(), schema);
+        properties.insert(
+            format!("{}@odata.bind", nav.name),
+            json!({ "type": "string", "description": format!("Reference to an existing {}, e.g. /<entity set>(<key>)", target) }),
+        );
+    }
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": format!("{} {} payload", entity_type.name, match kind {
+            PayloadKind::Create => "create",
+            PayloadKind::Update => "update",
+        }),
+        "type": "object",
+        "properties": properties,
+        "patternProperties": { "@": {} },
+        "additionalProperties": false,
+    })
+}
+
+/// Check a payload against an entity type; returns one message per invalid field
+pub fn validate_payload(
+    model: &EdmModel,
+    entity_type: &EntityType,
+    payload: &Map<String, Value>,
+    kind: PayloadKind,
+) -> Result<(), Vec<String>> {
+    let properties = model.properties_of(entity_type);
+    let navigations = model.navigation_properties_of(entity_type);
+    let keys = model.keys_of(entity_type);
+    let mut errors = Vec::new();
+
+    for (name, value) in payload {
+        if let Some((field, annotation)) = name.split_once('@') {
+            if annotation == "odata.bind" && !navigations.iter().any(|n| n.name == field) {
+                errors.push(unknown(
+                    field,
+                    "navigation property",
+                    entity_type,
+                    navigations.iter().map(|n| n.name.as_str()),
+                ));
+            }
+            continue;
+        }
+        if navigations.iter().any(|n| &n.name == name) {
+            continue;
+        }
+        let Some(property) = properties.iter().find(|p| &p.name == name) else {
+            let names = properties.iter().map(|p| p.name.as_str()).chain(navigations.iter().map(|n| n.name.as_str()));
+            errors.push(unknown(name, "field", entity_type, names));
+            continue;
+        };
+        if kind == PayloadKind::Update && keys.contains(name) {
+            errors.push(format!("Field '{}' is a key of {} and cannot be updated", name, entity_type.name));
+            continue;
+        }
+        if let Err(e) = check_value(model, property, value) {
+            errors.push(e);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        errors.truncate(MAX_REPORTED_ERRORS);
+        Err(errors)
+    }
+}
+
+fn unknown<'a>(name: &str, what: &str, entity_type: &EntityType, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    let mut message = format!("Unknown {} '{}' on {}", what, name, entity_type.name);
+    let suggestions = suggest_names(name, candidates, 3);
+    if !suggestions.is_empty() {
+        message.push_str(&format!(". Did you mean {}?", suggestions.join(" / ")));
+    }
+    message
+}
+
+fn check_value(model: &EdmModel, property: &Property, value: &Value) -> Result<(), String> {
+    if value.is_null() {
+        return match property.nullable {
+            true => Ok(()),
+            false => Err(format!("Field '{}' is not nullable", property.name)),
+        };
+    }
+    let Some(item) = collection_item(&property.edm_type) else {
+        return check_scalar(model, property, &property.edm_type, value);
+    };
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .try_for_each(|v| check_scalar(model, property, item, v)),
+        _ => Err(format!("Field '{}' expects an array of {}, got {}", property.name, item, describe(value))),
+    }
+}
+
+fn check_scalar(model: &EdmModel, property: &Property, edm_type: &str, value: &Value) -> Result<(), String> {
+    let valid = match edm_type {
+        "Edm.String" => match value.as_str() {
+            Some(s) => return check_length(property, s),
+            None => false,
+        },
+        "Edm.Guid" => value.as_str().is_some_and(is_guid),
+        "Edm.Boolean" => value.is_boolean(),
+        "Edm.Byte" => integer_in(value, 0, 255),
+        "Edm.SByte" => integer_in(value, -128, 127),
+        "Edm.Int16" => integer_in(value, i16::MIN.into(), i16::MAX.into()),
+        "Edm.Int32" => integer_in(value, i32::MIN.into(), i32::MAX.into()),
+        // IEEE754Compatible services exchange 64-bit and decimal numbers as strings
+        "Edm.Int64" => integer_in(value, i64::MIN, i64::MAX) || value.as_str().is_some_and(|s| s.parse::<i64>().is_ok()),
+        "Edm.Decimal" => value.is_number() || value.as_str().is_some_and(|s| s.parse::<f64>().is_ok()),
+        "Edm.Double" | "Edm.Single" => value.is_number(),
+        "Edm.DateTimeOffset" => value.as_str().and_then(parse_utc).is_some(),
+        "Edm.Date" => value.as_str().is_some_and(is_date),
+        other if other.starts_with("Edm.") => true,
+        other => match model.enum_type(other) {
+            Some(enum_type) => {
+                let member = |name: &str| enum_type.members.iter().any(|m| m.name == name.trim());
+                match value {
+                    Value::String(s) if enum_type.is_flags => s.split(',').all(member),
+                    Value::String(s) => member(s),
+                    Value::Number(n) => n.as_i64().is_some_and(|n| enum_type.members.iter().any(|m| m.value == n)),
+                    _ => false,
+                }
+            }
+            // Complex types are not modelled
+            None => true,
+        },
+    };
+    if valid {
+        return Ok(());
+    }
+    let expected = match edm_type {
+        "Edm.DateTimeOffset" => "Edm.DateTimeOffset (e.g. 2024-01-31T08:00:00Z)".to_string(),
+        "Edm.Date" => "Edm.Date (e.g. 2024-01-31)".to_string(),
+        "Edm.Guid" => "Edm.Guid (e.g. 00000000-0000-0000-0000-000000000001)".to_string(),
+        other => match model.enum_type(other).filter(|_| !other.starts_with("Edm.")) {
+            Some(e) => format!(
+                "{} ({})",
+                unqualified(other),
+                e.members.iter().map(|m| m.name.as_str()).collect::<Vec<_>>().join(", ")
+            ),
+            None => other.to_string(),
+        },
+    };
+    Err(format!("Field '{}' expects {}, got {}", property.name, expected, describe(value)))
+}
+
+fn check_length(property: &Property, text: &str) -> Result<(), String> {
+    match property.max_length.as_deref().and_then(|m| m.parse::<usize>().ok()) {
+        Some(max) if text.chars().count() > max => Err(format!(
+            "Field '{}' holds at most {} characters, got {}",
+            property.name,
+            max,
+            text.chars().count()
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn integer_in(value: &Value, min: i64, max: i64) -> bool {
+    value.as_i64().is_some_and(|n| (min..=max).contains(&n))
+}
+
+/// `YYYY-MM-DD`
+fn is_date(text: &str) -> bool {
+    text.len() == 10 && parse_utc(&format!("{}T00:00:00Z", text)).is_some()
+}
+
+/// Item type of `Collection(...)`
+fn collection_item(edm_type: &str) -> Option<&str> {
+    edm_type.strip_prefix("Collection(")?.strip_suffix(')')
+}
+
+/// Short rendering of a rejected value
+fn describe(value: &Value) -> String {
+    let text = value.to_string();
+    match text.char_indices().nth(40) {
+        Some((at, _)) => format!("{}...", &text[..at]),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = r#"<Schema Namespace="Microsoft.Dynamics.DataEntities">
+  <EnumType Name="NoYes"><Member Name="No" Value="0" /><Member Name="Yes" Value="1" /></EnumType>
+  <EntityType Name="CustomerV3">
+    <Key><PropertyRef Name="dataAreaId" /><PropertyRef Name="CustomerAccount" /></Key>
+    <Property Name="dataAreaId" Type="Edm.String" Nullable="false" MaxLength="4" />
+    <Property Name="CustomerAccount" Type="Edm.String" Nullable="false" MaxLength="20" />
+    <Property Name="OnHold" Type="Microsoft.Dynamics.DataEntities.NoYes" Nullable="false" />
+    <Property Name="CreditLimit" Type="Edm.Decimal" />
+    <Property Name="PaymentDay" Type="Edm.Int32" />
+    <Property Name="LastInvoiced" Type="Edm.DateTimeOffset" />
+    <NavigationProperty Name="CustomerGroup" Type="Microsoft.Dynamics.DataEntities.CustomerGroup" />
+  </EntityType>
+  <EntityContainer Name="Resources">
+    <EntitySet Name="CustomersV3" EntityType="Microsoft.Dynamics.DataEntities.CustomerV3" />
+  </EntityContainer>
+</Schema>"#;
+
+    fn payload(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_property_schema() {
+        let model = EdmModel::parse(MODEL);
+        let on_hold = &model.entity_types[0].properties[2];
+        assert_eq!(property_schema(&model, on_hold)["enum"], json!(["No", "Yes"]));
+    }
+
+    #[test]
+    fn test_payload_schema() {
+        let model = EdmModel::parse(MODEL);
+        let customer = entity_type_for_payload(&model, "CustomersV3").unwrap();
+        assert!(entity_type_for_payload(&model, "customersv3").is_none());
+
+        let create = payload_schema(&model, customer, PayloadKind::Create);
+        assert_eq!(create["properties"]["CustomerAccount"]["type"], "string");
+        assert_eq!(create["properties"]["CreditLimit"]["type"], json!(["number", "null"]));
+        assert!(create["properties"]["CustomerGroup@odata.bind"].is_object());
+        assert_eq!(create["additionalProperties"], false);
+
+        let update = payload_schema(&model, customer, PayloadKind::Update);
+        assert!(update["properties"].get("CustomerAccount").is_none());
+    }
+
+    #[test]
+    fn test_validate_payload() {
+        let model = EdmModel::parse(MODEL);
+        let customer = entity_type_for_payload(&model, "CustomersV3").unwrap();
+        let valid = payload(json!({
+            "dataAreaId": "usmf",
+            "CustomerAccount": "US-001",
+            "OnHold": "No",
+            "CreditLimit": "1000.50",
+            "PaymentDay": 15,
+            "LastInvoiced": "2024-01-31T08:00:00Z",
+            "CustomerGroup@odata.bind": "/CustomerGroups(dataAreaId='usmf',CustomerGroupId='10')",
+            "CustomerAccount@OData.Community.Display.V1.FormattedValue": "x"
+        }));
+        assert_eq!(validate_payload(&model, customer, &valid, PayloadKind::Create), Ok(()));
+
+        let invalid = payload(json!({
+            "CustomerAcount": "US-001",
+            "OnHold": "Maybe",
+            "PaymentDay": 1.5,
+            "LastInvoiced": "yesterday",
+            "dataAreaId": "toolong",
+            "Group@odata.bind": "/CustomerGroups('10')"
+        }));
+        let errors = validate_payload(&model, customer, &invalid, PayloadKind::Create).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "Unknown field 'CustomerAcount' on CustomerV3. Did you mean CustomerAccount / CustomerGroup?",
+                "Unknown navigation property 'Group' on CustomerV3. Did you mean CustomerGroup?",
+                "Field 'LastInvoiced' expects Edm.DateTimeOffset (e.g. 2024-01-31T08:00:00Z), got \"yesterday\"",
+                "Field 'OnHold' expects NoYes (No, Yes), got \"Maybe\"",
+                "Field 'PaymentDay' expects Edm.Int32, got 1.5",
+                "Field 'dataAreaId' holds at most 4 characters, got 7",
+            ]
+        );
+
+        let update = payload(json!({ "CustomerAccount": "US-002", "OnHold": null }));
+        let errors = validate_payload(&model, customer, &update, PayloadKind::Update).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "Field 'CustomerAccount' is a key of CustomerV3 and cannot be updated",
+                "Field 'OnHold' is not nullable",
+            ]
+        );
+    }
+}
@@ -0,0 +1,528 @@
+//! Change-tracking and watermark pulls
+//!
+//! The first pull of an entity reads every row with
+//! `Prefer: odata.track-changes` and keeps the returned delta link. Later
+//! pulls follow the stored link and get only rows created, updated or
+//! deleted since.
+//!
+//! Entities without change tracking (most F&O entities) fall back to a
+//! watermark: the highest value of a timestamp column such as `modifiedon`
+//! or `ModifiedDateTime`. Later pulls filter on `<field> ge <watermark -
+//! overlap>`; the overlap re-reads rows near the boundary so rows committed
+//! late or stamped by a skewed clock are not missed. Watermark pulls cannot
+//! see deletions.
+//!
+//! State is replaced only after every page of a pull was read. Long pulls
+//! save a checkpoint (the link of the next page) every few pages and when
+//! interrupted, so a pull over a large entity can be resumed instead of
+//! starting over.
+
+use super::state::{Checkpoint, EntitySyncState, StateStore, SyncPhase};
+use super::SyncError;
+use crate::config::{ProductType, RuntimeConfig};
+use crate::odata::time_window::{format_utc, parse_utc, AuditField};
+use crate::odata::{ODataClient, Partitioning, QueryOptions};
+use futures::StreamExt;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A record reported as removed by a delta response
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeletedRecord {
+    /// Record id (Dataverse GUID) or `@id` URL
+    pub id: String,
+    /// `deleted` or `changed` (moved out of the tracked set), when given
+    pub reason: Option<String>,
+}
+
+/// How changes of an entity are found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    ChangeTracking,
+    Watermark,
+}
+
+/// Timestamp column and overlap window of the watermark strategy
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub field: String,
+    pub overlap: Duration,
+}
+
+/// Settings of one pull
+#[derive(Debug, Clone)]
+pub struct PullOptions {
+    /// Ask for a delta link on initial pulls
+    pub change_tracking: bool,
+    /// Fallback when there is no delta link
+    pub watermark: Watermark,
+    /// Columns of initial pulls; the watermark field is always added
+    pub select: Option<Vec<String>>,
+    /// Continue from the stored checkpoint of an interrupted pull
+    pub resume: bool,
+    /// Save a checkpoint after this many pages (0 = only when interrupted)
+    pub checkpoint_pages: usize,
+    /// Read all F&O legal entities
+    pub cross_company: bool,
+    /// Deliver every existing row on the first pull; when false, tracking
+    /// starts from now and existing rows are skipped
+    pub initial_load: bool,
+    /// Keep the rows in the returned [`ChangeSet`]; off when pages are
+    /// consumed by the `on_page` callback only
+    pub collect: bool,
+    pub deadline: Option<Instant>,
+    /// Split fresh watermark loads into ranges read in parallel
+    pub partitioning: Option<Partitioning>,
+    /// Partitions read at once
+    pub concurrency: usize,
+}
+
+impl PullOptions {
+    /// Options for `entity` from the runtime config and its `[[entities]]` entry, if any.
+    /// F&O has no change tracking; a configured `watermark_field` opts out of it too.
+    pub fn for_entity(entity: &str, config: &RuntimeConfig) -> Self {
+        let configured = config.entity(entity);
+        let watermark_field = configured.and_then(|e| e.watermark_field.clone());
+        Self {
+            change_tracking: config.product == ProductType::Dataverse && watermark_field.is_none(),
+            watermark: Watermark {
+                field: watermark_field
+                    .unwrap_or_else(|| AuditField::Modified.name(&config.product).to_string()),
+                overlap: Duration::from_secs(config.delta_overlap_seconds),
+            },
+            select: None,
+            resume: false,
+            checkpoint_pages: config.delta_checkpoint_pages,
+            cross_company: configured.and_then(|e| e.cross_company).unwrap_or(false),
+            initial_load: configured.and_then(|e| e.initial_load).unwrap_or(true),
+            collect: true,
+            deadline: None,
+            partitioning: configured.and_then(|e| {
+                let by = e.partition_by.as_deref()?;
+                Some(Partitioning::parse(by, e.partitions.unwrap_or(config.concurrency)))
+            }),
+            concurrency: config.concurrency.max(1),
+        }
+    }
+}
+
+/// Result of one pull
+#[derive(Debug, Clone)]
+pub struct ChangeSet {
+    /// True when this pull finished the initial load
+    pub initial: bool,
+    /// The initial load was skipped (`initial_load = false`); no existing rows were delivered
+    pub initial_load_skipped: bool,
+    pub mode: SyncMode,
+    /// New or updated rows (watermark pulls may repeat rows inside the overlap window)
+    pub changed: Vec<Value>,
+    pub deleted: Vec<DeletedRecord>,
+    /// Counts of this pull, also kept when rows are not collected
+    pub changed_rows: usize,
+    pub deleted_rows: usize,
+    /// Rows read by earlier interrupted calls before the checkpoint this pull resumed from
+    pub resumed_rows: usize,
+    /// Delta link stored for the next pull
+    pub delta_link: Option<String>,
+    /// Watermark stored for the next pull
+    pub watermark: Option<String>,
+}
+
+/// Delta pulls with state kept in a [`StateStore`]
+pub struct DeltaSync {
+    client: Arc<ODataClient>,
+    store: Box<dyn StateStore>,
+}
+
+impl DeltaSync {
+    pub fn new(client: Arc<ODataClient>, store: Box<dyn StateStore>) -> Self {
+        Self { client, store }
+    }
+
+    pub fn store(&self) -> &dyn StateStore {
+        self.store.as_ref()
+    }
+
+    /// Pull the changes of `entity` since its last pull.
+    ///
+    /// Until an initial load has finished, pulls read every row (or skip
+    /// them with `initial_load = false`) and `change_tracking` selects
+    /// whether the read asks for a delta link; when the service returns none
+    /// the pull falls back to the watermark. Once the load has finished the
+    /// entity stays incremental in its mode until the state is removed.
+    ///
+    /// `on_page` sees every page before the checkpoint or state moves past
+    /// it; an error from it stops the pull at that page.
+    ///
+    /// A pull that fails or hits the deadline after reading rows saves a
+    /// checkpoint and returns [`SyncError::Interrupted`] with the collected
+    /// rows; a later pull with `resume` continues from the checkpoint.
+    pub async fn pull<F>(&self, entity: &str, pull: &PullOptions, mut on_page: F) -> Result<ChangeSet, SyncError>
+    where
+        F: FnMut(&[Value], usize) -> Result<(), SyncError>,
+    {
+        let started = SystemTime::now();
+        let previous = self.store.get(entity)?.unwrap_or_default();
+        // State written before phases were recorded has a position but no phase
+        let initial = previous.phase == SyncPhase::InitialLoad
+            && previous.delta_link.is_none()
+            && previous.watermark.is_none();
+        let skip_load = initial && !pull.initial_load;
+        // A stored watermark keeps the column it was taken from
+        let field = previous
+            .watermark_field
+            .clone()
+            .unwrap_or_else(|| pull.watermark.field.clone());
+
+        if skip_load && !pull.change_tracking {
+            // Nothing to read: changes are counted from now
+            let state = EntitySyncState {
+                watermark: Some(format_utc(started)),
+                watermark_field: Some(field),
+                last_sync: Some(format_utc(SystemTime::now())),
+                phase: SyncPhase::Incremental,
+                ..Default::default()
+            };
+            self.store.update(entity, state.clone())?;
+            return Ok(ChangeSet {
+                initial: true,
+                initial_load_skipped: true,
+                mode: SyncMode::Watermark,
+                changed: Vec::new(),
+                deleted: Vec::new(),
+                changed_rows: 0,
+                deleted_rows: 0,
+                resumed_rows: 0,
+                delta_link: None,
+                watermark: state.watermark,
+            });
+        }
+
+        let mut options = QueryOptions::default();
+        if skip_load {
+            // The rows are only read to obtain a delta link; keep them small
+            options.select = Some(vec![field.clone()]);
+            options.track_changes = true;
+            options.cross_company = pull.cross_company;
+        } else if initial {
+            options.select = pull.select.clone().map(|mut fields| {
+                if !fields.iter().any(|f| f.eq_ignore_ascii_case(&field)) {
+                    fields.push(field.clone());
+                }
+                fields
+            });
+            options.track_changes = pull.change_tracking;
+            options.cross_company = pull.cross_company;
+        } else if previous.delta_link.is_some() {
+            options.track_changes = true;
+        } else if let Some(since) = previous.watermark.as_deref() {
+            options.filter = Some(watermark_filter(&field, since, pull.watermark.overlap)?);
+            options.cross_company = pull.cross_company;
+        }
+
+        let checkpoint = previous.checkpoint.clone().filter(|_| pull.resume);
+        let resumed_rows = checkpoint.as_ref().map_or(0, |c| c.rows);
+        let mut mark = checkpoint.as_ref().and_then(|c| c.watermark.clone());
+        let mut link = checkpoint
+            .map(|c| c.next_link)
+            .or_else(|| previous.delta_link.clone());
+
+        let mut records = Vec::new();
+        let (mut read, mut deleted_rows, mut pages) = (0, 0, 0);
+        // Partitions have no common next link, so they are only used for
+        // fresh watermark loads, which restart rather than resume
+        let partitioned = pull
+            .partitioning
+            .as_ref()
+            .filter(|_| initial && !skip_load && !options.track_changes && link.is_none());
+        let delta_link = match partitioned {
+            Some(by) => {
+                let filters = match deadline_bound(pull.deadline, self.client.partition_filters(entity, &options, by)).await {
+                    Some(Ok(filters)) => filters,
+                    Some(Err(e)) => return Err(SyncError::OData(e)),
+                    None => return Err(SyncError::Deadline),
+                };
+                tracing::info!("Loading {} in {} partitions", entity, filters.len());
+                let mut partition_pages =
+                    std::pin::pin!(self.client.partitioned_pages(entity, &options, filters, pull.concurrency));
+                loop {
+                    let page = match deadline_bound(pull.deadline, partition_pages.next()).await {
+                        Some(Some(Ok(response))) => on_page(&response.value, read + response.value.len()).map(|_| response),
+                        Some(Some(Err(e))) => Err(SyncError::OData(e)),
+                        Some(None) => break None,
+                        None => Err(SyncError::Deadline),
+                    };
+                    let response = match page {
+                        Ok(response) => response,
+                        Err(reason) => {
+                            return Err(self.interrupt(entity, &previous, None, (0, read, mark), records, reason));
+                        }
+                    };
+                    mark = later(mark, max_timestamp(&response.value, &field));
+                    read += response.value.len();
+                    deleted_rows += response.value.iter().filter(|r| deleted_record(r).is_some()).count();
+                    if pull.collect {
+                        records.extend(response.value);
+                    }
+                }
+            }
+            None => loop {
+                    let request = self.client.fetch_entity_page(entity, link.as_deref(), &options);
+                    let page = match deadline_bound(pull.deadline, request).await {
+                        Some(Ok(response)) if skip_load => Ok(response),
+                        Some(Ok(response)) => {
+                            on_page(&response.value, resumed_rows + read + response.value.len()).map(|_| response)
+                        }
+                        Some(Err(e)) => Err(SyncError::OData(e)),
+                        None => Err(SyncError::Deadline),
+                    };
+                    let response = match page {
+                        Ok(response) => response,
+                        Err(reason) => {
+                            let progress = (resumed_rows, read, mark);
+                            return Err(self.interrupt(entity, &previous, link, progress, records, reason));
+                        }
+                    };
+
+                    mark = later(mark, max_timestamp(&response.value, &field));
+                    read += response.value.len();
+                    deleted_rows += response.value.iter().filter(|r| deleted_record(r).is_some()).count();
+                    if pull.collect && !skip_load {
+                        records.extend(response.value);
+                    }
+                    pages += 1;
+
+                    match response.next_link {
+                        Some(next) => {
+                            if pull.checkpoint_pages > 0 && pages % pull.checkpoint_pages == 0 {
+                                let checkpoint = Checkpoint::new(&next, resumed_rows + read, mark.clone());
+                                self.store.update(
+                                    entity,
+                                    EntitySyncState {
+                                        checkpoint: Some(checkpoint),
+                                        ..previous.clone()
+                                    },
+                                )?;
+                            }
+                            link = Some(next);
+                        }
+                        None => break response.delta_link,
+                    }
+            },
+        };
+
+        let mode = match (&delta_link, &previous.delta_link) {
+            (Some(_), _) => SyncMode::ChangeTracking,
+            (None, Some(_)) => return Err(SyncError::NoDeltaLink(entity.to_string())),
+            (None, None) => SyncMode::Watermark,
+        };
+        let (changed, deleted) = split_changes(records);
+
+        let state = match mode {
+            SyncMode::ChangeTracking => EntitySyncState {
+                delta_link,
+                ..Default::default()
+            },
+            SyncMode::Watermark => {
+                let mark = if skip_load { None } else { later(mark, previous.watermark) };
+                EntitySyncState {
+                    // A load without timestamps counts changes from its start
+                    watermark: mark.or_else(|| initial.then(|| format_utc(started))),
+                    watermark_field: Some(field),
+                    ..Default::default()
+                }
+            }
+        };
+        let (read, deleted_rows) = if skip_load { (0, 0) } else { (read, deleted_rows) };
+        let state = EntitySyncState {
+            last_sync: Some(format_utc(SystemTime::now())),
+            last_changes: resumed_rows + read,
+            phase: SyncPhase::Incremental,
+            ..state
+        };
+        self.store.update(entity, state.clone())?;
+
+        Ok(ChangeSet {
+            initial,
+            initial_load_skipped: skip_load,
+            mode,
+            changed,
+            deleted,
+            changed_rows: read - deleted_rows,
+            deleted_rows,
+            resumed_rows,
+            delta_link: state.delta_link,
+            watermark: state.watermark,
+        })
+    }
+
+    /// Save a checkpoint at `link` after `progress` (rows resumed past, rows
+    /// read by this pull, their watermark) and wrap the collected rows in the
+    /// error. Without a page read by this pull there is nothing new to resume
+    /// from and `reason` is returned as is.
+    fn interrupt(
+        &self,
+        entity: &str,
+        previous: &EntitySyncState,
+        link: Option<String>,
+        progress: (usize, usize, Option<String>),
+        rows: Vec<Value>,
+        reason: SyncError,
+    ) -> SyncError {
+        let (resumed_rows, read, mark) = progress;
+        let link = match link {
+            Some(link) if read > 0 => link,
+            _ => return reason,
+        };
+        let checkpoint = Checkpoint::new(&link, resumed_rows + read, mark);
+        let saved = self.store.update(
+            entity,
+            EntitySyncState {
+                checkpoint: Some(checkpoint),
+                ..previous.clone()
+            },
+        );
+        let (reason, resumable) = match saved {
+            Ok(()) => (reason.to_string(), true),
+            Err(e) => (format!("{} (saving the checkpoint failed: {})", reason, e), false),
+        };
+        SyncError::Interrupted {
+            reason,
+            resumable,
+            rows,
+        }
+    }
+}
+
+/// Await `request`, or `None` once the deadline passes
+async fn deadline_bound<T>(deadline: Option<Instant>, request: impl Future<Output = T>) -> Option<T> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), request)
+            .await
+            .ok(),
+        None => Some(request.await),
+    }
+}
+
+/// The later of two timestamps
+fn later(a: Option<String>, b: Option<String>) -> Option<String> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            if parse_utc(&b) > parse_utc(&a) {
+                Some(b)
+            } else {
+                Some(a)
+            }
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+/// `<field> ge <since - overlap>`
+fn watermark_filter(field: &str, since: &str, overlap: Duration) -> Result<String, SyncError> {
+    let since = parse_utc(since)
+        .ok_or_else(|| SyncError::State(format!("invalid stored watermark '{}'", since)))?;
+    let from = since.checked_sub(overlap).unwrap_or(UNIX_EPOCH);
+    Ok(format!("{} ge {}", field, format_utc(from)))
+}
+
+/// Highest timestamp in `field` across the records, as written by the service
+fn max_timestamp(records: &[Value], field: &str) -> Option<String> {
+    records
+        .iter()
+        .filter_map(|r| r.get(field).and_then(|v| v.as_str()))
+        .filter_map(|text| parse_utc(text).map(|time| (time, text)))
+        .max_by_key(|(time, _)| *time)
+        .map(|(_, text)| text.to_string())
+}
+
+/// Separate removed-record entries from new or updated rows
+pub fn split_changes(records: Vec<Value>) -> (Vec<Value>, Vec<DeletedRecord>) {
+    let mut changed = Vec::with_capacity(records.len());
+    let mut deleted = Vec::new();
+    for record in records {
+        match deleted_record(&record) {
+            Some(removed) => deleted.push(removed),
+            None => changed.push(record),
+        }
+    }
+    (changed, deleted)
+}
+
+/// Dataverse marks removals with a `$deletedEntity` context and `id`;
+/// OData 4.01 uses `@removed` with `@id`
+fn deleted_record(record: &Value) -> Option<DeletedRecord> {
+    let text = |name: &str| record.get(name).and_then(|v| v.as_str()).map(String::from);
+
+    let is_deleted_entity = text("@odata.context").is_some_and(|c| c.ends_with("$deletedEntity"));
+    if is_deleted_entity {
+        return Some(DeletedRecord {
+            id: text("id").or_else(|| text("@odata.id")).unwrap_or_default(),
+            reason: text("reason"),
+        });
+    }
+
+    let removed = record.get("@removed")?;
+    Some(DeletedRecord {
+        id: text("@id").or_else(|| text("@odata.id")).unwrap_or_default(),
+        reason: removed.get("reason").and_then(|v| v.as_str()).map(String::from),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_watermark_filter_and_max() {
+        let filter = watermark_filter("modifiedon", "2024-02-29T12:34:56Z", Duration::from_secs(300)).unwrap();
+        assert_eq!(filter, "modifiedon ge 2024-02-29T12:29:56Z");
+        assert!(watermark_filter("modifiedon", "yesterday", Duration::ZERO).is_err());
+
+        let records = vec![
+            json!({ "ModifiedDateTime": "2024-02-29T12:00:00Z" }),
+            json!({ "ModifiedDateTime": "2024-02-29T14:30:00+02:00" }),
+            json!({ "ModifiedDateTime": "2024-02-29T12:10:00.5Z" }),
+            json!({ "ModifiedDateTime": null }),
+        ];
+        assert_eq!(
+            max_timestamp(&records, "ModifiedDateTime").as_deref(),
+            Some("2024-02-29T14:30:00+02:00")
+        );
+        assert_eq!(max_timestamp(&records, "modifiedon"), None);
+    }
+
+    #[test]
+    fn test_later() {
+        let a = Some("2024-02-29T12:00:00Z".to_string());
+        let b = Some("2024-02-29T13:30:00+02:00".to_string());
+        assert_eq!(later(a.clone(), b.clone()), a);
+        assert_eq!(later(None, b.clone()), b);
+        assert_eq!(later(a.clone(), None), a);
+    }
+
+    #[test]
+    fn test_split_changes() {
+        let records = vec![
+            json!({ "accountid": "a1", "name": "Contoso" }),
+            json!({
+                "@odata.context": "https://org/api/data/v9.2/$metadata#accounts/$deletedEntity",
+                "id": "a2",
+                "reason": "deleted"
+            }),
+            json!({ "@removed": { "reason": "changed" }, "@id": "accounts(a3)" }),
+        ];
+        let (changed, deleted) = split_changes(records);
+        assert_eq!(changed, vec![json!({ "accountid": "a1", "name": "Contoso" })]);
+        assert_eq!(
+            deleted,
+            vec![
+                DeletedRecord { id: "a2".to_string(), reason: Some("deleted".to_string()) },
+                DeletedRecord { id: "accounts(a3)".to_string(), reason: Some("changed".to_string()) },
+            ]
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! Incremental sync
+//!
+//! Pulls only the records that changed since the last sync of an entity,
+//! using change-tracking delta links or a timestamp watermark, and keeps
+//! per-entity state in the `[delta] storage_path` file between runs.
+
+pub mod delta;
+pub mod scheduler;
+pub mod sink;
+pub mod state;
+
+use crate::odata::ODataError;
+use thiserror::Error;
+
+pub use delta::{split_changes, ChangeSet, DeletedRecord, DeltaSync, PullOptions, SyncMode, Watermark};
+pub use scheduler::{RunStatus, RunSummary, Scheduler};
+pub use sink::{open_sink, JsonlSink, Rotation, Sink};
+pub use state::{Checkpoint, EntitySyncState, JsonStateStore, StateStore, SyncPhase};
+
+/// Sync errors
+#[derive(Error, Debug)]
+pub enum SyncError {
+    #[error("{0}")]
+    OData(#[from] ODataError),
+
+    #[error("State file error: {0}")]
+    State(String),
+
+    #[error("Sink error: {0}")]
+    Sink(String),
+
+    #[error("{0} returned no delta link: change tracking is probably not enabled for this entity")]
+    NoDeltaLink(String),
+
+    #[error("Deadline reached")]
+    Deadline,
+
+    #[error("{reason}; {} rows were read before the pull stopped", rows.len())]
+    Interrupted {
+        reason: String,
+        /// Whether a checkpoint was saved to resume from
+        resumable: bool,
+        rows: Vec<serde_json::Value>,
+    },
+}
@@ -0,0 +1,265 @@
+//! Background sync
+//!
+//! When `[sync] enabled = true`, every `[[entities]]` entry with delta sync
+//! enabled is pulled on its own interval in a background task: the first run
+//! reads every row, later runs only the changes. The scheduler keeps its own
+//! state file so it never consumes changes meant for `get_changes` callers.
+//! An interrupted run resumes from its checkpoint on the next run.
+//!
+//! With a [`Sink`] every page is written out before the run moves past it;
+//! without one the runs only advance the sync position.
+
+use super::delta::{split_changes, DeltaSync, PullOptions, SyncMode};
+use super::sink::Sink;
+use super::SyncError;
+use crate::config::RuntimeConfig;
+use crate::odata::time_window::format_utc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// One scheduled entity
+#[derive(Debug, Clone)]
+pub struct EntitySchedule {
+    pub entity: String,
+    pub interval: Duration,
+    pub pull: PullOptions,
+}
+
+/// Outcome of a finished run
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    pub initial: bool,
+    pub mode: SyncMode,
+    pub changed: usize,
+    pub deleted: usize,
+}
+
+/// What the scheduler knows about one entity
+#[derive(Debug, Clone, Default)]
+pub struct RunStatus {
+    pub interval: Duration,
+    pub running: bool,
+    pub runs: u64,
+    pub failures: u64,
+    pub last_started: Option<String>,
+    pub last_finished: Option<String>,
+    /// Summary of the last run, or its error
+    pub last_result: Option<Result<RunSummary, String>>,
+    pub next_run: Option<String>,
+}
+
+/// Runs scheduled pulls and records their results
+pub struct Scheduler {
+    sync: DeltaSync,
+    sink: Option<Arc<dyn Sink>>,
+    schedules: Vec<EntitySchedule>,
+    status: Mutex<BTreeMap<String, RunStatus>>,
+    /// Schedule tasks, awaited by `stop`
+    tasks: TaskTracker,
+    stop: CancellationToken,
+}
+
+impl Scheduler {
+    pub fn new(sync: DeltaSync, schedules: Vec<EntitySchedule>) -> Self {
+        let status = schedules
+            .iter()
+            .map(|s| {
+                let status = RunStatus {
+                    interval: s.interval,
+                    ..Default::default()
+                };
+                (s.entity.clone(), status)
+            })
+            .collect();
+        Self {
+            sync,
+            sink: None,
+            schedules,
+            status: Mutex::new(status),
+            tasks: TaskTracker::new(),
+            stop: CancellationToken::new(),
+        }
+    }
+
+    /// Write the rows of every run to `sink`
+    pub fn with_sink(mut self, sink: Arc<dyn Sink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    pub fn sink(&self) -> Option<&dyn Sink> {
+        self.sink.as_deref()
+    }
+
+    /// Schedules for the configured entities that have delta sync enabled
+    pub fn schedules_from_config(config: &RuntimeConfig) -> Vec<EntitySchedule> {
+        config
+            .entities
+            .iter()
+            .filter(|e| e.delta_enabled != Some(false))
+            .map(|e| EntitySchedule {
+                entity: e.name.clone(),
+                interval: Duration::from_secs(
+                    e.sync_interval_seconds
+                        .unwrap_or(config.sync.interval_seconds)
+                        .max(1),
+                ),
+                pull: PullOptions {
+                    resume: true,
+                    // Pages go to the sink; a large initial load must not pile up in memory
+                    collect: false,
+                    ..PullOptions::for_entity(&e.name, config)
+                },
+            })
+            .collect()
+    }
+
+    pub fn sync(&self) -> &DeltaSync {
+        &self.sync
+    }
+
+    /// Spawn one task per schedule on the current Tokio runtime
+    pub fn start(self: &Arc<Self>) {
+        for index in 0..self.schedules.len() {
+            let scheduler = Arc::clone(self);
+            self.tasks.spawn(async move {
+                let schedule = &scheduler.schedules[index];
+                let mut ticker = tokio::time::interval(schedule.interval);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                loop {
+                    tokio::select! {
+                        biased;
+                        _ = scheduler.stop.cancelled() => break,
+                        _ = ticker.tick() => {}
+                    }
+                    // A started run is finished, so its state and sink writes are complete
+                    scheduler.run_once(schedule).await;
+                }
+            });
+        }
+    }
+
+    /// Start no further runs and wait for the running ones to finish
+    pub async fn stop(&self) {
+        self.stop.cancel();
+        self.tasks.close();
+        self.tasks.wait().await;
+    }
+
+    /// Run one pull and record the result
+    pub async fn run_once(&self, schedule: &EntitySchedule) {
+        self.update_status(&schedule.entity, |s| {
+            s.running = true;
+            s.last_started = Some(format_utc(SystemTime::now()));
+        });
+        tracing::info!("Background sync of {} started", schedule.entity);
+
+        let entity = &schedule.entity;
+        let result = self
+            .sync
+            .pull(entity, &schedule.pull, |page, _| match &self.sink {
+                Some(sink) => {
+                    let (changed, deleted) = split_changes(page.to_vec());
+                    sink.write(entity, &changed, &deleted)
+                }
+                None => Ok(()),
+            })
+            .await
+            .map(|changes| RunSummary {
+                initial: changes.initial,
+                mode: changes.mode,
+                changed: changes.resumed_rows + changes.changed_rows,
+                deleted: changes.deleted_rows,
+            })
+            .map_err(|e| match e {
+                SyncError::Interrupted { reason, resumable: true, .. } => {
+                    format!("{}; the next run resumes from the checkpoint", reason)
+                }
+                SyncError::Interrupted { reason, .. } => reason,
+                other => other.to_string(),
+            });
+
+        match &result {
+            Ok(summary) => tracing::info!(
+                "Background sync of {} finished: {} changed, {} deleted",
+                schedule.entity,
+                summary.changed,
+                summary.deleted
+            ),
+            Err(e) => tracing::warn!("Background sync of {} failed: {}", schedule.entity, e),
+        }
+        self.update_status(&schedule.entity, |s| {
+            s.running = false;
+            s.runs += 1;
+            if result.is_err() {
+                s.failures += 1;
+            }
+            s.last_finished = Some(format_utc(SystemTime::now()));
+            s.next_run = Some(format_utc(SystemTime::now() + schedule.interval));
+            s.last_result = Some(result);
+        });
+    }
+
+    /// Status of every scheduled entity, by name
+    pub fn status(&self) -> Vec<(String, RunStatus)> {
+        self.status
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(name, status)| (name.clone(), status.clone()))
+            .collect()
+    }
+
+    fn update_status(&self, entity: &str, f: impl FnOnce(&mut RunStatus)) {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        f(status.entry(entity.to_string()).or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{vars, Config};
+
+    #[test]
+    fn test_schedules_from_config() {
+        let config: Config = toml::from_str(
+            r#"
+[global]
+endpoint = "https://org.crm.dynamics.com/api/data/v9.2/"
+
+[sync]
+enabled = true
+interval_seconds = 600
+
+[[entities]]
+name = "accounts"
+
+[[entities]]
+name = "contacts"
+sync_interval_seconds = 60
+watermark_field = "modifiedon"
+initial_load = false
+
+[[entities]]
+name = "leads"
+delta_enabled = false
+"#,
+        )
+        .unwrap();
+        let env = [("TENANT_ID", "t"), ("CLIENT_ID", "c"), ("CLIENT_SECRET", "s")];
+        let runtime = vars::with_vars(&env, || config.to_runtime().unwrap());
+
+        let schedules = Scheduler::schedules_from_config(&runtime);
+        assert_eq!(schedules.len(), 2);
+        assert_eq!(schedules[0].entity, "accounts");
+        assert_eq!(schedules[0].interval, Duration::from_secs(600));
+        assert!(schedules[0].pull.change_tracking && schedules[0].pull.resume);
+        assert_eq!(schedules[1].interval, Duration::from_secs(60));
+        assert!(!schedules[1].pull.change_tracking);
+        assert!(schedules[0].pull.initial_load && !schedules[1].pull.initial_load);
+    }
+}
@@ -0,0 +1,266 @@
+//! Sync sinks
+//!
+//! A [`Sink`] receives the rows of background sync runs page by page, before
+//! the sync position moves past them. [`JsonlSink`] appends them to
+//! newline-delimited JSON files, one directory per entity, and starts a new
+//! file when the current one reaches a size limit or, with daily rotation,
+//! when the UTC date changes. Each line is one change:
+//!
+//! ```text
+//! {"op":"upsert","entity":"accounts","synced_at":"2024-01-31T08:00:00Z","record":{...}}
+//! {"op":"delete","entity":"accounts","synced_at":"2024-01-31T08:00:00Z","id":"...","reason":"deleted"}
+//! ```
+
+use super::delta::DeletedRecord;
+use super::SyncError;
+use crate::config::{SinkConfig, SinkKind, SinkRotation};
+use crate::odata::time_window::format_utc;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Destination of synced rows
+pub trait Sink: Send + Sync {
+    /// Write the new or updated rows and deletions of one page. Returning
+    /// means the rows are durable; an error stops the sync run at this page.
+    fn write(&self, entity: &str, changed: &[Value], deleted: &[DeletedRecord]) -> Result<(), SyncError>;
+
+    /// Where rows go, for status output
+    fn location(&self) -> String;
+}
+
+/// Build the configured sink
+pub fn open_sink(config: &SinkConfig) -> Arc<dyn Sink> {
+    match config.kind {
+        SinkKind::Jsonl => Arc::new(JsonlSink::new(
+            &config.path,
+            Rotation {
+                max_bytes: config.max_file_mb.map(|mb| mb.saturating_mul(1024 * 1024)),
+                daily: config.rotate == Some(SinkRotation::Daily),
+            },
+        )),
+    }
+}
+
+/// When [`JsonlSink`] starts a new file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rotation {
+    /// Start a new file once the current one reaches this size
+    pub max_bytes: Option<u64>,
+    /// Start a new file when the UTC date changes
+    pub daily: bool,
+}
+
+/// Newline-delimited JSON files under `<dir>/<entity>/`
+#[derive(Debug)]
+pub struct JsonlSink {
+    dir: PathBuf,
+    rotation: Rotation,
+    /// Open file per entity
+    files: Mutex<HashMap<String, OpenFile>>,
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    path: PathBuf,
+    date: String,
+    sequence: u32,
+    size: u64,
+}
+
+impl JsonlSink {
+    pub fn new(dir: impl Into<PathBuf>, rotation: Rotation) -> Self {
+        Self {
+            dir: dir.into(),
+            rotation,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// File for the next line of `entity`, rotating when needed
+    fn current_file(&self, files: &mut HashMap<String, OpenFile>, entity: &str, date: &str) -> Result<PathBuf, SyncError> {
+        let entity_dir = self.dir.join(sanitize(entity));
+        let date = if self.rotation.daily { date } else { "" };
+
+        let file = match files.remove(entity) {
+            Some(file) if file.date == date => file,
+            _ => {
+                fs::create_dir_all(&entity_dir).map_err(|e| sink_error(&entity_dir, e))?;
+                // Continue the newest existing file for the date after a restart
+                let sequence = last_sequence(&entity_dir, entity, date).unwrap_or(1);
+                let path = entity_dir.join(file_name(entity, date, sequence));
+                let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                OpenFile {
+                    path,
+                    date: date.to_string(),
+                    sequence,
+                    size,
+                }
+            }
+        };
+
+        let file = match self.rotation.max_bytes {
+            Some(max) if file.size >= max => {
+                let sequence = file.sequence + 1;
+                OpenFile {
+                    path: entity_dir.join(file_name(entity, date, sequence)),
+                    date: file.date,
+                    sequence,
+                    size: 0,
+                }
+            }
+            _ => file,
+        };
+        let path = file.path.clone();
+        files.insert(entity.to_string(), file);
+        Ok(path)
+    }
+}
+
+impl Sink for JsonlSink {
+    fn write(&self, entity: &str, changed: &[Value], deleted: &[DeletedRecord]) -> Result<(), SyncError> {
+        let synced_at = format_utc(SystemTime::now());
+        let date = synced_at[..10].to_string();
+        let lines = changed
+            .iter()
+            .map(|record| json!({ "op": "upsert", "entity": entity, "synced_at": synced_at, "record": record }))
+            .chain(deleted.iter().map(|d| {
+                json!({ "op": "delete", "entity": entity, "synced_at": synced_at, "id": d.id, "reason": d.reason })
+            }));
+
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        let mut writer: Option<(PathBuf, BufWriter<File>)> = None;
+        for line in lines {
+            let path = self.current_file(&mut files, entity, &date)?;
+            if writer.as_ref().map_or(true, |(open, _)| *open != path) {
+                if let Some((open, w)) = writer.take() {
+                    finish(&open, w)?;
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| sink_error(&path, e))?;
+                writer = Some((path.clone(), BufWriter::new(file)));
+            }
+
+            let mut text = line.to_string();
+            text.push('\n');
+            if let Some((open, w)) = writer.as_mut() {
+                w.write_all(text.as_bytes()).map_err(|e| sink_error(open, e))?;
+            }
+            if let Some(file) = files.get_mut(entity) {
+                file.size += text.len() as u64;
+            }
+        }
+        match writer {
+            Some((open, w)) => finish(&open, w),
+            None => Ok(()),
+        }
+    }
+
+    fn location(&self) -> String {
+        self.dir.display().to_string()
+    }
+}
+
+/// Flush buffered lines and wait until they are on disk
+fn finish(path: &Path, writer: BufWriter<File>) -> Result<(), SyncError> {
+    let file = writer.into_inner().map_err(|e| sink_error(path, e.into_error()))?;
+    file.sync_data().map_err(|e| sink_error(path, e))
+}
+
+fn sink_error(path: &Path, e: std::io::Error) -> SyncError {
+    SyncError::Sink(format!("{}: {}", path.display(), e))
+}
+
+/// `<entity>-<date>-<NNNN>.jsonl`, or `<entity>-<NNNN>.jsonl` without daily rotation
+fn file_name(entity: &str, date: &str, sequence: u32) -> String {
+    if date.is_empty() {
+        format!("{}-{:04}.jsonl", sanitize(entity), sequence)
+    } else {
+        format!("{}-{}-{:04}.jsonl", sanitize(entity), date, sequence)
+    }
+}
+
+/// Highest sequence number of existing files for the entity and date
+fn last_sequence(dir: &Path, entity: &str, date: &str) -> Option<u32> {
+    let prefix = file_name(entity, date, 0);
+    let prefix = prefix.trim_end_matches("0000.jsonl");
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            name.strip_prefix(prefix)?
+                .strip_suffix(".jsonl")?
+                .parse::<u32>()
+                .ok()
+        })
+        .max()
+}
+
+/// Entity names as file name parts
+fn sanitize(entity: &str) -> String {
+    entity
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("d365-sink-{}-{}", std::process::id(), name))
+    }
+
+    fn lines(path: &Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_jsonl_lines() {
+        let dir = temp_dir("lines");
+        let sink = JsonlSink::new(&dir, Rotation::default());
+        let deleted = [DeletedRecord { id: "a2".to_string(), reason: Some("deleted".to_string()) }];
+        sink.write("accounts", &[json!({ "accountid": "a1" })], &deleted).unwrap();
+
+        let written = lines(&dir.join("accounts").join("accounts-0001.jsonl"));
+        assert_eq!(written.len(), 2);
+        assert_eq!(written[0]["op"], "upsert");
+        assert_eq!(written[0]["record"]["accountid"], "a1");
+        assert_eq!(written[1]["op"], "delete");
+        assert_eq!(written[1]["id"], "a2");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotation_by_size_and_restart() {
+        let dir = temp_dir("rotate");
+        let rotation = Rotation { max_bytes: Some(1), daily: false };
+        let sink = JsonlSink::new(&dir, rotation);
+        let rows = [json!({ "id": 1 }), json!({ "id": 2 })];
+        sink.write("my.entity", &rows, &[]).unwrap();
+
+        let entity_dir = dir.join("my_entity");
+        assert_eq!(lines(&entity_dir.join("my_entity-0001.jsonl")).len(), 1);
+        assert_eq!(lines(&entity_dir.join("my_entity-0002.jsonl")).len(), 1);
+
+        // A new sink continues after the newest file
+        JsonlSink::new(&dir, rotation).write("my.entity", &rows[..1], &[]).unwrap();
+        assert!(entity_dir.join("my_entity-0003.jsonl").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,267 @@
+//! Persisted sync state
+//!
+//! [`StateStore`] is the storage interface of the sync subsystem.
+//! [`JsonStateStore`] keeps the state of every synced entity in one JSON
+//! file. It is read on first use and rewritten through a temporary file that
+//! is renamed over the old one, so a crash mid-write never leaves a
+//! truncated state file.
+
+use super::SyncError;
+use crate::odata::time_window::format_utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Sync state of one entity
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EntitySyncState {
+    /// `@odata.deltaLink` the next pull starts from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta_link: Option<String>,
+    /// Timestamp column of the watermark strategy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark_field: Option<String>,
+    /// Highest `watermark_field` value seen so far
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+    /// When the last successful pull finished (UTC)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<String>,
+    /// Rows returned by the last successful pull
+    #[serde(default)]
+    pub last_changes: usize,
+    /// Where an interrupted pull stopped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<Checkpoint>,
+    #[serde(default)]
+    pub phase: SyncPhase,
+}
+
+/// Whether the initial load of an entity has finished
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncPhase {
+    /// No load finished yet; the next pull reads every row
+    #[default]
+    InitialLoad,
+    /// Pulls read changes only
+    Incremental,
+}
+
+/// Position of an unfinished pull
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Link of the next page to read (carries the skiptoken or paging cookie)
+    pub next_link: String,
+    /// Rows read before `next_link`
+    pub rows: usize,
+    /// Highest watermark value among those rows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+    /// When the checkpoint was saved (UTC)
+    pub saved_at: String,
+}
+
+impl Checkpoint {
+    pub fn new(next_link: &str, rows: usize, watermark: Option<String>) -> Self {
+        Self {
+            next_link: next_link.to_string(),
+            rows,
+            watermark,
+            saved_at: format_utc(SystemTime::now()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    #[serde(default)]
+    entities: BTreeMap<String, EntitySyncState>,
+}
+
+/// Storage of per-entity sync state. Each `update` or `remove` is applied
+/// completely or not at all.
+pub trait StateStore: Send + Sync {
+    /// State of one entity
+    fn get(&self, entity: &str) -> Result<Option<EntitySyncState>, SyncError>;
+
+    /// State of every entity, by name
+    fn list(&self) -> Result<Vec<(String, EntitySyncState)>, SyncError>;
+
+    /// Replace the state of one entity
+    fn update(&self, entity: &str, state: EntitySyncState) -> Result<(), SyncError>;
+
+    /// Forget an entity; returns false if it had no state
+    fn remove(&self, entity: &str) -> Result<bool, SyncError>;
+
+    /// Where the state lives, for status output
+    fn location(&self) -> String;
+}
+
+/// Per-entity sync state backed by a JSON file
+#[derive(Debug)]
+pub struct JsonStateStore {
+    path: PathBuf,
+    /// Loaded lazily so a broken file surfaces as a tool error, not a startup failure
+    entities: Mutex<Option<BTreeMap<String, EntitySyncState>>>,
+}
+
+impl StateStore for JsonStateStore {
+    fn get(&self, entity: &str) -> Result<Option<EntitySyncState>, SyncError> {
+        self.with_entities(|entities| Ok(entities.get(entity).cloned()))
+    }
+
+    fn list(&self) -> Result<Vec<(String, EntitySyncState)>, SyncError> {
+        self.with_entities(|entities| {
+            Ok(entities
+                .iter()
+                .map(|(name, state)| (name.clone(), state.clone()))
+                .collect())
+        })
+    }
+
+    /// The in-memory state only changes once the file was written
+    fn update(&self, entity: &str, state: EntitySyncState) -> Result<(), SyncError> {
+        self.with_entities(|entities| {
+            let mut updated = entities.clone();
+            updated.insert(entity.to_string(), state);
+            self.save(&updated)?;
+            *entities = updated;
+            Ok(())
+        })
+    }
+
+    fn remove(&self, entity: &str) -> Result<bool, SyncError> {
+        self.with_entities(|entities| {
+            if !entities.contains_key(entity) {
+                return Ok(false);
+            }
+            let mut updated = entities.clone();
+            updated.remove(entity);
+            self.save(&updated)?;
+            *entities = updated;
+            Ok(true)
+        })
+    }
+
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+impl JsonStateStore {
+    /// Store backed by `path`; nothing is read until first use
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            entities: Mutex::new(None),
+        }
+    }
+
+    fn with_entities<T>(
+        &self,
+        f: impl FnOnce(&mut BTreeMap<String, EntitySyncState>) -> Result<T, SyncError>,
+    ) -> Result<T, SyncError> {
+        let mut guard = self.entities.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.is_none() {
+            *guard = Some(self.load()?);
+        }
+        f(guard.get_or_insert_with(BTreeMap::new))
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, EntitySyncState>, SyncError> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => {
+                return Err(SyncError::State(format!("cannot read {}: {}", self.path.display(), e)))
+            }
+        };
+        serde_json::from_str::<StateFile>(&text)
+            .map(|file| file.entities)
+            .map_err(|e| SyncError::State(format!("invalid state file {}: {}", self.path.display(), e)))
+    }
+
+    fn save(&self, entities: &BTreeMap<String, EntitySyncState>) -> Result<(), SyncError> {
+        let file = StateFile {
+            entities: entities.clone(),
+        };
+        let json = serde_json::to_vec_pretty(&file)
+            .map_err(|e| SyncError::State(format!("cannot serialize state: {}", e)))?;
+        write_atomic(&self.path, &json)
+            .map_err(|e| SyncError::State(format!("cannot write {}: {}", self.path.display(), e)))
+    }
+}
+
+/// Write `bytes` to a sibling temporary file, flush it to disk and rename it over `path`
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("d365-sync-state-{}-{}", std::process::id(), name))
+            .join("delta_state.json")
+    }
+
+    #[test]
+    fn test_update_persists_and_reloads() {
+        let path = temp_path("reload");
+        let store = JsonStateStore::new(&path);
+        assert_eq!(store.get("accounts").unwrap(), None);
+
+        let state = EntitySyncState {
+            delta_link: Some("https://org/api/data/v9.2/accounts?$deltatoken=1".to_string()),
+            watermark_field: None,
+            watermark: None,
+            last_sync: Some("2024-01-31T08:00:00Z".to_string()),
+            last_changes: 3,
+            checkpoint: Some(Checkpoint::new("https://org/api/data/v9.2/accounts?$skiptoken=2", 5000, None)),
+            phase: SyncPhase::Incremental,
+        };
+        store.update("accounts", state.clone()).unwrap();
+        assert!(!path.with_file_name("delta_state.json.tmp").exists());
+
+        let reopened = JsonStateStore::new(&path);
+        assert_eq!(reopened.get("accounts").unwrap(), Some(state));
+        assert!(reopened.remove("accounts").unwrap());
+        assert!(!reopened.remove("accounts").unwrap());
+        assert_eq!(JsonStateStore::new(&path).list().unwrap(), Vec::new());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_file_is_reported() {
+        let path = temp_path("invalid");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not json").unwrap();
+
+        let store = JsonStateStore::new(&path);
+        assert!(matches!(store.get("accounts"), Err(SyncError::State(_))));
+        // A failed load must not be replaced by an empty state on the next write
+        assert!(store.update("accounts", EntitySyncState::default()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not json");
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}